@@ -0,0 +1,640 @@
+// Streaming client for the Anthropic Messages API. Converts ChatRequests to
+// Anthropic's message and tool formats and reports the stream as ChatEvents.
+use serde_json::{json, Map, Value};
+use tauri::ipc::Channel;
+use tauri::State;
+
+use crate::cancellation::RequestRegistry;
+use crate::chat::{ChatCompletion, ChatEvent, ChatMessage, ChatRequest, ChatStream};
+use crate::credentials::CredentialStore;
+use crate::egress::{EgressPolicy, EgressPurpose};
+use crate::error::{CommandError, ErrorKind};
+use crate::http_client::HttpClients;
+use crate::openai_compat::{ensure_success, error_body_message};
+use crate::recorder::{ExchangeSource, Recorder};
+use crate::sse;
+
+pub const API_VERSION: &str = "2023-06-01";
+const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+fn text_block(text: &str) -> Value {
+    json!({ "type": "text", "text": text })
+}
+
+// Content blocks for one OpenChat message, and the Anthropic role they belong to
+fn message_blocks(message: &ChatMessage) -> (&'static str, Vec<Value>) {
+    let mut blocks = Vec::new();
+
+    if message.role == "tool" {
+        blocks.push(json!({
+            "type": "tool_result",
+            "tool_use_id": message.tool_call_id.clone().unwrap_or_default(),
+            "content": message.content,
+        }));
+        return ("user", blocks);
+    }
+
+    // The API rejects empty text blocks
+    if !message.content.trim().is_empty() {
+        blocks.push(text_block(&message.content));
+    }
+    for image in &message.images {
+        blocks.push(json!({
+            "type": "image",
+            "source": { "type": "base64", "media_type": image.mime_type, "data": image.data },
+        }));
+    }
+
+    if message.role == "assistant" {
+        // Signed thinking has to lead the turn it was produced in
+        let thinking = message.reasoning_blocks.iter().filter(|block| block.is_object()).cloned();
+        blocks.splice(0..0, thinking);
+        for call in &message.tool_calls {
+            let input = serde_json::from_str::<Value>(&call.function.arguments)
+                .ok()
+                .filter(Value::is_object)
+                .unwrap_or_else(|| json!({}));
+            blocks.push(json!({
+                "type": "tool_use",
+                "id": call.id,
+                "name": call.function.name,
+                "input": input,
+            }));
+        }
+        return ("assistant", blocks);
+    }
+    ("user", blocks)
+}
+
+// System prompts move to the top-level "system" field and consecutive
+// messages with the same role are merged, so tool results that follow one
+// assistant turn land in a single user message
+fn convert_messages(messages: &[ChatMessage]) -> (Option<String>, Vec<Value>) {
+    let mut system = Vec::new();
+    let mut converted: Vec<(&'static str, Vec<Value>)> = Vec::new();
+
+    for message in messages {
+        if message.role == "system" {
+            if !message.content.trim().is_empty() {
+                system.push(message.content.as_str());
+            }
+            continue;
+        }
+
+        let (role, blocks) = message_blocks(message);
+        if blocks.is_empty() {
+            continue;
+        }
+        match converted.last_mut() {
+            Some((last_role, last_blocks)) if *last_role == role => last_blocks.extend(blocks),
+            _ => converted.push((role, blocks)),
+        }
+    }
+
+    let system = (!system.is_empty()).then(|| system.join("\n\n"));
+    let messages = converted
+        .into_iter()
+        .map(|(role, content)| json!({ "role": role, "content": content }))
+        .collect();
+    (system, messages)
+}
+
+fn convert_tool(tool: &Value) -> Value {
+    // Already in Anthropic's shape
+    if tool.get("input_schema").is_some() {
+        return tool.clone();
+    }
+    let (name, description, parameters) = ChatRequest::tool_parts(tool);
+    json!({ "name": name, "description": description, "input_schema": parameters })
+}
+
+// Thinking counts against max_tokens, and Anthropic requires the budget to
+// be below it. Without a max_tokens of its own, a request with thinking gets
+// the default on top of the budget.
+fn max_tokens(request: &ChatRequest) -> Result<u32, CommandError> {
+    match (request.max_tokens, request.thinking_budget) {
+        (Some(max_tokens), Some(budget)) if max_tokens <= budget => Err(CommandError::new(
+            ErrorKind::InvalidRequest,
+            format!("max_tokens ({}) must be larger than the thinking budget ({})", max_tokens, budget),
+        )),
+        (Some(max_tokens), _) => Ok(max_tokens),
+        (None, Some(budget)) => Ok(budget.saturating_add(DEFAULT_MAX_TOKENS)),
+        (None, None) => Ok(DEFAULT_MAX_TOKENS),
+    }
+}
+
+fn request_body(request: &ChatRequest) -> Result<Value, CommandError> {
+    let (system, messages) = convert_messages(&request.messages);
+
+    let mut body = Map::new();
+    body.insert("model".into(), json!(request.model));
+    body.insert("messages".into(), Value::Array(messages));
+    body.insert("max_tokens".into(), json!(max_tokens(request)?));
+    body.insert("stream".into(), json!(true));
+    if let Some(system) = system {
+        body.insert("system".into(), json!(system));
+    }
+    if !request.tools.is_empty() {
+        body.insert("tools".into(), request.tools.iter().map(convert_tool).collect());
+    }
+    if !request.stop.is_empty() {
+        body.insert("stop_sequences".into(), json!(request.stop));
+    }
+
+    match request.thinking_budget {
+        // Extended thinking doesn't accept temperature or top_k changes
+        Some(budget) => {
+            body.insert("thinking".into(), json!({ "type": "enabled", "budget_tokens": budget }));
+        }
+        None => {
+            if let Some(temperature) = request.temperature {
+                body.insert("temperature".into(), json!(temperature));
+            }
+            if let Some(top_p) = request.top_p {
+                body.insert("top_p".into(), json!(top_p));
+            }
+            if let Some(top_k) = request.top_k {
+                body.insert("top_k".into(), json!(top_k));
+            }
+        }
+    }
+
+    for (key, value) in &request.extra {
+        body.insert(key.clone(), value.clone());
+    }
+    Ok(Value::Object(body))
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+struct AnthropicUsage {
+    input_tokens: Option<u64>,
+    output_tokens: Option<u64>,
+    cache_creation_input_tokens: Option<u64>,
+    cache_read_input_tokens: Option<u64>,
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+struct MessageInfo {
+    model: Option<String>,
+    usage: AnthropicUsage,
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+struct ContentBlock {
+    #[serde(rename = "type")]
+    kind: String,
+    id: Option<String>,
+    name: Option<String>,
+    text: Option<String>,
+    thinking: Option<String>,
+    signature: Option<String>,
+    // redacted_thinking
+    data: Option<String>,
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+struct BlockDelta {
+    #[serde(rename = "type")]
+    kind: String,
+    text: Option<String>,
+    thinking: Option<String>,
+    signature: Option<String>,
+    partial_json: Option<String>,
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+struct MessageDelta {
+    stop_reason: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AnthropicEvent {
+    MessageStart {
+        message: MessageInfo,
+    },
+    ContentBlockStart {
+        index: usize,
+        content_block: ContentBlock,
+    },
+    ContentBlockDelta {
+        index: usize,
+        delta: BlockDelta,
+    },
+    ContentBlockStop {
+        index: usize,
+    },
+    MessageDelta {
+        #[serde(default)]
+        delta: MessageDelta,
+        #[serde(default)]
+        usage: AnthropicUsage,
+    },
+    MessageStop,
+    Ping,
+    Error {
+        error: Value,
+    },
+    // Event types added to the API later are skipped
+    #[serde(other)]
+    Unknown,
+}
+
+// Translates Anthropic's block-based events into ChatStream calls
+struct Assembler<'a> {
+    stream: ChatStream<'a>,
+    input_tokens: u64,
+    output_tokens: u64,
+    // (content block index, ChatStream tool call index)
+    tool_blocks: Vec<(usize, usize)>,
+    // Thinking blocks being streamed, handed to the ChatStream when they stop
+    thinking_blocks: Vec<(usize, Value)>,
+    stopped: bool,
+}
+
+impl<'a> Assembler<'a> {
+    fn new(stream: ChatStream<'a>) -> Self {
+        Self {
+            stream,
+            input_tokens: 0,
+            output_tokens: 0,
+            tool_blocks: Vec::new(),
+            thinking_blocks: Vec::new(),
+            stopped: false,
+        }
+    }
+
+    fn tool_index(&self, block: usize) -> Option<usize> {
+        self.tool_blocks
+            .iter()
+            .find(|(index, _)| *index == block)
+            .map(|(_, call)| *call)
+    }
+
+    fn thinking_block(&mut self, block: usize) -> Option<&mut Value> {
+        self.thinking_blocks
+            .iter_mut()
+            .find(|(index, _)| *index == block)
+            .map(|(_, thinking)| thinking)
+    }
+
+    fn usage(&mut self, usage: &AnthropicUsage) -> Result<(), CommandError> {
+        if usage.input_tokens.is_none() && usage.output_tokens.is_none() {
+            return Ok(());
+        }
+        // Cached prompt tokens are reported separately from input_tokens
+        if let Some(input) = usage.input_tokens {
+            self.input_tokens = input
+                + usage.cache_creation_input_tokens.unwrap_or(0)
+                + usage.cache_read_input_tokens.unwrap_or(0);
+        }
+        if let Some(output) = usage.output_tokens {
+            self.output_tokens = output;
+        }
+        self.stream.usage(self.input_tokens, self.output_tokens)
+    }
+
+    fn apply(&mut self, event: AnthropicEvent) -> Result<(), CommandError> {
+        match event {
+            AnthropicEvent::MessageStart { message } => {
+                self.stream.model(message.model);
+                self.usage(&message.usage)?;
+            }
+            AnthropicEvent::ContentBlockStart { index, content_block } => match content_block.kind.as_str() {
+                "text" => self.stream.text(&content_block.text.unwrap_or_default())?,
+                "thinking" => {
+                    let thinking = content_block.thinking.unwrap_or_default();
+                    self.stream.reasoning(&thinking)?;
+                    let signature = content_block.signature.unwrap_or_default();
+                    let block = json!({ "type": "thinking", "thinking": thinking, "signature": signature });
+                    self.thinking_blocks.push((index, block));
+                }
+                "redacted_thinking" => {
+                    let block = json!({ "type": "redacted_thinking", "data": content_block.data.unwrap_or_default() });
+                    self.thinking_blocks.push((index, block));
+                }
+                "tool_use" => {
+                    let call = self.stream.tool_call_start(
+                        &content_block.id.unwrap_or_default(),
+                        &content_block.name.unwrap_or_default(),
+                    )?;
+                    self.tool_blocks.push((index, call));
+                }
+                // Server tool blocks carry nothing to show
+                _ => {}
+            },
+            AnthropicEvent::ContentBlockDelta { index, delta } => match delta.kind.as_str() {
+                "text_delta" => self.stream.text(&delta.text.unwrap_or_default())?,
+                "thinking_delta" => {
+                    let text = delta.thinking.unwrap_or_default();
+                    self.stream.reasoning(&text)?;
+                    if let Some(Value::String(thinking)) =
+                        self.thinking_block(index).and_then(|block| block.get_mut("thinking"))
+                    {
+                        thinking.push_str(&text);
+                    }
+                }
+                "signature_delta" => {
+                    if let Some(block) = self.thinking_block(index) {
+                        block["signature"] = json!(delta.signature.unwrap_or_default());
+                    }
+                }
+                "input_json_delta" => {
+                    if let Some(call) = self.tool_index(index) {
+                        self.stream.tool_call_args(call, &delta.partial_json.unwrap_or_default())?;
+                    }
+                }
+                // citations_delta
+                _ => {}
+            },
+            AnthropicEvent::ContentBlockStop { index } => {
+                if let Some(call) = self.tool_index(index) {
+                    self.stream.tool_call_end(call)?;
+                }
+                if let Some(position) = self.thinking_blocks.iter().position(|(block, _)| *block == index) {
+                    let (_, block) = self.thinking_blocks.remove(position);
+                    self.stream.reasoning_block(block);
+                }
+            }
+            AnthropicEvent::MessageDelta { delta, usage } => {
+                if let Some(reason) = delta.stop_reason {
+                    self.stream.finish_reason(&reason);
+                }
+                self.usage(&usage)?;
+            }
+            AnthropicEvent::MessageStop => self.stopped = true,
+            AnthropicEvent::Ping | AnthropicEvent::Unknown => {}
+            AnthropicEvent::Error { error } => {
+                let message = error
+                    .get("message")
+                    .and_then(Value::as_str)
+                    .unwrap_or("Anthropic stream error")
+                    .to_string();
+                // Overload, rate limit and server errors are worth retrying
+                let retryable = matches!(
+                    error.get("type").and_then(Value::as_str),
+                    Some("overloaded_error" | "api_error" | "rate_limit_error")
+                );
+                return Err(CommandError {
+                    retryable,
+                    ..CommandError::new(ErrorKind::Network, message)
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+// Streams a Messages API response. `base_url` is the API root, e.g.
+// "https://api.anthropic.com"; the key is the one stored for `provider_id`.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn anthropic_chat(
+    base_url: String,
+    provider_id: String,
+    request: ChatRequest,
+    request_id: Option<String>,
+    on_event: Channel<ChatEvent>,
+    requests: State<'_, RequestRegistry>,
+    clients: State<'_, HttpClients>,
+    credentials: State<'_, CredentialStore>,
+    policy: State<'_, EgressPolicy>,
+    recorder: State<'_, Recorder>,
+) -> Result<ChatCompletion, CommandError> {
+    let url = format!("{}/v1/messages", base_url.trim().trim_end_matches('/'));
+    let url = policy.check(&url, EgressPurpose::Provider)?;
+    eprintln!("[Anthropic] Stream request for {} ({} messages)", request.model, request.messages.len());
+
+    // No overall timeout: generation can legitimately take minutes
+    let client = clients.provider_for(&url).await?.client;
+    let mut request = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(reqwest::header::ACCEPT, "text/event-stream")
+        .header("anthropic-version", API_VERSION)
+        .body(request_body(&request)?.to_string())
+        .build()?;
+    credentials.authorize(&provider_id, &mut request)?;
+    let mut recording = recorder.start(ExchangeSource::Provider, &request);
+
+    let result = requests
+        .run(request_id, |_| async {
+            let result = async {
+                let response = client.execute(request).await?;
+                let mut response = ensure_success(response, &mut recording).await?;
+
+                let mut assembler = Assembler::new(ChatStream::new(&on_event));
+                sse::read_events(&mut response, &mut recording, |event| {
+                    let data = event.data.trim();
+                    if data.is_empty() {
+                        return Ok(true);
+                    }
+                    let parsed: AnthropicEvent = serde_json::from_str(data).map_err(|err| {
+                        let message = error_body_message(data)
+                            .unwrap_or_else(|| format!("Invalid stream event: {err}"));
+                        CommandError::new(ErrorKind::Parse, message)
+                    })?;
+                    assembler.apply(parsed)?;
+                    Ok(!assembler.stopped)
+                })
+                .await?;
+
+                if !assembler.stopped {
+                    return Err(CommandError::new(
+                        ErrorKind::Network,
+                        "Anthropic closed the stream before the message was complete",
+                    ));
+                }
+                let completion = assembler.stream.finish()?;
+                eprintln!(
+                    "[Anthropic] Stream finished: {} chars, {} tool calls, stop reason {:?}",
+                    completion.content.len(),
+                    completion.tool_calls.len(),
+                    completion.finish_reason
+                );
+                Ok(completion)
+            }
+            .await;
+            recording.finish_with(&result);
+            result
+        })
+        .await;
+
+    if let Err(err) = &result {
+        let _ = on_event.send(ChatEvent::Error(err.clone()));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::FinishReason;
+
+    fn request(fields: Value) -> ChatRequest {
+        let mut request = json!({ "model": "claude", "messages": [{ "role": "user", "content": "Hi" }] });
+        request.as_object_mut().unwrap().extend(fields.as_object().unwrap().clone());
+        serde_json::from_value(request).unwrap()
+    }
+
+    #[test]
+    fn max_tokens_leaves_room_after_thinking() {
+        let body = request_body(&request(json!({}))).unwrap();
+        assert_eq!(body["max_tokens"], DEFAULT_MAX_TOKENS);
+        assert!(body.get("thinking").is_none());
+
+        let body = request_body(&request(json!({ "thinkingBudget": 8000 }))).unwrap();
+        assert_eq!(body["max_tokens"], 8000 + DEFAULT_MAX_TOKENS);
+        assert_eq!(body["thinking"]["budget_tokens"], 8000);
+
+        let body = request_body(&request(json!({ "thinkingBudget": 8000, "maxTokens": 16000 }))).unwrap();
+        assert_eq!(body["max_tokens"], 16000);
+    }
+
+    #[test]
+    fn max_tokens_within_the_budget_is_refused() {
+        for max_tokens in [1024, 8000] {
+            let err = request_body(&request(json!({ "thinkingBudget": 8000, "maxTokens": max_tokens }))).unwrap_err();
+            assert_eq!(err.kind, ErrorKind::InvalidRequest);
+        }
+    }
+
+    fn assemble(events: &[Value]) -> Result<(ChatCompletion, bool), CommandError> {
+        let channel = Channel::new(|_| Ok(()));
+        let mut assembler = Assembler::new(ChatStream::new(&channel));
+        for event in events {
+            assembler.apply(serde_json::from_value(event.clone()).unwrap())?;
+        }
+        let stopped = assembler.stopped;
+        Ok((assembler.stream.finish()?, stopped))
+    }
+
+    fn block_start(index: usize, block: Value) -> Value {
+        json!({ "type": "content_block_start", "index": index, "content_block": block })
+    }
+
+    fn block_delta(index: usize, delta: Value) -> Value {
+        json!({ "type": "content_block_delta", "index": index, "delta": delta })
+    }
+
+    fn block_stop(index: usize) -> Value {
+        json!({ "type": "content_block_stop", "index": index })
+    }
+
+    fn message_start() -> Value {
+        json!({ "type": "message_start", "message": {
+            "model": "claude",
+            "usage": { "input_tokens": 10, "output_tokens": 1, "cache_read_input_tokens": 5 },
+        } })
+    }
+
+    #[test]
+    fn text_thinking_and_tool_use_are_assembled() {
+        let (completion, stopped) = assemble(&[
+            message_start(),
+            block_start(0, json!({ "type": "thinking", "thinking": "" })),
+            block_delta(0, json!({ "type": "thinking_delta", "thinking": "Let me " })),
+            block_delta(0, json!({ "type": "thinking_delta", "thinking": "look" })),
+            block_delta(0, json!({ "type": "signature_delta", "signature": "sig" })),
+            block_stop(0),
+            json!({ "type": "ping" }),
+            block_start(1, json!({ "type": "text", "text": "" })),
+            block_delta(1, json!({ "type": "text_delta", "text": "Searching" })),
+            block_stop(1),
+            block_start(2, json!({ "type": "tool_use", "id": "toolu_1", "name": "search", "input": {} })),
+            block_delta(2, json!({ "type": "input_json_delta", "partial_json": "{\"q\":" })),
+            block_delta(2, json!({ "type": "input_json_delta", "partial_json": "\"rust\"}" })),
+            block_stop(2),
+            json!({
+                "type": "message_delta",
+                "delta": { "stop_reason": "tool_use" },
+                "usage": { "output_tokens": 20 },
+            }),
+            json!({ "type": "some_future_event" }),
+            json!({ "type": "message_stop" }),
+        ])
+        .unwrap();
+
+        assert!(stopped);
+        assert_eq!(completion.model.as_deref(), Some("claude"));
+        assert_eq!(completion.content, "Searching");
+        assert_eq!(completion.reasoning.as_deref(), Some("Let me look"));
+        assert_eq!(completion.tool_calls.len(), 1);
+        assert_eq!(completion.tool_calls[0].id, "toolu_1");
+        assert_eq!(completion.tool_calls[0].name, "search");
+        assert_eq!(completion.tool_calls[0].arguments, "{\"q\":\"rust\"}");
+        assert_eq!(completion.finish_reason, FinishReason::ToolCalls);
+        let usage = completion.usage.unwrap();
+        assert_eq!((usage.input_tokens, usage.output_tokens), (15, 20));
+        assert_eq!(
+            completion.reasoning_blocks,
+            [json!({ "type": "thinking", "thinking": "Let me look", "signature": "sig" })]
+        );
+    }
+
+    #[test]
+    fn thinking_blocks_go_back_with_the_tool_turn() {
+        let (completion, _) = assemble(&[
+            message_start(),
+            block_start(0, json!({ "type": "redacted_thinking", "data": "opaque" })),
+            block_stop(0),
+            block_start(1, json!({ "type": "thinking", "thinking": "Hmm", "signature": "" })),
+            block_delta(1, json!({ "type": "signature_delta", "signature": "sig" })),
+            block_stop(1),
+            json!({ "type": "message_stop" }),
+        ])
+        .unwrap();
+        assert_eq!(completion.reasoning_blocks.len(), 2);
+
+        let body = request_body(&request(json!({ "messages": [
+            { "role": "user", "content": "Hi" },
+            {
+                "role": "assistant",
+                "content": "Searching",
+                "toolCalls": [{ "id": "toolu_1", "function": { "name": "search", "arguments": "{}" } }],
+                "reasoningBlocks": completion.reasoning_blocks,
+            },
+            { "role": "tool", "toolCallId": "toolu_1", "content": "found" },
+        ] })))
+        .unwrap();
+        let kinds: Vec<&str> = body["messages"][1]["content"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|block| block["type"].as_str().unwrap())
+            .collect();
+        assert_eq!(kinds, ["redacted_thinking", "thinking", "text", "tool_use"]);
+        assert_eq!(body["messages"][1]["content"][0]["data"], "opaque");
+        assert_eq!(body["messages"][1]["content"][1]["signature"], "sig");
+    }
+
+    #[test]
+    fn error_events_fail_the_stream() {
+        let error = |kind: &str| json!({ "type": "error", "error": { "type": kind, "message": "Overloaded" } });
+
+        let err = assemble(&[message_start(), error("overloaded_error")]).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Network);
+        assert_eq!(err.message, "Overloaded");
+        assert!(err.retryable);
+
+        let err = assemble(&[message_start(), error("invalid_request_error")]).unwrap_err();
+        assert!(!err.retryable);
+    }
+
+    #[test]
+    fn a_stream_without_message_stop_is_not_stopped() {
+        let (completion, stopped) = assemble(&[
+            message_start(),
+            block_start(0, json!({ "type": "text", "text": "Hel" })),
+            block_delta(0, json!({ "type": "text_delta", "text": "lo" })),
+        ])
+        .unwrap();
+        assert!(!stopped);
+        assert_eq!(completion.content, "Hello");
+    }
+}
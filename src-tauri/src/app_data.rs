@@ -0,0 +1,77 @@
+// JSON files kept in the app data directory: settings, provider lists, the
+// pull queue and server profiles all go through here
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tauri::Manager;
+
+use crate::error::{CommandError, ErrorKind};
+
+// The data directory is only known once the app is set up
+pub fn path<R: tauri::Runtime>(app: &tauri::AppHandle<R>, file: &str) -> Option<PathBuf> {
+    app.path().app_data_dir().ok().map(|dir| dir.join(file))
+}
+
+// A missing or unreadable file means defaults; an invalid one is logged
+pub fn read<T: DeserializeOwned + Default>(path: Option<&Path>) -> T {
+    let Some(path) = path else {
+        return T::default();
+    };
+    match std::fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str(&text).unwrap_or_else(|err| {
+            eprintln!("[AppData] Ignoring invalid {}: {}", path.display(), err);
+            T::default()
+        }),
+        Err(_) => T::default(),
+    }
+}
+
+pub fn write<T: Serialize + ?Sized>(path: Option<&Path>, value: &T) -> Result<(), CommandError> {
+    let path = path.ok_or_else(|| CommandError::new(ErrorKind::Internal, "App data directory is not available"))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|err| CommandError::new(ErrorKind::Internal, format!("Failed to create {}: {err}", dir.display())))?;
+    }
+    let text =
+        serde_json::to_string_pretty(value).map_err(|err| CommandError::new(ErrorKind::Internal, err.to_string()))?;
+    std::fs::write(path, text)
+        .map_err(|err| CommandError::new(ErrorKind::Internal, format!("Failed to write {}: {err}", path.display())))
+}
+
+pub fn load<R: tauri::Runtime, T: DeserializeOwned + Default>(app: &tauri::AppHandle<R>, file: &str) -> T {
+    read(path(app, file).as_deref())
+}
+
+pub fn save<R: tauri::Runtime, T: Serialize + ?Sized>(
+    app: &tauri::AppHandle<R>,
+    file: &str,
+    value: &T,
+) -> Result<(), CommandError> {
+    write(path(app, file).as_deref(), value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_and_falls_back_to_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("values.json");
+
+        assert_eq!(read::<Vec<u32>>(Some(&path)), Vec::<u32>::new());
+        write(Some(&path), &vec![1, 2, 3]).unwrap();
+        assert_eq!(read::<Vec<u32>>(Some(&path)), vec![1, 2, 3]);
+
+        std::fs::write(&path, "not json").unwrap();
+        assert_eq!(read::<Vec<u32>>(Some(&path)), Vec::<u32>::new());
+        assert_eq!(read::<Vec<u32>>(None), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn write_needs_a_directory() {
+        let err = write(None, &1).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Internal);
+    }
+}
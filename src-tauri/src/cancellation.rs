@@ -0,0 +1,308 @@
+// Registry of in-flight requests that the frontend can cancel by id
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::future::{AbortHandle, Abortable};
+use headless_chrome::Tab;
+use tokio::sync::Notify;
+
+use crate::error::CommandError;
+
+// How long a cancel for a request that hasn't started yet is remembered
+const EARLY_CANCEL_TTL: Duration = Duration::from_secs(60);
+
+struct RequestEntry {
+    abort: AbortHandle,
+    cancelled: AtomicBool,
+    notify: Notify,
+    tabs: Mutex<Vec<Arc<Tab>>>,
+}
+
+impl RequestEntry {
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.abort.abort();
+        self.notify.notify_waiters();
+
+        // Closing the tab makes any blocking navigation in the worker thread
+        // fail right away, which drops the browser and stops Chrome
+        let tabs = std::mem::take(&mut *self.tabs.lock().unwrap());
+        for tab in tabs {
+            if let Err(err) = tab.close(false) {
+                eprintln!("Failed to close tab of cancelled request: {}", err);
+            }
+        }
+    }
+}
+
+// Handle passed to the work of a cancellable request, including blocking
+// work running on another thread
+#[derive(Clone)]
+pub struct RequestToken {
+    entry: Arc<RequestEntry>,
+}
+
+impl RequestToken {
+    pub fn is_cancelled(&self) -> bool {
+        self.entry.cancelled.load(Ordering::SeqCst)
+    }
+
+    // Resolves once the request is cancelled, for async work driven from a
+    // blocking thread that the abort handle doesn't reach
+    pub async fn cancelled(&self) {
+        let notified = self.entry.notify.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+        if !self.is_cancelled() {
+            notified.await;
+        }
+    }
+
+    // Registers a browser tab to be closed if the request gets cancelled
+    pub fn attach_tab(&self, tab: Arc<Tab>) -> Result<(), CommandError> {
+        if self.is_cancelled() {
+            let _ = tab.close(false);
+            return Err(CommandError::cancelled());
+        }
+        self.entry.tabs.lock().unwrap().push(tab);
+        Ok(())
+    }
+}
+
+// Sleeps on a blocking thread in short slices, returning early with a
+// cancelled error if the request is cancelled meanwhile
+pub fn sleep_blocking(duration: Duration, token: Option<&RequestToken>) -> Result<(), CommandError> {
+    let deadline = Instant::now() + duration;
+    loop {
+        if token.is_some_and(|t| t.is_cancelled()) {
+            return Err(CommandError::cancelled());
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(());
+        }
+        std::thread::sleep((deadline - now).min(Duration::from_millis(100)));
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct RequestRegistry {
+    entries: Arc<Mutex<HashMap<String, Arc<RequestEntry>>>>,
+    // Ids cancelled before their request started, e.g. when the frontend
+    // cancels right after invoking
+    early_cancels: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+// Removes the entry once the request finishes, even if its future is dropped
+struct EntryGuard<'a> {
+    registry: &'a RequestRegistry,
+    request_id: String,
+    entry: Arc<RequestEntry>,
+}
+
+impl Drop for EntryGuard<'_> {
+    fn drop(&mut self) {
+        let mut entries = self.registry.entries.lock().unwrap();
+        if let Some(current) = entries.get(&self.request_id) {
+            if Arc::ptr_eq(current, &self.entry) {
+                entries.remove(&self.request_id);
+            }
+        }
+    }
+}
+
+impl RequestRegistry {
+    // Runs `work` so that cancel_request(request_id) aborts it. Without an id
+    // the work simply runs to completion.
+    pub async fn run<T, F, Fut>(&self, request_id: Option<String>, work: F) -> Result<T, CommandError>
+    where
+        F: FnOnce(Option<RequestToken>) -> Fut,
+        Fut: Future<Output = Result<T, CommandError>>,
+    {
+        let Some(request_id) = request_id else {
+            return work(None).await;
+        };
+
+        let (abort, registration) = AbortHandle::new_pair();
+        let entry = Arc::new(RequestEntry {
+            abort,
+            cancelled: AtomicBool::new(false),
+            notify: Notify::new(),
+            tabs: Mutex::new(Vec::new()),
+        });
+
+        if let Some(previous) = self
+            .entries
+            .lock()
+            .unwrap()
+            .insert(request_id.clone(), entry.clone())
+        {
+            eprintln!("Request id {} reused, cancelling the previous request", request_id);
+            previous.cancel();
+        }
+
+        let _guard = EntryGuard {
+            registry: self,
+            request_id: request_id.clone(),
+            entry: entry.clone(),
+        };
+        // `cancel` records early cancels while holding `entries`, so one
+        // that missed the entry above is already here
+        if self.early_cancels.lock().unwrap().remove(&request_id).is_some() {
+            return Err(CommandError::cancelled());
+        }
+
+        let token = RequestToken { entry };
+        match Abortable::new(work(Some(token)), registration).await {
+            Ok(result) => result,
+            Err(_) => Err(CommandError::cancelled()),
+        }
+    }
+
+    // Whether a running request was cancelled. A request that hasn't
+    // started yet is cancelled as soon as it does.
+    pub fn cancel(&self, request_id: &str) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.remove(request_id) {
+            Some(entry) => {
+                drop(entries);
+                eprintln!("Cancelling request {}", request_id);
+                entry.cancel();
+                true
+            }
+            None => {
+                let mut early = self.early_cancels.lock().unwrap();
+                early.retain(|_, at| at.elapsed() < EARLY_CANCEL_TTL);
+                early.insert(request_id.to_string(), Instant::now());
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+    use crate::error::ErrorKind;
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tauri::async_runtime::block_on(future)
+    }
+
+    #[test]
+    fn cancel_aborts_running_work() {
+        let registry = RequestRegistry::default();
+        let (started_tx, started) = tokio::sync::oneshot::channel();
+        let result = block_on(async {
+            let work = registry.run(Some("a".into()), |_| async move {
+                let _ = started_tx.send(());
+                std::future::pending::<Result<(), CommandError>>().await
+            });
+            let cancel = async {
+                started.await.unwrap();
+                assert!(registry.cancel("a"));
+            };
+            futures::join!(work, cancel).0
+        });
+        assert_eq!(result.unwrap_err().kind, ErrorKind::Cancelled);
+        assert!(registry.entries.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn tokens_see_the_cancel() {
+        let registry = RequestRegistry::default();
+        let (token_tx, token_rx) = std::sync::mpsc::channel();
+        let work = registry.run(Some("a".into()), |token| {
+            let _ = token_tx.send(token.unwrap());
+            std::future::pending::<Result<(), CommandError>>()
+        });
+        let watch = async {
+            let token = token_rx.recv().unwrap();
+            assert!(!token.is_cancelled());
+            // Blocking work polls the token; async work waits on it
+            let blocking = {
+                let token = token.clone();
+                std::thread::spawn(move || sleep_blocking(Duration::from_secs(30), Some(&token)))
+            };
+            let canceller = {
+                let registry = registry.clone();
+                std::thread::spawn(move || {
+                    std::thread::sleep(Duration::from_millis(50));
+                    registry.cancel("a")
+                })
+            };
+            token.cancelled().await;
+            assert!(canceller.join().unwrap());
+            assert_eq!(blocking.join().unwrap().unwrap_err().kind, ErrorKind::Cancelled);
+        };
+        let (result, ()) = block_on(async { futures::join!(work, watch) });
+        assert_eq!(result.unwrap_err().kind, ErrorKind::Cancelled);
+    }
+
+    #[test]
+    fn cancel_before_the_request_starts() {
+        let registry = RequestRegistry::default();
+        assert!(!registry.cancel("early"));
+
+        let runs = AtomicUsize::new(0);
+        let result = block_on(registry.run(Some("early".into()), |_| async {
+            runs.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }));
+        assert_eq!(result.unwrap_err().kind, ErrorKind::Cancelled);
+        assert_eq!(runs.load(Ordering::SeqCst), 0);
+
+        // The early cancel is used up by the request it was meant for
+        let result = block_on(registry.run(Some("early".into()), |_| async { Ok(1) }));
+        assert_eq!(result.unwrap(), 1);
+    }
+
+    #[test]
+    fn old_early_cancels_are_forgotten() {
+        let registry = RequestRegistry::default();
+        registry.cancel("stale");
+        let long_ago = Instant::now().checked_sub(EARLY_CANCEL_TTL + Duration::from_secs(1));
+        if let Some(long_ago) = long_ago {
+            registry.early_cancels.lock().unwrap().insert("stale".into(), long_ago);
+            registry.cancel("fresh");
+            let early = registry.early_cancels.lock().unwrap();
+            assert!(!early.contains_key("stale") && early.contains_key("fresh"));
+        }
+    }
+
+    #[test]
+    fn reused_ids_cancel_the_previous_request() {
+        let registry = RequestRegistry::default();
+        let (started_tx, started) = tokio::sync::oneshot::channel();
+        let (first, second) = block_on(async {
+            let first = registry.run(Some("a".into()), |_| async move {
+                let _ = started_tx.send(());
+                std::future::pending::<Result<u32, CommandError>>().await
+            });
+            let second = async {
+                started.await.unwrap();
+                registry.run(Some("a".into()), |_| async { Ok(2) }).await
+            };
+            futures::join!(first, second)
+        });
+        assert_eq!(first.unwrap_err().kind, ErrorKind::Cancelled);
+        assert_eq!(second.unwrap(), 2);
+        assert!(registry.entries.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn requests_without_an_id_just_run() {
+        let registry = RequestRegistry::default();
+        let result = block_on(registry.run(None, |token| async move {
+            assert!(token.is_none());
+            Ok("done")
+        }));
+        assert_eq!(result.unwrap(), "done");
+        assert!(!registry.cancel("anything-else") && registry.entries.lock().unwrap().is_empty());
+    }
+}
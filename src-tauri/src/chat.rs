@@ -0,0 +1,506 @@
+// Provider-neutral chat request and stream events. Every backend provider
+// client takes a ChatRequest and reports progress as ChatEvents, so the
+// frontend handles a single schema whatever the provider.
+use serde_json::{Map, Value};
+use tauri::ipc::Channel;
+
+use crate::error::CommandError;
+use crate::tool_emulation::{Segment, ToolCallParser, ToolDialect};
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatImage {
+    // Base64 encoded, without a data: prefix
+    pub data: String,
+    pub mime_type: String,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct ChatFunctionCall {
+    pub name: String,
+    // JSON text
+    #[serde(default)]
+    pub arguments: String,
+}
+
+// Same shape as the frontend's ToolCall (src/types/tools.ts)
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct ChatToolCall {
+    pub id: String,
+    pub function: ChatFunctionCall,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatMessage {
+    // "system", "user", "assistant" or "tool"
+    pub role: String,
+    #[serde(default)]
+    pub content: String,
+    #[serde(default)]
+    pub images: Vec<ChatImage>,
+    #[serde(default)]
+    pub tool_calls: Vec<ChatToolCall>,
+    // For "tool" messages, the call they answer
+    #[serde(default)]
+    pub tool_call_id: Option<String>,
+    // ChatCompletion::reasoning_blocks of an assistant message, sent back as is
+    #[serde(default)]
+    pub reasoning_blocks: Vec<Value>,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatRequest {
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    // ToolDefinitions in the OpenAI shape ({"type": "function", "function": {...}})
+    #[serde(default)]
+    pub tools: Vec<Value>,
+    // JSON Schema the reply must follow, for providers that can enforce one
+    #[serde(default)]
+    pub response_schema: Option<Value>,
+    // Describe the tools in the prompt and parse calls out of the reply in
+    // this dialect, for models without native tool calling
+    #[serde(default)]
+    pub tool_dialect: Option<ToolDialect>,
+    #[serde(default)]
+    pub max_tokens: Option<u32>,
+    #[serde(default)]
+    pub temperature: Option<f64>,
+    #[serde(default)]
+    pub top_p: Option<f64>,
+    #[serde(default)]
+    pub top_k: Option<u32>,
+    #[serde(default)]
+    pub frequency_penalty: Option<f64>,
+    #[serde(default)]
+    pub presence_penalty: Option<f64>,
+    #[serde(default)]
+    pub seed: Option<i64>,
+    #[serde(default)]
+    pub stop: Vec<String>,
+    // Turns on reasoning for models that support it; Anthropic uses the
+    // value as the thinking budget in tokens
+    #[serde(default)]
+    pub thinking_budget: Option<u32>,
+    // Provider-specific fields merged into the request body as-is
+    #[serde(default)]
+    pub extra: Map<String, Value>,
+}
+
+impl ChatRequest {
+    // Tool definition parts: name, description and JSON schema parameters
+    pub fn tool_parts(tool: &Value) -> (Value, Value, Value) {
+        let function = tool.get("function").unwrap_or(tool);
+        (
+            function.get("name").cloned().unwrap_or(Value::Null),
+            function.get("description").cloned().unwrap_or_else(|| Value::String(String::new())),
+            function
+                .get("parameters")
+                .cloned()
+                .unwrap_or_else(|| serde_json::json!({ "type": "object", "properties": {} })),
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason {
+    Stop,
+    Length,
+    ToolCalls,
+    ContentFilter,
+    Other,
+}
+
+impl FinishReason {
+    // Maps the reasons reported by OpenAI-style APIs, Anthropic and Ollama
+    pub fn from_provider(reason: &str) -> Self {
+        match reason {
+            "stop" | "end_turn" | "stop_sequence" => Self::Stop,
+            "length" | "max_tokens" | "model_context_window_exceeded" => Self::Length,
+            "tool_calls" | "function_call" | "tool_use" => Self::ToolCalls,
+            "content_filter" | "refusal" => Self::ContentFilter,
+            _ => Self::Other,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub total_tokens: u64,
+}
+
+#[derive(Clone, Debug, Default, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatToolCallResult {
+    pub id: String,
+    pub name: String,
+    // JSON text, exactly as the model produced it
+    pub arguments: String,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatCompletion {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    pub content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<String>,
+    // Provider reasoning blocks that must go back unchanged with the
+    // assistant message when tool results follow (Anthropic's signed thinking)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub reasoning_blocks: Vec<Value>,
+    pub tool_calls: Vec<ChatToolCallResult>,
+    pub finish_reason: FinishReason,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<ChatUsage>,
+}
+
+// Messages sent to the frontend while a chat response is streamed. Tool
+// call indexes count the calls of one response from zero.
+#[derive(Clone, serde::Serialize)]
+#[serde(tag = "event", content = "data", rename_all = "camelCase")]
+pub enum ChatEvent {
+    TextDelta { text: String },
+    ReasoningDelta { text: String },
+    ToolCallStart { index: usize, id: String, name: String },
+    ToolCallArgsDelta { index: usize, arguments: String },
+    ToolCallEnd { index: usize, id: String, name: String, arguments: String },
+    Usage(ChatUsage),
+    Finish { reason: FinishReason },
+    Error(CommandError),
+}
+
+struct PendingToolCall {
+    call: ChatToolCallResult,
+    ended: bool,
+}
+
+// Turns provider deltas into ChatEvents and assembles the final completion.
+// Provider clients only translate their wire format into these calls.
+pub struct ChatStream<'a> {
+    channel: &'a Channel<ChatEvent>,
+    model: Option<String>,
+    content: String,
+    reasoning: Option<String>,
+    reasoning_blocks: Vec<Value>,
+    tool_calls: Vec<PendingToolCall>,
+    usage: Option<ChatUsage>,
+    finish_reason: Option<FinishReason>,
+    // Set when tool calls are emulated in the text (tool_emulation.rs)
+    tool_parser: Option<ToolCallParser>,
+}
+
+impl<'a> ChatStream<'a> {
+    pub fn new(channel: &'a Channel<ChatEvent>) -> Self {
+        Self {
+            channel,
+            model: None,
+            content: String::new(),
+            reasoning: None,
+            reasoning_blocks: Vec::new(),
+            tool_calls: Vec::new(),
+            usage: None,
+            finish_reason: None,
+            tool_parser: None,
+        }
+    }
+
+    // Parses tool calls in `dialect` out of the text from here on
+    pub fn emulate_tools(&mut self, dialect: ToolDialect, tools: &[Value]) {
+        self.tool_parser = Some(ToolCallParser::new(dialect, tools));
+    }
+
+    fn send(&self, event: ChatEvent) -> Result<(), CommandError> {
+        self.channel.send(event)?;
+        Ok(())
+    }
+
+    pub fn model(&mut self, model: Option<String>) {
+        if self.model.is_none() {
+            self.model = model;
+        }
+    }
+
+    pub fn text(&mut self, text: &str) -> Result<(), CommandError> {
+        match self.tool_parser.as_mut() {
+            Some(parser) => {
+                let segments = parser.push(text);
+                self.segments(segments)
+            }
+            None => self.plain_text(text),
+        }
+    }
+
+    fn plain_text(&mut self, text: &str) -> Result<(), CommandError> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        self.content.push_str(text);
+        self.send(ChatEvent::TextDelta { text: text.to_string() })
+    }
+
+    fn segments(&mut self, segments: Vec<Segment>) -> Result<(), CommandError> {
+        for segment in segments {
+            match segment {
+                Segment::Text(text) => self.plain_text(&text)?,
+                Segment::Call { id, name, arguments } => self.tool_call(&id, &name, &arguments)?,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn reasoning(&mut self, text: &str) -> Result<(), CommandError> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        self.reasoning.get_or_insert_with(String::new).push_str(text);
+        self.send(ChatEvent::ReasoningDelta { text: text.to_string() })
+    }
+
+    // A complete reasoning block in the provider's format, kept for the next turn
+    pub fn reasoning_block(&mut self, block: Value) {
+        self.reasoning_blocks.push(block);
+    }
+
+    pub fn tool_call_count(&self) -> usize {
+        self.tool_calls.len()
+    }
+
+    // Starts a new tool call and returns its index
+    pub fn tool_call_start(&mut self, id: &str, name: &str) -> Result<usize, CommandError> {
+        let index = self.tool_calls.len();
+        self.tool_calls.push(PendingToolCall {
+            call: ChatToolCallResult {
+                id: id.to_string(),
+                name: name.to_string(),
+                arguments: String::new(),
+            },
+            ended: false,
+        });
+        self.send(ChatEvent::ToolCallStart {
+            index,
+            id: id.to_string(),
+            name: name.to_string(),
+        })?;
+        Ok(index)
+    }
+
+    // Some servers send the name after the delta that started the call
+    pub fn tool_call_name(&mut self, index: usize, name: &str) {
+        if let Some(pending) = self.tool_calls.get_mut(index) {
+            if pending.call.name.is_empty() && !pending.ended {
+                pending.call.name = name.to_string();
+            }
+        }
+    }
+
+    pub fn tool_call_args(&mut self, index: usize, arguments: &str) -> Result<(), CommandError> {
+        let Some(pending) = self.tool_calls.get_mut(index) else {
+            return Ok(());
+        };
+        if arguments.is_empty() || pending.ended {
+            return Ok(());
+        }
+        pending.call.arguments.push_str(arguments);
+        self.send(ChatEvent::ToolCallArgsDelta { index, arguments: arguments.to_string() })
+    }
+
+    pub fn tool_call_end(&mut self, index: usize) -> Result<(), CommandError> {
+        let Some(pending) = self.tool_calls.get_mut(index) else {
+            return Ok(());
+        };
+        if pending.ended {
+            return Ok(());
+        }
+        pending.ended = true;
+        // A tool called without arguments may stream no JSON at all
+        if pending.call.arguments.trim().is_empty() {
+            pending.call.arguments = "{}".to_string();
+        }
+        let call = pending.call.clone();
+        self.send(ChatEvent::ToolCallEnd {
+            index,
+            id: call.id,
+            name: call.name,
+            arguments: call.arguments,
+        })
+    }
+
+    // A tool call that arrives in one piece
+    pub fn tool_call(&mut self, id: &str, name: &str, arguments: &str) -> Result<(), CommandError> {
+        let index = self.tool_call_start(id, name)?;
+        self.tool_call_args(index, arguments)?;
+        self.tool_call_end(index)
+    }
+
+    pub fn usage(&mut self, input_tokens: u64, output_tokens: u64) -> Result<(), CommandError> {
+        let usage = ChatUsage {
+            input_tokens,
+            output_tokens,
+            total_tokens: input_tokens + output_tokens,
+        };
+        self.usage = Some(usage.clone());
+        self.send(ChatEvent::Usage(usage))
+    }
+
+    pub fn finish_reason(&mut self, reason: &str) {
+        self.finish_reason = Some(FinishReason::from_provider(reason));
+    }
+
+    // Closes any open tool calls, sends Finish and returns the completion
+    pub fn finish(mut self) -> Result<ChatCompletion, CommandError> {
+        if let Some(mut parser) = self.tool_parser.take() {
+            self.segments(parser.finish())?;
+        }
+        for index in 0..self.tool_calls.len() {
+            self.tool_call_end(index)?;
+        }
+
+        // Some servers report "stop" even when the response is tool calls
+        let reason = match self.finish_reason {
+            Some(FinishReason::Stop) | None if !self.tool_calls.is_empty() => FinishReason::ToolCalls,
+            Some(reason) => reason,
+            None => FinishReason::Stop,
+        };
+        self.send(ChatEvent::Finish { reason })?;
+
+        Ok(ChatCompletion {
+            model: self.model,
+            content: self.content,
+            reasoning: self.reasoning,
+            reasoning_blocks: self.reasoning_blocks,
+            tool_calls: self.tool_calls.into_iter().map(|pending| pending.call).collect(),
+            finish_reason: reason,
+            usage: self.usage,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::json;
+    use tauri::ipc::InvokeResponseBody;
+
+    use super::*;
+    use crate::error::ErrorKind;
+
+    // A channel that keeps every event it was sent as JSON
+    fn channel() -> (Channel<ChatEvent>, Arc<Mutex<Vec<Value>>>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sent = events.clone();
+        let channel = Channel::new(move |body| {
+            if let InvokeResponseBody::Json(text) = body {
+                sent.lock().unwrap().push(serde_json::from_str(&text).unwrap());
+            }
+            Ok(())
+        });
+        (channel, events)
+    }
+
+    fn event_names(events: &Mutex<Vec<Value>>) -> Vec<String> {
+        let events = events.lock().unwrap();
+        events.iter().map(|event| event["event"].as_str().unwrap().to_string()).collect()
+    }
+
+    #[test]
+    fn events_serialize_as_tagged_camel_case() {
+        let cases = [
+            (ChatEvent::TextDelta { text: "Hi".into() }, json!({ "event": "textDelta", "data": { "text": "Hi" } })),
+            (
+                ChatEvent::ToolCallStart { index: 0, id: "a".into(), name: "search".into() },
+                json!({ "event": "toolCallStart", "data": { "index": 0, "id": "a", "name": "search" } }),
+            ),
+            (
+                ChatEvent::Usage(ChatUsage { input_tokens: 1, output_tokens: 2, total_tokens: 3 }),
+                json!({ "event": "usage", "data": { "inputTokens": 1, "outputTokens": 2, "totalTokens": 3 } }),
+            ),
+            (
+                ChatEvent::Finish { reason: FinishReason::ToolCalls },
+                json!({ "event": "finish", "data": { "reason": "tool_calls" } }),
+            ),
+            (
+                ChatEvent::Error(CommandError::new(ErrorKind::Timeout, "Too slow")),
+                json!({ "event": "error", "data": { "kind": "timeout", "message": "Too slow", "retryable": true } }),
+            ),
+        ];
+        for (event, expected) in cases {
+            assert_eq!(serde_json::to_value(event).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn events_arrive_in_order_and_open_calls_end_at_finish() {
+        let (channel, events) = channel();
+        let mut stream = ChatStream::new(&channel);
+        stream.model(Some("m".into()));
+        stream.model(Some("other".into()));
+        stream.reasoning("Hmm").unwrap();
+        stream.text("Hi").unwrap();
+        stream.text("").unwrap();
+        let first = stream.tool_call_start("a", "search").unwrap();
+        stream.tool_call_args(first, "{\"q\":1}").unwrap();
+        let second = stream.tool_call_start("b", "").unwrap();
+        stream.tool_call_name(second, "now");
+        stream.tool_call_end(first).unwrap();
+        stream.tool_call_args(first, "ignored").unwrap();
+        stream.usage(3, 4).unwrap();
+        let completion = stream.finish().unwrap();
+
+        assert_eq!(
+            event_names(&events),
+            [
+                "reasoningDelta",
+                "textDelta",
+                "toolCallStart",
+                "toolCallArgsDelta",
+                "toolCallStart",
+                "toolCallEnd",
+                "usage",
+                "toolCallEnd",
+                "finish",
+            ]
+        );
+        let events = events.lock().unwrap();
+        assert_eq!(events[5]["data"]["arguments"], "{\"q\":1}");
+        assert_eq!(events[7]["data"], json!({ "index": 1, "id": "b", "name": "now", "arguments": "{}" }));
+
+        let completion = serde_json::to_value(completion).unwrap();
+        assert_eq!(completion["model"], "m");
+        assert_eq!(completion["content"], "Hi");
+        assert_eq!(completion["toolCalls"][0]["arguments"], "{\"q\":1}");
+        assert_eq!(completion["finishReason"], "tool_calls");
+        assert_eq!(completion["usage"]["totalTokens"], 7);
+        assert!(completion.get("reasoningBlocks").is_none());
+    }
+
+    #[test]
+    fn tool_calls_override_a_stop_or_missing_finish_reason() {
+        let finish = |reason: Option<&str>, tool_call: bool| {
+            let (channel, _) = channel();
+            let mut stream = ChatStream::new(&channel);
+            if tool_call {
+                stream.tool_call("a", "search", "{}").unwrap();
+            }
+            if let Some(reason) = reason {
+                stream.finish_reason(reason);
+            }
+            stream.finish().unwrap().finish_reason
+        };
+        assert_eq!(finish(Some("stop"), true), FinishReason::ToolCalls);
+        assert_eq!(finish(None, true), FinishReason::ToolCalls);
+        assert_eq!(finish(Some("length"), true), FinishReason::Length);
+        assert_eq!(finish(Some("stop"), false), FinishReason::Stop);
+        assert_eq!(finish(None, false), FinishReason::Stop);
+        assert_eq!(finish(Some("end_turn"), false), FinishReason::Stop);
+        assert_eq!(finish(Some("whatever"), false), FinishReason::Other);
+    }
+}
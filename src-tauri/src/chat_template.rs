@@ -0,0 +1,604 @@
+// Renders chat messages into a raw prompt with a Jinja chat template, for
+// endpoints that take plain text (llama.cpp's /completion, koboldcpp's
+// generate, the built-in local engine). The template is a user override,
+// the one stored in the GGUF, or one of the built-in formats below, picked
+// from the model's control tokens when the GGUF has none. Templates are
+// rendered the way Hugging Face's apply_chat_template does: trim_blocks,
+// lstrip_blocks, Python string methods, raise_exception and strftime_now.
+use std::fmt::Write;
+use std::path::Path;
+
+use minijinja::value::{Kwargs, ValueKind};
+use minijinja::{Environment, Value as JinjaValue};
+use serde_json::{json, Value};
+
+use crate::chat::ChatMessage;
+use crate::error::{CommandError, ErrorKind};
+use crate::gguf::{self, ChatTokens};
+
+struct Builtin {
+    name: &'static str,
+    template: &'static str,
+    bos_token: &'static str,
+    eos_token: &'static str,
+    // Ends of turn, besides the EOS token
+    stop: &'static [&'static str],
+}
+
+const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "chatml",
+        template: "{% for message in messages %}{{ '<|im_start|>' + message['role'] + '\\n' + message['content'] + '<|im_end|>' + '\\n' }}{% endfor %}{% if add_generation_prompt %}{{ '<|im_start|>assistant\\n' }}{% endif %}",
+        bos_token: "",
+        eos_token: "<|im_end|>",
+        stop: &["<|im_end|>", "<|endoftext|>"],
+    },
+    Builtin {
+        name: "llama3",
+        template: "{% for message in messages %}{% set content = '<|start_header_id|>' + message['role'] + '<|end_header_id|>\\n\\n' + message['content'] | trim + '<|eot_id|>' %}{% if loop.index0 == 0 %}{% set content = bos_token + content %}{% endif %}{{ content }}{% endfor %}{% if add_generation_prompt %}{{ '<|start_header_id|>assistant<|end_header_id|>\\n\\n' }}{% endif %}",
+        bos_token: "<|begin_of_text|>",
+        eos_token: "<|eot_id|>",
+        stop: &["<|eot_id|>", "<|eom_id|>", "<|end_of_text|>"],
+    },
+    // The system prompt goes in front of the first user message
+    Builtin {
+        name: "mistral",
+        template: "{% if messages[0]['role'] == 'system' %}{% set system_message = messages[0]['content'] %}{% set loop_messages = messages[1:] %}{% else %}{% set loop_messages = messages %}{% endif %}{{ bos_token }}{% for message in loop_messages %}{% if message['role'] == 'assistant' %}{{ ' ' + message['content'] | trim + eos_token }}{% elif loop.first and system_message is defined %}{{ '[INST] ' + system_message + '\\n\\n' + message['content'] | trim + ' [/INST]' }}{% else %}{{ '[INST] ' + message['content'] | trim + ' [/INST]' }}{% endif %}{% endfor %}",
+        bos_token: "<s>",
+        eos_token: "</s>",
+        stop: &["</s>", "[INST]"],
+    },
+    // Gemma has no system role; the system prompt opens the first turn
+    Builtin {
+        name: "gemma",
+        template: "{{ bos_token }}{% if messages[0]['role'] == 'system' %}{% set first_user_prefix = messages[0]['content'] + '\\n\\n' %}{% set loop_messages = messages[1:] %}{% else %}{% set first_user_prefix = '' %}{% set loop_messages = messages %}{% endif %}{% for message in loop_messages %}{% if message['role'] == 'assistant' %}{% set role = 'model' %}{% else %}{% set role = 'user' %}{% endif %}{{ '<start_of_turn>' + role + '\\n' + (first_user_prefix if loop.first else '') + message['content'] | trim + '<end_of_turn>\\n' }}{% endfor %}{% if add_generation_prompt %}{{ '<start_of_turn>model\\n' }}{% endif %}",
+        bos_token: "<bos>",
+        eos_token: "<eos>",
+        stop: &["<end_of_turn>"],
+    },
+    Builtin {
+        name: "phi3",
+        template: "{% for message in messages %}{% if message['role'] in ['system', 'assistant'] %}{% set role = message['role'] %}{% else %}{% set role = 'user' %}{% endif %}{{ '<|' + role + '|>\\n' + message['content'] + '<|end|>\\n' }}{% endfor %}{% if add_generation_prompt %}{{ '<|assistant|>\\n' }}{% else %}{{ eos_token }}{% endif %}",
+        bos_token: "<s>",
+        eos_token: "<|endoftext|>",
+        stop: &["<|end|>", "<|endoftext|>"],
+    },
+];
+
+// Ends of turn looked for in templates that aren't built in
+const TURN_MARKERS: &[&str] = &[
+    "<|im_end|>",
+    "<|eot_id|>",
+    "<|eom_id|>",
+    "<end_of_turn>",
+    "<|end|>",
+    "<|endoftext|>",
+];
+
+fn template_error(err: minijinja::Error) -> CommandError {
+    let mut message = format!("Chat template error: {}", err);
+    if let Some(detail) = err.detail() {
+        if !message.contains(detail) {
+            let _ = write!(message, " ({})", detail);
+        }
+    }
+    CommandError::new(ErrorKind::InvalidRequest, message)
+}
+
+// Python's json.dumps(value, ensure_ascii=False, indent=indent), which is
+// what Hugging Face templates get from `tojson`
+fn write_json(out: &mut String, value: &JinjaValue, indent: Option<usize>, level: usize) -> Result<(), minijinja::Error> {
+    let newline = |out: &mut String, level: usize| {
+        if let Some(indent) = indent {
+            out.push('\n');
+            out.push_str(&" ".repeat(indent * level));
+        }
+    };
+    let separator = if indent.is_some() { "," } else { ", " };
+
+    match value.kind() {
+        ValueKind::Undefined | ValueKind::None => out.push_str("null"),
+        ValueKind::Bool => out.push_str(if value.is_true() { "true" } else { "false" }),
+        ValueKind::Number => match value.as_i64() {
+            Some(int) if value.is_integer() => {
+                let _ = write!(out, "{}", int);
+            }
+            _ => {
+                let float = f64::try_from(value.clone()).unwrap_or(f64::NAN);
+                let _ = write!(out, "{:?}", float);
+            }
+        },
+        ValueKind::Seq | ValueKind::Iterable => {
+            let items: Vec<JinjaValue> = value.try_iter()?.collect();
+            if items.is_empty() {
+                out.push_str("[]");
+                return Ok(());
+            }
+            out.push('[');
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    out.push_str(separator);
+                }
+                newline(out, level + 1);
+                write_json(out, item, indent, level + 1)?;
+            }
+            newline(out, level);
+            out.push(']');
+        }
+        ValueKind::Map => {
+            let keys: Vec<JinjaValue> = value.try_iter()?.collect();
+            if keys.is_empty() {
+                out.push_str("{}");
+                return Ok(());
+            }
+            out.push('{');
+            for (index, key) in keys.iter().enumerate() {
+                if index > 0 {
+                    out.push_str(separator);
+                }
+                newline(out, level + 1);
+                out.push_str(&serde_json::to_string(&key.to_string()).unwrap_or_default());
+                out.push_str(": ");
+                write_json(out, &value.get_item(key)?, indent, level + 1)?;
+            }
+            newline(out, level);
+            out.push('}');
+        }
+        _ => out.push_str(&serde_json::to_string(&value.to_string()).unwrap_or_default()),
+    }
+    Ok(())
+}
+
+fn environment() -> Environment<'static> {
+    let mut env = Environment::new();
+    env.set_trim_blocks(true);
+    env.set_lstrip_blocks(true);
+    env.set_unknown_method_callback(minijinja_contrib::pycompat::unknown_method_callback);
+    env.add_filter("tojson", |value: JinjaValue, kwargs: Kwargs| -> Result<JinjaValue, minijinja::Error> {
+        let indent: Option<usize> = kwargs.get("indent")?;
+        // Accepted for compatibility; keys keep their order, output isn't escaped
+        let _: Option<JinjaValue> = kwargs.get("ensure_ascii")?;
+        let _: Option<JinjaValue> = kwargs.get("separators")?;
+        let _: Option<JinjaValue> = kwargs.get("sort_keys")?;
+        kwargs.assert_all_used()?;
+        let mut out = String::new();
+        write_json(&mut out, &value, indent, 0)?;
+        Ok(JinjaValue::from_safe_string(out))
+    });
+    env.add_function("raise_exception", |message: String| -> Result<JinjaValue, minijinja::Error> {
+        Err(minijinja::Error::new(minijinja::ErrorKind::InvalidOperation, message))
+    });
+    env.add_function("strftime_now", |format: String| {
+        let mut out = String::new();
+        let _ = write!(out, "{}", chrono::Local::now().format(&format));
+        out
+    });
+    env
+}
+
+// Messages in the shape Hugging Face templates expect, with tool call
+// arguments as objects
+fn template_messages(messages: &[ChatMessage]) -> Vec<Value> {
+    messages
+        .iter()
+        .map(|message| {
+            let mut value = json!({ "role": message.role, "content": message.content });
+            if !message.tool_calls.is_empty() {
+                value["tool_calls"] = message
+                    .tool_calls
+                    .iter()
+                    .map(|call| {
+                        let arguments = serde_json::from_str(&call.function.arguments)
+                            .unwrap_or_else(|_| Value::String(call.function.arguments.clone()));
+                        json!({
+                            "id": call.id,
+                            "type": "function",
+                            "function": { "name": call.function.name, "arguments": arguments },
+                        })
+                    })
+                    .collect();
+            }
+            if let Some(id) = &message.tool_call_id {
+                value["tool_call_id"] = json!(id);
+            }
+            value
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug)]
+pub struct ChatTemplate {
+    pub source: String,
+    // "override", "model" (from the GGUF) or "builtin"
+    pub origin: &'static str,
+    // Set for built-in templates
+    pub name: Option<&'static str>,
+    pub bos_token: String,
+    pub eos_token: String,
+}
+
+impl ChatTemplate {
+    fn builtin(builtin: &Builtin, origin: &'static str) -> Self {
+        Self {
+            source: builtin.template.to_string(),
+            origin,
+            name: Some(builtin.name),
+            bos_token: builtin.bos_token.to_string(),
+            eos_token: builtin.eos_token.to_string(),
+        }
+    }
+
+    // `template` overrides the model's: a built-in name or Jinja source.
+    // Without either, the format is guessed from the model's control tokens.
+    pub fn resolve(template: Option<&str>, tokens: Option<&ChatTokens>) -> Result<Self, CommandError> {
+        let override_source = template.map(str::trim).filter(|t| !t.is_empty());
+        let mut resolved = match override_source {
+            Some(name) if !name.contains("{") => match BUILTINS.iter().find(|b| b.name == name) {
+                Some(builtin) => Self::builtin(builtin, "override"),
+                None => {
+                    return Err(CommandError::new(
+                        ErrorKind::InvalidRequest,
+                        format!("Unknown chat template {:?}", name),
+                    ))
+                }
+            },
+            Some(source) => Self {
+                source: source.to_string(),
+                origin: "override",
+                name: None,
+                bos_token: String::new(),
+                eos_token: String::new(),
+            },
+            None => match tokens.and_then(|t| t.chat_template.as_deref()).filter(|t| !t.trim().is_empty()) {
+                Some(source) => Self {
+                    source: source.to_string(),
+                    origin: "model",
+                    name: None,
+                    bos_token: String::new(),
+                    eos_token: String::new(),
+                },
+                None => {
+                    let Some(tokens) = tokens else {
+                        return Err(CommandError::new(
+                            ErrorKind::InvalidRequest,
+                            "No chat template: pass a template or the model file",
+                        ));
+                    };
+                    Self::builtin(detect(&tokens.control_tokens), "builtin")
+                }
+            },
+        };
+
+        // The model's own BOS and EOS text wins over the defaults
+        if let Some(tokens) = tokens {
+            if let Some(bos) = &tokens.bos_token {
+                resolved.bos_token = bos.clone();
+            }
+            if let Some(eos) = &tokens.eos_token {
+                resolved.eos_token = eos.clone();
+            }
+        }
+        Ok(resolved)
+    }
+
+    // Strings that end the assistant's turn
+    pub fn stop_strings(&self) -> Vec<String> {
+        let markers = match self.name.and_then(|name| BUILTINS.iter().find(|b| b.name == name)) {
+            Some(builtin) => builtin.stop,
+            None => TURN_MARKERS,
+        };
+        let mut stop: Vec<String> = markers
+            .iter()
+            .filter(|marker| self.name.is_some() || self.source.contains(*marker))
+            .map(|marker| marker.to_string())
+            .collect();
+        if !self.eos_token.is_empty() && !stop.contains(&self.eos_token) {
+            stop.push(self.eos_token.clone());
+        }
+        stop
+    }
+
+    fn render_messages(
+        &self,
+        messages: &[ChatMessage],
+        tools: &[Value],
+        add_generation_prompt: bool,
+    ) -> Result<String, CommandError> {
+        let env = environment();
+        let template = env.template_from_str(&self.source).map_err(template_error)?;
+        template
+            .render(json!({
+                "messages": template_messages(messages),
+                "tools": if tools.is_empty() { Value::Null } else { json!(tools) },
+                "bos_token": self.bos_token,
+                "eos_token": self.eos_token,
+                "add_generation_prompt": add_generation_prompt,
+            }))
+            .map_err(template_error)
+    }
+
+    // The prompt for the assistant's reply. When the last message is the
+    // assistant's, the reply continues it instead of starting a new turn.
+    // The result starts with the BOS text if the template writes it.
+    pub fn render(&self, messages: &[ChatMessage], tools: &[Value], add_generation_prompt: bool) -> Result<String, CommandError> {
+        let Some((last, rest)) = messages.split_last().filter(|(last, _)| last.role == "assistant") else {
+            return self.render_messages(messages, tools, add_generation_prompt);
+        };
+        let content = last.content.trim();
+        if content.is_empty() {
+            return self.render_messages(rest, tools, add_generation_prompt);
+        }
+
+        // Cut the closing of the last turn so the model picks up from there.
+        // When the template changed the text so it can't be found, the
+        // prompt opens a new turn with it instead.
+        let mut prompt = self.render_messages(messages, tools, false)?;
+        match prompt.rfind(content) {
+            Some(end) => prompt.truncate(end + content.len()),
+            None => {
+                prompt = self.render_messages(rest, tools, true)?;
+                prompt.push_str(content);
+            }
+        }
+        Ok(prompt)
+    }
+
+    // Removes the BOS text the template put in front, for tokenizers that
+    // add the BOS token themselves
+    pub fn strip_bos<'a>(&self, prompt: &'a str) -> &'a str {
+        if self.bos_token.is_empty() {
+            return prompt;
+        }
+        prompt.strip_prefix(&self.bos_token).unwrap_or(prompt)
+    }
+}
+
+// Built-in format matching the model's control tokens
+fn detect(control_tokens: &[String]) -> &'static Builtin {
+    let has = |token: &str| control_tokens.iter().any(|t| t == token);
+    let name = if has("<|im_start|>") {
+        "chatml"
+    } else if has("<|start_header_id|>") {
+        "llama3"
+    } else if has("<start_of_turn>") {
+        "gemma"
+    } else if has("<|user|>") && has("<|end|>") {
+        "phi3"
+    } else {
+        "mistral"
+    };
+    BUILTINS.iter().find(|b| b.name == name).unwrap_or(&BUILTINS[0])
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatTemplateRequest {
+    pub messages: Vec<ChatMessage>,
+    #[serde(default)]
+    pub tools: Vec<Value>,
+    // GGUF file to take the template and BOS/EOS tokens from
+    #[serde(default)]
+    pub model_path: Option<String>,
+    // Built-in name or Jinja source; overrides the model's template
+    #[serde(default)]
+    pub template: Option<String>,
+    #[serde(default)]
+    pub bos_token: Option<String>,
+    #[serde(default)]
+    pub eos_token: Option<String>,
+    // Defaults to true
+    #[serde(default)]
+    pub add_generation_prompt: Option<bool>,
+    // The leading BOS text is dropped unless this is set or the model says
+    // not to add BOS, since servers add the BOS token when they tokenize
+    #[serde(default)]
+    pub keep_bos: bool,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenderedChat {
+    pub prompt: String,
+    // "override", "model" or "builtin"
+    pub source: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template_name: Option<String>,
+    pub bos_token: String,
+    pub eos_token: String,
+    // Stop strings for the completion request
+    pub stop: Vec<String>,
+}
+
+pub fn render_request(request: &ChatTemplateRequest) -> Result<RenderedChat, CommandError> {
+    let tokens = match &request.model_path {
+        Some(path) => Some(gguf::chat_tokens(Path::new(path))?),
+        None => None,
+    };
+    let mut template = ChatTemplate::resolve(request.template.as_deref(), tokens.as_ref())?;
+    if let Some(bos) = &request.bos_token {
+        template.bos_token = bos.clone();
+    }
+    if let Some(eos) = &request.eos_token {
+        template.eos_token = eos.clone();
+    }
+
+    let prompt = template.render(&request.messages, &request.tools, request.add_generation_prompt.unwrap_or(true))?;
+    let keep_bos = request.keep_bos || tokens.as_ref().and_then(|t| t.add_bos_token) == Some(false);
+    let prompt = if keep_bos { prompt.as_str() } else { template.strip_bos(&prompt) };
+    Ok(RenderedChat {
+        prompt: prompt.to_string(),
+        source: template.origin.to_string(),
+        template_name: template.name.map(str::to_string),
+        stop: template.stop_strings(),
+        bos_token: template.bos_token,
+        eos_token: template.eos_token,
+    })
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BuiltinChatTemplate {
+    pub name: String,
+    pub template: String,
+    pub bos_token: String,
+    pub eos_token: String,
+}
+
+// The built-in templates, for picking or editing an override
+#[tauri::command]
+pub fn list_chat_templates() -> Vec<BuiltinChatTemplate> {
+    BUILTINS
+        .iter()
+        .map(|builtin| BuiltinChatTemplate {
+            name: builtin.name.to_string(),
+            template: builtin.template.to_string(),
+            bos_token: builtin.bos_token.to_string(),
+            eos_token: builtin.eos_token.to_string(),
+        })
+        .collect()
+}
+
+#[tauri::command]
+pub async fn render_chat_template(request: ChatTemplateRequest) -> Result<RenderedChat, CommandError> {
+    tokio::task::spawn_blocking(move || render_request(&request)).await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(messages: Value) -> Vec<ChatMessage> {
+        serde_json::from_value(messages).unwrap()
+    }
+
+    fn conversation() -> Vec<ChatMessage> {
+        messages(json!([
+            { "role": "system", "content": "Be brief." },
+            { "role": "user", "content": "Hi" },
+            { "role": "assistant", "content": "Hello!" },
+            { "role": "user", "content": "Bye" },
+        ]))
+    }
+
+    fn render(template: &str, messages: &[ChatMessage], add_generation_prompt: bool) -> String {
+        ChatTemplate::resolve(Some(template), None).unwrap().render(messages, &[], add_generation_prompt).unwrap()
+    }
+
+    // Each built-in with and without the generation prompt, and continuing
+    // an assistant message
+    #[test]
+    fn builtins() {
+        let cases = [
+            (
+                "chatml",
+                "<|im_start|>system\nBe brief.<|im_end|>\n<|im_start|>user\nHi<|im_end|>\n\
+                 <|im_start|>assistant\nHello!<|im_end|>\n<|im_start|>user\nBye<|im_end|>\n",
+                "<|im_start|>assistant\n",
+                "<|im_start|>user\nHi<|im_end|>\n<|im_start|>assistant\nHello",
+            ),
+            (
+                "llama3",
+                "<|begin_of_text|><|start_header_id|>system<|end_header_id|>\n\nBe brief.<|eot_id|>\
+                 <|start_header_id|>user<|end_header_id|>\n\nHi<|eot_id|>\
+                 <|start_header_id|>assistant<|end_header_id|>\n\nHello!<|eot_id|>\
+                 <|start_header_id|>user<|end_header_id|>\n\nBye<|eot_id|>",
+                "<|start_header_id|>assistant<|end_header_id|>\n\n",
+                "<|begin_of_text|><|start_header_id|>user<|end_header_id|>\n\nHi<|eot_id|>\
+                 <|start_header_id|>assistant<|end_header_id|>\n\nHello",
+            ),
+            (
+                "mistral",
+                "<s>[INST] Be brief.\n\nHi [/INST] Hello!</s>[INST] Bye [/INST]",
+                "",
+                "<s>[INST] Hi [/INST] Hello",
+            ),
+            (
+                "gemma",
+                "<bos><start_of_turn>user\nBe brief.\n\nHi<end_of_turn>\n<start_of_turn>model\nHello!<end_of_turn>\n\
+                 <start_of_turn>user\nBye<end_of_turn>\n",
+                "<start_of_turn>model\n",
+                "<bos><start_of_turn>user\nHi<end_of_turn>\n<start_of_turn>model\nHello",
+            ),
+            (
+                "phi3",
+                "<|system|>\nBe brief.<|end|>\n<|user|>\nHi<|end|>\n<|assistant|>\nHello!<|end|>\n\
+                 <|user|>\nBye<|end|>\n",
+                "<|assistant|>\n",
+                "<|user|>\nHi<|end|>\n<|assistant|>\nHello",
+            ),
+        ];
+
+        let prefill = messages(json!([
+            { "role": "user", "content": "Hi" },
+            { "role": "assistant", "content": "Hello " },
+        ]));
+        let empty_reply = messages(json!([
+            { "role": "user", "content": "Hi" },
+            { "role": "assistant", "content": "" },
+        ]));
+        for (name, turns, generation_prompt, continued) in cases {
+            let prompt = render(name, &conversation(), true);
+            assert_eq!(prompt, format!("{}{}", turns, generation_prompt), "{}", name);
+            let prompt = render(name, &conversation(), false);
+            // Phi-3 closes the conversation with EOS when no reply follows
+            let end = if name == "phi3" { "<|endoftext|>" } else { "" };
+            assert_eq!(prompt, format!("{}{}", turns, end), "{}", name);
+
+            assert_eq!(render(name, &prefill, true), continued, "{}", name);
+            assert_eq!(render(name, &empty_reply, true), render(name, &prefill[..1], true), "{}", name);
+        }
+    }
+
+    // A template that leaves assistant messages out can't be cut after one
+    #[test]
+    fn prefill_the_template_drops() {
+        let template = "{% for message in messages %}{% if message.role == 'user' %}User: {{ message.content }}\n\
+                        {% endif %}{% endfor %}{% if add_generation_prompt %}Assistant:{% endif %}";
+        let prefill = messages(json!([
+            { "role": "user", "content": "Hi" },
+            { "role": "assistant", "content": " Hello" },
+        ]));
+        assert_eq!(render(template, &prefill, true), "User: Hi\nAssistant:Hello");
+    }
+
+    #[test]
+    fn bos_and_eos() {
+        let request = |fields: Value| -> RenderedChat {
+            let mut request = json!({ "messages": [{ "role": "user", "content": "Hi" }] });
+            request.as_object_mut().unwrap().extend(fields.as_object().unwrap().clone());
+            render_request(&serde_json::from_value(request).unwrap()).unwrap()
+        };
+
+        // Servers add the BOS token themselves, so its text is dropped
+        let rendered = request(json!({ "template": "llama3" }));
+        assert_eq!(
+            rendered.prompt,
+            "<|start_header_id|>user<|end_header_id|>\n\nHi<|eot_id|><|start_header_id|>assistant<|end_header_id|>\n\n"
+        );
+        assert_eq!(rendered.bos_token, "<|begin_of_text|>");
+        assert_eq!(rendered.stop, ["<|eot_id|>", "<|eom_id|>", "<|end_of_text|>"]);
+        let rendered = request(json!({ "template": "llama3", "keepBos": true }));
+        assert!(rendered.prompt.starts_with("<|begin_of_text|><|start_header_id|>user"));
+
+        let rendered = request(json!({ "template": "mistral", "bosToken": "<BOS>", "eosToken": "<EOS>" }));
+        assert_eq!(rendered.prompt, "[INST] Hi [/INST]");
+        assert_eq!(rendered.stop, ["</s>", "[INST]", "<EOS>"]);
+        let rendered = request(json!({ "template": "mistral", "bosToken": "<BOS>", "keepBos": true }));
+        assert_eq!(rendered.prompt, "<BOS>[INST] Hi [/INST]");
+
+        // The model's tokens win over the built-in defaults
+        let tokens = ChatTokens {
+            chat_template: None,
+            bos_token: Some("<|bos|>".into()),
+            eos_token: Some("<|eos|>".into()),
+            add_bos_token: Some(true),
+            control_tokens: vec!["<start_of_turn>".into(), "<end_of_turn>".into()],
+        };
+        let template = ChatTemplate::resolve(None, Some(&tokens)).unwrap();
+        assert_eq!((template.name, template.origin), (Some("gemma"), "builtin"));
+        let prompt = template.render(&messages(json!([{ "role": "user", "content": "Hi" }])), &[], true).unwrap();
+        assert_eq!(prompt, "<|bos|><start_of_turn>user\nHi<end_of_turn>\n<start_of_turn>model\n");
+        assert_eq!(template.strip_bos(&prompt), "<start_of_turn>user\nHi<end_of_turn>\n<start_of_turn>model\n");
+        assert_eq!(template.stop_strings(), ["<end_of_turn>", "<|eos|>"]);
+    }
+}
@@ -0,0 +1,478 @@
+// API keys for remote providers, keyed by provider id. Keys never go back to
+// the webview: the proxy and provider clients attach them to outgoing
+// requests themselves, and the UI only sees a masked preview. The keys live
+// in the encrypted secrets vault, each bound to the origin it was saved for
+// so a request elsewhere never carries it.
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use reqwest::header::{HeaderName, HeaderValue, AUTHORIZATION};
+use tauri::{Manager, State};
+
+use crate::anthropic;
+use crate::egress::{EgressPolicy, EgressPurpose};
+use crate::error::{CommandError, ErrorKind};
+use crate::http_client::HttpClients;
+use crate::openai_compat::ensure_success;
+use crate::recorder::{ExchangeSource, Recorder};
+use crate::vault::Vault;
+
+const VAULT_ENTRY: &str = "providerKeys";
+// Plain-text key file written by earlier versions
+const LEGACY_FILE: &str = "credentials.json";
+
+type ProviderKeys = HashMap<String, StoredKey>;
+
+// How a key is sent: "Authorization: Bearer <key>" or "x-api-key: <key>"
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthScheme {
+    Bearer,
+    XApiKey,
+}
+
+impl AuthScheme {
+    fn for_provider(provider_id: &str) -> Self {
+        if provider_id == "anthropic" {
+            Self::XApiKey
+        } else {
+            Self::Bearer
+        }
+    }
+
+    fn header_name(self) -> HeaderName {
+        match self {
+            Self::Bearer => AUTHORIZATION,
+            Self::XApiKey => HeaderName::from_static("x-api-key"),
+        }
+    }
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StoredKey {
+    key: String,
+    scheme: AuthScheme,
+    // "scheme://host:port" the key may be sent to. Keys saved by earlier
+    // versions have none.
+    #[serde(default)]
+    origin: Option<String>,
+    updated_at: String,
+}
+
+impl StoredKey {
+    fn new(key: &str, scheme: AuthScheme, origin: String) -> Self {
+        Self {
+            key: key.to_string(),
+            scheme,
+            origin: Some(origin),
+            updated_at: chrono::Utc::now().to_rfc3339(),
+        }
+    }
+
+    // Keys from earlier versions fall back to the well-known origin of the
+    // built-in providers; any other such key has to be saved again
+    fn origin(&self, provider_id: &str) -> Option<String> {
+        self.origin.clone().or_else(|| {
+            let origin = match provider_id {
+                "anthropic" => "https://api.anthropic.com",
+                "openai" => "https://api.openai.com",
+                "openrouter" => "https://openrouter.ai",
+                _ => return None,
+            };
+            Some(origin.to_string())
+        })
+    }
+
+    // Refuses to hand the key to any origin but its own
+    fn check_origin(&self, provider_id: &str, url: &reqwest::Url) -> Result<(), CommandError> {
+        let target = url.origin().ascii_serialization();
+        match self.origin(provider_id) {
+            Some(origin) if origin == target => Ok(()),
+            Some(origin) => Err(CommandError::new(
+                ErrorKind::Blocked,
+                format!("The API key for \"{}\" is only sent to {}, not {}", provider_id, origin, target),
+            )
+            .with_url(url.as_str())),
+            None => Err(CommandError::new(
+                ErrorKind::InvalidRequest,
+                format!("The API key for \"{}\" has no base URL; save it again", provider_id),
+            )),
+        }
+    }
+}
+
+// What the UI gets to know about a stored key
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyStatus {
+    pub provider_id: String,
+    pub preview: String,
+    pub scheme: AuthScheme,
+    pub origin: Option<String>,
+    pub updated_at: String,
+}
+
+// "sk-…abcd"; short keys show nothing but their last two characters
+fn mask(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    if chars.len() < 12 {
+        let tail: String = chars[chars.len().saturating_sub(2)..].iter().collect();
+        return format!("…{}", tail);
+    }
+    let head: String = chars[..3].iter().collect();
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}…{}", head, tail)
+}
+
+fn not_configured(provider_id: &str) -> CommandError {
+    CommandError::new(
+        ErrorKind::InvalidRequest,
+        format!("No API key configured for provider \"{}\"", provider_id),
+    )
+}
+
+// Origin a key is bound to, from the provider's base URL
+fn origin_of(base_url: &str) -> Result<String, CommandError> {
+    let base_url = base_url.trim();
+    let url = reqwest::Url::parse(base_url)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https") && url.host().is_some())
+        .ok_or_else(|| {
+            CommandError::new(ErrorKind::InvalidUrl, format!("Invalid provider base URL: {}", base_url))
+        })?;
+    Ok(url.origin().ascii_serialization())
+}
+
+fn header_value(scheme: AuthScheme, key: &str) -> Result<HeaderValue, CommandError> {
+    let text = match scheme {
+        AuthScheme::Bearer => format!("Bearer {}", key),
+        AuthScheme::XApiKey => key.to_string(),
+    };
+    let mut value = HeaderValue::from_str(&text)
+        .map_err(|_| CommandError::new(ErrorKind::InvalidRequest, "API key contains invalid characters"))?;
+    value.set_sensitive(true);
+    Ok(value)
+}
+
+pub struct CredentialStore {
+    vault: Vault,
+    legacy_path: Option<PathBuf>,
+}
+
+impl CredentialStore {
+    pub fn new<R: tauri::Runtime>(app: &tauri::AppHandle<R>, vault: Vault) -> Self {
+        Self::open_dir(app.path().app_data_dir().ok(), vault)
+    }
+
+    pub(crate) fn open_dir(dir: Option<PathBuf>, vault: Vault) -> Self {
+        let legacy_path = dir.map(|dir| dir.join(LEGACY_FILE));
+        Self { vault, legacy_path }
+    }
+
+    // Moves keys from the old plain-text file into the vault. While the
+    // vault is locked the file stays and the move is retried on next use.
+    fn import_legacy(&self) {
+        let Some(path) = self.legacy_path.as_ref().filter(|path| path.exists()) else {
+            return;
+        };
+        let legacy: ProviderKeys = match std::fs::read_to_string(path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+        {
+            Some(keys) => keys,
+            None => {
+                eprintln!("[Credentials] Ignoring unreadable {}", path.display());
+                return;
+            }
+        };
+        let count = legacy.len();
+        let imported = self.vault.update(VAULT_ENTRY, |keys: &mut ProviderKeys| {
+            for (provider_id, stored) in legacy {
+                keys.entry(provider_id).or_insert(stored);
+            }
+        });
+        if imported.is_err() {
+            return;
+        }
+        eprintln!("[Credentials] Moved {} API keys into the vault", count);
+        if let Err(err) = std::fs::remove_file(path) {
+            eprintln!("[Credentials] Failed to delete {}: {}", path.display(), err);
+        }
+    }
+
+    fn keys(&self) -> Result<ProviderKeys, CommandError> {
+        self.import_legacy();
+        Ok(self.vault.get(VAULT_ENTRY)?.unwrap_or_default())
+    }
+
+    fn status(provider_id: &str, stored: &StoredKey) -> KeyStatus {
+        KeyStatus {
+            provider_id: provider_id.to_string(),
+            preview: mask(&stored.key),
+            scheme: stored.scheme,
+            origin: stored.origin(provider_id),
+            updated_at: stored.updated_at.clone(),
+        }
+    }
+
+    fn get(&self, provider_id: &str) -> Result<Option<StoredKey>, CommandError> {
+        Ok(self.keys()?.remove(provider_id))
+    }
+
+    pub fn list(&self) -> Result<Vec<KeyStatus>, CommandError> {
+        let mut list: Vec<KeyStatus> = self.keys()?.iter().map(|(id, stored)| Self::status(id, stored)).collect();
+        list.sort_by(|a, b| a.provider_id.cmp(&b.provider_id));
+        Ok(list)
+    }
+
+    fn set(&self, provider_id: &str, stored: StoredKey) -> Result<KeyStatus, CommandError> {
+        self.import_legacy();
+        self.vault.update(VAULT_ENTRY, |keys: &mut ProviderKeys| {
+            keys.insert(provider_id.to_string(), stored.clone());
+        })?;
+        Ok(Self::status(provider_id, &stored))
+    }
+
+    // Stores a key that is only ever sent to the origin of `base_url`
+    pub(crate) fn save(
+        &self,
+        provider_id: &str,
+        key: &str,
+        base_url: &str,
+        scheme: Option<AuthScheme>,
+    ) -> Result<KeyStatus, CommandError> {
+        let key = required_key(key)?;
+        let origin = origin_of(base_url)?;
+        let scheme = scheme.unwrap_or_else(|| AuthScheme::for_provider(provider_id));
+        self.set(provider_id, StoredKey::new(key, scheme, origin))
+    }
+
+    fn remove(&self, provider_id: &str) -> Result<bool, CommandError> {
+        if self.get(provider_id)?.is_none() {
+            return Ok(false);
+        }
+        self.vault
+            .update(VAULT_ENTRY, |keys: &mut ProviderKeys| keys.remove(provider_id).is_some())
+    }
+
+    // Sets the provider's auth header on a built request, replacing any the
+    // caller passed in. Fails when the request goes to another origin than
+    // the one the key was saved for.
+    pub fn authorize(&self, provider_id: &str, request: &mut reqwest::Request) -> Result<(), CommandError> {
+        let stored = self.get(provider_id)?;
+        Self::apply(provider_id, stored, request)
+    }
+
+    // authorize for background requests (health checks): reading the key
+    // doesn't count as vault activity, so the vault still auto-locks
+    pub fn authorize_background(&self, provider_id: &str, request: &mut reqwest::Request) -> Result<(), CommandError> {
+        let mut keys: ProviderKeys = self.vault.peek(VAULT_ENTRY)?.unwrap_or_default();
+        Self::apply(provider_id, keys.remove(provider_id), request)
+    }
+
+    fn apply(provider_id: &str, stored: Option<StoredKey>, request: &mut reqwest::Request) -> Result<(), CommandError> {
+        let stored = stored.ok_or_else(|| not_configured(provider_id))?;
+        stored.check_origin(provider_id, request.url())?;
+        let headers = request.headers_mut();
+        headers.remove(AUTHORIZATION);
+        headers.remove("x-api-key");
+        headers.insert(stored.scheme.header_name(), header_value(stored.scheme, &stored.key)?);
+        Ok(())
+    }
+}
+
+// Makes a cheap authenticated request (the model list) to check a key, as
+// long as `base_url` is on the key's origin. OpenAI-style base URLs include
+// the version ("https://api.openai.com/v1"); Anthropic's don't
+// ("https://api.anthropic.com").
+async fn verify_key(
+    provider_id: &str,
+    stored: &StoredKey,
+    base_url: &str,
+    clients: &HttpClients,
+    policy: &EgressPolicy,
+    recorder: &Recorder,
+) -> Result<(), CommandError> {
+    let scheme = stored.scheme;
+    let base_url = base_url.trim().trim_end_matches('/');
+    let url = match scheme {
+        AuthScheme::Bearer => format!("{}/models", base_url),
+        AuthScheme::XApiKey => format!("{}/v1/models", base_url),
+    };
+    let url = policy.check(&url, EgressPurpose::Provider)?;
+    stored.check_origin(provider_id, &url)?;
+
+    let profile = clients.provider_for(&url).await?;
+    let mut request = profile
+        .client
+        .get(url)
+        .header(scheme.header_name(), header_value(scheme, &stored.key)?)
+        .timeout(profile.request_timeout);
+    if scheme == AuthScheme::XApiKey {
+        request = request.header("anthropic-version", anthropic::API_VERSION);
+    }
+    let request = request.build()?;
+    let mut recording = recorder.start(ExchangeSource::Provider, &request);
+
+    let result = async {
+        let response = profile.client.execute(request).await?;
+        ensure_success(response, &mut recording).await?;
+        Ok(())
+    }
+    .await;
+    recording.finish_with(&result);
+    result
+}
+
+fn required_key(key: &str) -> Result<&str, CommandError> {
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(CommandError::new(ErrorKind::InvalidRequest, "API key is empty"));
+    }
+    Ok(key)
+}
+
+#[tauri::command]
+pub fn list_provider_keys(credentials: State<'_, CredentialStore>) -> Result<Vec<KeyStatus>, CommandError> {
+    credentials.list()
+}
+
+// `scheme` defaults to x-api-key for "anthropic" and Bearer for everything else
+#[tauri::command]
+pub fn set_provider_key(
+    provider_id: String,
+    key: String,
+    base_url: String,
+    scheme: Option<AuthScheme>,
+    credentials: State<'_, CredentialStore>,
+) -> Result<KeyStatus, CommandError> {
+    eprintln!("[Credentials] Storing API key for {}", provider_id);
+    credentials.save(&provider_id, &key, &base_url, scheme)
+}
+
+// Checks the stored key against the provider
+#[tauri::command]
+pub async fn test_provider_key(
+    provider_id: String,
+    base_url: String,
+    credentials: State<'_, CredentialStore>,
+    clients: State<'_, HttpClients>,
+    policy: State<'_, EgressPolicy>,
+    recorder: State<'_, Recorder>,
+) -> Result<(), CommandError> {
+    let stored = credentials.get(&provider_id)?.ok_or_else(|| not_configured(&provider_id))?;
+    eprintln!("[Credentials] Testing API key for {}", provider_id);
+    verify_key(&provider_id, &stored, &base_url, &clients, &policy, &recorder).await
+}
+
+// Replaces a key only once the new one has been accepted by the provider;
+// a rejected key leaves the current one in place. A new key keeps the origin
+// of the one it replaces.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn rotate_provider_key(
+    provider_id: String,
+    key: String,
+    base_url: String,
+    credentials: State<'_, CredentialStore>,
+    clients: State<'_, HttpClients>,
+    policy: State<'_, EgressPolicy>,
+    recorder: State<'_, Recorder>,
+) -> Result<KeyStatus, CommandError> {
+    let key = required_key(&key)?;
+    let current = credentials.get(&provider_id)?;
+    let origin = match current.as_ref().and_then(|stored| stored.origin(&provider_id)) {
+        Some(origin) => origin,
+        None => origin_of(&base_url)?,
+    };
+    let scheme = current.map_or_else(|| AuthScheme::for_provider(&provider_id), |stored| stored.scheme);
+    let stored = StoredKey::new(key, scheme, origin);
+    eprintln!("[Credentials] Rotating API key for {}", provider_id);
+    verify_key(&provider_id, &stored, &base_url, &clients, &policy, &recorder)
+        .await
+        .map_err(|err| err.context("New API key was rejected"))?;
+    credentials.set(&provider_id, stored)
+}
+
+#[tauri::command]
+pub fn delete_provider_key(
+    provider_id: String,
+    credentials: State<'_, CredentialStore>,
+) -> Result<bool, CommandError> {
+    eprintln!("[Credentials] Deleting API key for {}", provider_id);
+    credentials.remove(&provider_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(dir: &std::path::Path) -> CredentialStore {
+        CredentialStore::open_dir(Some(dir.to_path_buf()), Vault::open_dir(Some(dir.to_path_buf())))
+    }
+
+    fn request(url: &str) -> reqwest::Request {
+        reqwest::Request::new(reqwest::Method::GET, url.parse().unwrap())
+    }
+
+    #[test]
+    fn keys_are_only_sent_to_their_origin() {
+        let dir = tempfile::tempdir().unwrap();
+        let credentials = store(dir.path());
+        let status = credentials.save("example", " sk-test ", "https://api.example.com/v1/", None).unwrap();
+        assert_eq!(status.origin.as_deref(), Some("https://api.example.com"));
+
+        let mut allowed = request("https://api.example.com/v1/chat/completions");
+        credentials.authorize("example", &mut allowed).unwrap();
+        assert_eq!(allowed.headers()[AUTHORIZATION], "Bearer sk-test");
+        let mut background = request("https://api.example.com/v1/models");
+        credentials.authorize_background("example", &mut background).unwrap();
+        assert_eq!(background.headers()[AUTHORIZATION], "Bearer sk-test");
+
+        for url in [
+            "https://evil.example.net/v1/chat/completions",
+            "http://api.example.com/v1/chat/completions",
+            "https://api.example.com:8443/v1/chat/completions",
+        ] {
+            let mut refused = request(url);
+            let err = credentials.authorize("example", &mut refused).unwrap_err();
+            assert_eq!(err.kind, ErrorKind::Blocked, "{}", url);
+            assert!(refused.headers().get(AUTHORIZATION).is_none());
+        }
+    }
+
+    #[test]
+    fn base_urls_need_a_host() {
+        assert!(origin_of("file:///etc/passwd").is_err());
+        assert!(origin_of("not a url").is_err());
+        assert_eq!(origin_of(" http://localhost:8080 ").unwrap(), "http://localhost:8080");
+    }
+
+    #[test]
+    fn legacy_keys_move_into_the_vault() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy = dir.path().join(LEGACY_FILE);
+        let updated_at = "2024-01-01T00:00:00Z";
+        let keys = serde_json::json!({
+            "anthropic": { "key": "sk-ant-legacy", "scheme": "x_api_key", "updatedAt": updated_at },
+            "custom": { "key": "sk-custom-legacy", "scheme": "bearer", "updatedAt": updated_at },
+        });
+        std::fs::write(&legacy, keys.to_string()).unwrap();
+
+        let credentials = store(dir.path());
+        let list = credentials.list().unwrap();
+        assert_eq!(list.len(), 2);
+        assert!(!legacy.exists());
+
+        // Built-in providers keep working at their well-known origin
+        let mut messages = request("https://api.anthropic.com/v1/messages");
+        credentials.authorize("anthropic", &mut messages).unwrap();
+        assert_eq!(messages.headers()["x-api-key"], "sk-ant-legacy");
+
+        // Others have to be saved again with their base URL
+        let mut models = request("https://custom.example.com/v1/models");
+        let err = credentials.authorize("custom", &mut models).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InvalidRequest);
+    }
+}
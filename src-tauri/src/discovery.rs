@@ -0,0 +1,459 @@
+// Finds local LLM servers on their well-known ports. Every port is probed
+// concurrently with short timeouts; a server that answers is identified by
+// endpoints only its software serves, then asked for its version and models.
+// Results use the provider types and base URLs of src/providers/factory.ts.
+use std::time::Duration;
+
+use futures::future::join_all;
+use serde_json::Value;
+use tauri::State;
+
+use crate::egress::{EgressPolicy, EgressPurpose};
+use crate::error::CommandError;
+use crate::http_client::HttpClients;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(1000);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Server {
+    Ollama,
+    LmStudio,
+    LlamaCpp,
+    KoboldCpp,
+    TextGen,
+    // Gradio UI of text-generation-webui, without its API
+    TextGenUi,
+    Vllm,
+    // Anything else serving /v1/models
+    OpenAiCompatible,
+}
+
+// Well-known ports and the server expected on each
+const KNOWN_PORTS: &[(u16, Server)] = &[
+    (11434, Server::Ollama),
+    (1234, Server::LmStudio),
+    (8080, Server::LlamaCpp),
+    (5001, Server::KoboldCpp),
+    (5000, Server::TextGen),
+    (7860, Server::TextGenUi),
+    (8000, Server::Vllm),
+];
+
+// Tried after the expected server, most specific first
+const FINGERPRINT_ORDER: &[Server] = &[
+    Server::Ollama,
+    Server::KoboldCpp,
+    Server::LmStudio,
+    Server::LlamaCpp,
+    Server::TextGen,
+    Server::Vllm,
+];
+
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiscoveredProvider {
+    // ProviderType from src/types
+    pub provider_type: String,
+    // The software found, which can differ from the provider type used to
+    // talk to it (e.g. "vllm")
+    pub server: String,
+    pub name: String,
+    pub base_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    pub models: Vec<String>,
+    pub latency_ms: u64,
+    // Set when the server was found but can't be used as is
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+enum Response {
+    Json(Value),
+    // Answered, but not with a successful JSON response
+    Other,
+    // Nothing listening, or no answer within the timeout
+    Unreachable,
+}
+
+struct Probe {
+    client: reqwest::Client,
+    origin: String,
+    port: u16,
+    timeout: Duration,
+}
+
+impl Probe {
+    async fn get(&self, path: &str) -> Response {
+        let request = self
+            .client
+            .get(format!("{}{}", self.origin, path))
+            .header(reqwest::header::ACCEPT, "application/json")
+            .timeout(self.timeout);
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(_) => return Response::Unreachable,
+        };
+        if !response.status().is_success() {
+            return Response::Other;
+        }
+        match response.text().await.ok().and_then(|text| serde_json::from_str(&text).ok()) {
+            Some(json) => Response::Json(json),
+            None => Response::Other,
+        }
+    }
+
+    async fn json(&self, path: &str) -> Option<Value> {
+        match self.get(path).await {
+            Response::Json(json) => Some(json),
+            _ => None,
+        }
+    }
+
+    // The factory's default configs use localhost
+    fn base_url(&self) -> String {
+        format!("http://localhost:{}", self.port)
+    }
+
+    fn found(&self, provider_type: &str, server: &str, name: &str) -> DiscoveredProvider {
+        DiscoveredProvider {
+            provider_type: provider_type.to_string(),
+            server: server.to_string(),
+            name: name.to_string(),
+            base_url: self.base_url(),
+            version: None,
+            models: Vec::new(),
+            latency_ms: 0,
+            hint: None,
+        }
+    }
+}
+
+fn string_at(value: &Value, pointer: &str) -> Option<String> {
+    value.pointer(pointer).and_then(Value::as_str).map(|s| s.to_string())
+}
+
+// Model ids from an OpenAI-style {"data": [{"id": ...}]} list
+fn openai_model_ids(list: &Value) -> Option<Vec<String>> {
+    let data = list.get("data")?.as_array()?;
+    Some(data.iter().filter_map(|model| string_at(model, "/id")).collect())
+}
+
+async fn identify(server: Server, probe: &Probe) -> Option<DiscoveredProvider> {
+    match server {
+        Server::Ollama => {
+            // {"version": "0.5.7"}
+            let version = string_at(&probe.json("/api/version").await?, "/version")?;
+            let tags = probe.json("/api/tags").await;
+            let models = tags
+                .as_ref()
+                .and_then(|tags| tags.get("models")?.as_array().cloned())
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|model| string_at(model, "/name"))
+                .collect();
+            Some(DiscoveredProvider {
+                version: Some(version),
+                models,
+                ..probe.found("ollama", "ollama", "Ollama")
+            })
+        }
+        Server::KoboldCpp => {
+            // {"result": "KoboldCpp", "version": "1.80"}
+            let info = probe.json("/api/extra/version").await?;
+            if string_at(&info, "/result")? != "KoboldCpp" {
+                return None;
+            }
+            // {"result": "koboldcpp/Model-Name"}
+            let models = probe
+                .json("/api/v1/model")
+                .await
+                .and_then(|model| string_at(&model, "/result"))
+                .map(|name| name.trim_start_matches("koboldcpp/").to_string())
+                .into_iter()
+                .collect();
+            Some(DiscoveredProvider {
+                version: string_at(&info, "/version"),
+                models,
+                ..probe.found("koboldcpp", "koboldcpp", "KoboldCpp")
+            })
+        }
+        Server::LmStudio => {
+            // LM Studio's native REST API; entries carry its load state
+            let list = probe.json("/api/v0/models").await?;
+            let data = list.get("data")?.as_array()?;
+            if !data.iter().all(|model| model.get("state").is_some() || model.get("compatibility_type").is_some()) {
+                return None;
+            }
+            Some(DiscoveredProvider {
+                models: openai_model_ids(&list).unwrap_or_default(),
+                ..probe.found("lmstudio", "lmstudio", "LM Studio")
+            })
+        }
+        Server::LlamaCpp => {
+            let props = probe.json("/props").await?;
+            if props.get("default_generation_settings").is_none() && props.get("total_slots").is_none() {
+                return None;
+            }
+            let mut models = probe
+                .json("/v1/models")
+                .await
+                .and_then(|list| openai_model_ids(&list))
+                .unwrap_or_default();
+            if models.is_empty() {
+                // Older builds only report the model file
+                if let Some(path) = string_at(&props, "/model_path") {
+                    let name = path.rsplit(['/', '\\']).next().unwrap_or(&path).to_string();
+                    models.push(name);
+                }
+            }
+            Some(DiscoveredProvider {
+                version: string_at(&props, "/build_info"),
+                models,
+                ..probe.found("llamacpp", "llamacpp", "llama.cpp")
+            })
+        }
+        Server::TextGen => {
+            // {"model_name": "...", "lora_names": [...]}
+            let info = probe.json("/v1/internal/model/info").await?;
+            info.get("model_name")?;
+            let mut models: Vec<String> = probe
+                .json("/v1/internal/model/list")
+                .await
+                .and_then(|list| list.get("model_names")?.as_array().cloned())
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|name| name.as_str().map(|s| s.to_string()))
+                .collect();
+            if models.is_empty() {
+                models.extend(string_at(&info, "/model_name").filter(|name| name != "None"));
+            }
+            Some(DiscoveredProvider {
+                models,
+                ..probe.found("textgen-webui", "textgen-webui", "Text Generation WebUI")
+            })
+        }
+        Server::TextGenUi => {
+            // Gradio serves its app config, title included, at /config
+            let config = probe.json("/config").await?;
+            let title = string_at(&config, "/title").unwrap_or_default();
+            if !title.to_lowercase().contains("text generation") {
+                return None;
+            }
+            Some(DiscoveredProvider {
+                version: string_at(&config, "/version"),
+                hint: Some("Web UI only; start text-generation-webui with --api to use its API on port 5000".to_string()),
+                ..probe.found("textgen-webui", "textgen-webui", "Text Generation WebUI")
+            })
+        }
+        Server::Vllm => {
+            let list = probe.json("/v1/models").await?;
+            let data = list.get("data")?.as_array()?;
+            if !data.iter().any(|model| model.get("owned_by").and_then(Value::as_str) == Some("vllm")) {
+                return None;
+            }
+            // vLLM speaks the OpenAI API without keys, like LM Studio's client expects
+            Some(DiscoveredProvider {
+                version: probe.json("/version").await.and_then(|info| string_at(&info, "/version")),
+                models: openai_model_ids(&list).unwrap_or_default(),
+                ..probe.found("lmstudio", "vllm", "vLLM")
+            })
+        }
+        Server::OpenAiCompatible => {
+            let list = probe.json("/v1/models").await?;
+            Some(DiscoveredProvider {
+                models: openai_model_ids(&list)?,
+                ..probe.found("lmstudio", "openai-compatible", &format!("OpenAI-compatible server on port {}", probe.port))
+            })
+        }
+    }
+}
+
+// Identifies whatever listens on one port: the expected server first, then
+// the other fingerprints, then a plain OpenAI-compatible API
+async fn discover_port(probe: Probe, expected: Option<Server>) -> Option<DiscoveredProvider> {
+    let started = std::time::Instant::now();
+    // One request tells a closed port apart from one with a server on it
+    if let Response::Unreachable = probe.get("/").await {
+        return None;
+    }
+
+    let mut order: Vec<Server> = expected.into_iter().collect();
+    if expected != Some(Server::TextGenUi) {
+        order.extend(FINGERPRINT_ORDER.iter().copied().filter(|server| Some(*server) != expected));
+        order.push(Server::OpenAiCompatible);
+    }
+
+    for server in order {
+        if let Some(mut found) = identify(server, &probe).await {
+            found.latency_ms = started.elapsed().as_millis() as u64;
+            eprintln!(
+                "[Discovery] Found {} on port {} ({} models)",
+                found.server,
+                probe.port,
+                found.models.len()
+            );
+            return Some(found);
+        }
+    }
+    None
+}
+
+// Probes the well-known ports, or `ports` when given (each of them is tried
+// against every fingerprint)
+#[tauri::command]
+pub async fn discover_local_providers(
+    ports: Option<Vec<u16>>,
+    timeout_ms: Option<u64>,
+    clients: State<'_, HttpClients>,
+    policy: State<'_, EgressPolicy>,
+) -> Result<Vec<DiscoveredProvider>, CommandError> {
+    let timeout = timeout_ms.map(Duration::from_millis).unwrap_or(DEFAULT_TIMEOUT);
+    let targets: Vec<(u16, Option<Server>)> = match ports {
+        Some(ports) => ports
+            .into_iter()
+            .map(|port| (port, KNOWN_PORTS.iter().find(|(known, _)| *known == port).map(|(_, s)| *s)))
+            .collect(),
+        None => KNOWN_PORTS.iter().map(|(port, server)| (*port, Some(*server))).collect(),
+    };
+    eprintln!("[Discovery] Probing {} local ports", targets.len());
+
+    let mut probes = Vec::new();
+    for (port, expected) in targets {
+        let origin = format!("http://127.0.0.1:{}", port);
+        let url = policy.check(&origin, EgressPurpose::Provider)?;
+        let client = clients.provider_for(&url).await?.client;
+        probes.push(discover_port(Probe { client, origin, port, timeout }, expected));
+    }
+
+    let mut found: Vec<DiscoveredProvider> = join_all(probes).await.into_iter().flatten().collect();
+    // The web UI entry only matters when the API itself wasn't found
+    if found.iter().any(|p| p.server == "textgen-webui" && p.hint.is_none()) {
+        found.retain(|p| !(p.server == "textgen-webui" && p.hint.is_some()));
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    use serde_json::json;
+
+    use super::*;
+
+    // Serves `routes` as JSON on a random port until the test ends; other
+    // paths get a 404
+    fn serve(routes: Vec<(&'static str, Value)>) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else {
+                    continue;
+                };
+                let mut head = Vec::new();
+                let mut buffer = [0u8; 1024];
+                while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+                    match stream.read(&mut buffer) {
+                        Ok(0) | Err(_) => break,
+                        Ok(read) => head.extend_from_slice(&buffer[..read]),
+                    }
+                }
+                let head = String::from_utf8_lossy(&head);
+                let path = head.split(' ').nth(1).unwrap_or_default();
+                let response = match routes.iter().find(|(route, _)| *route == path) {
+                    Some((_, body)) => {
+                        let body = body.to_string();
+                        format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len())
+                    }
+                    None => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+                };
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        port
+    }
+
+    fn discover(port: u16, expected: Option<Server>) -> Option<DiscoveredProvider> {
+        let probe = Probe {
+            client: reqwest::Client::builder().no_proxy().build().unwrap(),
+            origin: format!("http://127.0.0.1:{}", port),
+            port,
+            timeout: Duration::from_secs(5),
+        };
+        tauri::async_runtime::block_on(discover_port(probe, expected))
+    }
+
+    #[test]
+    fn finds_ollama() {
+        let port = serve(vec![
+            ("/api/version", json!({ "version": "0.5.7" })),
+            ("/api/tags", json!({ "models": [{ "name": "llama3.2:latest" }] })),
+            // Ollama serves the OpenAI API too
+            ("/v1/models", json!({ "data": [{ "id": "llama3.2:latest" }] })),
+        ]);
+        for expected in [Some(Server::Ollama), None] {
+            let found = discover(port, expected).unwrap();
+            assert_eq!(found.provider_type, "ollama");
+            assert_eq!(found.version.as_deref(), Some("0.5.7"));
+            assert_eq!(found.models, ["llama3.2:latest"]);
+            assert_eq!(found.base_url, format!("http://localhost:{}", port));
+        }
+    }
+
+    #[test]
+    fn finds_koboldcpp() {
+        let port = serve(vec![
+            ("/api/extra/version", json!({ "result": "KoboldCpp", "version": "1.80" })),
+            ("/api/v1/model", json!({ "result": "koboldcpp/Mistral-7B" })),
+            ("/v1/models", json!({ "data": [{ "id": "koboldcpp/Mistral-7B" }] })),
+        ]);
+        let found = discover(port, None).unwrap();
+        assert_eq!((found.provider_type.as_str(), found.server.as_str()), ("koboldcpp", "koboldcpp"));
+        assert_eq!(found.version.as_deref(), Some("1.80"));
+        assert_eq!(found.models, ["Mistral-7B"]);
+    }
+
+    #[test]
+    fn finds_llamacpp() {
+        let port = serve(vec![
+            ("/health", json!({ "status": "ok" })),
+            (
+                "/props",
+                json!({ "default_generation_settings": {}, "total_slots": 1, "model_path": "/models/qwen2.5-7b.gguf" }),
+            ),
+            ("/v1/models", json!({ "data": [] })),
+        ]);
+        let found = discover(port, Some(Server::LlamaCpp)).unwrap();
+        assert_eq!((found.provider_type.as_str(), found.server.as_str()), ("llamacpp", "llamacpp"));
+        // The model file stands in for an empty model list
+        assert_eq!(found.models, ["qwen2.5-7b.gguf"]);
+    }
+
+    #[test]
+    fn finds_openai_style_servers() {
+        let port = serve(vec![("/v1/models", json!({ "data": [{ "id": "m1" }, { "id": "m2" }] }))]);
+        let found = discover(port, Some(Server::LlamaCpp)).unwrap();
+        assert_eq!((found.provider_type.as_str(), found.server.as_str()), ("lmstudio", "openai-compatible"));
+        assert_eq!(found.models, ["m1", "m2"]);
+
+        let port = serve(vec![
+            ("/v1/models", json!({ "data": [{ "id": "m1", "owned_by": "vllm" }] })),
+            ("/version", json!({ "version": "0.6.4" })),
+        ]);
+        let found = discover(port, None).unwrap();
+        assert_eq!(found.server, "vllm");
+        assert_eq!(found.version.as_deref(), Some("0.6.4"));
+    }
+
+    #[test]
+    fn skips_closed_ports_and_unknown_servers() {
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        assert!(discover(closed, None).is_none());
+
+        let port = serve(vec![("/", json!({ "hello": "world" }))]);
+        assert!(discover(port, None).is_none());
+    }
+}
@@ -0,0 +1,947 @@
+// Egress policy for every outgoing request made by the backend.
+// Commands call EgressPolicy::check before touching the network; the shared
+// HTTP clients and browser tabs re-check resolved addresses and redirects.
+// Persisted to egress.json in the app data dir.
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use headless_chrome::browser::tab::RequestPausedDecision;
+use headless_chrome::protocol::cdp::{Fetch, Network};
+use headless_chrome::Tab;
+use reqwest::Url;
+use url::Host;
+
+use crate::app_data;
+use crate::error::{CommandError, ErrorKind};
+
+const SETTINGS_FILE: &str = "egress.json";
+const MAX_VIOLATIONS: usize = 200;
+const MAX_REDIRECTS: usize = 10;
+
+// Resolved addresses are reused for a short while, so redirect hops and a
+// page's subresources don't each wait for DNS
+const RESOLVE_TTL: Duration = Duration::from_secs(30);
+const MAX_RESOLVED: usize = 512;
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+const BLOCKED_PREFIX: &str = "Blocked by egress policy";
+
+// Hostnames of cloud instance metadata services
+const METADATA_HOSTS: &[&str] = &[
+    "metadata",
+    "metadata.google.internal",
+    "metadata.goog",
+    "metadata.azure.com",
+    "instance-data",
+    "instance-data.ec2.internal",
+];
+
+// What the request is for; local provider endpoints are only reachable
+// for provider traffic, never from web search or scraping
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EgressPurpose {
+    Provider,
+    Web,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleAction {
+    Allow,
+    Deny,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EgressRule {
+    // Origin pattern such as "https://api.openai.com", "*.example.com",
+    // "http://192.168.1.20:*" or "*"
+    pub pattern: String,
+    pub action: RuleAction,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EgressPolicySettings {
+    // Evaluated deny-first, then allow
+    pub rules: Vec<EgressRule>,
+    // Loopback endpoints (Ollama, LM Studio, llama.cpp...) for provider traffic
+    pub allow_local_providers: bool,
+    // Action for origins no rule matches
+    pub default_action: RuleAction,
+    // Lets web search and scraping reach private, loopback and intranet
+    // addresses; cloud metadata endpoints stay blocked regardless
+    #[serde(default)]
+    pub allow_intranet_scraping: bool,
+}
+
+impl Default for EgressPolicySettings {
+    fn default() -> Self {
+        Self {
+            rules: Vec::new(),
+            allow_local_providers: true,
+            default_action: RuleAction::Allow,
+            allow_intranet_scraping: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EgressViolation {
+    pub timestamp: String,
+    pub url: String,
+    pub purpose: EgressPurpose,
+    pub reason: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum HostClass {
+    Metadata,
+    Loopback,
+    Private,
+    Other,
+}
+
+// Error raised from the DNS resolver and redirect policy of the shared
+// clients; found again in the reqwest error's source chain
+#[derive(Debug)]
+pub struct BlockedAddress(pub String);
+
+impl std::fmt::Display for BlockedAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", BLOCKED_PREFIX, self.0)
+    }
+}
+
+impl std::error::Error for BlockedAddress {}
+
+struct OriginPattern {
+    scheme: Option<String>,
+    host: String,
+    port: Option<u16>,
+    any_port: bool,
+}
+
+impl OriginPattern {
+    fn parse(pattern: &str) -> Result<Self, String> {
+        let pattern = pattern.trim().to_lowercase();
+        let (scheme, rest) = match pattern.split_once("://") {
+            Some(("*", rest)) => (None, rest),
+            Some((scheme, rest)) => (Some(scheme.to_string()), rest),
+            None => (None, pattern.as_str()),
+        };
+        let rest = rest.trim_end_matches('/');
+
+        // Bracketed IPv6 hosts contain colons of their own
+        let (host, port) = if let Some(stripped) = rest.strip_prefix('[') {
+            let (host, after) = stripped
+                .split_once(']')
+                .ok_or_else(|| format!("Invalid pattern: {}", pattern))?;
+            (host.to_string(), after.strip_prefix(':'))
+        } else {
+            match rest.rsplit_once(':') {
+                Some((host, port)) => (host.to_string(), Some(port)),
+                None => (rest.to_string(), None),
+            }
+        };
+
+        if host.is_empty() {
+            return Err(format!("Invalid pattern: {}", pattern));
+        }
+
+        let (port, any_port) = match port {
+            None => (None, false),
+            Some("*") => (None, true),
+            Some(port) => (
+                Some(port.parse().map_err(|_| format!("Invalid port in pattern: {}", pattern))?),
+                false,
+            ),
+        };
+
+        Ok(Self { scheme, host, port, any_port })
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        if let Some(scheme) = &self.scheme {
+            if scheme != url.scheme() {
+                return false;
+            }
+        }
+
+        if !self.any_port {
+            let port = url.port_or_known_default();
+            let expected = self.port.or(match self.scheme.as_deref() {
+                Some("http") => Some(80),
+                Some("https") => Some(443),
+                _ => None,
+            });
+            if expected.is_some() && port != expected {
+                return false;
+            }
+        }
+
+        let host = match url.host() {
+            Some(Host::Ipv6(ip)) => ip.to_string(),
+            Some(host) => host.to_string().to_lowercase(),
+            None => return false,
+        };
+
+        if self.host == "*" {
+            true
+        } else if let Some(suffix) = self.host.strip_prefix("*.") {
+            host.ends_with(&format!(".{}", suffix))
+        } else {
+            host == self.host
+        }
+    }
+}
+
+pub fn is_link_local(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => v4.is_link_local(),
+        IpAddr::V6(v6) => (v6.segments()[0] & 0xffc0) == 0xfe80,
+    }
+}
+
+pub fn is_metadata_ip(ip: &IpAddr) -> bool {
+    match ip {
+        // Alibaba Cloud uses 100.100.100.200 outside the link-local range
+        IpAddr::V4(v4) => v4.is_link_local() || *v4 == Ipv4Addr::new(100, 100, 100, 200),
+        // AWS IMDS over IPv6
+        IpAddr::V6(v6) => {
+            is_link_local(ip) || *v6 == Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254)
+        }
+    }
+}
+
+// RFC 1918, carrier-grade NAT, IPv6 unique local and similar non-public ranges
+pub fn is_private_ip(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            v4.is_private()
+                || v4.is_broadcast()
+                || v4.is_multicast()
+                || a == 0
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 198 && (18..20).contains(&b))
+        }
+        IpAddr::V6(v6) => (v6.segments()[0] & 0xfe00) == 0xfc00 || v6.is_multicast(),
+    }
+}
+
+fn normalize_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => IpAddr::V6(v6),
+        },
+        v4 => v4,
+    }
+}
+
+fn classify_ip(ip: &IpAddr) -> HostClass {
+    let ip = normalize_ip(*ip);
+    if is_metadata_ip(&ip) {
+        HostClass::Metadata
+    } else if ip.is_loopback() || ip.is_unspecified() {
+        HostClass::Loopback
+    } else if is_private_ip(&ip) {
+        HostClass::Private
+    } else {
+        HostClass::Other
+    }
+}
+
+fn classify(url: &Url) -> HostClass {
+    let ip = match url.host() {
+        Some(Host::Ipv4(ip)) => IpAddr::V4(ip),
+        Some(Host::Ipv6(ip)) => IpAddr::V6(ip),
+        Some(Host::Domain(domain)) => {
+            let domain = domain.trim_end_matches('.').to_lowercase();
+            if METADATA_HOSTS.contains(&domain.as_str()) {
+                return HostClass::Metadata;
+            }
+            if domain == "localhost" || domain.ends_with(".localhost") {
+                return HostClass::Loopback;
+            }
+            return HostClass::Other;
+        }
+        None => return HostClass::Other,
+    };
+
+    classify_ip(&ip)
+}
+
+struct PolicyState {
+    settings: RwLock<EgressPolicySettings>,
+    violations: Mutex<VecDeque<EgressViolation>>,
+    resolved: Mutex<HashMap<String, (Instant, Vec<SocketAddr>)>>,
+}
+
+// Cheap to clone so the HTTP clients' resolvers and browser interceptors
+// share the live settings
+#[derive(Clone)]
+pub struct EgressPolicy {
+    state: Arc<PolicyState>,
+}
+
+impl EgressPolicy {
+    pub fn new(settings: EgressPolicySettings) -> Result<Self, CommandError> {
+        validate(&settings)?;
+        Ok(Self {
+            state: Arc::new(PolicyState {
+                settings: RwLock::new(settings),
+                violations: Mutex::new(VecDeque::new()),
+                resolved: Mutex::new(HashMap::new()),
+            }),
+        })
+    }
+
+    pub fn settings(&self) -> EgressPolicySettings {
+        self.state.settings.read().unwrap().clone()
+    }
+
+    pub fn update(&self, settings: EgressPolicySettings) -> Result<(), CommandError> {
+        validate(&settings)?;
+        *self.state.settings.write().unwrap() = settings;
+        Ok(())
+    }
+
+    pub fn violations(&self) -> Vec<EgressViolation> {
+        self.state.violations.lock().unwrap().iter().cloned().collect()
+    }
+
+    // Parses and checks a URL; the only way commands obtain a URL to request
+    pub fn check(&self, url: &str, purpose: EgressPurpose) -> Result<Url, CommandError> {
+        let parsed = Url::parse(url).map_err(|err| {
+            CommandError::new(ErrorKind::InvalidUrl, format!("Invalid URL: {err}")).with_url(url)
+        })?;
+
+        match parsed.scheme() {
+            "http" | "https" => {}
+            _ => {
+                return Err(CommandError::new(ErrorKind::InvalidUrl, "Only http and https schemes are allowed")
+                    .with_url(url))
+            }
+        }
+
+        if let Err(reason) = self.evaluate(&parsed, purpose) {
+            self.record_violation(&parsed, purpose, &reason);
+            return Err(blocked(&parsed, &reason));
+        }
+
+        Ok(parsed)
+    }
+
+    // Checks the addresses a host actually resolves to; any blocked address
+    // rejects the whole host so DNS answers can't be mixed to slip through.
+    // Rules match origins, not addresses, so only the switches apply here.
+    fn check_ip(&self, ip: &IpAddr, purpose: EgressPurpose) -> Result<(), String> {
+        let settings = self.state.settings.read().unwrap();
+        match (classify_ip(ip), purpose) {
+            (HostClass::Metadata, _) => Err(format!("{} is a cloud metadata or link-local address", ip)),
+            (HostClass::Loopback, EgressPurpose::Provider) if !settings.allow_local_providers => {
+                Err(format!("{} is a loopback address and local provider endpoints are disabled", ip))
+            }
+            (HostClass::Loopback | HostClass::Private, EgressPurpose::Web) if !settings.allow_intranet_scraping => {
+                Err(format!("{} is a private address and intranet scraping is disabled", ip))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_addrs(&self, host: &str, addrs: &[SocketAddr], purpose: EgressPurpose) -> Result<(), String> {
+        for addr in addrs {
+            if let Err(reason) = self.check_ip(&addr.ip(), purpose) {
+                let reason = format!("{} resolves to {}", host, reason);
+                eprintln!("[Egress] Blocked {:?} request: {}", purpose, reason);
+                self.push_violation(host.to_string(), purpose, &reason);
+                return Err(reason);
+            }
+        }
+        Ok(())
+    }
+
+    // Blocking check of a URL including DNS resolution, for the headless
+    // browser which resolves hosts on its own
+    pub fn check_resolved(&self, url: &Url, purpose: EgressPurpose) -> Result<(), CommandError> {
+        if let Err(reason) = self.evaluate(url, purpose) {
+            self.record_violation(url, purpose, &reason);
+            return Err(blocked(url, &reason));
+        }
+        self.check_lookup(url, purpose).map_err(|reason| blocked(url, &reason))
+    }
+
+    // Blocking lookup for the redirect policy and the browser's request
+    // interception, which can't await. Hosts seen recently come from the
+    // cache; unresolvable hosts pass and fail later with a network error.
+    fn check_lookup(&self, url: &Url, purpose: EgressPurpose) -> Result<(), String> {
+        let Some(Host::Domain(domain)) = url.host() else {
+            return Ok(());
+        };
+        match self.lookup_blocking(domain) {
+            Some(addrs) => self.check_addrs(domain, &addrs, purpose),
+            None => Ok(()),
+        }
+    }
+
+    // Resolves and checks the host of a URL that is about to go through a
+    // proxy. The proxy resolves the host itself, so the clients' resolver
+    // never sees it. Hosts that only the proxy can resolve pass: the URL
+    // itself has already been checked.
+    pub async fn check_proxied(&self, url: &Url, purpose: EgressPurpose) -> Result<(), CommandError> {
+        let Some(Host::Domain(domain)) = url.host() else {
+            return Ok(());
+        };
+        let Ok(addrs) = self.lookup(domain).await else {
+            return Ok(());
+        };
+        self.check_addrs(domain, &addrs, purpose)
+            .map_err(|reason| blocked(url, &reason))
+    }
+
+    fn cached(&self, host: &str) -> Option<Vec<SocketAddr>> {
+        let resolved = self.state.resolved.lock().unwrap();
+        resolved
+            .get(&host.to_lowercase())
+            .filter(|(at, _)| at.elapsed() < RESOLVE_TTL)
+            .map(|(_, addrs)| addrs.clone())
+    }
+
+    fn remember(&self, host: &str, addrs: &[SocketAddr]) {
+        let mut resolved = self.state.resolved.lock().unwrap();
+        if resolved.len() >= MAX_RESOLVED {
+            resolved.retain(|_, (at, _)| at.elapsed() < RESOLVE_TTL);
+        }
+        if resolved.len() < MAX_RESOLVED {
+            resolved.insert(host.to_lowercase(), (Instant::now(), addrs.to_vec()));
+        }
+    }
+
+    async fn lookup(&self, host: &str) -> std::io::Result<Vec<SocketAddr>> {
+        if let Some(addrs) = self.cached(host) {
+            return Ok(addrs);
+        }
+        let addrs: Vec<SocketAddr> = tokio::time::timeout(LOOKUP_TIMEOUT, tokio::net::lookup_host((host, 0)))
+            .await
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "DNS lookup timed out"))??
+            .collect();
+        self.remember(host, &addrs);
+        Ok(addrs)
+    }
+
+    fn lookup_blocking(&self, host: &str) -> Option<Vec<SocketAddr>> {
+        if let Some(addrs) = self.cached(host) {
+            return Some(addrs);
+        }
+        match tokio::runtime::Handle::try_current() {
+            // On a runtime worker (reqwest's redirect policy) the lookup runs
+            // on the runtime while the worker hands off its other tasks
+            Ok(handle) if handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread => {
+                tokio::task::block_in_place(|| handle.block_on(self.lookup(host))).ok()
+            }
+            _ => {
+                let addrs: Vec<SocketAddr> = (host, 0).to_socket_addrs().ok()?.collect();
+                self.remember(host, &addrs);
+                Some(addrs)
+            }
+        }
+    }
+
+    // DNS resolver for the shared reqwest clients
+    pub fn resolver(&self, purpose: EgressPurpose) -> Arc<EgressResolver> {
+        Arc::new(EgressResolver { policy: self.clone(), purpose })
+    }
+
+    // Redirect policy for the shared reqwest clients: every hop is checked
+    // against the same rules as the original URL. Behind a proxy
+    // (`resolve_hops`) each hop's host is also resolved and checked here.
+    pub fn redirect_policy(&self, purpose: EgressPurpose, resolve_hops: bool) -> reqwest::redirect::Policy {
+        let policy = self.clone();
+        reqwest::redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                return attempt.error("too many redirects");
+            }
+            match attempt.url().scheme() {
+                "http" | "https" => {}
+                _ => return attempt.error(BlockedAddress("redirect to a non-http scheme".to_string())),
+            }
+            let url = attempt.url().clone();
+            match policy.evaluate(&url, purpose) {
+                Ok(()) if resolve_hops => match policy.check_lookup(&url, purpose) {
+                    Ok(()) => attempt.follow(),
+                    Err(reason) => attempt.error(BlockedAddress(format!("redirect to {} {}", url, reason))),
+                },
+                Ok(()) => attempt.follow(),
+                Err(reason) => {
+                    policy.record_violation(&url, purpose, &format!("redirect {}", reason));
+                    attempt.error(BlockedAddress(format!("redirect to {} {}", url, reason)))
+                }
+            }
+        })
+    }
+
+    // Fails every request of the tab (including redirects and subresources)
+    // that the policy blocks. The returned flag is set when a document
+    // navigation was blocked.
+    pub fn guard_tab(&self, tab: &Tab, purpose: EgressPurpose) -> Result<Arc<AtomicBool>, CommandError> {
+        let blocked_document = Arc::new(AtomicBool::new(false));
+        let policy = self.clone();
+        let flag = blocked_document.clone();
+
+        tab.enable_fetch(None, None)
+            .map_err(interception_error)?;
+        tab.enable_request_interception(Arc::new(move |_transport, _session_id, event: Fetch::events::RequestPausedEvent| {
+            let params = event.params;
+            let allowed = match Url::parse(&params.request.url) {
+                Ok(url) if matches!(url.scheme(), "http" | "https" | "ws" | "wss") => {
+                    policy.check_resolved(&url, purpose).is_ok()
+                }
+                // data:, blob: and similar never leave the browser
+                _ => true,
+            };
+
+            if allowed {
+                RequestPausedDecision::Continue(None)
+            } else {
+                if params.resource_Type == Network::ResourceType::Document {
+                    flag.store(true, Ordering::SeqCst);
+                }
+                RequestPausedDecision::Fail(Fetch::FailRequest {
+                    request_id: params.request_id,
+                    error_reason: Network::ErrorReason::BlockedByClient,
+                })
+            }
+        }))
+        .map_err(interception_error)?;
+
+        Ok(blocked_document)
+    }
+
+    fn evaluate(&self, url: &Url, purpose: EgressPurpose) -> Result<(), String> {
+        let class = classify(url);
+
+        // Metadata and link-local endpoints can never be allowed by a rule
+        if class == HostClass::Metadata {
+            return Err("cloud metadata and link-local addresses are not reachable".to_string());
+        }
+
+        let settings = self.state.settings.read().unwrap();
+
+        for rule in settings.rules.iter().filter(|r| r.action == RuleAction::Deny) {
+            if OriginPattern::parse(&rule.pattern).is_ok_and(|p| p.matches(url)) {
+                return Err(format!("denied by rule '{}'", rule.pattern));
+            }
+        }
+
+        for rule in settings.rules.iter().filter(|r| r.action == RuleAction::Allow) {
+            if OriginPattern::parse(&rule.pattern).is_ok_and(|p| p.matches(url)) {
+                return Ok(());
+            }
+        }
+
+        if class == HostClass::Loopback {
+            return match purpose {
+                EgressPurpose::Provider if settings.allow_local_providers => Ok(()),
+                EgressPurpose::Provider => Err("local provider endpoints are disabled".to_string()),
+                EgressPurpose::Web if settings.allow_intranet_scraping => Ok(()),
+                EgressPurpose::Web => Err("localhost is not reachable from web requests".to_string()),
+            };
+        }
+
+        if class == HostClass::Private && purpose == EgressPurpose::Web && !settings.allow_intranet_scraping {
+            return Err("private addresses are not reachable from web requests".to_string());
+        }
+
+        match settings.default_action {
+            RuleAction::Allow => Ok(()),
+            RuleAction::Deny => Err("origin is not on the allow list".to_string()),
+        }
+    }
+
+    fn record_violation(&self, url: &Url, purpose: EgressPurpose, reason: &str) {
+        eprintln!("[Egress] Blocked {:?} request to {}: {}", purpose, url, reason);
+        self.push_violation(url.to_string(), purpose, reason);
+    }
+
+    fn push_violation(&self, url: String, purpose: EgressPurpose, reason: &str) {
+        let mut violations = self.state.violations.lock().unwrap();
+        if violations.len() >= MAX_VIOLATIONS {
+            violations.pop_front();
+        }
+        violations.push_back(EgressViolation {
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            url,
+            purpose,
+            reason: reason.to_string(),
+        });
+    }
+}
+
+pub struct EgressResolver {
+    policy: EgressPolicy,
+    purpose: EgressPurpose,
+}
+
+impl reqwest::dns::Resolve for EgressResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let policy = self.policy.clone();
+        let purpose = self.purpose;
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let addrs = policy.lookup(&host).await?;
+            policy
+                .check_addrs(&host, &addrs, purpose)
+                .map_err(BlockedAddress)?;
+            let addrs: reqwest::dns::Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+fn blocked(url: &Url, reason: &str) -> CommandError {
+    CommandError::new(ErrorKind::Blocked, format!("{}: {}", BLOCKED_PREFIX, reason)).with_url(url.as_str())
+}
+
+fn interception_error(err: impl std::fmt::Display) -> CommandError {
+    CommandError::new(
+        ErrorKind::BrowserUnavailable,
+        format!("Failed to enable request interception: {err}"),
+    )
+}
+
+fn validate(settings: &EgressPolicySettings) -> Result<(), CommandError> {
+    for rule in &settings.rules {
+        OriginPattern::parse(&rule.pattern).map_err(|err| CommandError::new(ErrorKind::InvalidRequest, err))?;
+    }
+    Ok(())
+}
+
+// Reads the persisted policy; a missing or unreadable file means defaults
+pub fn load<R: tauri::Runtime>(app: &tauri::AppHandle<R>) -> EgressPolicySettings {
+    app_data::load(app, SETTINGS_FILE)
+}
+
+#[tauri::command]
+pub fn get_egress_policy(policy: tauri::State<'_, EgressPolicy>) -> EgressPolicySettings {
+    policy.settings()
+}
+
+// Invalid rules are rejected before anything is saved
+#[tauri::command]
+pub fn update_egress_policy(
+    settings: EgressPolicySettings,
+    app: tauri::AppHandle,
+    policy: tauri::State<'_, EgressPolicy>,
+) -> Result<(), CommandError> {
+    policy.update(settings.clone())?;
+    app_data::save(&app, SETTINGS_FILE, &settings)
+}
+
+#[tauri::command]
+pub fn get_egress_violations(policy: tauri::State<'_, EgressPolicy>) -> Vec<EgressViolation> {
+    policy.violations()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::http_client::{HttpClientSettings, HttpClients};
+    use crate::network::{NetworkSettings, ProxySettings};
+
+    #[test]
+    fn proxied_hosts_are_resolved_and_checked() {
+        let policy = EgressPolicy::new(EgressPolicySettings::default()).unwrap();
+        // A name, not an IP literal, that resolves to loopback
+        let url = Url::parse("http://localhost:8080/").unwrap();
+
+        let web = tauri::async_runtime::block_on(policy.check_proxied(&url, EgressPurpose::Web));
+        assert_eq!(web.unwrap_err().kind, ErrorKind::Blocked);
+        let provider = tauri::async_runtime::block_on(policy.check_proxied(&url, EgressPurpose::Provider));
+        assert!(provider.is_ok());
+    }
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    fn rule(pattern: &str, action: RuleAction) -> EgressRule {
+        EgressRule { pattern: pattern.to_string(), action }
+    }
+
+    fn policy_with(rules: Vec<EgressRule>) -> EgressPolicy {
+        EgressPolicy::new(EgressPolicySettings { rules, ..EgressPolicySettings::default() }).unwrap()
+    }
+
+    fn addr(ip: &str) -> SocketAddr {
+        SocketAddr::new(ip.parse().unwrap(), 443)
+    }
+
+    #[test]
+    fn patterns_match_scheme_and_default_ports() {
+        let pattern = OriginPattern::parse("https://api.openai.com").unwrap();
+        assert!(pattern.matches(&url("https://api.openai.com/v1/chat")));
+        assert!(pattern.matches(&url("https://API.OpenAI.com:443/")));
+        assert!(!pattern.matches(&url("http://api.openai.com/")));
+        assert!(!pattern.matches(&url("https://api.openai.com:8443/")));
+
+        let pattern = OriginPattern::parse("http://192.168.1.20:*").unwrap();
+        assert!(pattern.matches(&url("http://192.168.1.20:11434/")));
+        assert!(pattern.matches(&url("http://192.168.1.20/")));
+        assert!(!pattern.matches(&url("https://192.168.1.20/")));
+
+        // Without a scheme there's no default port to compare against
+        let pattern = OriginPattern::parse("example.com").unwrap();
+        assert!(pattern.matches(&url("http://example.com:8080/")));
+        assert!(pattern.matches(&url("https://example.com/")));
+        let pattern = OriginPattern::parse("*://example.com:8080").unwrap();
+        assert!(pattern.matches(&url("http://example.com:8080/")));
+        assert!(!pattern.matches(&url("http://example.com/")));
+    }
+
+    #[test]
+    fn patterns_match_subdomains_and_ipv6() {
+        let pattern = OriginPattern::parse("*.example.com").unwrap();
+        assert!(pattern.matches(&url("https://api.example.com/")));
+        assert!(pattern.matches(&url("https://a.b.example.com/")));
+        assert!(!pattern.matches(&url("https://example.com/")));
+        assert!(!pattern.matches(&url("https://badexample.com/")));
+
+        let pattern = OriginPattern::parse("http://[::1]:11434").unwrap();
+        assert!(pattern.matches(&url("http://[::1]:11434/api/tags")));
+        assert!(!pattern.matches(&url("http://[::1]:8080/")));
+        let pattern = OriginPattern::parse("[fd00::1]").unwrap();
+        assert!(pattern.matches(&url("https://[fd00::1]/")));
+
+        assert!(OriginPattern::parse("*").unwrap().matches(&url("https://anything.test/")));
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected() {
+        assert!(OriginPattern::parse("").is_err());
+        assert!(OriginPattern::parse("https://").is_err());
+        assert!(OriginPattern::parse("[::1").is_err());
+        assert!(OriginPattern::parse("example.com:http").is_err());
+        let settings = EgressPolicySettings {
+            rules: vec![rule("example.com:99999", RuleAction::Allow)],
+            ..EgressPolicySettings::default()
+        };
+        assert_eq!(EgressPolicy::new(settings).err().unwrap().kind, ErrorKind::InvalidRequest);
+    }
+
+    #[test]
+    fn deny_rules_win_over_allow_rules() {
+        let policy = policy_with(vec![
+            rule("*.example.com", RuleAction::Allow),
+            rule("https://tracker.example.com", RuleAction::Deny),
+        ]);
+        assert!(policy.check("https://api.example.com/", EgressPurpose::Web).is_ok());
+        let err = policy.check("https://tracker.example.com/", EgressPurpose::Web).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Blocked);
+        assert!(err.message.contains("denied by rule"));
+        assert_eq!(policy.violations().len(), 1);
+
+        let mut settings = policy.settings();
+        settings.default_action = RuleAction::Deny;
+        policy.update(settings).unwrap();
+        assert!(policy.check("https://api.example.com/", EgressPurpose::Web).is_ok());
+        assert!(policy.check("https://other.test/", EgressPurpose::Web).is_err());
+    }
+
+    #[test]
+    fn metadata_stays_blocked_whatever_the_rules_say() {
+        let mut settings = EgressPolicySettings {
+            rules: vec![
+                rule("*", RuleAction::Allow),
+                rule("http://169.254.169.254", RuleAction::Allow),
+                rule("100.100.100.200", RuleAction::Allow),
+            ],
+            ..EgressPolicySettings::default()
+        };
+        settings.allow_intranet_scraping = true;
+        let policy = EgressPolicy::new(settings).unwrap();
+
+        for target in [
+            "http://169.254.169.254/latest/meta-data/",
+            "http://100.100.100.200/latest/meta-data/",
+            "http://[fe80::1]/",
+            "http://[fd00:ec2::254]/",
+            "http://metadata.google.internal/computeMetadata/v1/",
+        ] {
+            for purpose in [EgressPurpose::Provider, EgressPurpose::Web] {
+                assert_eq!(policy.check(target, purpose).unwrap_err().kind, ErrorKind::Blocked, "{target}");
+            }
+        }
+        for ip in ["169.254.169.254", "100.100.100.200", "fe80::1"] {
+            assert!(policy.check_ip(&ip.parse().unwrap(), EgressPurpose::Provider).is_err(), "{ip}");
+        }
+    }
+
+    #[test]
+    fn mapped_ipv6_addresses_are_classified_as_ipv4() {
+        assert_eq!(classify_ip(&"::ffff:127.0.0.1".parse().unwrap()), HostClass::Loopback);
+        assert_eq!(classify_ip(&"::ffff:10.1.2.3".parse().unwrap()), HostClass::Private);
+        assert_eq!(classify_ip(&"::ffff:169.254.169.254".parse().unwrap()), HostClass::Metadata);
+        assert_eq!(classify_ip(&"::ffff:100.100.100.200".parse().unwrap()), HostClass::Metadata);
+        assert_eq!(classify_ip(&"::ffff:8.8.8.8".parse().unwrap()), HostClass::Other);
+
+        let policy = policy_with(vec![rule("*", RuleAction::Allow)]);
+        assert!(policy.check("http://[::ffff:169.254.169.254]/", EgressPurpose::Provider).is_err());
+        assert!(policy.check("http://[::ffff:127.0.0.1]:8080/", EgressPurpose::Web).is_ok());
+        assert!(policy.check_ip(&"::ffff:127.0.0.1".parse().unwrap(), EgressPurpose::Web).is_err());
+    }
+
+    #[test]
+    fn any_blocked_address_rejects_the_host() {
+        let policy = policy_with(Vec::new());
+        let mixed = [addr("93.184.216.34"), addr("10.0.0.5")];
+        assert!(policy.check_addrs("mixed.test", &mixed[..1], EgressPurpose::Web).is_ok());
+        let reason = policy.check_addrs("mixed.test", &mixed, EgressPurpose::Web).unwrap_err();
+        assert!(reason.starts_with("mixed.test resolves to 10.0.0.5"), "{reason}");
+        assert_eq!(policy.violations()[0].url, "mixed.test");
+
+        // Providers may use private addresses, never metadata ones
+        assert!(policy.check_addrs("mixed.test", &mixed, EgressPurpose::Provider).is_ok());
+        let metadata = [addr("93.184.216.34"), addr("169.254.169.254")];
+        assert!(policy.check_addrs("mixed.test", &metadata, EgressPurpose::Provider).is_err());
+    }
+
+    #[test]
+    fn resolved_loopback_follows_the_local_provider_switch() {
+        let policy = policy_with(Vec::new());
+        let loopback = [addr("127.0.0.1"), addr("::1")];
+        assert!(policy.check_addrs("llm.lan", &loopback, EgressPurpose::Provider).is_ok());
+        assert!(policy.check_addrs("llm.lan", &loopback, EgressPurpose::Web).is_err());
+
+        let mut settings = policy.settings();
+        settings.allow_local_providers = false;
+        policy.update(settings).unwrap();
+        assert!(policy.check("http://localhost:11434/", EgressPurpose::Provider).is_err());
+        let reason = policy.check_addrs("llm.lan", &loopback, EgressPurpose::Provider).unwrap_err();
+        assert!(reason.contains("local provider endpoints are disabled"), "{reason}");
+        assert!(policy.check_addrs("llm.lan", &[addr("10.0.0.5")], EgressPurpose::Provider).is_ok());
+    }
+
+    // Answers one connection with `response` and returns the request head
+    fn serve_once(response: &'static str) -> (u16, std::thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut head = Vec::new();
+            let mut buffer = [0u8; 1024];
+            while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+                let read = stream.read(&mut buffer).unwrap();
+                if read == 0 {
+                    break;
+                }
+                head.extend_from_slice(&buffer[..read]);
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+            String::from_utf8_lossy(&head).to_lowercase()
+        });
+        (port, server)
+    }
+
+    const OK: &str = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok";
+
+    async fn get(clients: &HttpClients, target: &str, purpose: EgressPurpose) -> Result<String, CommandError> {
+        let target = url(target);
+        let profile = match purpose {
+            EgressPurpose::Provider => clients.provider_for(&target).await?,
+            EgressPurpose::Web => clients.scraping_for(&target).await?,
+        };
+        let response = profile.client.get(target).send().await?;
+        Ok(response.text().await?)
+    }
+
+    #[test]
+    fn web_requests_reach_private_addresses_only_with_the_opt_in() {
+        let policy = policy_with(Vec::new());
+        for target in ["http://127.0.0.1:8080/", "http://192.168.1.20/", "http://[fd00::1]/", "http://localhost/"] {
+            assert_eq!(policy.check(target, EgressPurpose::Web).unwrap_err().kind, ErrorKind::Blocked, "{target}");
+            assert!(policy.check(target, EgressPurpose::Provider).is_ok(), "{target}");
+        }
+
+        // A name skips the URL check; the clients' resolver catches it
+        let clients =
+            HttpClients::new(HttpClientSettings::default(), NetworkSettings::default(), policy.clone()).unwrap();
+        let (port, server) = serve_once(OK);
+        let target = format!("http://localhost:{port}/");
+        let err = tauri::async_runtime::block_on(get(&clients, &target, EgressPurpose::Web)).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Blocked);
+
+        let mut settings = policy.settings();
+        settings.allow_intranet_scraping = true;
+        policy.update(settings).unwrap();
+        for target in ["http://127.0.0.1:8080/", "http://192.168.1.20/", "http://localhost/"] {
+            assert!(policy.check(target, EgressPurpose::Web).is_ok(), "{target}");
+        }
+        let body = tauri::async_runtime::block_on(get(&clients, &target, EgressPurpose::Web)).unwrap();
+        assert_eq!(body, "ok");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn redirects_to_blocked_addresses_are_rejected() {
+        let policy = policy_with(Vec::new());
+        let clients =
+            HttpClients::new(HttpClientSettings::default(), NetworkSettings::default(), policy.clone()).unwrap();
+        let (port, server) = serve_once(
+            "HTTP/1.1 302 Found\r\nLocation: http://169.254.169.254/latest/meta-data/\r\nContent-Length: 0\r\n\r\n",
+        );
+
+        let target = format!("http://127.0.0.1:{port}/");
+        let err = tauri::async_runtime::block_on(get(&clients, &target, EgressPurpose::Provider)).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Blocked);
+        assert!(err.message.contains("redirect"), "{}", err.message);
+        assert!(policy.violations().iter().any(|violation| violation.url.contains("169.254.169.254")));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn proxied_redirect_hops_are_resolved_and_checked() {
+        let policy = policy_with(Vec::new());
+        // Stand-ins for DNS answers so the test doesn't depend on a resolver
+        policy.remember("public.test", &[addr("93.184.216.34")]);
+        policy.remember("intranet.test", &[addr("10.0.0.5")]);
+
+        let (port, proxy) =
+            serve_once("HTTP/1.1 302 Found\r\nLocation: http://intranet.test/\r\nContent-Length: 0\r\n\r\n");
+        let network = NetworkSettings {
+            proxy: Some(ProxySettings {
+                url: format!("http://127.0.0.1:{port}"),
+                username: None,
+                password: None,
+                has_password: false,
+            }),
+            ..NetworkSettings::default()
+        };
+        let clients = HttpClients::new(HttpClientSettings::default(), network, policy.clone()).unwrap();
+
+        let err = tauri::async_runtime::block_on(get(&clients, "http://public.test/", EgressPurpose::Web)).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Blocked);
+        assert!(err.message.contains("intranet.test resolves to 10.0.0.5"), "{}", err.message);
+        assert!(proxy.join().unwrap().starts_with("get http://public.test/"));
+    }
+
+    #[test]
+    fn lookups_are_cached_per_host() {
+        let policy = policy_with(Vec::new());
+        policy.remember("Cached.Test", &[addr("10.0.0.5")]);
+        assert_eq!(policy.lookup_blocking("cached.test"), Some(vec![addr("10.0.0.5")]));
+        let reason = policy.check_lookup(&url("https://cached.test/page"), EgressPurpose::Web).unwrap_err();
+        assert!(reason.contains("10.0.0.5"));
+        assert!(policy.check_lookup(&url("https://cached.test/page"), EgressPurpose::Provider).is_ok());
+        assert!(policy.check_resolved(&url("https://cached.test/"), EgressPurpose::Web).is_err());
+    }
+}
@@ -0,0 +1,130 @@
+// Serializable error returned by the networked commands. The frontend keys
+// off `kind` instead of matching on message text.
+use std::fmt;
+
+use crate::egress::BlockedAddress;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    Network,
+    Timeout,
+    HttpStatus,
+    InvalidUrl,
+    Blocked,
+    BrowserUnavailable,
+    Parse,
+    Cancelled,
+    // The command was called with arguments it can't act on (e.g. an unknown method)
+    InvalidRequest,
+    // Failures inside the backend itself, such as a crashed worker task
+    Internal,
+    // The secrets vault must be unlocked first, or the passphrase was wrong
+    Locked,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandError {
+    pub kind: ErrorKind,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    pub retryable: bool,
+}
+
+impl CommandError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            status: None,
+            url: None,
+            retryable: matches!(kind, ErrorKind::Network | ErrorKind::Timeout),
+        }
+    }
+
+    pub fn with_url(mut self, url: impl Into<String>) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+
+    pub fn cancelled() -> Self {
+        Self::new(ErrorKind::Cancelled, "Request cancelled")
+    }
+
+    pub fn http_status(status: reqwest::StatusCode) -> Self {
+        let code = status.as_u16();
+        Self {
+            kind: ErrorKind::HttpStatus,
+            message: format!("Request failed with status {}", status),
+            status: Some(code),
+            url: None,
+            retryable: code == 408 || code == 425 || code == 429 || status.is_server_error(),
+        }
+    }
+
+    // Prefixes the message with what the command was doing
+    pub fn context(mut self, context: &str) -> Self {
+        self.message = format!("{}: {}", context, self.message);
+        self
+    }
+
+    // Classifies a reqwest error; policy blocks raised by the shared clients'
+    // resolver and redirect policy are found in its source chain
+    pub fn from_reqwest(err: reqwest::Error, context: &str) -> Self {
+        let url = err.url().map(|u| u.to_string());
+
+        let mut source = std::error::Error::source(&err);
+        while let Some(inner) = source {
+            if let Some(blocked) = inner.downcast_ref::<BlockedAddress>() {
+                return Self { url, ..Self::new(ErrorKind::Blocked, blocked.to_string()) };
+            }
+            source = inner.source();
+        }
+
+        let kind = if err.is_timeout() {
+            ErrorKind::Timeout
+        } else if err.is_builder() {
+            ErrorKind::InvalidUrl
+        } else if err.is_decode() {
+            ErrorKind::Parse
+        } else {
+            ErrorKind::Network
+        };
+
+        Self {
+            status: err.status().map(|s| s.as_u16()),
+            url,
+            ..Self::new(kind, format!("{context}: {err}"))
+        }
+    }
+}
+
+impl From<reqwest::Error> for CommandError {
+    fn from(err: reqwest::Error) -> Self {
+        Self::from_reqwest(err, "Request failed")
+    }
+}
+
+impl From<tauri::Error> for CommandError {
+    fn from(err: tauri::Error) -> Self {
+        Self::new(ErrorKind::Internal, err.to_string())
+    }
+}
+
+impl From<tokio::task::JoinError> for CommandError {
+    fn from(err: tokio::task::JoinError) -> Self {
+        Self::new(ErrorKind::Internal, format!("Task error: {err}"))
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for CommandError {}
@@ -0,0 +1,1197 @@
+// Converts JSON Schemas into GBNF, the grammar format llama.cpp uses to
+// constrain sampling. Small local models often produce malformed JSON for
+// tool calls and structured output; with a grammar they can only produce
+// text the schema accepts. Covers objects (required properties first, then
+// optional ones, in schema order), arrays, enums and const values, strings
+// with length limits, patterns and common formats, integer and number
+// bounds, anyOf/oneOf/allOf and local $refs. Keywords it doesn't know are
+// ignored.
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use serde_json::{json, Map, Value};
+
+use crate::chat::ChatRequest;
+use crate::error::{CommandError, ErrorKind};
+
+// Rules shared by all grammars, added when first used: name, body and the
+// rules the body refers to. Whitespace is limited so a model can't stall
+// in an endless run of spaces.
+const PRIMITIVES: &[(&str, &str, &[&str])] = &[
+    ("ws", r#"| " " | "\n" [ \t]{0,20}"#, &[]),
+    ("char", r#"[^"\\\x7F\x00-\x1F] | "\\" (["\\/bfnrt] | "u" [0-9a-fA-F]{4})"#, &[]),
+    ("string", r#""\"" char* "\"""#, &["char"]),
+    ("integer", r#""-"? ("0" | [1-9] [0-9]{0,15})"#, &[]),
+    ("number", r#""-"? ("0" | [1-9] [0-9]{0,15}) ("." [0-9]+)? ([eE] [-+]? [0-9]+)?"#, &[]),
+    ("boolean", r#""true" | "false""#, &[]),
+    ("null", r#""null""#, &[]),
+    ("value", "object | array | string | number | boolean | null", &["object", "array", "string", "number", "boolean", "null"]),
+    ("object", r#""{" ws (string ws ":" ws value ws ("," ws string ws ":" ws value ws)*)? "}""#, &["ws", "string", "value"]),
+    ("array", r#""[" ws (value ws ("," ws value ws)*)? "]""#, &["ws", "value"]),
+];
+
+const QUOTE: &str = r#""\"""#;
+const DATE: &str = r#"[0-9]{4} "-" ("0" [1-9] | "1" [0-2]) "-" ("0" [1-9] | [12] [0-9] | "3" [01])"#;
+const TIME: &str = r#"([01] [0-9] | "2" [0-3]) ":" [0-5] [0-9] ":" [0-5] [0-9] ("." [0-9]{1,6})? ("Z" | [+-] ([01] [0-9] | "2" [0-3]) ":" [0-5] [0-9])"#;
+const UUID: &str = r#"[0-9a-fA-F]{8} "-" [0-9a-fA-F]{4} "-" [0-9a-fA-F]{4} "-" [0-9a-fA-F]{4} "-" [0-9a-fA-F]{12}"#;
+// Any character a JSON string can hold unescaped
+const JSON_CHAR_EXCLUDED: &str = r"\x22\x5C\x7F\x00-\x1F";
+
+fn unsupported(message: impl std::fmt::Display) -> CommandError {
+    CommandError::new(ErrorKind::InvalidRequest, format!("Unsupported JSON schema: {}", message))
+}
+
+// GBNF rule names only allow letters, digits and dashes
+fn rule_name(name: &str) -> String {
+    name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '-' }).collect()
+}
+
+fn is_rule_name(expr: &str) -> bool {
+    !expr.is_empty() && expr.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+// A GBNF string literal matching `text` exactly
+fn literal(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\x{:02X}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// Matches `value` serialized as JSON
+fn json_literal(value: &Value) -> String {
+    literal(&value.to_string())
+}
+
+// Matches `c` the way it's written inside a JSON string
+fn json_char(c: char) -> String {
+    let encoded = Value::String(c.to_string()).to_string();
+    literal(&encoded[1..encoded.len() - 1])
+}
+
+fn repeat(expr: &str, min: u64, max: Option<u64>) -> String {
+    match (min, max) {
+        (_, Some(0)) => String::new(),
+        (0, None) => format!("{}*", expr),
+        (1, None) => format!("{}+", expr),
+        (0, Some(1)) => format!("{}?", expr),
+        (1, Some(1)) => expr.to_string(),
+        (min, None) => format!("{}{{{},}}", expr, min),
+        (min, Some(max)) if min == max => format!("{}{{{}}}", expr, min),
+        (min, Some(max)) => format!("{}{{{},{}}}", expr, min, max),
+    }
+}
+
+fn alternatives(mut parts: Vec<String>) -> String {
+    if parts.len() == 1 {
+        parts.remove(0)
+    } else {
+        format!("({})", parts.join(" | "))
+    }
+}
+
+fn digit_class(from: u8, to: u8) -> String {
+    if from == to {
+        format!("\"{}\"", from as char)
+    } else {
+        format!("[{}-{}]", from as char, to as char)
+    }
+}
+
+// Decimal numbers from `a` to `b`, both with the same number of digits
+fn same_length(a: &[u8], b: &[u8]) -> String {
+    if a.len() <= 1 {
+        return a.first().zip(b.first()).map(|(&a, &b)| digit_class(a, b)).unwrap_or_default();
+    }
+    if a[0] == b[0] {
+        return format!("\"{}\" {}", a[0] as char, same_length(&a[1..], &b[1..]));
+    }
+
+    // Split into the lowest leading digit, full runs of the digits between
+    // and the highest leading digit
+    let rest = a.len() - 1;
+    let from_zero = a[1..].iter().all(|&d| d == b'0');
+    let to_nine = b[1..].iter().all(|&d| d == b'9');
+    let mut parts = Vec::new();
+    if !from_zero {
+        parts.push(format!("\"{}\" {}", a[0] as char, same_length(&a[1..], &vec![b'9'; rest])));
+    }
+    let (low, high) = (if from_zero { a[0] } else { a[0] + 1 }, if to_nine { b[0] } else { b[0] - 1 });
+    if low <= high {
+        parts.push(format!("{} {}", digit_class(low, high), repeat("[0-9]", rest as u64, Some(rest as u64))));
+    }
+    if !to_nine {
+        parts.push(format!("\"{}\" {}", b[0] as char, same_length(&vec![b'0'; rest], &b[1..])));
+    }
+    alternatives(parts)
+}
+
+fn digits(n: u64) -> u32 {
+    n.checked_ilog10().unwrap_or(0) + 1
+}
+
+// Unsigned integers from `min` to `max`, without leading zeros
+fn uint_range(min: u64, max: Option<u64>) -> Option<String> {
+    if max.is_some_and(|max| max < min) {
+        return None;
+    }
+    let min_len = digits(min);
+    let max_len = max.map_or(min_len, digits);
+    let mut parts = Vec::new();
+    for len in min_len..=max_len {
+        let from = if len == min_len { min } else { 10u64.pow(len - 1) };
+        let to = match max {
+            Some(max) if len == max_len => max,
+            _ => 10u64.checked_pow(len).map_or(u64::MAX, |n| n - 1),
+        };
+        parts.push(same_length(from.to_string().as_bytes(), to.to_string().as_bytes()));
+    }
+    // Without an upper bound, anything longer goes
+    if max.is_none() && min_len < 16 {
+        parts.push(format!("[1-9] [0-9]{{{},15}}", min_len));
+    }
+    Some(alternatives(parts))
+}
+
+// Signed integers from `min` to `max`, either of which may be open
+fn int_range(min: Option<i64>, max: Option<i64>) -> Option<String> {
+    let mut parts = Vec::new();
+    if max.is_none_or(|max| max >= 0) {
+        parts.extend(uint_range(min.map_or(0, |min| min.max(0) as u64), max.map(|max| max as u64)));
+    }
+    if min.is_none_or(|min| min < 0) {
+        let negative = uint_range(max.map_or(1, |max| max.min(-1).unsigned_abs()), min.map(i64::unsigned_abs));
+        parts.extend(negative.map(|range| format!("\"-\" {}", range)));
+    }
+    (!parts.is_empty()).then(|| parts.join(" | "))
+}
+
+// A lower or upper bound and whether it's exclusive. Draft 4 schemas mark
+// exclusive bounds with a boolean instead.
+fn bound(schema: &Map<String, Value>, inclusive: &str, exclusive: &str) -> Option<(f64, bool)> {
+    match schema.get(exclusive) {
+        Some(Value::Number(n)) => n.as_f64().map(|v| (v, true)),
+        Some(Value::Bool(true)) => schema.get(inclusive)?.as_f64().map(|v| (v, true)),
+        _ => schema.get(inclusive)?.as_f64().map(|v| (v, false)),
+    }
+}
+
+fn integer_bounds(schema: &Map<String, Value>) -> (Option<i64>, Option<i64>) {
+    let min = bound(schema, "minimum", "exclusiveMinimum")
+        .map(|(v, exclusive)| (if exclusive { v.floor() + 1.0 } else { v.ceil() }) as i64);
+    let max = bound(schema, "maximum", "exclusiveMaximum")
+        .map(|(v, exclusive)| (if exclusive { v.ceil() - 1.0 } else { v.floor() }) as i64);
+    (min, max)
+}
+
+// Numbers with a fraction whose integer part runs from `low` to `high`.
+// When `low` is an exclusive bound, `low.0` equals it and is left out.
+fn fractions(sign: &str, low: u64, open: bool, high: Option<u64>) -> Vec<String> {
+    if high.is_some_and(|high| high < low) {
+        return Vec::new();
+    }
+    let mut parts = Vec::new();
+    let mut low = low;
+    if open {
+        parts.push(format!("{}\"{}\" \".\" [0-9]* [1-9]", sign, low));
+        low += 1;
+    }
+    parts.extend(uint_range(low, high).map(|range| format!("{}{} \".\" [0-9]+", sign, range)));
+    parts
+}
+
+fn class_char(c: char) -> String {
+    if c.is_ascii_alphanumeric() || !c.is_ascii() {
+        c.to_string()
+    } else {
+        format!("\\x{:02X}", c as u32)
+    }
+}
+
+fn class_range((from, to): (char, char)) -> String {
+    if from == to {
+        class_char(from)
+    } else {
+        format!("{}-{}", class_char(from), class_char(to))
+    }
+}
+
+// Any JSON string character outside `ranges`, escaped quotes and
+// backslashes included
+fn negated_class(ranges: &[(char, char)]) -> String {
+    let items: String = ranges.iter().copied().map(class_range).collect();
+    let mut parts = vec![format!("[^{}{}]", items, JSON_CHAR_EXCLUDED)];
+    for special in ['"', '\\'] {
+        if !ranges.iter().any(|&(from, to)| from <= special && special <= to) {
+            parts.push(json_char(special));
+        }
+    }
+    alternatives(parts)
+}
+
+// Translates a regular expression into a GBNF expression over the JSON
+// text of a string: literals, escapes, classes, groups, alternation and
+// quantifiers. Backreferences and lookaround can't be expressed.
+struct PatternParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl PatternParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+
+    fn alternation(&mut self) -> Result<String, CommandError> {
+        let mut parts = vec![self.sequence()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            parts.push(self.sequence()?);
+        }
+        Ok(parts.join(" | "))
+    }
+
+    fn sequence(&mut self) -> Result<String, CommandError> {
+        let mut items: Vec<String> = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            let quantifier = self.peek();
+            let quantified = match quantifier {
+                Some(q @ ('*' | '+' | '?')) => {
+                    self.pos += 1;
+                    format!("{}{}", atom, q)
+                }
+                Some('{') => {
+                    self.pos += 1;
+                    let mut spec = String::new();
+                    while let Some(c) = self.next() {
+                        if c == '}' {
+                            break;
+                        }
+                        spec.push(c);
+                    }
+                    let (min, max) = spec.split_once(',').unwrap_or((&spec, &spec));
+                    let min: u64 = min.trim().parse().map_err(|_| unsupported(format!("quantifier {{{}}}", spec)))?;
+                    let max = match max.trim() {
+                        "" => None,
+                        max => Some(max.parse().map_err(|_| unsupported(format!("quantifier {{{}}}", spec)))?),
+                    };
+                    repeat(&atom, min, max)
+                }
+                _ => atom,
+            };
+            // Lazy and greedy match the same strings
+            if matches!(quantifier, Some('*' | '+' | '?' | '{')) && self.peek() == Some('?') {
+                self.pos += 1;
+            }
+            items.push(quantified);
+        }
+        Ok(items.join(" "))
+    }
+
+    fn atom(&mut self) -> Result<String, CommandError> {
+        match self.next() {
+            Some('(') => {
+                if self.peek() == Some('?') {
+                    self.pos += 1;
+                    match self.next() {
+                        Some(':') => {}
+                        Some('<') if !matches!(self.peek(), Some('=' | '!')) => {
+                            while self.next().is_some_and(|c| c != '>') {}
+                        }
+                        _ => return Err(unsupported("lookaround in pattern")),
+                    }
+                }
+                let inner = self.alternation()?;
+                if self.next() != Some(')') {
+                    return Err(unsupported("unbalanced parentheses in pattern"));
+                }
+                Ok(format!("({})", inner))
+            }
+            Some('[') => self.class(),
+            Some('.') => Ok("char".to_string()),
+            Some('\\') => self.escape(),
+            Some(c @ ('^' | '$')) => Err(unsupported(format!("`{}` inside pattern", c))),
+            Some(c @ ('*' | '+' | '?' | '{')) => Err(unsupported(format!("nothing to repeat before `{}`", c))),
+            Some(c) => Ok(json_char(c)),
+            None => Err(unsupported("pattern ends early")),
+        }
+    }
+
+    fn escape(&mut self) -> Result<String, CommandError> {
+        let expr = match self.next().ok_or_else(|| unsupported("pattern ends with `\\`"))? {
+            'd' => "[0-9]".to_string(),
+            'w' => "[0-9A-Za-z_]".to_string(),
+            's' => "\" \"".to_string(),
+            'D' => negated_class(&[('0', '9')]),
+            'W' => negated_class(&[('0', '9'), ('A', 'Z'), ('a', 'z'), ('_', '_')]),
+            'S' => negated_class(&[(' ', ' ')]),
+            'n' => json_char('\n'),
+            't' => json_char('\t'),
+            'r' => json_char('\r'),
+            c if c.is_ascii_alphanumeric() => return Err(unsupported(format!("`\\{}` in pattern", c))),
+            c => json_char(c),
+        };
+        Ok(expr)
+    }
+
+    // One character of a class, or the ranges of an escape such as \d
+    fn class_item(&mut self) -> Result<Vec<(char, char)>, CommandError> {
+        let c = self.next().ok_or_else(|| unsupported("unterminated class in pattern"))?;
+        if c != '\\' {
+            return Ok(vec![(c, c)]);
+        }
+        let ranges = match self.next().ok_or_else(|| unsupported("unterminated class in pattern"))? {
+            'd' => vec![('0', '9')],
+            'w' => vec![('0', '9'), ('A', 'Z'), ('a', 'z'), ('_', '_')],
+            's' => vec![(' ', ' ')],
+            'n' => vec![('\n', '\n')],
+            't' => vec![('\t', '\t')],
+            'r' => vec![('\r', '\r')],
+            c if c.is_ascii_alphanumeric() => return Err(unsupported(format!("`\\{}` in pattern class", c))),
+            c => vec![(c, c)],
+        };
+        Ok(ranges)
+    }
+
+    fn class(&mut self) -> Result<String, CommandError> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+        let mut ranges = Vec::new();
+        let mut first = true;
+        while first || self.peek() != Some(']') {
+            first = false;
+            let mut item = self.class_item()?;
+            if item.len() == 1 && self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&c| c != ']') {
+                self.pos += 1;
+                let end = self.class_item()?;
+                item[0].1 = end[0].1;
+            }
+            ranges.extend(item);
+        }
+        self.pos += 1;
+
+        if negated {
+            return Ok(negated_class(&ranges));
+        }
+
+        // Quotes and backslashes are escaped in JSON, and control characters
+        // can't appear unescaped at all
+        let mut parts = Vec::new();
+        let mut items = String::new();
+        for (from, to) in ranges {
+            let mut start = from.max(' ');
+            for special in ['"', '\\'] {
+                if start <= special && special <= to {
+                    if start < special {
+                        items.push_str(&class_range((start, char::from_u32(special as u32 - 1).unwrap_or(start))));
+                    }
+                    parts.push(json_char(special));
+                    start = char::from_u32(special as u32 + 1).unwrap_or(special);
+                }
+            }
+            if start <= to {
+                items.push_str(&class_range((start, to)));
+            }
+        }
+        if !items.is_empty() {
+            parts.insert(0, format!("[{}]", items));
+        }
+        if parts.is_empty() {
+            return Err(unsupported("empty class in pattern"));
+        }
+        Ok(alternatives(parts))
+    }
+}
+
+struct Converter<'a> {
+    // Schema that local $refs point into
+    root: &'a Value,
+    rules: Vec<(String, String)>,
+    // $ref -> rule name, so recursive schemas terminate
+    refs: HashMap<String, String>,
+}
+
+impl<'a> Converter<'a> {
+    fn new(root: &'a Value) -> Self {
+        Self { root, rules: Vec::new(), refs: HashMap::new() }
+    }
+
+    fn primitive(&mut self, name: &'static str) -> String {
+        if !self.rules.iter().any(|(rule, _)| rule == name) {
+            let (_, body, uses) = PRIMITIVES.iter().find(|(rule, _, _)| *rule == name).expect("known primitive");
+            self.rules.push((name.to_string(), body.to_string()));
+            for used in *uses {
+                self.primitive(used);
+            }
+        }
+        name.to_string()
+    }
+
+    // Adds a rule, reusing one with the same name and body; returns its name
+    fn add(&mut self, name: &str, body: String) -> String {
+        let base = rule_name(name);
+        let mut candidate = base.clone();
+        let mut n = 1;
+        loop {
+            match self.rules.iter().find(|(rule, _)| *rule == candidate) {
+                None => {
+                    self.rules.push((candidate.clone(), body));
+                    return candidate;
+                }
+                Some((_, existing)) if *existing == body => return candidate,
+                Some(_) => {
+                    candidate = format!("{}{}", base, n);
+                    n += 1;
+                }
+            }
+        }
+    }
+
+    // A rule for `schema`, or the name of an existing one
+    fn visit(&mut self, schema: &Value, name: &str) -> Result<String, CommandError> {
+        let expr = self.expression(schema, name)?;
+        Ok(if is_rule_name(&expr) { expr } else { self.add(name, expr) })
+    }
+
+    fn expression(&mut self, schema: &Value, name: &str) -> Result<String, CommandError> {
+        let schema = match schema {
+            Value::Object(schema) => schema,
+            Value::Bool(true) => return Ok(self.primitive("value")),
+            _ => return Err(unsupported("a `false` schema accepts nothing")),
+        };
+
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            return self.reference(reference);
+        }
+        if let Some(options) = schema.get("anyOf").or_else(|| schema.get("oneOf")).and_then(Value::as_array) {
+            let options = options
+                .iter()
+                .enumerate()
+                .map(|(i, option)| self.visit(option, &format!("{}-{}", name, i)))
+                .collect::<Result<Vec<_>, _>>()?;
+            return Ok(options.join(" | "));
+        }
+        if let Some(parts) = schema.get("allOf").and_then(Value::as_array) {
+            let merged = self.merge(schema, parts)?;
+            return self.expression(&merged, name);
+        }
+        if let Some(value) = schema.get("const") {
+            return Ok(json_literal(value));
+        }
+        if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+            return Ok(values.iter().map(json_literal).collect::<Vec<_>>().join(" | "));
+        }
+
+        match schema.get("type") {
+            Some(Value::Array(types)) => {
+                let options = types
+                    .iter()
+                    .enumerate()
+                    .map(|(i, kind)| {
+                        let mut option = schema.clone();
+                        option.insert("type".into(), kind.clone());
+                        self.visit(&Value::Object(option), &format!("{}-{}", name, i))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(options.join(" | "))
+            }
+            Some(Value::String(kind)) => match kind.as_str() {
+                "object" => self.object(schema, name),
+                "array" => self.array(schema, name),
+                "string" => self.string(schema),
+                "integer" => self.integer(schema),
+                "number" => self.number(schema),
+                "boolean" => Ok(self.primitive("boolean")),
+                "null" => Ok(self.primitive("null")),
+                other => Err(unsupported(format!("type \"{}\"", other))),
+            },
+            _ if schema.contains_key("properties") => self.object(schema, name),
+            _ if schema.contains_key("items") => self.array(schema, name),
+            _ => Ok(self.primitive("value")),
+        }
+    }
+
+    fn reference(&mut self, reference: &str) -> Result<String, CommandError> {
+        if let Some(rule) = self.refs.get(reference) {
+            return Ok(rule.clone());
+        }
+        let root = self.root;
+        let target = reference
+            .strip_prefix('#')
+            .and_then(|pointer| root.pointer(pointer))
+            .ok_or_else(|| unsupported(format!("$ref \"{}\"", reference)))?;
+
+        // Reserve the name before visiting, for schemas that refer to themselves
+        let mut rule = rule_name(&format!("def-{}", reference.rsplit('/').next().unwrap_or_default()));
+        while self.rules.iter().any(|(name, _)| *name == rule) {
+            rule.push('x');
+        }
+        self.rules.push((rule.clone(), String::new()));
+        self.refs.insert(reference.to_string(), rule.clone());
+
+        let body = self.expression(target, &rule)?;
+        if let Some(entry) = self.rules.iter_mut().find(|(name, _)| *name == rule) {
+            entry.1 = body;
+        }
+        Ok(rule)
+    }
+
+    // allOf of object schemas: their properties and required lists combined
+    fn merge(&self, schema: &Map<String, Value>, parts: &[Value]) -> Result<Value, CommandError> {
+        let mut merged = schema.clone();
+        merged.remove("allOf");
+        let mut properties = Map::new();
+        let mut required = Vec::new();
+        for part in std::iter::once(&Value::Object(schema.clone())).chain(parts) {
+            let part = match part.get("$ref").and_then(Value::as_str) {
+                Some(reference) => reference
+                    .strip_prefix('#')
+                    .and_then(|pointer| self.root.pointer(pointer))
+                    .ok_or_else(|| unsupported(format!("$ref \"{}\"", reference)))?,
+                None => part,
+            };
+            if let Some(part_properties) = part.get("properties").and_then(Value::as_object) {
+                properties.extend(part_properties.clone());
+            }
+            if let Some(part_required) = part.get("required").and_then(Value::as_array) {
+                required.extend(part_required.iter().cloned());
+            }
+        }
+        merged.insert("type".into(), json!("object"));
+        merged.insert("properties".into(), Value::Object(properties));
+        merged.insert("required".into(), Value::Array(required));
+        Ok(Value::Object(merged))
+    }
+
+    fn object(&mut self, schema: &Map<String, Value>, name: &str) -> Result<String, CommandError> {
+        let properties = schema.get("properties").and_then(Value::as_object);
+        // Extra keys are left out unless the schema asks for them, or gives
+        // no properties at all
+        let additional = match schema.get("additionalProperties") {
+            Some(Value::Bool(false)) => None,
+            None if properties.is_some() => None,
+            None => Some(&Value::Bool(true)),
+            Some(extra) => Some(extra),
+        };
+        if properties.is_none() && additional == Some(&Value::Bool(true)) {
+            return Ok(self.primitive("object"));
+        }
+        let required: Vec<&str> = schema
+            .get("required")
+            .and_then(Value::as_array)
+            .map(|keys| keys.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+
+        self.primitive("ws");
+        let mut mandatory = Vec::new();
+        let mut optional = Vec::new();
+        for (key, property) in properties.into_iter().flatten() {
+            let value = self.visit(property, &format!("{}-{}", name, key))?;
+            let pair = format!("{} ws \":\" ws {} ws", json_literal(&json!(key)), value);
+            if required.contains(&key.as_str()) {
+                mandatory.push(pair);
+            } else {
+                optional.push(pair);
+            }
+        }
+        let extra = match additional {
+            Some(extra) => {
+                let value = self.visit(extra, &format!("{}-additional", name))?;
+                self.primitive("string");
+                Some(self.add(&format!("{}-kv", name), format!("string ws \":\" ws {} ws", value)))
+            }
+            None => None,
+        };
+
+        let mut body = String::from("\"{\" ws");
+        if !mandatory.is_empty() {
+            let _ = write!(body, " {}", mandatory.join(" \",\" ws "));
+            for pair in &optional {
+                let _ = write!(body, " (\",\" ws {})?", pair);
+            }
+            if let Some(extra) = &extra {
+                let _ = write!(body, " (\",\" ws {})*", extra);
+            }
+        } else {
+            // With nothing required, whichever pair comes first has no comma
+            let mut firsts = Vec::new();
+            for (i, pair) in optional.iter().enumerate() {
+                let mut first = pair.clone();
+                for later in &optional[i + 1..] {
+                    let _ = write!(first, " (\",\" ws {})?", later);
+                }
+                if let Some(extra) = &extra {
+                    let _ = write!(first, " (\",\" ws {})*", extra);
+                }
+                firsts.push(first);
+            }
+            if let Some(extra) = &extra {
+                firsts.push(format!("{} (\",\" ws {})*", extra, extra));
+            }
+            if !firsts.is_empty() {
+                let _ = write!(body, " ({})?", firsts.join(" | "));
+            }
+        }
+        body.push_str(" \"}\"");
+        Ok(body)
+    }
+
+    fn array(&mut self, schema: &Map<String, Value>, name: &str) -> Result<String, CommandError> {
+        let item = match schema.get("items") {
+            Some(items) => self.visit(items, &format!("{}-item", name))?,
+            None => self.primitive("value"),
+        };
+        let min = schema.get("minItems").and_then(Value::as_u64).unwrap_or(0);
+        let max = schema.get("maxItems").and_then(Value::as_u64);
+        if max.is_some_and(|max| max < min) {
+            return Err(unsupported(format!("minItems {} is above maxItems", min)));
+        }
+
+        self.primitive("ws");
+        let more = format!("(\",\" ws {} ws)", item);
+        Ok(match (min, max) {
+            (_, Some(0)) => "\"[\" ws \"]\"".to_string(),
+            (0, _) => format!("\"[\" ws ({} ws {})? \"]\"", item, repeat(&more, 0, max.map(|max| max - 1))),
+            _ => format!("\"[\" ws {} ws {} \"]\"", item, repeat(&more, min - 1, max.map(|max| max - 1))),
+        })
+    }
+
+    fn string(&mut self, schema: &Map<String, Value>) -> Result<String, CommandError> {
+        if let Some(pattern) = schema.get("pattern").and_then(Value::as_str) {
+            return self.pattern(pattern);
+        }
+        let format = match schema.get("format").and_then(Value::as_str) {
+            Some("date") => Some(DATE.to_string()),
+            Some("time") => Some(TIME.to_string()),
+            Some("date-time") => Some(format!("{} \"T\" {}", DATE, TIME)),
+            Some("uuid") => Some(UUID.to_string()),
+            _ => None,
+        };
+        if let Some(format) = format {
+            return Ok(format!("{} {} {}", QUOTE, format, QUOTE));
+        }
+
+        let min = schema.get("minLength").and_then(Value::as_u64).unwrap_or(0);
+        let max = schema.get("maxLength").and_then(Value::as_u64);
+        if min == 0 && max.is_none() {
+            return Ok(self.primitive("string"));
+        }
+        let char = self.primitive("char");
+        Ok(format!("{} {} {}", QUOTE, repeat(&char, min, max), QUOTE))
+    }
+
+    // Patterns match anywhere in the string unless anchored
+    fn pattern(&mut self, pattern: &str) -> Result<String, CommandError> {
+        let anchored_start = pattern.starts_with('^');
+        let anchored_end = pattern.ends_with('$') && !pattern.ends_with("\\$");
+        let body = &pattern[anchored_start as usize..pattern.len() - anchored_end as usize];
+
+        let mut parser = PatternParser { chars: body.chars().collect(), pos: 0 };
+        let expr = parser.alternation()?;
+        if parser.pos < parser.chars.len() {
+            return Err(unsupported("unbalanced parentheses in pattern"));
+        }
+        self.primitive("char");
+        Ok(format!(
+            "{} {}({}){} {}",
+            QUOTE,
+            if anchored_start { "" } else { "char* " },
+            expr,
+            if anchored_end { "" } else { " char*" },
+            QUOTE
+        ))
+    }
+
+    fn integer(&mut self, schema: &Map<String, Value>) -> Result<String, CommandError> {
+        match integer_bounds(schema) {
+            (None, None) => Ok(self.primitive("integer")),
+            (min, max) => int_range(min, max).ok_or_else(|| unsupported("no integer lies within the bounds")),
+        }
+    }
+
+    // Bounded numbers are written without exponents
+    fn number(&mut self, schema: &Map<String, Value>) -> Result<String, CommandError> {
+        let (int_min, int_max) = integer_bounds(schema);
+        if int_min.is_none() && int_max.is_none() {
+            return Ok(self.primitive("number"));
+        }
+        let mut parts: Vec<String> = int_range(int_min, int_max).into_iter().collect();
+
+        // A fraction on n >= 0 lies in [n, n + 1), and on -n in (-n - 1, -n]
+        let min = bound(schema, "minimum", "exclusiveMinimum");
+        let max = bound(schema, "maximum", "exclusiveMaximum");
+        if max.is_none_or(|(max, _)| max >= 1.0) {
+            let low = min.map_or(0.0, |(min, _)| min.ceil().max(0.0));
+            let open = min.is_some_and(|(min, exclusive)| exclusive && min == low);
+            let high = max.map(|(max, _)| max.floor() as u64 - 1);
+            parts.extend(fractions("", low as u64, open, high));
+        }
+        if min.is_none_or(|(min, _)| min <= -1.0) {
+            let low = max.map_or(0.0, |(max, _)| (-max).ceil().max(0.0));
+            let open = max.is_some_and(|(max, exclusive)| exclusive && -max == low);
+            let high = min.map(|(min, _)| (-min).floor() as u64 - 1);
+            parts.extend(fractions("\"-\" ", low as u64, open, high));
+        }
+        if parts.is_empty() {
+            return Err(unsupported("no number lies within the bounds"));
+        }
+        Ok(parts.join(" | "))
+    }
+
+    fn finish(self, root: String) -> String {
+        let mut grammar = format!("root ::= {}\n", root);
+        for (name, body) in self.rules {
+            let _ = writeln!(grammar, "{} ::= {}", name, body);
+        }
+        grammar
+    }
+}
+
+pub fn from_schema(schema: &Value) -> Result<String, CommandError> {
+    let mut converter = Converter::new(schema);
+    let root = converter.expression(schema, "root")?;
+    Ok(converter.finish(root))
+}
+
+// A reply that is either plain text or a single tool call written as
+// {"name": ..., "arguments": {...}}, with arguments following the tool's
+// parameter schema. Text can't start with `{`, even after whitespace, so
+// the first visible character tells the two apart.
+pub fn for_tools(tools: &[Value]) -> Result<String, CommandError> {
+    let tools: Vec<(Value, Value)> = tools
+        .iter()
+        .map(|tool| {
+            let (name, _, parameters) = ChatRequest::tool_parts(tool);
+            (name, parameters)
+        })
+        .collect();
+
+    let mut converter = Converter::new(&Value::Null);
+    converter.primitive("ws");
+    let mut calls = Vec::new();
+    for (name, parameters) in &tools {
+        let name = name.as_str().ok_or_else(|| unsupported("tool without a name"))?;
+        // $refs in each tool's parameters point into that schema
+        converter.root = parameters;
+        converter.refs.clear();
+        let arguments = converter.visit(parameters, &format!("call-{}-arguments", name))?;
+        let body = format!(
+            "{} ws \",\" ws {} ws \":\" ws {} ws",
+            json_literal(&json!(name)),
+            json_literal(&json!("arguments")),
+            arguments
+        );
+        calls.push(converter.add(&format!("call-{}", name), body));
+    }
+    if calls.is_empty() {
+        return Err(unsupported("no tools to call"));
+    }
+
+    let call = format!(
+        "\"{{\" ws {} ws \":\" ws ({}) \"}}\"",
+        json_literal(&json!("name")),
+        calls.join(" | ")
+    );
+    let call = converter.add("tool-call", call);
+    let text = converter.add("text", r#"[ \t\r\n]* [^{ \t\r\n] .*"#.to_string());
+    Ok(converter.finish(format!("{} | {}", call, text)))
+}
+
+// GBNF for a JSON schema, for callers that send llama.cpp raw requests
+#[tauri::command]
+pub fn json_schema_to_grammar(schema: Value) -> Result<String, CommandError> {
+    from_schema(&schema)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+
+    // Just enough of GBNF to run the grammars above against sample text
+    enum Node {
+        Literal(Vec<char>),
+        Class(bool, Vec<(char, char)>),
+        Any,
+        Rule(String),
+        Sequence(Vec<Node>),
+        Choice(Vec<Node>),
+        Repeat(Box<Node>, u64, Option<u64>),
+    }
+
+    struct Reader<'a> {
+        chars: Vec<char>,
+        pos: usize,
+        line: &'a str,
+    }
+
+    impl Reader<'_> {
+        fn peek(&mut self) -> Option<char> {
+            while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+                self.pos += 1;
+            }
+            self.chars.get(self.pos).copied()
+        }
+
+        fn next_char(&mut self) -> char {
+            let c = *self.chars.get(self.pos).unwrap_or_else(|| panic!("{} ends early", self.line));
+            self.pos += 1;
+            c
+        }
+
+        fn escaped(&mut self) -> char {
+            match self.next_char() {
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                'x' => {
+                    let hex: String = [self.next_char(), self.next_char()].iter().collect();
+                    char::from_u32(u32::from_str_radix(&hex, 16).unwrap()).unwrap()
+                }
+                c => c,
+            }
+        }
+
+        fn choice(&mut self) -> Node {
+            let mut options = vec![self.sequence()];
+            while self.peek() == Some('|') {
+                self.pos += 1;
+                options.push(self.sequence());
+            }
+            Node::Choice(options)
+        }
+
+        fn sequence(&mut self) -> Node {
+            let mut items = Vec::new();
+            while let Some(c) = self.peek() {
+                if c == '|' || c == ')' {
+                    break;
+                }
+                let atom = self.atom();
+                let (min, max) = match self.chars.get(self.pos) {
+                    Some('*') => (0, None),
+                    Some('+') => (1, None),
+                    Some('?') => (0, Some(1)),
+                    Some('{') => {
+                        let end = self.pos + self.chars[self.pos..].iter().position(|&c| c == '}').unwrap();
+                        let spec: String = self.chars[self.pos + 1..end].iter().collect();
+                        self.pos = end;
+                        let (min, max) = spec.split_once(',').unwrap_or((&spec, &spec));
+                        (min.parse().unwrap(), max.parse().ok())
+                    }
+                    _ => {
+                        items.push(atom);
+                        continue;
+                    }
+                };
+                self.pos += 1;
+                items.push(Node::Repeat(Box::new(atom), min, max));
+            }
+            Node::Sequence(items)
+        }
+
+        fn atom(&mut self) -> Node {
+            match self.next_char() {
+                '"' => {
+                    let mut text = Vec::new();
+                    loop {
+                        match self.next_char() {
+                            '"' => break,
+                            '\\' => text.push(self.escaped()),
+                            c => text.push(c),
+                        }
+                    }
+                    Node::Literal(text)
+                }
+                '[' => {
+                    let negated = self.chars[self.pos] == '^';
+                    self.pos += negated as usize;
+                    let mut ranges = Vec::new();
+                    while self.chars[self.pos] != ']' {
+                        let from = match self.next_char() {
+                            '\\' => self.escaped(),
+                            c => c,
+                        };
+                        let to = if self.chars[self.pos] == '-' && self.chars[self.pos + 1] != ']' {
+                            self.pos += 1;
+                            match self.next_char() {
+                                '\\' => self.escaped(),
+                                c => c,
+                            }
+                        } else {
+                            from
+                        };
+                        ranges.push((from, to));
+                    }
+                    self.pos += 1;
+                    Node::Class(negated, ranges)
+                }
+                '(' => {
+                    let inner = self.choice();
+                    assert_eq!(self.next_char(), ')', "unbalanced group in {}", self.line);
+                    inner
+                }
+                '.' => Node::Any,
+                c if c.is_ascii_alphanumeric() || c == '-' => {
+                    let mut name = c.to_string();
+                    while self.chars.get(self.pos).is_some_and(|c| c.is_ascii_alphanumeric() || *c == '-') {
+                        name.push(self.next_char());
+                    }
+                    Node::Rule(name)
+                }
+                c => panic!("unexpected {:?} in {}", c, self.line),
+            }
+        }
+    }
+
+    struct Grammar(HashMap<String, Node>);
+
+    impl Grammar {
+        fn parse(grammar: &str) -> Self {
+            let rules = grammar
+                .lines()
+                .map(|line| {
+                    let (name, body) = line.split_once(" ::= ").unwrap();
+                    let mut reader = Reader { chars: body.chars().collect(), pos: 0, line };
+                    let node = reader.choice();
+                    assert_eq!(reader.peek(), None, "trailing text in {}", line);
+                    (name.to_string(), node)
+                })
+                .collect();
+            Self(rules)
+        }
+
+        // Every position a match of `node` starting at `pos` can end at
+        fn ends(&self, node: &Node, text: &[char], pos: usize) -> BTreeSet<usize> {
+            let single = |matches: bool| if matches { BTreeSet::from([pos + 1]) } else { BTreeSet::new() };
+            match node {
+                Node::Literal(literal) => {
+                    let matches = text[pos..].starts_with(literal);
+                    if matches { BTreeSet::from([pos + literal.len()]) } else { BTreeSet::new() }
+                }
+                Node::Class(negated, ranges) => single(text.get(pos).is_some_and(|&c| {
+                    ranges.iter().any(|&(from, to)| from <= c && c <= to) != *negated
+                })),
+                Node::Any => single(text.get(pos).is_some_and(|&c| c != '\n')),
+                Node::Rule(name) => self.ends(&self.0[name], text, pos),
+                Node::Sequence(items) => items.iter().fold(BTreeSet::from([pos]), |starts, item| {
+                    starts.into_iter().flat_map(|start| self.ends(item, text, start)).collect()
+                }),
+                Node::Choice(options) => options.iter().flat_map(|option| self.ends(option, text, pos)).collect(),
+                Node::Repeat(item, min, max) => {
+                    let mut ends = BTreeSet::new();
+                    let mut frontier = BTreeSet::from([pos]);
+                    let mut count = 0;
+                    while !frontier.is_empty() && max.is_none_or(|max| count <= max) {
+                        if count >= *min {
+                            ends.extend(&frontier);
+                        }
+                        let next: BTreeSet<usize> =
+                            frontier.iter().flat_map(|&start| self.ends(item, text, start)).collect();
+                        // Stop once an item can only match empty text
+                        frontier = next.into_iter().filter(|end| !ends.contains(end) || count < *min).collect();
+                        count += 1;
+                    }
+                    ends
+                }
+            }
+        }
+
+        fn accepts(&self, text: &str) -> bool {
+            let text: Vec<char> = text.chars().collect();
+            self.ends(&self.0["root"], &text, 0).contains(&text.len())
+        }
+    }
+
+    fn grammar(schema: Value) -> Grammar {
+        Grammar::parse(&from_schema(&schema).unwrap())
+    }
+
+    #[track_caller]
+    fn check(grammar: &Grammar, accepted: &[&str], rejected: &[&str]) {
+        for text in accepted {
+            assert!(grammar.accepts(text), "should accept {}", text);
+        }
+        for text in rejected {
+            assert!(!grammar.accepts(text), "should reject {}", text);
+        }
+    }
+
+    #[test]
+    fn objects() {
+        let grammar = grammar(json!({
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "age": { "type": "integer" },
+                "tags": { "type": "array", "items": { "type": "string" } },
+            },
+            "required": ["name"],
+        }));
+        check(
+            &grammar,
+            &[
+                r#"{"name": "Ada"}"#,
+                r#"{ "name":"Ada", "age": 36 }"#,
+                r#"{"name": "Ada", "tags": ["a", "b"]}"#,
+                r#"{"name": "Ada", "age": 36, "tags": []}"#,
+            ],
+            &[
+                r#"{}"#,
+                r#"{"age": 36}"#,
+                // Optional keys follow the required ones in schema order
+                r#"{"age": 36, "name": "Ada"}"#,
+                r#"{"name": "Ada", "tags": [], "age": 36}"#,
+                r#"{"name": "Ada", "age": "36"}"#,
+                r#"{"name": "Ada", "other": 1}"#,
+            ],
+        );
+
+        let grammar = self::grammar(json!({
+            "properties": { "a": { "type": "integer" }, "b": { "type": "integer" } },
+        }));
+        check(&grammar, &[r#"{}"#, r#"{"a": 1}"#, r#"{"b": 2}"#, r#"{"a": 1, "b": 2}"#], &[r#"{"b": 2, "a": 1}"#]);
+
+        let grammar = self::grammar(json!({ "type": "object", "additionalProperties": { "type": "integer" } }));
+        check(&grammar, &[r#"{}"#, r#"{"x": 1, "y": 2}"#], &[r#"{"x": "1"}"#]);
+    }
+
+    #[test]
+    fn arrays() {
+        let grammar = grammar(json!({ "type": "array", "items": { "type": "integer" }, "minItems": 1, "maxItems": 3 }));
+        check(&grammar, &["[1]", "[1, 2]", "[ 1,2,3 ]"], &["[]", "[1, 2, 3, 4]", "[1,]", r#"["1"]"#]);
+
+        let grammar = self::grammar(json!({ "type": "array", "maxItems": 2 }));
+        check(&grammar, &["[]", r#"[1, "a"]"#, "[[], {}]"], &["[1, 2, 3]"]);
+
+        let grammar = self::grammar(json!({ "type": "array", "minItems": 2 }));
+        check(&grammar, &["[1, 2]", "[1, 2, 3, 4]"], &["[]", "[1]"]);
+    }
+
+    #[test]
+    fn enums_and_consts() {
+        let grammar = grammar(json!({ "enum": ["red", "green", 3, null] }));
+        check(&grammar, &[r#""red""#, r#""green""#, "3", "null"], &[r#""blue""#, r#""Red""#, "red", "4"]);
+
+        let grammar = self::grammar(json!({ "const": { "a": [1] } }));
+        check(&grammar, &[r#"{"a":[1]}"#], &[r#"{"a": [1]}"#, r#"{"a":[2]}"#]);
+    }
+
+    #[test]
+    fn patterns() {
+        let grammar = grammar(json!({ "type": "string", "pattern": "^[A-Z]{2}-\\d{3,4}$" }));
+        check(
+            &grammar,
+            &[r#""AB-123""#, r#""XY-1234""#],
+            &[r#""ab-123""#, r#""AB-12""#, r#""AB-12345""#, r#"" AB-123""#],
+        );
+
+        // Unanchored patterns match anywhere in the string
+        let grammar = self::grammar(json!({ "type": "string", "pattern": "cat|dog" }));
+        check(&grammar, &[r#""cat""#, r#""hotdogs""#], &[r#""cow""#]);
+
+        // Quotes and backslashes are matched in their escaped form
+        let grammar = self::grammar(json!({ "type": "string", "pattern": "^[^a]+$" }));
+        check(&grammar, &[r#""b\"c""#, r#""\\""#], &[r#""bac""#, r#""b"c""#]);
+
+        assert!(from_schema(&json!({ "type": "string", "pattern": "(?=a)" })).is_err());
+    }
+
+    #[test]
+    fn integer_bounds() {
+        let grammar = grammar(json!({ "type": "integer", "minimum": -5, "maximum": 120 }));
+        check(
+            &grammar,
+            &["-5", "-1", "0", "9", "10", "99", "100", "120"],
+            &["-6", "121", "200", "1000", "01", "-0", "1.5"],
+        );
+
+        let grammar = self::grammar(json!({ "type": "integer", "exclusiveMinimum": 0, "exclusiveMaximum": 10 }));
+        check(&grammar, &["1", "9"], &["0", "10", "-1"]);
+
+        // Draft 4 booleans
+        let grammar = self::grammar(json!({ "type": "integer", "minimum": 0, "exclusiveMinimum": true }));
+        check(&grammar, &["1", "12345"], &["0"]);
+
+        let grammar = self::grammar(json!({ "type": "integer", "maximum": -10 }));
+        check(&grammar, &["-10", "-11", "-1000"], &["-9", "0", "10"]);
+
+        assert!(from_schema(&json!({ "type": "integer", "minimum": 3, "maximum": 2 })).is_err());
+    }
+
+    #[test]
+    fn number_bounds() {
+        let grammar = grammar(json!({ "type": "number", "minimum": 0, "maximum": 1 }));
+        check(&grammar, &["0", "1", "0.5", "0.0", "0.999"], &["-0.5", "1.5", "2", "1e3"]);
+
+        let grammar = self::grammar(json!({ "type": "number", "exclusiveMinimum": 0 }));
+        check(&grammar, &["1", "0.5", "0.01", "3.0", "1000.25"], &["0", "0.0", "0.000", "-0.5", "-1"]);
+
+        let grammar = self::grammar(json!({ "type": "number", "exclusiveMinimum": 2, "maximum": 3 }));
+        check(&grammar, &["3", "2.5", "2.01"], &["2", "2.0", "1.5", "3.5"]);
+
+        let grammar = self::grammar(json!({ "type": "number", "exclusiveMaximum": 0 }));
+        check(&grammar, &["-1", "-0.5", "-12.75"], &["0", "-0.0", "0.5", "1"]);
+
+        let grammar = self::grammar(json!({ "type": "number", "minimum": -2.5, "maximum": 2.5 }));
+        check(&grammar, &["-2", "2", "-1.5", "1.5", "0.25"], &["-3", "3", "-3.5"]);
+    }
+
+    #[test]
+    fn tool_calls_or_text() {
+        let tools = [json!({
+            "type": "function",
+            "function": {
+                "name": "get_weather",
+                "parameters": {
+                    "type": "object",
+                    "properties": { "city": { "type": "string" } },
+                    "required": ["city"],
+                },
+            },
+        })];
+        let grammar = Grammar::parse(&for_tools(&tools).unwrap());
+        check(
+            &grammar,
+            &[
+                r#"{"name": "get_weather", "arguments": {"city": "Oslo"}}"#,
+                "It's sunny.",
+                "\n  It's sunny.",
+            ],
+            &[
+                r#"{"name": "get_time", "arguments": {}}"#,
+                r#"{"name": "get_weather", "arguments": {}}"#,
+                r#"  {"name": "get_weather", "arguments": {"city": "Oslo"}}"#,
+                "{ not a call",
+                "",
+            ],
+        );
+
+        assert!(for_tools(&[]).is_err());
+    }
+}
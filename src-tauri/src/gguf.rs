@@ -0,0 +1,660 @@
+// Reads the header of GGUF model files: metadata and tensor shapes, without
+// loading any weights. Feeds the model selector and the llama.cpp settings
+// with context length, architecture, quantization, parameter count,
+// tokenizer and chat template. Split models (name-00001-of-00003.gguf) are
+// reported once, with the sizes and parameters of all their parts.
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+
+use crate::error::{CommandError, ErrorKind};
+
+const MAGIC: &[u8; 4] = b"GGUF";
+// Strings and arrays larger than this mean a corrupt file, not a real header
+const MAX_STRING_LEN: u64 = 64 * 1024 * 1024;
+const MAX_ARRAY_LEN: u64 = 16 * 1024 * 1024;
+const MAX_TENSORS: u64 = 1 << 20;
+// Arrays longer than this (vocabularies, merges) are left out of `metadata`
+const MAX_LISTED_ARRAY: usize = 16;
+const MAX_SCAN_DEPTH: usize = 6;
+
+#[derive(Clone, Debug, Default, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GgufInfo {
+    pub path: String,
+    pub file_name: String,
+    // All parts of a split model
+    pub size_bytes: u64,
+    pub split_count: u32,
+    pub version: u32,
+    // "model", "mmproj" (vision projector), "adapter" (LoRA)
+    pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub architecture: Option<String>,
+    // e.g. "Q4_K_M"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quantization: Option<String>,
+    pub parameter_count: u64,
+    // e.g. "8B", from the metadata when present, else from the parameter count
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size_label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context_length: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub embedding_length: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_count: Option<u64>,
+    // "llama" (SentencePiece), "gpt2" (BPE), ...
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tokenizer_model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vocab_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chat_template: Option<String>,
+    pub tensor_count: u64,
+    // The remaining metadata, with long arrays replaced by their length
+    pub metadata: BTreeMap<String, Value>,
+}
+
+// One model of a folder scan
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GgufSummary {
+    pub path: String,
+    pub file_name: String,
+    pub size_bytes: u64,
+    pub split_count: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub architecture: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quantization: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parameter_count: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size_label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context_length: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub has_chat_template: Option<bool>,
+    // Set when the header couldn't be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+fn parse_error(path: &Path, message: impl std::fmt::Display) -> CommandError {
+    CommandError::new(ErrorKind::Parse, format!("{} is not a valid GGUF file: {}", path.display(), message))
+}
+
+// llama_ftype, stored as general.file_type
+fn file_type_name(file_type: u64) -> Option<&'static str> {
+    Some(match file_type {
+        0 => "F32",
+        1 => "F16",
+        2 => "Q4_0",
+        3 => "Q4_1",
+        7 => "Q8_0",
+        8 => "Q5_0",
+        9 => "Q5_1",
+        10 => "Q2_K",
+        11 => "Q3_K_S",
+        12 => "Q3_K_M",
+        13 => "Q3_K_L",
+        14 => "Q4_K_S",
+        15 => "Q4_K_M",
+        16 => "Q5_K_S",
+        17 => "Q5_K_M",
+        18 => "Q6_K",
+        19 => "IQ2_XXS",
+        20 => "IQ2_XS",
+        21 => "Q2_K_S",
+        22 => "IQ3_XS",
+        23 => "IQ3_XXS",
+        24 => "IQ1_S",
+        25 => "IQ4_NL",
+        26 => "IQ3_S",
+        27 => "IQ3_M",
+        28 => "IQ2_S",
+        29 => "IQ2_M",
+        30 => "IQ4_XS",
+        31 => "IQ1_M",
+        32 => "BF16",
+        36 => "TQ1_0",
+        37 => "TQ2_0",
+        38 => "MXFP4",
+        _ => return None,
+    })
+}
+
+// ggml_type of a tensor
+fn tensor_type_name(tensor_type: u32) -> Option<&'static str> {
+    Some(match tensor_type {
+        0 => "F32",
+        1 => "F16",
+        2 => "Q4_0",
+        3 => "Q4_1",
+        6 => "Q5_0",
+        7 => "Q5_1",
+        8 => "Q8_0",
+        9 => "Q8_1",
+        10 => "Q2_K",
+        11 => "Q3_K",
+        12 => "Q4_K",
+        13 => "Q5_K",
+        14 => "Q6_K",
+        15 => "Q8_K",
+        16 => "IQ2_XXS",
+        17 => "IQ2_XS",
+        18 => "IQ3_XXS",
+        19 => "IQ1_S",
+        20 => "IQ4_NL",
+        21 => "IQ3_S",
+        22 => "IQ2_S",
+        23 => "IQ4_XS",
+        24 => "I8",
+        25 => "I16",
+        26 => "I32",
+        27 => "I64",
+        28 => "F64",
+        29 => "IQ1_M",
+        30 => "BF16",
+        34 => "TQ1_0",
+        35 => "TQ2_0",
+        39 => "MXFP4",
+        _ => return None,
+    })
+}
+
+// "8B", "135M", ...
+fn size_label(parameters: u64) -> Option<String> {
+    let (value, unit) = match parameters {
+        0 => return None,
+        p if p >= 1_000_000_000_000 => (p as f64 / 1e12, "T"),
+        p if p >= 1_000_000_000 => (p as f64 / 1e9, "B"),
+        p if p >= 1_000_000 => (p as f64 / 1e6, "M"),
+        p => (p as f64 / 1e3, "K"),
+    };
+    let rounded = if value >= 10.0 { format!("{:.0}", value) } else { format!("{:.1}", value) };
+    Some(format!("{}{}", rounded.trim_end_matches(".0"), unit))
+}
+
+struct Reader<R> {
+    inner: R,
+    path: PathBuf,
+    // Version 1 used 32-bit lengths and counts
+    version: u32,
+}
+
+impl<R: Read> Reader<R> {
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], CommandError> {
+        let mut buf = [0u8; N];
+        self.inner
+            .read_exact(&mut buf)
+            .map_err(|err| parse_error(&self.path, format!("truncated header ({err})")))?;
+        Ok(buf)
+    }
+
+    fn u32(&mut self) -> Result<u32, CommandError> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, CommandError> {
+        Ok(u64::from_le_bytes(self.bytes()?))
+    }
+
+    // Lengths, counts and dimensions
+    fn size(&mut self, limit: u64, what: &str) -> Result<u64, CommandError> {
+        let size = if self.version == 1 { self.u32()? as u64 } else { self.u64()? };
+        if size > limit {
+            return Err(parse_error(&self.path, format!("{what} of {size} is too large")));
+        }
+        Ok(size)
+    }
+
+    fn skip(&mut self, len: u64) -> Result<(), CommandError> {
+        let skipped = std::io::copy(&mut (&mut self.inner).take(len), &mut std::io::sink())
+            .map_err(|err| parse_error(&self.path, err))?;
+        if skipped < len {
+            return Err(parse_error(&self.path, "truncated header"));
+        }
+        Ok(())
+    }
+
+    fn string(&mut self) -> Result<String, CommandError> {
+        let len = self.size(MAX_STRING_LEN, "string length")?;
+        let mut buf = Vec::with_capacity(len as usize);
+        (&mut self.inner)
+            .take(len)
+            .read_to_end(&mut buf)
+            .map_err(|err| parse_error(&self.path, err))?;
+        if (buf.len() as u64) < len {
+            return Err(parse_error(&self.path, "truncated header"));
+        }
+        Ok(String::from_utf8_lossy(&buf).into_owned())
+    }
+
+    // A metadata value; `keep` is false for array items that are only
+    // counted, `full` lists arrays of any length
+    fn value(&mut self, value_type: u32, keep: bool, full: bool) -> Result<Value, CommandError> {
+        Ok(match value_type {
+            0 => json!(self.bytes::<1>()?[0]),
+            1 => json!(self.bytes::<1>()?[0] as i8),
+            2 => json!(u16::from_le_bytes(self.bytes()?)),
+            3 => json!(i16::from_le_bytes(self.bytes()?)),
+            4 => json!(self.u32()?),
+            5 => json!(i32::from_le_bytes(self.bytes()?)),
+            6 => json!(f32::from_le_bytes(self.bytes()?)),
+            7 => json!(self.bytes::<1>()?[0] != 0),
+            8 if keep => json!(self.string()?),
+            8 => {
+                let len = self.size(MAX_STRING_LEN, "string length")?;
+                self.skip(len)?;
+                Value::Null
+            }
+            9 => {
+                let item_type = self.u32()?;
+                let len = self.size(MAX_ARRAY_LEN, "array length")?;
+                let listed = keep && (full || len as usize <= MAX_LISTED_ARRAY);
+                let mut items = Vec::new();
+                for _ in 0..len {
+                    let item = self.value(item_type, listed, false)?;
+                    if listed {
+                        items.push(item);
+                    }
+                }
+                if listed {
+                    Value::Array(items)
+                } else {
+                    json!({ "arrayLength": len })
+                }
+            }
+            10 => json!(self.u64()?),
+            11 => json!(i64::from_le_bytes(self.bytes()?)),
+            12 => json!(f64::from_le_bytes(self.bytes()?)),
+            other => return Err(parse_error(&self.path, format!("unknown value type {other}"))),
+        })
+    }
+}
+
+// Header of a single file
+struct Header {
+    version: u32,
+    metadata: BTreeMap<String, Value>,
+    tensor_count: u64,
+    parameter_count: u64,
+    // Parameters per tensor type, to guess the quantization of files
+    // without general.file_type
+    parameters_by_type: BTreeMap<u32, u64>,
+}
+
+// `full_arrays` are metadata keys whose arrays are read whole
+fn read_header(path: &Path, full_arrays: &[&str]) -> Result<Header, CommandError> {
+    let file = File::open(path).map_err(|err| {
+        CommandError::new(ErrorKind::InvalidRequest, format!("Failed to open {}: {err}", path.display()))
+    })?;
+    let mut reader = Reader {
+        inner: BufReader::new(file),
+        path: path.to_path_buf(),
+        version: 3,
+    };
+
+    if &reader.bytes::<4>()? != MAGIC {
+        return Err(parse_error(path, "missing GGUF magic"));
+    }
+    let version = reader.u32()?;
+    if !(1..=3).contains(&version) {
+        return Err(parse_error(path, format!("unsupported version {version}")));
+    }
+    reader.version = version;
+
+    let tensor_count = reader.size(MAX_TENSORS, "tensor count")?;
+    let kv_count = reader.size(MAX_ARRAY_LEN, "metadata count")?;
+    let mut metadata = BTreeMap::new();
+    for _ in 0..kv_count {
+        let key = reader.string()?;
+        let value_type = reader.u32()?;
+        let value = reader.value(value_type, true, full_arrays.contains(&key.as_str()))?;
+        metadata.insert(key, value);
+    }
+
+    let mut parameter_count = 0u64;
+    let mut parameters_by_type = BTreeMap::new();
+    for _ in 0..tensor_count {
+        let _name = reader.string()?;
+        let dims = reader.u32()?;
+        if dims > 8 {
+            return Err(parse_error(path, format!("tensor with {dims} dimensions")));
+        }
+        let mut elements = 1u64;
+        for _ in 0..dims {
+            elements = elements.saturating_mul(reader.size(u64::MAX, "dimension")?);
+        }
+        let tensor_type = reader.u32()?;
+        let _offset = reader.u64()?;
+        parameter_count = parameter_count.saturating_add(elements);
+        // 1-D tensors (norms, biases) stay in full precision whatever the quantization
+        if dims > 1 {
+            let total = parameters_by_type.entry(tensor_type).or_insert(0u64);
+            *total = total.saturating_add(elements);
+        }
+    }
+
+    Ok(Header {
+        version,
+        metadata,
+        tensor_count,
+        parameter_count,
+        parameters_by_type,
+    })
+}
+
+// "model-00001-of-00003" -> ("model", 1, 3)
+fn split_parts(stem: &str) -> Option<(&str, u32, u32)> {
+    let (rest, total) = stem.rsplit_once("-of-")?;
+    let (base, index) = rest.rsplit_once('-')?;
+    if index.len() != 5 || total.len() != 5 {
+        return None;
+    }
+    Some((base, index.parse().ok()?, total.parse().ok()?))
+}
+
+fn split_files(path: &Path) -> Vec<PathBuf> {
+    let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+        return vec![path.to_path_buf()];
+    };
+    let Some((base, _, total)) = split_parts(stem) else {
+        return vec![path.to_path_buf()];
+    };
+    (1..=total)
+        .map(|index| path.with_file_name(format!("{base}-{index:05}-of-{total:05}.gguf")))
+        .collect()
+}
+
+fn string_key(metadata: &BTreeMap<String, Value>, key: &str) -> Option<String> {
+    metadata.get(key).and_then(Value::as_str).map(|s| s.to_string())
+}
+
+fn u64_key(metadata: &BTreeMap<String, Value>, key: &str) -> Option<u64> {
+    metadata.get(key).and_then(Value::as_u64)
+}
+
+pub fn inspect(path: &Path) -> Result<GgufInfo, CommandError> {
+    let header = read_header(path, &[])?;
+    let mut metadata = header.metadata;
+    let mut size_bytes = std::fs::metadata(path).map(|meta| meta.len()).unwrap_or(0);
+    let mut parameter_count = header.parameter_count;
+    let mut parameters_by_type = header.parameters_by_type;
+    let mut tensor_count = header.tensor_count;
+
+    // The metadata lives in the first part; the tensors are spread over all
+    let split_count = u64_key(&metadata, "split.count").unwrap_or(1).max(1) as u32;
+    if split_count > 1 {
+        for part in split_files(path).iter().filter(|part| part.as_path() != path) {
+            let Ok(part_header) = read_header(part, &[]) else {
+                eprintln!("[GGUF] Missing or unreadable part {}", part.display());
+                continue;
+            };
+            size_bytes += std::fs::metadata(part).map(|meta| meta.len()).unwrap_or(0);
+            parameter_count = parameter_count.saturating_add(part_header.parameter_count);
+            tensor_count = tensor_count.saturating_add(part_header.tensor_count);
+            for (tensor_type, count) in part_header.parameters_by_type {
+                let total = parameters_by_type.entry(tensor_type).or_insert(0u64);
+                *total = total.saturating_add(count);
+            }
+        }
+    }
+
+    let architecture = string_key(&metadata, "general.architecture");
+    let arch_key = |name: &str| architecture.as_ref().and_then(|arch| u64_key(&metadata, &format!("{arch}.{name}")));
+    let context_length = arch_key("context_length");
+    let embedding_length = arch_key("embedding_length");
+    let block_count = arch_key("block_count");
+
+    let quantization = u64_key(&metadata, "general.file_type")
+        .and_then(file_type_name)
+        .or_else(|| {
+            parameters_by_type
+                .iter()
+                .max_by_key(|(_, count)| **count)
+                .and_then(|(tensor_type, _)| tensor_type_name(*tensor_type))
+        })
+        .map(|name| name.to_string());
+
+    let kind = match string_key(&metadata, "general.type") {
+        Some(kind) => kind,
+        None if architecture.as_deref() == Some("clip") => "mmproj".to_string(),
+        None => "model".to_string(),
+    };
+
+    let vocab_size = metadata
+        .get("tokenizer.ggml.tokens")
+        .and_then(|tokens| tokens.get("arrayLength").and_then(Value::as_u64).or_else(|| tokens.as_array().map(|a| a.len() as u64)));
+    // Shown as their own fields
+    let chat_template = metadata.remove("tokenizer.chat_template").and_then(|v| v.as_str().map(|s| s.to_string()));
+
+    Ok(GgufInfo {
+        path: path.display().to_string(),
+        file_name: path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default(),
+        size_bytes,
+        split_count,
+        version: header.version,
+        kind,
+        name: string_key(&metadata, "general.name"),
+        quantization,
+        parameter_count,
+        size_label: string_key(&metadata, "general.size_label").or_else(|| size_label(parameter_count)),
+        context_length,
+        embedding_length,
+        block_count,
+        tokenizer_model: string_key(&metadata, "tokenizer.ggml.model"),
+        vocab_size,
+        chat_template,
+        tensor_count,
+        architecture,
+        metadata,
+    })
+}
+
+// What prompt formatting needs from a model: its chat template, the text of
+// its BOS and EOS tokens, and its control tokens (<|im_start|>, ...)
+#[derive(Clone, Debug, Default)]
+pub struct ChatTokens {
+    pub chat_template: Option<String>,
+    pub bos_token: Option<String>,
+    pub eos_token: Option<String>,
+    pub add_bos_token: Option<bool>,
+    pub control_tokens: Vec<String>,
+}
+
+pub fn chat_tokens(path: &Path) -> Result<ChatTokens, CommandError> {
+    let header = read_header(path, &["tokenizer.ggml.tokens", "tokenizer.ggml.token_type"])?;
+    let metadata = header.metadata;
+    let tokens = metadata.get("tokenizer.ggml.tokens").and_then(Value::as_array);
+    let token = |key: &str| {
+        let id = u64_key(&metadata, key)? as usize;
+        tokens?.get(id)?.as_str().map(|text| text.to_string())
+    };
+    // Token type 3 is control, 4 user-defined
+    let control_tokens = match (tokens, metadata.get("tokenizer.ggml.token_type").and_then(Value::as_array)) {
+        (Some(tokens), Some(types)) => tokens
+            .iter()
+            .zip(types)
+            .filter(|(_, token_type)| matches!(token_type.as_i64(), Some(3 | 4)))
+            .filter_map(|(token, _)| token.as_str().map(|text| text.to_string()))
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    Ok(ChatTokens {
+        chat_template: string_key(&metadata, "tokenizer.chat_template"),
+        bos_token: token("tokenizer.ggml.bos_token_id"),
+        eos_token: token("tokenizer.ggml.eos_token_id"),
+        add_bos_token: metadata.get("tokenizer.ggml.add_bos_token").and_then(Value::as_bool),
+        control_tokens,
+    })
+}
+
+fn summarize(path: &Path) -> GgufSummary {
+    match inspect(path) {
+        Ok(info) => GgufSummary {
+            path: info.path,
+            file_name: info.file_name,
+            size_bytes: info.size_bytes,
+            split_count: info.split_count,
+            kind: Some(info.kind),
+            name: info.name,
+            architecture: info.architecture,
+            quantization: info.quantization,
+            parameter_count: Some(info.parameter_count),
+            size_label: info.size_label,
+            context_length: info.context_length,
+            has_chat_template: Some(info.chat_template.is_some()),
+            error: None,
+        },
+        Err(err) => GgufSummary {
+            path: path.display().to_string(),
+            file_name: path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default(),
+            size_bytes: std::fs::metadata(path).map(|meta| meta.len()).unwrap_or(0),
+            split_count: 1,
+            kind: None,
+            name: None,
+            architecture: None,
+            quantization: None,
+            parameter_count: None,
+            size_label: None,
+            context_length: None,
+            has_chat_template: None,
+            error: Some(err.message),
+        },
+    }
+}
+
+fn collect(dir: &Path, depth: usize, found: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        // Hidden folders hold caches and partial downloads
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if file_type.is_dir() {
+            if depth < MAX_SCAN_DEPTH && !hidden {
+                collect(&path, depth + 1, found);
+            }
+        } else if !hidden && path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("gguf")) {
+            // Later parts of a split model are covered by the first
+            let later_part = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(split_parts)
+                .is_some_and(|(_, index, _)| index > 1);
+            if !later_part {
+                found.push(path);
+            }
+        }
+    }
+}
+
+pub fn scan(dir: &Path) -> Result<Vec<GgufSummary>, CommandError> {
+    if !dir.is_dir() {
+        return Err(CommandError::new(
+            ErrorKind::InvalidRequest,
+            format!("{} is not a folder", dir.display()),
+        ));
+    }
+    let mut paths = Vec::new();
+    collect(dir, 0, &mut paths);
+    paths.sort();
+    Ok(paths.iter().map(|path| summarize(path)).collect())
+}
+
+#[tauri::command]
+pub async fn inspect_gguf(path: String) -> Result<GgufInfo, CommandError> {
+    tokio::task::spawn_blocking(move || inspect(Path::new(&path))).await?
+}
+
+// Every GGUF model under `dir`, including subfolders
+#[tauri::command]
+pub async fn scan_gguf_models(dir: String) -> Result<Vec<GgufSummary>, CommandError> {
+    tokio::task::spawn_blocking(move || scan(Path::new(&dir))).await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A GGUF v3 header with string metadata and (name, dims, type) tensors
+    fn gguf(metadata: &[(&str, &str)], tensors: &[(&str, &[u64], u32)]) -> Vec<u8> {
+        fn string(out: &mut Vec<u8>, text: &str) {
+            out.extend_from_slice(&(text.len() as u64).to_le_bytes());
+            out.extend_from_slice(text.as_bytes());
+        }
+
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&3u32.to_le_bytes());
+        out.extend_from_slice(&(tensors.len() as u64).to_le_bytes());
+        out.extend_from_slice(&(metadata.len() as u64).to_le_bytes());
+        for (key, value) in metadata {
+            string(&mut out, key);
+            out.extend_from_slice(&8u32.to_le_bytes());
+            string(&mut out, value);
+        }
+        for (name, dims, tensor_type) in tensors {
+            string(&mut out, name);
+            out.extend_from_slice(&(dims.len() as u32).to_le_bytes());
+            for dim in *dims {
+                out.extend_from_slice(&dim.to_le_bytes());
+            }
+            out.extend_from_slice(&tensor_type.to_le_bytes());
+            out.extend_from_slice(&0u64.to_le_bytes());
+        }
+        out
+    }
+
+    fn inspect_bytes(bytes: &[u8]) -> Result<GgufInfo, CommandError> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("model.gguf");
+        std::fs::write(&path, bytes).unwrap();
+        inspect(&path)
+    }
+
+    #[test]
+    fn reads_the_header() {
+        let info = inspect_bytes(&gguf(
+            &[("general.architecture", "llama"), ("general.name", "Tiny")],
+            &[("token_embd.weight", &[64, 1000], 0), ("output_norm.weight", &[64], 0)],
+        ))
+        .unwrap();
+        assert_eq!(info.architecture.as_deref(), Some("llama"));
+        assert_eq!(info.name.as_deref(), Some("Tiny"));
+        assert_eq!(info.tensor_count, 2);
+        assert_eq!(info.parameter_count, 64_064);
+        assert_eq!(info.size_label.as_deref(), Some("64K"));
+    }
+
+    #[test]
+    fn huge_tensors_saturate() {
+        let huge = u64::MAX / 2;
+        let info = inspect_bytes(&gguf(&[], &[("a", &[huge, 2], 2), ("b", &[huge, 2], 2), ("c", &[4, 4], 2)])).unwrap();
+        assert_eq!(info.parameter_count, u64::MAX);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert_eq!(inspect_bytes(b"PK\x03\x04").unwrap_err().kind, ErrorKind::Parse);
+        let mut truncated = gguf(&[("general.architecture", "llama")], &[]);
+        truncated.truncate(truncated.len() - 2);
+        assert!(inspect_bytes(&truncated).is_err());
+    }
+}
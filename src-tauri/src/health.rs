@@ -0,0 +1,423 @@
+// Background health checks for the configured providers. One task, started
+// with the app, probes every provider on a schedule, backs off while a
+// provider is down and emits `provider-health` to all windows after each
+// check. The provider list is persisted to health.json in the app data dir
+// so checks start before any window has loaded.
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use futures::future::join_all;
+use tauri::{Emitter, Manager, State};
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+use crate::anthropic;
+use crate::app_data;
+use crate::credentials::CredentialStore;
+use crate::egress::{EgressPolicy, EgressPurpose};
+use crate::error::{CommandError, ErrorKind};
+use crate::http_client::HttpClients;
+
+const TARGETS_FILE: &str = "health.json";
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+// How long the task sleeps when nothing is configured, unless woken
+const IDLE_WAIT: Duration = Duration::from_secs(3600);
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthTarget {
+    pub provider_id: String,
+    // Provider type ("ollama", "openai", ...); picks the endpoint to probe
+    pub kind: String,
+    pub base_url: String,
+}
+
+impl HealthTarget {
+    // Cheap GET that only succeeds when the server is up
+    fn probe_path(&self) -> &'static str {
+        match self.kind.as_str() {
+            "ollama" => "/api/version",
+            "llamacpp" => "/health",
+            "openai" => "/models",
+            "anthropic" => "/v1/models",
+            "openrouter" => "/api/v1/models",
+            _ => "/v1/models",
+        }
+    }
+
+    // Cloud APIs reject unauthenticated model listings
+    fn requires_key(&self) -> bool {
+        matches!(self.kind.as_str(), "openai" | "anthropic")
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    // Not checked yet, or couldn't be checked (no API key, locked vault)
+    Unknown,
+    Up,
+    Down,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderHealth {
+    pub provider_id: String,
+    pub status: HealthStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checked_at: Option<String>,
+    // When the status last changed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since: Option<String>,
+    pub consecutive_failures: u32,
+}
+
+enum Outcome {
+    Up(Duration),
+    Down(String),
+    Unknown(String),
+}
+
+struct Tracked {
+    target: HealthTarget,
+    health: ProviderHealth,
+    next_check: Instant,
+}
+
+impl Tracked {
+    fn new(target: HealthTarget) -> Self {
+        Self {
+            health: ProviderHealth {
+                provider_id: target.provider_id.clone(),
+                status: HealthStatus::Unknown,
+                latency_ms: None,
+                error: None,
+                checked_at: None,
+                since: None,
+                consecutive_failures: 0,
+            },
+            target,
+            next_check: Instant::now(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct HealthMonitor {
+    tracked: Arc<Mutex<Vec<Tracked>>>,
+    wake: Arc<Notify>,
+    path: Option<PathBuf>,
+}
+
+// 30s, 60s, 120s, ... while a provider stays down, capped at five minutes
+fn backoff(failures: u32) -> Duration {
+    if failures == 0 {
+        return CHECK_INTERVAL;
+    }
+    CHECK_INTERVAL
+        .saturating_mul(1 << (failures - 1).min(8))
+        .min(MAX_BACKOFF)
+}
+
+async fn probe(
+    target: &HealthTarget,
+    clients: &HttpClients,
+    policy: &EgressPolicy,
+    credentials: &CredentialStore,
+) -> Outcome {
+    let url = format!("{}{}", target.base_url.trim().trim_end_matches('/'), target.probe_path());
+    let url = match policy.check(&url, EgressPurpose::Provider) {
+        Ok(url) => url,
+        Err(err) => return Outcome::Unknown(err.message),
+    };
+
+    let client = match clients.provider_for(&url).await {
+        Ok(profile) => profile.client,
+        Err(err) => return Outcome::Unknown(err.message),
+    };
+    let mut request = match client.get(url).timeout(CHECK_TIMEOUT).build() {
+        Ok(request) => request,
+        Err(err) => return Outcome::Unknown(err.to_string()),
+    };
+    if target.kind == "anthropic" {
+        request
+            .headers_mut()
+            .insert("anthropic-version", reqwest::header::HeaderValue::from_static(anthropic::API_VERSION));
+    }
+    // Local servers take a key when one is stored but don't need it. A key
+    // saved for another origin stays off the request, which goes out
+    // without auth. Checks aren't vault activity, so they don't keep it unlocked.
+    match credentials.authorize_background(&target.provider_id, &mut request) {
+        Ok(()) => {}
+        Err(err) if err.kind == ErrorKind::Blocked => {}
+        Err(err) if target.requires_key() => return Outcome::Unknown(err.message),
+        Err(_) => {}
+    }
+
+    // Health checks run around the clock, so they stay out of the recorder
+    let started = std::time::Instant::now();
+    match client.execute(request).await {
+        Ok(response) if response.status().is_success() => Outcome::Up(started.elapsed()),
+        Ok(response) => Outcome::Down(CommandError::http_status(response.status()).message),
+        Err(err) => Outcome::Down(CommandError::from_reqwest(err, "Health check failed").message),
+    }
+}
+
+impl HealthMonitor {
+    // Reads the persisted provider list; a missing or unreadable file means none
+    pub fn load<R: tauri::Runtime>(app: &tauri::AppHandle<R>) -> Self {
+        let path = app_data::path(app, TARGETS_FILE);
+        let targets: Vec<HealthTarget> = app_data::read(path.as_deref());
+        Self {
+            tracked: Arc::new(Mutex::new(targets.into_iter().map(Tracked::new).collect())),
+            wake: Arc::new(Notify::new()),
+            path,
+        }
+    }
+
+    fn tracked(&self) -> MutexGuard<'_, Vec<Tracked>> {
+        self.tracked.lock().unwrap()
+    }
+
+    fn save(&self, targets: &[HealthTarget]) -> Result<(), CommandError> {
+        app_data::write(self.path.as_deref(), targets)
+    }
+
+    pub fn snapshot(&self) -> Vec<ProviderHealth> {
+        self.tracked().iter().map(|tracked| tracked.health.clone()).collect()
+    }
+
+    // Replaces the provider list. Providers whose type and URL didn't change
+    // keep their history; new ones are checked right away.
+    pub fn set_targets(&self, targets: Vec<HealthTarget>) -> Result<(), CommandError> {
+        self.save(&targets)?;
+        {
+            let mut tracked = self.tracked();
+            let mut previous: HashMap<String, Tracked> = tracked
+                .drain(..)
+                .map(|entry| (entry.target.provider_id.clone(), entry))
+                .collect();
+            for target in targets {
+                let entry = match previous.remove(&target.provider_id) {
+                    Some(entry) if entry.target == target => entry,
+                    _ => Tracked::new(target),
+                };
+                tracked.push(entry);
+            }
+        }
+        self.wake.notify_one();
+        Ok(())
+    }
+
+    fn targets(&self, provider_ids: Option<&[String]>) -> Vec<HealthTarget> {
+        self.tracked()
+            .iter()
+            .filter(|tracked| provider_ids.is_none_or(|ids| ids.contains(&tracked.target.provider_id)))
+            .map(|tracked| tracked.target.clone())
+            .collect()
+    }
+
+    // Targets whose next check is due; they're pushed back a full interval
+    // so a slow check isn't started twice
+    fn take_due(&self) -> Vec<HealthTarget> {
+        let now = Instant::now();
+        let mut tracked = self.tracked();
+        tracked
+            .iter_mut()
+            .filter(|tracked| tracked.next_check <= now)
+            .map(|tracked| {
+                tracked.next_check = now + CHECK_INTERVAL;
+                tracked.target.clone()
+            })
+            .collect()
+    }
+
+    fn next_wake(&self) -> Instant {
+        self.tracked()
+            .iter()
+            .map(|tracked| tracked.next_check)
+            .min()
+            .unwrap_or_else(|| Instant::now() + IDLE_WAIT)
+    }
+
+    // Applies a check result; None when the provider was removed or changed
+    // while it was being checked
+    fn record(&self, target: &HealthTarget, outcome: Outcome) -> Option<ProviderHealth> {
+        let mut tracked = self.tracked();
+        let entry = tracked.iter_mut().find(|tracked| &tracked.target == target)?;
+        let health = &mut entry.health;
+        let previous = health.status;
+        let now = chrono::Utc::now().to_rfc3339();
+
+        match outcome {
+            Outcome::Up(latency) => {
+                health.status = HealthStatus::Up;
+                health.latency_ms = Some(latency.as_millis() as u64);
+                health.error = None;
+                health.consecutive_failures = 0;
+            }
+            Outcome::Down(error) => {
+                health.status = HealthStatus::Down;
+                health.latency_ms = None;
+                health.error = Some(error);
+                health.consecutive_failures += 1;
+            }
+            Outcome::Unknown(error) => {
+                health.status = HealthStatus::Unknown;
+                health.latency_ms = None;
+                health.error = Some(error);
+                health.consecutive_failures = 0;
+            }
+        }
+        health.checked_at = Some(now.clone());
+        if health.status != previous {
+            if previous != HealthStatus::Unknown || health.status == HealthStatus::Down {
+                eprintln!(
+                    "[Health] {} is now {:?}{}",
+                    health.provider_id,
+                    health.status,
+                    health.error.as_deref().map(|e| format!(" ({})", e)).unwrap_or_default()
+                );
+            }
+            health.since = Some(now);
+        }
+        entry.next_check = Instant::now() + backoff(health.consecutive_failures);
+        Some(health.clone())
+    }
+
+    // Checks the targets concurrently and emits each result
+    async fn check<R: tauri::Runtime>(&self, app: &tauri::AppHandle<R>, targets: Vec<HealthTarget>) -> Vec<ProviderHealth> {
+        let clients = app.state::<HttpClients>();
+        let policy = app.state::<EgressPolicy>();
+        let credentials = app.state::<CredentialStore>();
+        let outcomes = join_all(
+            targets
+                .iter()
+                .map(|target| probe(target, clients.inner(), policy.inner(), credentials.inner())),
+        )
+        .await;
+
+        let mut results = Vec::new();
+        for (target, outcome) in targets.iter().zip(outcomes) {
+            if let Some(health) = self.record(target, outcome) {
+                let _ = app.emit("provider-health", &health);
+                results.push(health);
+            }
+        }
+        results
+    }
+}
+
+pub fn spawn_monitor<R: tauri::Runtime>(app: tauri::AppHandle<R>, monitor: HealthMonitor) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let due = monitor.take_due();
+            if !due.is_empty() {
+                monitor.check(&app, due).await;
+            }
+            tokio::select! {
+                _ = tokio::time::sleep_until(monitor.next_wake()) => {}
+                _ = monitor.wake.notified() => {}
+            }
+        }
+    });
+}
+
+#[tauri::command]
+pub fn get_provider_health(monitor: State<'_, HealthMonitor>) -> Vec<ProviderHealth> {
+    monitor.snapshot()
+}
+
+#[tauri::command]
+pub fn set_health_targets(targets: Vec<HealthTarget>, monitor: State<'_, HealthMonitor>) -> Result<(), CommandError> {
+    monitor.set_targets(targets)
+}
+
+// Checks now instead of waiting for the schedule; all providers when
+// `provider_ids` is left out
+#[tauri::command]
+pub async fn check_provider_health(
+    provider_ids: Option<Vec<String>>,
+    app: tauri::AppHandle,
+    monitor: State<'_, HealthMonitor>,
+) -> Result<Vec<ProviderHealth>, CommandError> {
+    let targets = monitor.targets(provider_ids.as_deref());
+    Ok(monitor.check(&app, targets).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::egress::EgressPolicySettings;
+    use crate::http_client::HttpClientSettings;
+    use crate::network::NetworkSettings;
+    use crate::vault::Vault;
+
+    // Answers one request with 200 and hands back its head
+    fn serve_once() -> (String, std::thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut head = Vec::new();
+            let mut buffer = [0u8; 1024];
+            while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+                let read = stream.read(&mut buffer).unwrap();
+                if read == 0 {
+                    break;
+                }
+                head.extend_from_slice(&buffer[..read]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}")
+                .unwrap();
+            String::from_utf8_lossy(&head).to_lowercase()
+        });
+        (base_url, server)
+    }
+
+    fn probe_with_key(key_base_url: impl FnOnce(&str) -> String) -> (Outcome, String) {
+        let dir = tempfile::tempdir().unwrap();
+        let credentials = CredentialStore::open_dir(None, Vault::open_dir(Some(dir.path().to_path_buf())));
+        let policy = EgressPolicy::new(EgressPolicySettings::default()).unwrap();
+        let clients =
+            HttpClients::new(HttpClientSettings::default(), NetworkSettings::default(), policy.clone()).unwrap();
+
+        let (base_url, server) = serve_once();
+        credentials.save("openai", "sk-test", &key_base_url(&base_url), None).unwrap();
+        let target = HealthTarget {
+            provider_id: "openai".to_string(),
+            kind: "openai".to_string(),
+            base_url,
+        };
+        let outcome = tauri::async_runtime::block_on(probe(&target, &clients, &policy, &credentials));
+        (outcome, server.join().unwrap())
+    }
+
+    #[test]
+    fn probes_carry_the_key_on_its_origin() {
+        let (outcome, head) = probe_with_key(|base_url| base_url.to_string());
+        assert!(matches!(outcome, Outcome::Up(_)));
+        assert!(head.contains("authorization: bearer sk-test"));
+    }
+
+    #[test]
+    fn probes_elsewhere_go_without_the_key() {
+        let (outcome, head) = probe_with_key(|_| "https://api.openai.com/v1".to_string());
+        assert!(matches!(outcome, Outcome::Up(_)));
+        assert!(!head.contains("authorization"));
+        assert!(!head.contains("sk-test"));
+    }
+}
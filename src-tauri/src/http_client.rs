@@ -0,0 +1,370 @@
+// Shared HTTP clients held in Tauri managed state so connections, TLS
+// sessions and keep-alive are reused across commands
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use reqwest::Url;
+
+use crate::app_data;
+use crate::egress::{EgressPolicy, EgressPurpose};
+use crate::error::{CommandError, ErrorKind};
+use crate::network::NetworkSettings;
+
+const SETTINGS_FILE: &str = "http.json";
+const BROWSER_USER_AGENT: &str =
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:109.0) Gecko/20100101 Firefox/115.0";
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpProfileSettings {
+    // Default total timeout for non-streaming requests
+    pub request_timeout_secs: u64,
+    pub connect_timeout_secs: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    pub pool_max_idle_per_host: usize,
+    pub pool_idle_timeout_secs: u64,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpClientSettings {
+    // LLM provider traffic: long timeouts, generation can take minutes
+    pub provider: HttpProfileSettings,
+    // Web search and scraping: browser-like user agent, short timeouts
+    pub scraping: HttpProfileSettings,
+}
+
+impl Default for HttpClientSettings {
+    fn default() -> Self {
+        Self {
+            provider: HttpProfileSettings {
+                request_timeout_secs: 300,
+                connect_timeout_secs: 30,
+                user_agent: None,
+                pool_max_idle_per_host: 8,
+                pool_idle_timeout_secs: 90,
+            },
+            scraping: HttpProfileSettings {
+                request_timeout_secs: 20,
+                connect_timeout_secs: 10,
+                user_agent: Some(BROWSER_USER_AGENT.to_string()),
+                pool_max_idle_per_host: 16,
+                pool_idle_timeout_secs: 60,
+            },
+        }
+    }
+}
+
+// A built client together with the profile it was built from
+#[derive(Clone)]
+pub struct HttpProfile {
+    pub client: reqwest::Client,
+    pub request_timeout: Duration,
+}
+
+// A profile's regular client, plus one that accepts invalid certificates
+// when the network settings list hosts for it
+struct ProfileClients {
+    secure: HttpProfile,
+    insecure: Option<HttpProfile>,
+}
+
+impl ProfileClients {
+    fn for_url(&self, url: &Url, network: &NetworkSettings) -> HttpProfile {
+        match &self.insecure {
+            Some(insecure) if network.is_insecure_host(url) => insecure.clone(),
+            _ => self.secure.clone(),
+        }
+    }
+}
+
+struct ClientSet {
+    settings: HttpClientSettings,
+    network: NetworkSettings,
+    provider: ProfileClients,
+    scraping: ProfileClients,
+}
+
+// Cheap to clone; clones share the same clients
+#[derive(Clone)]
+pub struct HttpClients {
+    current: Arc<RwLock<Arc<ClientSet>>>,
+    policy: EgressPolicy,
+}
+
+fn build_profile(
+    profile: &HttpProfileSettings,
+    network: &NetworkSettings,
+    policy: &EgressPolicy,
+    purpose: EgressPurpose,
+    accept_invalid_certs: bool,
+) -> Result<HttpProfile, CommandError> {
+    let mut builder = reqwest::Client::builder()
+        // Resolved addresses and redirect hops are checked against the egress policy
+        .dns_resolver(policy.resolver(purpose))
+        .connect_timeout(Duration::from_secs(profile.connect_timeout_secs))
+        .pool_max_idle_per_host(profile.pool_max_idle_per_host)
+        .pool_idle_timeout(Duration::from_secs(profile.pool_idle_timeout_secs));
+
+    builder = if accept_invalid_certs {
+        // Never follow a redirect away from a host we trust blindly
+        builder
+            .danger_accept_invalid_certs(true)
+            .redirect(reqwest::redirect::Policy::none())
+    } else {
+        builder.redirect(policy.redirect_policy(purpose, network.proxy.is_some()))
+    };
+
+    builder = match network.reqwest_proxy()? {
+        Some(proxy) => builder.proxy(proxy),
+        // Ignore HTTP(S)_PROXY from the environment; the settings are explicit
+        None => builder.no_proxy(),
+    };
+
+    for certificate in network.root_certificates()? {
+        builder = builder.add_root_certificate(certificate);
+    }
+
+    if let Some(user_agent) = &profile.user_agent {
+        builder = builder.user_agent(user_agent.as_str());
+    }
+
+    let client = builder
+        .build()
+        .map_err(|err| CommandError::new(ErrorKind::Internal, format!("Failed to build HTTP client: {err}")))?;
+
+    Ok(HttpProfile {
+        client,
+        request_timeout: Duration::from_secs(profile.request_timeout_secs),
+    })
+}
+
+fn build_profile_clients(
+    profile: &HttpProfileSettings,
+    network: &NetworkSettings,
+    policy: &EgressPolicy,
+    purpose: EgressPurpose,
+) -> Result<ProfileClients, CommandError> {
+    let insecure = if network.insecure_hosts.is_empty() {
+        None
+    } else {
+        Some(build_profile(profile, network, policy, purpose, true)?)
+    };
+    Ok(ProfileClients {
+        secure: build_profile(profile, network, policy, purpose, false)?,
+        insecure,
+    })
+}
+
+fn build_client_set(
+    settings: HttpClientSettings,
+    network: NetworkSettings,
+    policy: &EgressPolicy,
+) -> Result<ClientSet, CommandError> {
+    Ok(ClientSet {
+        provider: build_profile_clients(&settings.provider, &network, policy, EgressPurpose::Provider)?,
+        scraping: build_profile_clients(&settings.scraping, &network, policy, EgressPurpose::Web)?,
+        settings,
+        network,
+    })
+}
+
+impl HttpClients {
+    pub fn new(
+        settings: HttpClientSettings,
+        network: NetworkSettings,
+        policy: EgressPolicy,
+    ) -> Result<Self, CommandError> {
+        Ok(Self {
+            current: Arc::new(RwLock::new(Arc::new(build_client_set(settings, network, &policy)?))),
+            policy,
+        })
+    }
+
+    fn current(&self) -> Arc<ClientSet> {
+        self.current.read().unwrap().clone()
+    }
+
+    // Behind a proxy the target host is resolved by the proxy, out of sight
+    // of the egress resolver, so it's resolved and checked here first
+    async fn check_proxied(&self, current: &ClientSet, url: &Url, purpose: EgressPurpose) -> Result<(), CommandError> {
+        if current.network.proxy.is_some() {
+            self.policy.check_proxied(url, purpose).await?;
+        }
+        Ok(())
+    }
+
+    // Provider client for a request to `url`
+    pub async fn provider_for(&self, url: &Url) -> Result<HttpProfile, CommandError> {
+        let current = self.current();
+        self.check_proxied(&current, url, EgressPurpose::Provider).await?;
+        Ok(current.provider.for_url(url, &current.network))
+    }
+
+    // Scraping client for a request to `url`
+    pub async fn scraping_for(&self, url: &Url) -> Result<HttpProfile, CommandError> {
+        let current = self.current();
+        self.check_proxied(&current, url, EgressPurpose::Web).await?;
+        Ok(current.scraping.for_url(url, &current.network))
+    }
+
+    pub fn settings(&self) -> HttpClientSettings {
+        self.current().settings.clone()
+    }
+
+    pub fn network(&self) -> NetworkSettings {
+        self.current().network.clone()
+    }
+
+    // Rebuilds all clients; requests already in flight keep the old ones
+    pub fn update(&self, settings: HttpClientSettings) -> Result<(), CommandError> {
+        let set = build_client_set(settings, self.network(), &self.policy)?;
+        *self.current.write().unwrap() = Arc::new(set);
+        Ok(())
+    }
+
+    pub fn update_network(&self, network: NetworkSettings) -> Result<(), CommandError> {
+        let clients = self.prepare_network(network)?;
+        self.install(clients);
+        Ok(())
+    }
+
+    // Builds clients for new network settings without using them yet, so
+    // the settings can be checked before anything else changes
+    pub fn prepare_network(&self, network: NetworkSettings) -> Result<PreparedClients, CommandError> {
+        Ok(PreparedClients(build_client_set(self.settings(), network, &self.policy)?))
+    }
+
+    pub fn install(&self, clients: PreparedClients) {
+        *self.current.write().unwrap() = Arc::new(clients.0);
+    }
+}
+
+pub struct PreparedClients(ClientSet);
+
+// Reads the persisted settings; a missing or unreadable file means defaults
+pub fn load<R: tauri::Runtime>(app: &tauri::AppHandle<R>) -> HttpClientSettings {
+    app_data::load(app, SETTINGS_FILE)
+}
+
+#[tauri::command]
+pub fn get_http_settings(clients: tauri::State<'_, HttpClients>) -> HttpClientSettings {
+    clients.settings()
+}
+
+// Settings the clients can't be built from are rejected before anything is saved
+#[tauri::command]
+pub fn update_http_settings(
+    settings: HttpClientSettings,
+    app: tauri::AppHandle,
+    clients: tauri::State<'_, HttpClients>,
+) -> Result<(), CommandError> {
+    clients.update(settings.clone())?;
+    app_data::save(&app, SETTINGS_FILE, &settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::egress::EgressPolicySettings;
+
+    fn policy() -> EgressPolicy {
+        EgressPolicy::new(EgressPolicySettings::default()).unwrap()
+    }
+
+    fn profile(timeout_secs: u64) -> HttpProfile {
+        HttpProfile {
+            client: reqwest::Client::new(),
+            request_timeout: Duration::from_secs(timeout_secs),
+        }
+    }
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn providers_and_scraping_get_their_own_profile() {
+        let clients = HttpClients::new(HttpClientSettings::default(), NetworkSettings::default(), policy()).unwrap();
+        let target = url("https://api.example.com/v1/models");
+
+        let (provider, scraping) = tauri::async_runtime::block_on(async {
+            (clients.provider_for(&target).await.unwrap(), clients.scraping_for(&target).await.unwrap())
+        });
+        assert_eq!(provider.request_timeout, Duration::from_secs(300));
+        assert_eq!(scraping.request_timeout, Duration::from_secs(20));
+    }
+
+    #[test]
+    fn insecure_client_is_only_built_when_hosts_are_listed() {
+        let settings = HttpClientSettings::default();
+        let network = NetworkSettings::default();
+        let clients = build_profile_clients(&settings.provider, &network, &policy(), EgressPurpose::Provider).unwrap();
+        assert!(clients.insecure.is_none());
+
+        let network = NetworkSettings {
+            insecure_hosts: vec!["gpu-box.lan".to_string()],
+            ..NetworkSettings::default()
+        };
+        let clients = build_profile_clients(&settings.provider, &network, &policy(), EgressPurpose::Provider).unwrap();
+        assert!(clients.insecure.is_some());
+    }
+
+    #[test]
+    fn insecure_client_is_only_used_for_listed_hosts() {
+        let network = NetworkSettings {
+            insecure_hosts: vec!["GPU-box.lan".to_string(), " ::1 ".to_string()],
+            ..NetworkSettings::default()
+        };
+        let clients = ProfileClients {
+            secure: profile(1),
+            insecure: Some(profile(2)),
+        };
+        let timeout = |target: &str| clients.for_url(&url(target), &network).request_timeout.as_secs();
+
+        assert_eq!(timeout("https://gpu-box.lan:8443/v1"), 2);
+        assert_eq!(timeout("https://[::1]:8443/v1"), 2);
+        assert_eq!(timeout("https://api.example.com/v1"), 1);
+        assert_eq!(timeout("https://sub.gpu-box.lan/v1"), 1);
+
+        // Without an insecure client every host gets the regular one
+        let clients = ProfileClients {
+            secure: profile(1),
+            insecure: None,
+        };
+        assert_eq!(clients.for_url(&url("https://gpu-box.lan/"), &network).request_timeout.as_secs(), 1);
+    }
+
+    #[test]
+    fn updates_keep_the_other_half_of_the_settings() {
+        let clients = HttpClients::new(HttpClientSettings::default(), NetworkSettings::default(), policy()).unwrap();
+
+        let mut settings = HttpClientSettings::default();
+        settings.scraping.request_timeout_secs = 45;
+        clients.update(settings).unwrap();
+        clients
+            .update_network(NetworkSettings {
+                insecure_hosts: vec!["gpu-box.lan".to_string()],
+                ..NetworkSettings::default()
+            })
+            .unwrap();
+
+        assert_eq!(clients.settings().scraping.request_timeout_secs, 45);
+        assert_eq!(clients.network().insecure_hosts, vec!["gpu-box.lan".to_string()]);
+        assert!(clients.current().provider.insecure.is_some());
+    }
+
+    #[test]
+    fn settings_round_trip_through_json() {
+        let mut settings = HttpClientSettings::default();
+        settings.provider.user_agent = Some("OpenChat".to_string());
+        let json = serde_json::to_value(&settings).unwrap();
+        assert_eq!(json["provider"]["requestTimeoutSecs"], 300);
+        assert!(json["scraping"]["userAgent"].is_string());
+
+        let parsed: HttpClientSettings = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.provider.user_agent.as_deref(), Some("OpenChat"));
+        assert_eq!(parsed.scraping.pool_max_idle_per_host, 16);
+    }
+}
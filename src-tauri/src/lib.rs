@@ -1,143 +1,530 @@
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
+mod anthropic;
+mod app_data;
+mod cancellation;
+mod chat;
+mod chat_template;
+mod credentials;
+mod discovery;
+mod egress;
+mod error;
+mod gbnf;
+mod gguf;
+mod health;
+mod http_client;
+mod llama_server;
+#[cfg(feature = "local-inference")]
+mod local_engine;
+mod local_inference;
+mod network;
+mod ollama;
+mod openai_compat;
+mod pulls;
+mod rate_limit;
+mod recorder;
+mod sse;
+mod tool_emulation;
+mod vault;
+
+use std::collections::HashMap;
 use std::time::Duration;
 
-use reqwest::blocking::Client;
 use reqwest::Url;
 use headless_chrome::{Browser, LaunchOptions};
 use tokio::time::timeout;
 use futures::future::join_all;
+use tauri::{Manager, State};
+
+use cancellation::{RequestRegistry, RequestToken};
+use credentials::CredentialStore;
+use egress::{EgressPolicy, EgressPolicySettings, EgressPurpose};
+use error::{CommandError, ErrorKind};
+use health::HealthMonitor;
+use http_client::{HttpClientSettings, HttpClients};
+use llama_server::LlamaServers;
+use local_inference::LocalModels;
+use network::{BrowserNetwork, NetworkSettings};
+use pulls::PullManager;
+use rate_limit::{RateLimitSettings, RateLimiter};
+use recorder::{ExchangeSource, Recorder, RecorderSettings, Recording};
+use vault::Vault;
 
 #[tauri::command]
 fn greet(name: &str) -> String {
     format!("Hello, {}! You've been greeted from Rust!", name)
 }
 
+// Full response returned by proxy_http_request; non-2xx statuses are not
+// errors so provider error bodies (401, 429, 404...) reach the UI intact
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProxyResponse {
+    status: u16,
+    status_text: String,
+    headers: HashMap<String, String>,
+    body: String,
+}
+
+// Helper function to build a proxied request with caller-supplied method and headers
+fn build_proxy_request(
+    client: &reqwest::Client,
+    url: &str,
+    method: &str,
+    body: Option<String>,
+    headers: Option<HashMap<String, String>>,
+) -> Result<reqwest::RequestBuilder, CommandError> {
+    let method = match method.to_uppercase().as_str() {
+        "GET" => reqwest::Method::GET,
+        "POST" => reqwest::Method::POST,
+        "PUT" => reqwest::Method::PUT,
+        "PATCH" => reqwest::Method::PATCH,
+        "DELETE" => reqwest::Method::DELETE,
+        "HEAD" => reqwest::Method::HEAD,
+        "OPTIONS" => reqwest::Method::OPTIONS,
+        _ => {
+            return Err(CommandError::new(
+                ErrorKind::InvalidRequest,
+                format!("Unsupported method: {}", method),
+            ))
+        }
+    };
+
+    let headers = headers.unwrap_or_default();
+    let has_content_type = headers.keys().any(|k| k.eq_ignore_ascii_case("content-type"));
+
+    let mut request = client.request(method, url);
+    for (name, value) in &headers {
+        request = request.header(name.as_str(), value.as_str());
+    }
+
+    if let Some(b) = body {
+        // Keep the old default for callers that only send JSON bodies
+        if !has_content_type {
+            request = request.header("Content-Type", "application/json");
+        }
+        request = request.body(b);
+    }
+
+    Ok(request)
+}
+
+// Helper function to collect response headers into a plain map
+fn collect_headers(headers: &reqwest::header::HeaderMap) -> HashMap<String, String> {
+    let mut map = HashMap::new();
+    for (name, value) in headers {
+        if let Ok(value) = value.to_str() {
+            map.entry(name.as_str().to_string())
+                .and_modify(|existing: &mut String| {
+                    existing.push_str(", ");
+                    existing.push_str(value);
+                })
+                .or_insert_with(|| value.to_string());
+        }
+    }
+    map
+}
+
 #[tauri::command]
-async fn proxy_http_request(url: String, method: String, body: Option<String>) -> Result<String, String> {
+#[allow(clippy::too_many_arguments)]
+async fn proxy_http_request(
+    url: String,
+    method: String,
+    body: Option<String>,
+    headers: Option<HashMap<String, String>>,
+    provider_id: Option<String>,
+    request_id: Option<String>,
+    requests: State<'_, RequestRegistry>,
+    clients: State<'_, HttpClients>,
+    credentials: State<'_, CredentialStore>,
+    policy: State<'_, EgressPolicy>,
+    recorder: State<'_, Recorder>,
+) -> Result<ProxyResponse, CommandError> {
     eprintln!("[Rust Proxy] Request: {} {}", method, url);
     
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(30))
-        .build()
-        .map_err(|e| format!("Failed to build client: {}", e))?;
-    
-    let request = match method.to_uppercase().as_str() {
-        "GET" => client.get(&url),
-        "POST" => {
-            let mut req = client.post(&url);
-            if let Some(b) = body {
-                req = req.header("Content-Type", "application/json").body(b);
+    let url = policy.check(&url, EgressPurpose::Provider)?;
+    let provider = clients.provider_for(&url).await?;
+    let mut request = build_proxy_request(&provider.client, url.as_str(), &method, body, headers)?
+        .timeout(provider.request_timeout)
+        .build()?;
+    if let Some(provider_id) = &provider_id {
+        credentials.authorize(provider_id, &mut request)?;
+    }
+    let mut recording = recorder.start(ExchangeSource::Proxy, &request);
+    
+    requests.run(request_id, |_| async move {
+        let result = async {
+            let response = provider.client.execute(request).await?;
+            recording.response(&response);
+            
+            let status = response.status();
+            eprintln!("[Rust Proxy] Response status: {}", status);
+            
+            let headers = collect_headers(response.headers());
+            
+            let text = response
+                .text()
+                .await
+                .map_err(|e| CommandError::from_reqwest(e, "Failed to read response"))?;
+            recording.body(&text);
+            
+            eprintln!("[Rust Proxy] Response length: {} bytes", text.len());
+            Ok(ProxyResponse {
+                status: status.as_u16(),
+                status_text: status.canonical_reason().unwrap_or("").to_string(),
+                headers,
+                body: text,
+            })
+        }.await;
+        recording.finish_with(&result);
+        result
+    }).await
+}
+
+// Messages sent to the frontend while a proxied response is streamed
+#[derive(Clone, serde::Serialize)]
+#[serde(tag = "event", content = "data", rename_all = "camelCase")]
+enum ProxyStreamEvent {
+    Started { status: u16, headers: HashMap<String, String> },
+    Chunk { text: String },
+    #[serde(rename_all = "camelCase")]
+    Finished { status: u16, total_bytes: usize },
+    Error(CommandError),
+}
+
+// Decodes `buffer` up to an incomplete trailing sequence, which is kept for
+// the next chunk. Invalid bytes elsewhere become U+FFFD.
+fn take_utf8_prefix(buffer: &mut Vec<u8>) -> String {
+    let mut text = String::new();
+    let mut start = 0;
+    while start < buffer.len() {
+        match std::str::from_utf8(&buffer[start..]) {
+            Ok(valid) => {
+                text.push_str(valid);
+                start = buffer.len();
+            }
+            Err(err) => {
+                let valid_up_to = start + err.valid_up_to();
+                text.push_str(&String::from_utf8_lossy(&buffer[start..valid_up_to]));
+                match err.error_len() {
+                    Some(len) => {
+                        text.push(char::REPLACEMENT_CHARACTER);
+                        start = valid_up_to + len;
+                    }
+                    None => {
+                        start = valid_up_to;
+                        break;
+                    }
+                }
             }
-            req
         }
-        _ => return Err(format!("Unsupported method: {}", method)),
-    };
-    
-    let response = request
-        .send()
-        .await
-        .map_err(|e| format!("Request failed: {}", e))?;
-    
-    let status = response.status();
-    eprintln!("[Rust Proxy] Response status: {}", status);
-    
-    if !status.is_success() {
-        return Err(format!("Request failed with status: {}", status));
     }
-    
-    let text = response
-        .text()
-        .await
-        .map_err(|e| format!("Failed to read response: {}", e))?;
-    
-    eprintln!("[Rust Proxy] Response length: {} bytes", text.len());
-    Ok(text)
+    buffer.drain(..start);
+    text
 }
 
+// Streaming variant of proxy_http_request: chunks are forwarded over the
+// channel as they arrive (NDJSON from Ollama, SSE from OpenAI-style APIs)
 #[tauri::command]
-fn fetch_url(url: String) -> Result<String, String> {
-    let parsed = Url::parse(&url).map_err(|err| format!("Invalid URL: {err}"))?;
+#[allow(clippy::too_many_arguments)]
+async fn proxy_http_request_stream(
+    url: String,
+    method: String,
+    body: Option<String>,
+    headers: Option<HashMap<String, String>>,
+    provider_id: Option<String>,
+    request_id: Option<String>,
+    on_event: tauri::ipc::Channel<ProxyStreamEvent>,
+    requests: State<'_, RequestRegistry>,
+    clients: State<'_, HttpClients>,
+    credentials: State<'_, CredentialStore>,
+    policy: State<'_, EgressPolicy>,
+    recorder: State<'_, Recorder>,
+) -> Result<(), CommandError> {
+    eprintln!("[Rust Proxy] Stream request: {} {}", method, url);
 
-    match parsed.scheme() {
-        "http" | "https" => {}
-        _ => return Err("Only http and https schemes are allowed".to_string()),
+    let url = policy.check(&url, EgressPurpose::Provider)?;
+
+    // No overall timeout here: generation can legitimately take minutes
+    let client = clients.provider_for(&url).await?.client;
+    let mut request = build_proxy_request(&client, url.as_str(), &method, body, headers)?.build()?;
+    if let Some(provider_id) = &provider_id {
+        credentials.authorize(provider_id, &mut request)?;
+    }
+    let mut recording = recorder.start(ExchangeSource::ProxyStream, &request);
+
+    let result = requests
+        .run(request_id, |_| async {
+            let result = stream_proxy_response(&client, request, &on_event, &mut recording).await;
+            recording.finish_with(&result);
+            result
+        })
+        .await;
+
+    if let Err(err) = &result {
+        let _ = on_event.send(ProxyStreamEvent::Error(err.clone()));
+    }
+    result
+}
+
+async fn stream_proxy_response(
+    client: &reqwest::Client,
+    request: reqwest::Request,
+    on_event: &tauri::ipc::Channel<ProxyStreamEvent>,
+    recording: &mut Recording,
+) -> Result<(), CommandError> {
+    let mut response = client.execute(request).await?;
+    recording.response(&response);
+
+    let status = response.status();
+    eprintln!("[Rust Proxy] Stream response status: {}", status);
+
+    let headers = collect_headers(response.headers());
+
+    on_event.send(ProxyStreamEvent::Started { status: status.as_u16(), headers })?;
+
+    let mut pending = Vec::new();
+    let mut total_bytes = 0;
+
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| CommandError::from_reqwest(e, "Failed to read response"))?
+    {
+        total_bytes += chunk.len();
+        pending.extend_from_slice(&chunk);
+        let text = take_utf8_prefix(&mut pending);
+        if !text.is_empty() {
+            recording.body(&text);
+            on_event.send(ProxyStreamEvent::Chunk { text })?;
+        }
     }
 
-    let client = Client::builder()
-        .timeout(Duration::from_secs(20))
-        .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:109.0) Gecko/20100101 Firefox/115.0")
-        .build()
-        .map_err(|err| format!("Failed to build HTTP client: {err}"))?;
+    if !pending.is_empty() {
+        let text = String::from_utf8_lossy(&pending).into_owned();
+        recording.body(&text);
+        let _ = on_event.send(ProxyStreamEvent::Chunk { text });
+    }
+
+    eprintln!("[Rust Proxy] Stream finished: {} bytes", total_bytes);
+    on_event.send(ProxyStreamEvent::Finished { status: status.as_u16(), total_bytes })?;
+
+    Ok(())
+}
+
+// Helper function to send a scraping request and return the body of a
+// successful response. Waits for the host's rate limit and records the exchange.
+async fn fetch_text(
+    client: &reqwest::Client,
+    request: reqwest::RequestBuilder,
+    recorder: &Recorder,
+    limiter: &RateLimiter,
+    source: ExchangeSource,
+) -> Result<String, CommandError> {
+    let request = request.build()?;
+    limiter.acquire(request.url()).await;
+    send_text(client, request, recorder, limiter, source).await
+}
+
+// fetch_text for callers that already waited for the rate limit
+async fn send_text(
+    client: &reqwest::Client,
+    request: reqwest::Request,
+    recorder: &Recorder,
+    limiter: &RateLimiter,
+    source: ExchangeSource,
+) -> Result<String, CommandError> {
+    let url = request.url().clone();
+    let mut recording = recorder.start(source, &request);
+
+    let result = async {
+        let response = client.execute(request).await?;
+        limiter.observe(&url, &response);
+        recording.response(&response);
+
+        let status = response.status();
+        let text = response
+            .text()
+            .await
+            .map_err(|err| CommandError::from_reqwest(err, "Failed to read response body"))?;
+        recording.body(&text);
 
-    let response = client
-        .get(parsed.clone())
+        if !status.is_success() {
+            return Err(CommandError::http_status(status).with_url(url.as_str()));
+        }
+        Ok(text)
+    }.await;
+
+    recording.finish_with(&result);
+    result
+}
+
+#[tauri::command]
+async fn fetch_url(
+    url: String,
+    request_id: Option<String>,
+    requests: State<'_, RequestRegistry>,
+    clients: State<'_, HttpClients>,
+    policy: State<'_, EgressPolicy>,
+    recorder: State<'_, Recorder>,
+    limiter: State<'_, RateLimiter>,
+) -> Result<String, CommandError> {
+    let parsed = policy.check(&url, EgressPurpose::Web)?;
+
+    let scraping = clients.scraping_for(&parsed).await?;
+    let request = scraping
+        .client
+        .get(parsed)
+        .timeout(scraping.request_timeout)
         .header("Accept", "text/html,application/xhtml+xml,application/xml;q=0.9,image/webp,*/*;q=0.8")
         .header("Accept-Language", "en-US,en;q=0.9")
         .header("Accept-Encoding", "gzip, deflate, br")
         .header("DNT", "1")
         .header("Connection", "keep-alive")
-        .header("Upgrade-Insecure-Requests", "1")
-        .send()
-        .map_err(|err| format!("Request failed: {err}"))?;
-
-    if !response.status().is_success() {
-        return Err(format!("Request failed with status {}", response.status()));
-    }
+        .header("Upgrade-Insecure-Requests", "1");
+    let recorder = recorder.inner();
+    let limiter = limiter.inner();
 
-    response
-        .text()
-        .map_err(|err| format!("Failed to read response body: {err}"))
+    requests.run(request_id, |_| async move {
+        fetch_text(&scraping.client, request, recorder, limiter, ExchangeSource::Fetch).await
+    }).await
 }
 
 #[tauri::command]
-fn fetch_url_browser(url: String, browser_path: Option<String>) -> Result<String, String> {
-    let parsed = Url::parse(&url).map_err(|err| format!("Invalid URL: {err}"))?;
+#[allow(clippy::too_many_arguments)]
+async fn fetch_url_browser(
+    url: String,
+    browser_path: Option<String>,
+    request_id: Option<String>,
+    requests: State<'_, RequestRegistry>,
+    clients: State<'_, HttpClients>,
+    policy: State<'_, EgressPolicy>,
+    recorder: State<'_, Recorder>,
+    limiter: State<'_, RateLimiter>,
+) -> Result<String, CommandError> {
+    let parsed = policy.check(&url, EgressPurpose::Web)?;
+    let policy = policy.inner().clone();
+    let network = clients.network();
+    let limiter = limiter.inner().clone();
+    let mut recording = recorder.start_browser(&url);
 
-    match parsed.scheme() {
-        "http" | "https" => {}
-        _ => return Err("Only http and https schemes are allowed".to_string()),
-    }
+    requests.run(request_id, |token| async move {
+        let result = tokio::task::spawn_blocking(move || {
+            limiter.acquire_blocking(&parsed, token.as_ref())?;
+            fetch_with_browser(&url, browser_path, &policy, &network, token.as_ref())
+        })
+        .await
+        .map_err(CommandError::from)
+        .and_then(|result| result);
+
+        if let Ok(html) = &result {
+            recording.browser_response();
+            recording.body(html);
+        }
+        recording.finish_with(&result);
+        result
+    }).await
+}
+
+fn fetch_with_browser(
+    url: &str,
+    browser_path: Option<String>,
+    policy: &EgressPolicy,
+    network: &NetworkSettings,
+    token: Option<&RequestToken>,
+) -> Result<String, CommandError> {
+    let parsed = policy.check(url, EgressPurpose::Web)?;
+
+    // Chrome resolves hosts itself, so check the resolved addresses up front
+    policy.check_resolved(&parsed, EgressPurpose::Web)?;
 
-    // Launch headless browser with custom path if provided
-    let mut launch_options = LaunchOptions {
-        headless: true,
-        sandbox: false,
-        ..Default::default()
-    };
-    
     // Use provided path, or try to find Chrome automatically
-    if let Some(path) = browser_path {
-        launch_options.path = Some(std::path::PathBuf::from(path));
-    } else if let Some(path) = find_chrome_path() {
-        launch_options.path = Some(path);
-    }
+    let path = browser_path.map(std::path::PathBuf::from).or_else(find_chrome_path);
+    let launch = network.browser_launch(&parsed);
     
-    let browser = Browser::new(launch_options)
-        .map_err(|err| format!("Failed to launch browser: {err}"))?;
+    let browser = launch_browser(path, &launch).map_err(|err| {
+        CommandError::new(ErrorKind::BrowserUnavailable, format!("Failed to launch browser: {err}"))
+    })?;
 
     // Create a new tab
-    let tab = browser
-        .new_tab()
-        .map_err(|err| format!("Failed to create tab: {err}"))?;
+    let tab = browser.new_tab().map_err(tab_error)?;
 
-    // Navigate to URL with timeout
-    tab.navigate_to(&url)
-        .map_err(|err| format!("Failed to navigate: {err}"))?;
+    if let Some(token) = token {
+        token.attach_tab(tab.clone())?;
+    }
+
+    // Redirects and subresources are checked as the page loads
+    let blocked_document = policy.guard_tab(&tab, EgressPurpose::Web)?;
+    answer_proxy_auth(&tab, &launch)?;
 
-    tab.wait_until_navigated()
-        .map_err(|err| format!("Navigation timeout: {err}"))?;
+    // Navigate to URL with timeout
+    navigate(&tab, url, &blocked_document)?;
 
     // Wait a bit for JavaScript to execute
     std::thread::sleep(Duration::from_millis(1000));
 
     // Get the page HTML
-    let html = tab
-        .get_content()
-        .map_err(|err| format!("Failed to get content: {err}"))?;
+    let html = tab.get_content().map_err(|err| {
+        CommandError::new(ErrorKind::BrowserUnavailable, format!("Failed to get content: {err}")).with_url(url)
+    })?;
 
     Ok(html)
 }
 
+// Launches headless Chrome with the configured proxy and certificate exceptions
+fn launch_browser(path: Option<std::path::PathBuf>, launch: &BrowserNetwork) -> Result<Browser, String> {
+    let launch_options = LaunchOptions {
+        headless: true,
+        sandbox: false,
+        path,
+        proxy_server: launch.proxy_server.as_deref(),
+        args: launch.args.iter().map(|arg| arg.as_os_str()).collect(),
+        ignore_certificate_errors: launch.ignore_certificate_errors,
+        ..Default::default()
+    };
+    Browser::new(launch_options).map_err(|err| err.to_string())
+}
+
+// Chrome can't take proxy credentials on the command line, so the tab
+// answers the proxy's auth challenge instead
+fn answer_proxy_auth(tab: &headless_chrome::Tab, launch: &BrowserNetwork) -> Result<(), CommandError> {
+    let Some((username, password)) = &launch.proxy_auth else {
+        return Ok(());
+    };
+    tab.authenticate(Some(username.clone()), password.clone())
+        .and_then(|tab| tab.enable_fetch(None, Some(true)))
+        .map_err(|err| {
+            CommandError::new(ErrorKind::BrowserUnavailable, format!("Failed to set up proxy authentication: {err}"))
+        })?;
+    Ok(())
+}
+
+fn tab_error(err: impl std::fmt::Display) -> CommandError {
+    CommandError::new(ErrorKind::BrowserUnavailable, format!("Failed to create tab: {err}"))
+}
+
+// Navigates a guarded tab and waits for the page, reporting policy blocks
+// that happened along the way (e.g. a redirect to an intranet address)
+fn navigate(tab: &headless_chrome::Tab, url: &str, blocked_document: &std::sync::atomic::AtomicBool) -> Result<(), CommandError> {
+    tab.navigate_to(url).map_err(|err| {
+        CommandError::new(ErrorKind::Network, format!("Failed to navigate: {err}")).with_url(url)
+    })?;
+
+    let navigated = tab.wait_until_navigated();
+
+    if blocked_document.load(std::sync::atomic::Ordering::SeqCst) {
+        return Err(CommandError::new(
+            ErrorKind::Blocked,
+            "Blocked by egress policy: page redirected to a blocked address",
+        )
+        .with_url(url));
+    }
+
+    navigated.map_err(|err| {
+        CommandError::new(ErrorKind::Timeout, format!("Navigation timeout: {err}")).with_url(url)
+    })?;
+    Ok(())
+}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 struct SearchResult {
     title: String,
@@ -169,15 +556,22 @@ struct ScrapeResult {
     #[serde(skip_serializing_if = "Option::is_none")]
     content: Option<ScrapedContent>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    error: Option<String>,
+    error: Option<CommandError>,
 }
 
 #[tauri::command]
-fn web_search_and_scrape(query: String, max_results: Option<usize>) -> Result<Vec<ScrapedContent>, String> {
+async fn web_search_and_scrape(
+    query: String,
+    max_results: Option<usize>,
+    clients: State<'_, HttpClients>,
+    policy: State<'_, EgressPolicy>,
+    recorder: State<'_, Recorder>,
+    limiter: State<'_, RateLimiter>,
+) -> Result<Vec<ScrapedContent>, CommandError> {
     let _limit = max_results.unwrap_or(5);
     
     // Step 1: Search DuckDuckGo using POST (required by DuckDuckGo)
-    let _search_html = search_duckduckgo(&query)?;
+    let _search_html = duckduckgo_html(&clients, &policy, &recorder, &limiter, &query).await?;
     
     // Step 2: Parse search results (done in frontend, so we return empty for now)
     // Frontend will handle parsing and call backend for scraping individual URLs
@@ -186,47 +580,49 @@ fn web_search_and_scrape(query: String, max_results: Option<usize>) -> Result<Ve
 }
 
 #[tauri::command]
-fn search_duckduckgo(query: &str) -> Result<String, String> {
+async fn search_duckduckgo(
+    query: String,
+    clients: State<'_, HttpClients>,
+    policy: State<'_, EgressPolicy>,
+    recorder: State<'_, Recorder>,
+    limiter: State<'_, RateLimiter>,
+) -> Result<String, CommandError> {
+    duckduckgo_html(&clients, &policy, &recorder, &limiter, &query).await
+}
+
+async fn duckduckgo_html(
+    clients: &HttpClients,
+    policy: &EgressPolicy,
+    recorder: &Recorder,
+    limiter: &RateLimiter,
+    query: &str,
+) -> Result<String, CommandError> {
+    let url = policy.check("https://html.duckduckgo.com/html/", EgressPurpose::Web)?;
+    let scraping = clients.scraping_for(&url).await?;
+
+
     // reqwest automatically handles decompression when using .text()
     // The key is to NOT manually set Accept-Encoding header
-    let client = Client::builder()
-        .timeout(Duration::from_secs(20))
-        .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:109.0) Gecko/20100101 Firefox/115.0")
-        .build()
-        .map_err(|err| format!("Failed to build HTTP client: {err}"))?;
 
     // DuckDuckGo requires POST request with form data
     let params = [("q", query), ("b", ""), ("kl", "wt-wt")];
     
     eprintln!("Searching DuckDuckGo for: {}", query);
     
-    let response = client
-        .post("https://html.duckduckgo.com/html/")
+    let request = scraping
+        .client
+        .post(url)
+        .timeout(scraping.request_timeout)
         .form(&params)
         .header("Accept", "text/html,application/xhtml+xml,application/xml;q=0.9,image/webp,*/*;q=0.8")
         .header("Accept-Language", "en-US,en;q=0.9")
         // NOTE: Do NOT set Accept-Encoding - let reqwest handle it automatically
         .header("DNT", "1")
         .header("Connection", "keep-alive")
-        .header("Upgrade-Insecure-Requests", "1")
-        .send()
-        .map_err(|err| format!("Request failed: {err}"))?;
-
-    eprintln!("Response status: {}", response.status());
-    eprintln!("Response headers: {:?}", response.headers());
-
-    if !response.status().is_success() {
-        return Err(format!("Request failed with status {}", response.status()));
-    }
+        .header("Upgrade-Insecure-Requests", "1");
 
     // Using .text() automatically handles decompression
-    let text = response
-        .text()
-        .map_err(|err| format!("Failed to read response body: {err}"))?;
-    
-    // Debug: Log response info
-    eprintln!("Received {} characters of text", text.len());
-    eprintln!("First 200 chars: {}", &text.chars().take(200).collect::<String>());
+    let text = fetch_text(&scraping.client, request, recorder, limiter, ExchangeSource::Search).await?;
     
     Ok(text)
 }
@@ -257,15 +653,43 @@ fn clean_text(text: &str) -> String {
         .to_string()
 }
 
+// Wait after failed attempt `attempt` (from 1) before the next one
+fn retry_backoff(attempt: u32) -> Duration {
+    Duration::from_millis(1000 * 2_u64.pow(attempt - 1))
+}
+
+// Time for every attempt, each with a few seconds to spare, and the
+// backoff between them
+fn overall_scrape_timeout(timeout_ms: u64, max_retries: u32) -> Duration {
+    let attempts = max_retries.max(1);
+    let backoff: Duration = (1..attempts).map(retry_backoff).sum();
+    Duration::from_millis(timeout_ms + 5000) * attempts + backoff
+}
+
 // Helper function to scrape a single URL with retry mechanism
-fn scrape_single_url_with_retry(url: String, timeout_ms: u64, max_retries: u32) -> ScrapeResult {
+#[allow(clippy::too_many_arguments)]
+fn scrape_single_url_with_retry(
+    url: String,
+    timeout_ms: u64,
+    max_retries: u32,
+    clients: HttpClients,
+    policy: EgressPolicy,
+    token: Option<RequestToken>,
+    recorder: Recorder,
+    limiter: RateLimiter,
+) -> ScrapeResult {
     let mut attempts = 0;
-    let mut last_error = String::new();
+    let mut last_error = CommandError::new(ErrorKind::Network, "No attempts made").with_url(url.as_str());
     
     while attempts < max_retries {
+        if token.as_ref().is_some_and(|t| t.is_cancelled()) {
+            last_error = CommandError::cancelled();
+            break;
+        }
+        
         attempts += 1;
         
-        match scrape_single_url_internal(&url, timeout_ms) {
+        match scrape_single_url_internal(&url, timeout_ms, &clients, &policy, token.as_ref(), &recorder, &limiter) {
             Ok(content) => {
                 return ScrapeResult {
                     success: true,
@@ -273,14 +697,25 @@ fn scrape_single_url_with_retry(url: String, timeout_ms: u64, max_retries: u32)
                     error: None,
                 };
             }
+            // Retrying can't change the outcome of these
+            Err(err) if matches!(err.kind, ErrorKind::Blocked | ErrorKind::InvalidUrl | ErrorKind::Cancelled) => {
+                eprintln!("Not retrying {}: {}", url, err);
+                return ScrapeResult {
+                    success: false,
+                    content: None,
+                    error: Some(err),
+                };
+            }
             Err(err) => {
-                last_error = format!("Attempt {}/{}: {}", attempts, max_retries, err);
+                last_error = err.context(&format!("Attempt {}/{}", attempts, max_retries));
                 eprintln!("Error scraping {}: {}", url, last_error);
                 
-                // Wait before retry (exponential backoff)
+                // Wait before retry (exponential backoff), stopping early on cancel
                 if attempts < max_retries {
-                    let wait_ms = 1000 * (2_u64.pow(attempts - 1));
-                    std::thread::sleep(Duration::from_millis(wait_ms));
+                    if let Err(err) = cancellation::sleep_blocking(retry_backoff(attempts), token.as_ref()) {
+                        last_error = err;
+                        break;
+                    }
                 }
             }
         }
@@ -294,27 +729,36 @@ fn scrape_single_url_with_retry(url: String, timeout_ms: u64, max_retries: u32)
 }
 
 // Fallback function to scrape using reqwest (no browser)
-fn scrape_with_reqwest(url: &str, timeout_ms: u64) -> Result<ScrapedContent, String> {
-    let client = Client::builder()
-        .timeout(Duration::from_millis(timeout_ms))
-        .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:109.0) Gecko/20100101 Firefox/115.0")
-        .build()
-        .map_err(|err| format!("Failed to build HTTP client: {err}"))?;
-
-    let response = client
-        .get(url)
-        .header("Accept", "text/html,application/xhtml+xml,application/xml;q=0.9,image/webp,*/*;q=0.8")
-        .header("Accept-Language", "en-US,en;q=0.9")
-        .send()
-        .map_err(|err| format!("Request failed: {err}"))?;
-
-    if !response.status().is_success() {
-        return Err(format!("Request failed with status {}", response.status()));
-    }
-
-    let html = response
-        .text()
-        .map_err(|err| format!("Failed to read response body: {err}"))?;
+// Runs on the scraping worker thread, so it blocks on the shared async client
+// until the page arrives or the request is cancelled.
+// The caller has already waited for the host's rate limit.
+fn scrape_with_reqwest(
+    url: &Url,
+    timeout_ms: u64,
+    clients: &HttpClients,
+    token: Option<&RequestToken>,
+    recorder: &Recorder,
+    limiter: &RateLimiter,
+) -> Result<ScrapedContent, CommandError> {
+    let fetch = async {
+        let client = clients.scraping_for(url).await?.client;
+        let request = client
+            .get(url.clone())
+            .timeout(Duration::from_millis(timeout_ms))
+            .header("Accept", "text/html,application/xhtml+xml,application/xml;q=0.9,image/webp,*/*;q=0.8")
+            .header("Accept-Language", "en-US,en;q=0.9")
+            .build()?;
+        send_text(&client, request, recorder, limiter, ExchangeSource::Scrape).await
+    };
+    let html = tauri::async_runtime::block_on(async {
+        match token {
+            Some(token) => tokio::select! {
+                html = fetch => html,
+                () = token.cancelled() => Err(CommandError::cancelled()),
+            },
+            None => fetch.await,
+        }
+    })?;
 
     // Simple HTML parsing - extract title and body text
     let title = html
@@ -327,7 +771,7 @@ fn scrape_with_reqwest(url: &str, timeout_ms: u64) -> Result<ScrapedContent, Str
     // Very basic content extraction - just get the text
     let content = clean_text(&html);
     let word_count = content.split_whitespace().count();
-    let domain = extract_domain(url);
+    let domain = extract_domain(url.as_str());
 
     Ok(ScrapedContent {
         url: url.to_string(),
@@ -373,51 +817,72 @@ fn find_chrome_path() -> Option<std::path::PathBuf> {
 }
 
 // Internal function to scrape a single URL
-fn scrape_single_url_internal(url: &str, timeout_ms: u64) -> Result<ScrapedContent, String> {
+#[allow(clippy::too_many_arguments)]
+fn scrape_single_url_internal(
+    url: &str,
+    timeout_ms: u64,
+    clients: &HttpClients,
+    policy: &EgressPolicy,
+    token: Option<&RequestToken>,
+    recorder: &Recorder,
+    limiter: &RateLimiter,
+) -> Result<ScrapedContent, CommandError> {
     // Validate URL
-    let parsed = Url::parse(url).map_err(|err| format!("Invalid URL: {err}"))?;
+    let parsed = policy.check(url, EgressPurpose::Web)?;
     
-    match parsed.scheme() {
-        "http" | "https" => {}
-        _ => return Err("Only http and https schemes are allowed".to_string()),
-    }
+    // Chrome resolves hosts itself, so check the resolved addresses up front
+    policy.check_resolved(&parsed, EgressPurpose::Web)?;
+    
+    // Wait for the host's rate limit before starting a browser for it
+    limiter.acquire_blocking(&parsed, token)?;
     
     // Try to find Chrome on the system
     let chrome_path = find_chrome_path();
+    let launch = clients.network().browser_launch(&parsed);
     
     // Try to launch headless browser, fallback to reqwest if it fails
-    let mut launch_options = LaunchOptions {
-        headless: true,
-        sandbox: false,
-        ..Default::default()
-    };
-    
-    // Use found Chrome path if available
-    if let Some(path) = chrome_path {
-        launch_options.path = Some(path);
-    }
-    
-    let browser = match Browser::new(launch_options) {
+    let browser = match launch_browser(chrome_path, &launch) {
         Ok(b) => b,
         Err(err) => {
             eprintln!("Failed to launch browser, falling back to reqwest: {}", err);
-            return scrape_with_reqwest(url, timeout_ms);
+            return scrape_with_reqwest(&parsed, timeout_ms, clients, token, recorder, limiter);
         }
     };
     
-    let tab = browser
-        .new_tab()
-        .map_err(|err| format!("Failed to create tab: {err}"))?;
+    let mut recording = recorder.start_browser(url);
+    let result = extract_with_browser(&browser, url, timeout_ms, policy, &launch, token);
+    if let Ok(content) = &result {
+        recording.browser_response();
+        recording.body(&content.content);
+    }
+    recording.finish_with(&result);
+    result
+}
+
+// Loads the page in a guarded tab and extracts its text and metadata
+fn extract_with_browser(
+    browser: &Browser,
+    url: &str,
+    timeout_ms: u64,
+    policy: &EgressPolicy,
+    launch: &BrowserNetwork,
+    token: Option<&RequestToken>,
+) -> Result<ScrapedContent, CommandError> {
+    let tab = browser.new_tab().map_err(tab_error)?;
+    
+    if let Some(token) = token {
+        token.attach_tab(tab.clone())?;
+    }
+    
+    // Redirects and subresources are checked as the page loads
+    let blocked_document = policy.guard_tab(&tab, EgressPurpose::Web)?;
+    answer_proxy_auth(&tab, launch)?;
     
     // Set timeout for navigation
     tab.set_default_timeout(Duration::from_millis(timeout_ms));
     
     // Navigate to URL
-    tab.navigate_to(url)
-        .map_err(|err| format!("Failed to navigate: {err}"))?;
-    
-    tab.wait_until_navigated()
-        .map_err(|err| format!("Navigation timeout: {err}"))?;
+    navigate(&tab, url, &blocked_document)?;
     
     // Wait for content to load
     std::thread::sleep(Duration::from_millis(1500));
@@ -490,9 +955,7 @@ fn scrape_single_url_internal(url: &str, timeout_ms: u64) -> Result<ScrapedConte
     
     let result = tab
         .evaluate(extraction_script, false)
-        .map_err(|err| format!("Failed to extract content: {err}"))?;
-    
-    eprintln!("Extraction result: {:?}", result);
+        .map_err(|err| CommandError::new(ErrorKind::Parse, format!("Failed to extract content: {err}")).with_url(url))?;
     
     // Parse the result with better error handling
     let value = match result.value {
@@ -503,7 +966,9 @@ fn scrape_single_url_internal(url: &str, timeout_ms: u64) -> Result<ScrapedConte
             let fallback_script = "document.body.innerText";
             let fallback_result = tab
                 .evaluate(fallback_script, false)
-                .map_err(|err| format!("Fallback extraction failed: {err}"))?;
+                .map_err(|err| {
+                    CommandError::new(ErrorKind::Parse, format!("Fallback extraction failed: {err}")).with_url(url)
+                })?;
             
             if let Some(text) = fallback_result.value.and_then(|v| v.as_str().map(|s| s.to_string())) {
                 let title_script = "document.title";
@@ -529,7 +994,11 @@ fn scrape_single_url_internal(url: &str, timeout_ms: u64) -> Result<ScrapedConte
                     },
                 });
             } else {
-                return Err("No value returned from extraction and fallback failed".to_string());
+                return Err(CommandError::new(
+                    ErrorKind::Parse,
+                    "No value returned from extraction and fallback failed",
+                )
+                .with_url(url));
             }
         }
     };
@@ -579,36 +1048,66 @@ fn scrape_single_url_internal(url: &str, timeout_ms: u64) -> Result<ScrapedConte
 }
 
 // Async wrapper for scraping with timeout
-async fn scrape_url_async(url: String, timeout_ms: u64, max_retries: u32) -> ScrapeResult {
+#[allow(clippy::too_many_arguments)]
+async fn scrape_url_async(
+    url: String,
+    timeout_ms: u64,
+    max_retries: u32,
+    clients: &HttpClients,
+    policy: &EgressPolicy,
+    token: Option<RequestToken>,
+    recorder: &Recorder,
+    limiter: &RateLimiter,
+) -> ScrapeResult {
+    if let Err(err) = policy.check(&url, EgressPurpose::Web) {
+        return ScrapeResult {
+            success: false,
+            content: None,
+            error: Some(err),
+        };
+    }
+    
     // Run the blocking scrape operation in a separate thread
+    let clients = clients.clone();
+    let policy = policy.clone();
+    let recorder = recorder.clone();
+    let limiter = limiter.clone();
+    let overall_url = url.clone();
     let result = tokio::task::spawn_blocking(move || {
-        scrape_single_url_with_retry(url, timeout_ms, max_retries)
+        scrape_single_url_with_retry(url, timeout_ms, max_retries, clients, policy, token, recorder, limiter)
     });
     
-    // Apply timeout to the entire operation
-    match timeout(Duration::from_millis(timeout_ms + 5000), result).await {
+    // Apply timeout to the entire operation, retries included
+    match timeout(overall_scrape_timeout(timeout_ms, max_retries), result).await {
         Ok(Ok(scrape_result)) => scrape_result,
         Ok(Err(err)) => ScrapeResult {
             success: false,
             content: None,
-            error: Some(format!("Task error: {}", err)),
+            error: Some(CommandError::from(err).with_url(overall_url)),
         },
         Err(_) => ScrapeResult {
             success: false,
             content: None,
-            error: Some("Overall timeout exceeded".to_string()),
+            error: Some(CommandError::new(ErrorKind::Timeout, "Overall timeout exceeded").with_url(overall_url)),
         },
     }
 }
 
 // Main command to scrape multiple URLs in parallel
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 async fn scrape_urls(
     urls: Vec<String>,
     timeout_ms: Option<u64>,
     max_retries: Option<u32>,
     max_concurrent: Option<usize>,
-) -> Result<Vec<ScrapeResult>, String> {
+    request_id: Option<String>,
+    requests: State<'_, RequestRegistry>,
+    clients: State<'_, HttpClients>,
+    policy: State<'_, EgressPolicy>,
+    recorder: State<'_, Recorder>,
+    limiter: State<'_, RateLimiter>,
+) -> Result<Vec<ScrapeResult>, CommandError> {
     let timeout_ms = timeout_ms.unwrap_or(45000); // Default 45 seconds (increased for slow sites like GitHub)
     let max_retries = max_retries.unwrap_or(3); // Default 3 retries
     let max_concurrent = max_concurrent.unwrap_or(5); // Default 5 concurrent requests
@@ -619,21 +1118,30 @@ async fn scrape_urls(
     
     eprintln!("Starting scrape of {} URLs with max {} concurrent requests", urls.len(), max_concurrent);
     
+    let clients = clients.inner();
+    let policy = policy.inner();
+    let recorder = recorder.inner();
+    let limiter = limiter.inner();
+    
     // Process URLs in batches to limit concurrency
-    let mut all_results = Vec::new();
-    
-    for chunk in urls.chunks(max_concurrent) {
-        let futures: Vec<_> = chunk
-            .iter()
-            .map(|url| {
-                let url = url.clone();
-                scrape_url_async(url, timeout_ms, max_retries)
-            })
-            .collect();
+    let all_results = requests.run(request_id, |token| async move {
+        let mut all_results = Vec::new();
         
-        let results = join_all(futures).await;
-        all_results.extend(results);
-    }
+        for chunk in urls.chunks(max_concurrent) {
+            let futures: Vec<_> = chunk
+                .iter()
+                .map(|url| {
+                    let url = url.clone();
+                    scrape_url_async(url, timeout_ms, max_retries, clients, policy, token.clone(), recorder, limiter)
+                })
+                .collect();
+            
+            let results = join_all(futures).await;
+            all_results.extend(results);
+        }
+        
+        Ok(all_results)
+    }).await?;
     
     // Log summary
     let successful = all_results.iter().filter(|r| r.success).count();
@@ -645,23 +1153,88 @@ async fn scrape_urls(
 
 // Command to scrape a single URL (for convenience)
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 async fn scrape_url(
     url: String,
     timeout_ms: Option<u64>,
     max_retries: Option<u32>,
-) -> Result<ScrapeResult, String> {
+    request_id: Option<String>,
+    requests: State<'_, RequestRegistry>,
+    clients: State<'_, HttpClients>,
+    policy: State<'_, EgressPolicy>,
+    recorder: State<'_, Recorder>,
+    limiter: State<'_, RateLimiter>,
+) -> Result<ScrapeResult, CommandError> {
     let timeout_ms = timeout_ms.unwrap_or(45000); // Increased for slow sites
     let max_retries = max_retries.unwrap_or(3);
+    let clients = clients.inner();
+    let policy = policy.inner();
+    let recorder = recorder.inner();
+    let limiter = limiter.inner();
     
-    Ok(scrape_url_async(url, timeout_ms, max_retries).await)
+    requests.run(request_id, |token| async move {
+        Ok(scrape_url_async(url, timeout_ms, max_retries, clients, policy, token, recorder, limiter).await)
+    }).await
+}
+
+// Cancels an in-flight request started with the given request id
+#[tauri::command]
+fn cancel_request(request_id: String, requests: State<'_, RequestRegistry>) -> bool {
+    requests.cancel(&request_id)
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
+        .manage(RequestRegistry::default())
+        .manage(Recorder::new(RecorderSettings::default()))
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_http::init())
         .plugin(tauri_plugin_fs::init())
+        .setup(|app| {
+            // Egress, rate limit, HTTP and network settings are persisted in the
+            // app data dir, which is only known once the app is set up
+            let rate_limiter = RateLimiter::new(rate_limit::load(app.handle())).or_else(|err| {
+                eprintln!("[RateLimit] Ignoring saved settings: {}", err);
+                RateLimiter::new(RateLimitSettings::default())
+            })?;
+            app.manage(rate_limiter);
+            let egress_policy = EgressPolicy::new(egress::load(app.handle())).or_else(|err| {
+                eprintln!("[Egress] Ignoring saved policy: {}", err);
+                EgressPolicy::new(EgressPolicySettings::default())
+            })?;
+            app.manage(egress_policy.clone());
+            // The vault comes first: it holds the proxy password
+            let vault = Vault::load(app.handle());
+            vault::spawn_auto_lock(app.handle().clone(), vault.clone());
+            let network = network::load(app.handle(), &vault);
+            let http_clients = HttpClients::new(http_client::load(app.handle()), network, egress_policy.clone())
+                .or_else(|err| {
+                    eprintln!("[Network] Ignoring saved HTTP and network settings: {}", err);
+                    HttpClients::new(HttpClientSettings::default(), NetworkSettings::default(), egress_policy.clone())
+                })?;
+            let recorder = app.state::<Recorder>().inner().clone();
+            network::redact_password(&recorder, &http_clients.network());
+            vault.on_unlock({
+                let (clients, vault) = (http_clients.clone(), vault.clone());
+                move || network::refresh_password(&clients, &recorder, &vault)
+            });
+            app.manage(http_clients);
+            app.manage(CredentialStore::new(app.handle(), vault.clone()));
+            app.manage(vault);
+
+            // Started last: its checks use the clients and keys managed above
+            let health = HealthMonitor::load(app.handle());
+            health::spawn_monitor(app.handle().clone(), health.clone());
+            app.manage(health);
+
+            let pulls = PullManager::load(app.handle());
+            pulls::spawn_worker(app.handle().clone(), pulls.clone());
+            app.manage(pulls);
+            app.manage(LlamaServers::load(app.handle()));
+            app.manage(LocalModels::new(app.handle()));
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             greet, 
             fetch_url, 
@@ -670,8 +1243,120 @@ pub fn run() {
             search_duckduckgo,
             scrape_urls,
             scrape_url,
-            proxy_http_request
+            proxy_http_request,
+            proxy_http_request_stream,
+            cancel_request,
+            http_client::get_http_settings,
+            http_client::update_http_settings,
+            network::get_network_settings,
+            network::update_network_settings,
+            credentials::list_provider_keys,
+            credentials::set_provider_key,
+            credentials::test_provider_key,
+            credentials::rotate_provider_key,
+            credentials::delete_provider_key,
+            vault::vault_status,
+            vault::vault_create,
+            vault::vault_unlock,
+            vault::vault_lock,
+            vault::vault_change_passphrase,
+            vault::vault_set_auto_lock,
+            health::get_provider_health,
+            health::set_health_targets,
+            health::check_provider_health,
+            discovery::discover_local_providers,
+            ollama::ollama_list_models,
+            ollama::ollama_show_model,
+            ollama::ollama_chat,
+            ollama::ollama_pull_model,
+            ollama::ollama_delete_model,
+            ollama::ollama_copy_model,
+            pulls::list_model_pulls,
+            pulls::queue_model_pull,
+            pulls::cancel_model_pull,
+            pulls::retry_model_pull,
+            pulls::clear_finished_model_pulls,
+            llama_server::list_llama_server_profiles,
+            llama_server::save_llama_server_profile,
+            llama_server::delete_llama_server_profile,
+            llama_server::get_llama_server_status,
+            llama_server::start_llama_server,
+            llama_server::stop_llama_server,
+            llama_server::restart_llama_server,
+            llama_server::get_llama_server_logs,
+            gguf::inspect_gguf,
+            gguf::scan_gguf_models,
+            chat_template::list_chat_templates,
+            chat_template::render_chat_template,
+            gbnf::json_schema_to_grammar,
+            local_inference::local_inference_status,
+            local_inference::local_list_models,
+            local_inference::local_load_model,
+            local_inference::local_unload_model,
+            local_inference::local_chat,
+            openai_compat::openai_compat_chat,
+            anthropic::anthropic_chat,
+            egress::get_egress_policy,
+            egress::update_egress_policy,
+            egress::get_egress_violations,
+            recorder::get_recorder_settings,
+            recorder::update_recorder_settings,
+            recorder::get_recorded_exchanges,
+            recorder::clear_recorded_exchanges,
+            recorder::export_har,
+            recorder::start_replay_server,
+            recorder::stop_replay_server,
+            rate_limit::get_rate_limit_settings,
+            rate_limit::update_rate_limit_settings,
+            rate_limit::get_rate_limit_status
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            // Local llama.cpp servers would otherwise outlive the app
+            if let tauri::RunEvent::Exit = event {
+                app.state::<LlamaServers>().shutdown();
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{overall_scrape_timeout, take_utf8_prefix};
+
+    #[test]
+    fn scrape_timeout_covers_retries() {
+        assert_eq!(overall_scrape_timeout(10_000, 1), Duration::from_secs(15));
+        // Three attempts of 15s, with 1s and 2s of backoff between them
+        assert_eq!(overall_scrape_timeout(10_000, 3), Duration::from_secs(48));
+        assert_eq!(overall_scrape_timeout(10_000, 0), Duration::from_secs(15));
+    }
+
+    #[test]
+    fn utf8_prefix_holds_back_split_code_point() {
+        let euro = "€".as_bytes();
+        let mut buffer = b"price: ".to_vec();
+        buffer.extend_from_slice(&euro[..2]);
+        assert_eq!(take_utf8_prefix(&mut buffer), "price: ");
+        assert_eq!(buffer, &euro[..2]);
+
+        buffer.extend_from_slice(&euro[2..]);
+        buffer.extend_from_slice(b" 5");
+        assert_eq!(take_utf8_prefix(&mut buffer), "€ 5");
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn utf8_prefix_replaces_invalid_bytes_only() {
+        let mut buffer = b"a\xffb\xc3".to_vec();
+        assert_eq!(take_utf8_prefix(&mut buffer), "a\u{FFFD}b");
+        assert_eq!(buffer, b"\xc3");
+
+        // An invalid continuation is not held back
+        let mut buffer = b"x\xc3(y".to_vec();
+        assert_eq!(take_utf8_prefix(&mut buffer), "x\u{FFFD}(y");
+        assert!(buffer.is_empty());
+    }
 }
@@ -0,0 +1,888 @@
+// Supervises llama.cpp `llama-server` processes started from saved profiles.
+// Each running process gets a task that marks it ready once /health answers
+// and restarts it with backoff when it crashes. Output is kept per profile
+// for the log viewer and emitted as `llama-server-log`; state changes are
+// emitted as `llama-server-status`. Profiles are persisted to
+// llama_servers.json in the app data dir, and every process is killed when
+// the app exits.
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use futures::future::join_all;
+use reqwest::Url;
+use tauri::async_runtime::JoinHandle;
+use tauri::{Emitter, Manager, State};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+use tokio::time::Instant;
+
+use crate::app_data;
+use crate::egress::{EgressPolicy, EgressPurpose};
+use crate::error::{CommandError, ErrorKind};
+use crate::http_client::HttpClients;
+
+const PROFILES_FILE: &str = "llama_servers.json";
+const STATUS_EVENT: &str = "llama-server-status";
+const LOG_EVENT: &str = "llama-server-log";
+const MAX_LOG_LINES: usize = 2000;
+// How often the supervisor checks whether the process is still running
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+// /health answers 503 while the model loads, which can take minutes
+const HEALTH_INTERVAL: Duration = Duration::from_millis(500);
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(2);
+// Crashes in a row, without becoming ready in between, before giving up
+const MAX_RESTARTS: u32 = 5;
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(30);
+// How long an exited process gets to flush its output
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LlamaServerProfile {
+    // Assigned when a new profile is saved
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    // Path to llama-server, or a bare name looked up on PATH
+    pub binary_path: String,
+    // GGUF file to load
+    pub model_path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_size: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threads: Option<u32>,
+    pub port: u16,
+    // Passed after the generated arguments, e.g. ["--n-gpu-layers", "0"]
+    #[serde(default)]
+    pub extra_args: Vec<String>,
+    #[serde(default = "default_restart_on_crash")]
+    pub restart_on_crash: bool,
+}
+
+fn default_restart_on_crash() -> bool {
+    true
+}
+
+impl LlamaServerProfile {
+    fn args(&self) -> Vec<String> {
+        let mut args = vec![
+            "--model".to_string(),
+            self.model_path.clone(),
+            "--host".to_string(),
+            "127.0.0.1".to_string(),
+            "--port".to_string(),
+            self.port.to_string(),
+        ];
+        if let Some(context_size) = self.context_size {
+            args.extend(["--ctx-size".to_string(), context_size.to_string()]);
+        }
+        if let Some(threads) = self.threads {
+            args.extend(["--threads".to_string(), threads.to_string()]);
+        }
+        args.extend(self.extra_args.iter().cloned());
+        args
+    }
+
+    // Matches the llama.cpp provider's default config
+    fn base_url(&self) -> String {
+        format!("http://localhost:{}", self.port)
+    }
+
+    // Catches what would otherwise only show up as a crash in the log
+    fn check_launchable(&self) -> Result<(), CommandError> {
+        let invalid = |message: String| CommandError::new(ErrorKind::InvalidRequest, message);
+        let binary = Path::new(&self.binary_path);
+        if binary.components().count() > 1 && !binary.is_file() {
+            return Err(invalid(format!("llama-server not found at {}", self.binary_path)));
+        }
+        if !Path::new(&self.model_path).is_file() {
+            return Err(invalid(format!("Model file not found: {}", self.model_path)));
+        }
+        Ok(())
+    }
+
+    fn spawn(&self) -> Result<Child, CommandError> {
+        Command::new(&self.binary_path)
+            .args(self.args())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|err| {
+                CommandError::new(ErrorKind::InvalidRequest, format!("Failed to start {}: {err}", self.binary_path))
+            })
+    }
+}
+
+// llama-server can't tell us up front whether its port is free; it logs
+// "couldn't bind HTTP server socket" and exits when it isn't
+fn is_port_conflict(line: &str) -> bool {
+    let line = line.to_lowercase();
+    line.contains("couldn't bind") || line.contains("address already in use")
+}
+
+// Delay before the restart after `crashes` crashes in a row; None once
+// it's time to give up
+fn restart_delay(crashes: u32) -> Option<Duration> {
+    (1..=MAX_RESTARTS)
+        .contains(&crashes)
+        .then(|| Duration::from_secs(1 << (crashes - 1)).min(MAX_RESTART_DELAY))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerState {
+    Stopped,
+    // Running, model still loading
+    Starting,
+    Ready,
+    // Exited on its own; may be waiting to be restarted
+    Crashed,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerStatus {
+    pub profile_id: String,
+    pub state: ServerState,
+    pub base_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ready_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    // Automatic restarts since the server was last started by hand
+    pub restarts: u32,
+}
+
+impl ServerStatus {
+    fn stopped(profile: &LlamaServerProfile) -> Self {
+        Self {
+            profile_id: profile.id.clone(),
+            state: ServerState::Stopped,
+            base_url: profile.base_url(),
+            pid: None,
+            started_at: None,
+            ready_at: None,
+            exit_code: None,
+            error: None,
+            restarts: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+    // Notes from the supervisor itself (started, exited, ...)
+    System,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogLine {
+    pub seq: u64,
+    pub stream: LogStream,
+    pub text: String,
+    pub at: String,
+}
+
+#[derive(Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LogEvent<'a> {
+    profile_id: &'a str,
+    line: &'a LogLine,
+}
+
+struct Instance {
+    status: ServerStatus,
+    child: Option<Child>,
+    // Bumped whenever a process is started or stopped, so the tasks of an
+    // earlier process know to stop
+    generation: u64,
+    // Crashes since the server was last ready
+    crashes: u32,
+    // Set when the current process reported its port as taken
+    port_conflict: bool,
+    logs: VecDeque<LogLine>,
+    next_seq: u64,
+}
+
+struct Servers {
+    profiles: Vec<LlamaServerProfile>,
+    instances: HashMap<String, Instance>,
+}
+
+impl Servers {
+    fn profile(&self, profile_id: &str) -> Result<&LlamaServerProfile, CommandError> {
+        self.profiles
+            .iter()
+            .find(|profile| profile.id == profile_id)
+            .ok_or_else(|| CommandError::new(ErrorKind::InvalidRequest, format!("Unknown llama-server profile {}", profile_id)))
+    }
+
+    fn instance(&mut self, profile_id: &str) -> Result<&mut Instance, CommandError> {
+        let status = ServerStatus::stopped(self.profile(profile_id)?);
+        Ok(self.instances.entry(profile_id.to_string()).or_insert_with(|| Instance {
+            status,
+            child: None,
+            generation: 0,
+            crashes: 0,
+            port_conflict: false,
+            logs: VecDeque::new(),
+            next_seq: 1,
+        }))
+    }
+
+    // The instance of a process that's still current
+    fn current(&mut self, profile_id: &str, generation: u64) -> Option<&mut Instance> {
+        self.instances
+            .get_mut(profile_id)
+            .filter(|instance| instance.generation == generation)
+    }
+
+    // Records a process that exited without being stopped. Returns its status
+    // and, when it's to be restarted, the delay before that; the delay
+    // doubles with each crash in a row. A port conflict is a failed launch
+    // and isn't retried.
+    fn exited(
+        &mut self,
+        profile_id: &str,
+        generation: u64,
+        code: Option<i32>,
+    ) -> Option<(ServerStatus, Option<Duration>)> {
+        let exit = match code {
+            Some(code) => format!("Exited with code {}", code),
+            None => "Killed by a signal".to_string(),
+        };
+        let (restart_on_crash, port) = self
+            .profile(profile_id)
+            .map(|profile| (profile.restart_on_crash, profile.port))
+            .unwrap_or((false, 0));
+        let instance = self.current(profile_id, generation)?;
+        instance.child = None;
+        instance.crashes += 1;
+        let restart = restart_on_crash && !instance.port_conflict;
+        let delay = restart.then(|| restart_delay(instance.crashes)).flatten();
+
+        let status = &mut instance.status;
+        status.state = ServerState::Crashed;
+        status.pid = None;
+        status.exit_code = code;
+        status.error = Some(match delay {
+            Some(delay) => format!("{}; restarting in {}s", exit, delay.as_secs()),
+            None if instance.port_conflict => format!("{}; port {} is already in use", exit, port),
+            None if restart_on_crash => format!("{}; gave up after {} restarts", exit, MAX_RESTARTS),
+            None => exit,
+        });
+        Some((status.clone(), delay))
+    }
+}
+
+#[derive(Clone)]
+pub struct LlamaServers {
+    servers: Arc<Mutex<Servers>>,
+    path: Option<PathBuf>,
+}
+
+impl LlamaServers {
+    pub fn load<R: tauri::Runtime>(app: &tauri::AppHandle<R>) -> Self {
+        Self::open(app_data::path(app, PROFILES_FILE))
+    }
+
+    // Reads the saved profiles; a missing or unreadable file means none
+    fn open(path: Option<PathBuf>) -> Self {
+        let profiles: Vec<LlamaServerProfile> = app_data::read(path.as_deref());
+        Self {
+            servers: Arc::new(Mutex::new(Servers {
+                profiles,
+                instances: HashMap::new(),
+            })),
+            path,
+        }
+    }
+
+    fn servers(&self) -> MutexGuard<'_, Servers> {
+        self.servers.lock().unwrap()
+    }
+
+    fn save(&self, profiles: &[LlamaServerProfile]) -> Result<(), CommandError> {
+        app_data::write(self.path.as_deref(), profiles)
+    }
+
+    pub fn profiles(&self) -> Vec<LlamaServerProfile> {
+        self.servers().profiles.clone()
+    }
+
+    // Adds the profile, or replaces the one with the same id. A running
+    // server picks up the changes when it's restarted.
+    pub fn save_profile(&self, mut profile: LlamaServerProfile) -> Result<LlamaServerProfile, CommandError> {
+        if profile.name.trim().is_empty() || profile.binary_path.trim().is_empty() || profile.model_path.trim().is_empty() {
+            return Err(CommandError::new(
+                ErrorKind::InvalidRequest,
+                "A profile needs a name, a llama-server binary and a model file",
+            ));
+        }
+        if profile.port == 0 {
+            return Err(CommandError::new(ErrorKind::InvalidRequest, "A profile needs a port"));
+        }
+
+        let mut servers = self.servers();
+        if profile.id.is_empty() {
+            profile.id = format!("llama_{}", chrono::Utc::now().timestamp_millis());
+            while servers.profiles.iter().any(|existing| existing.id == profile.id) {
+                profile.id.push('_');
+            }
+        }
+        let mut profiles = servers.profiles.clone();
+        match profiles.iter_mut().find(|existing| existing.id == profile.id) {
+            Some(existing) => *existing = profile.clone(),
+            None => profiles.push(profile.clone()),
+        }
+        self.save(&profiles)?;
+        servers.profiles = profiles;
+        Ok(profile)
+    }
+
+    pub fn statuses(&self) -> Vec<ServerStatus> {
+        let servers = self.servers();
+        servers
+            .profiles
+            .iter()
+            .map(|profile| match servers.instances.get(&profile.id) {
+                Some(instance) => instance.status.clone(),
+                None => ServerStatus::stopped(profile),
+            })
+            .collect()
+    }
+
+    // Lines after `since`, or the whole buffer
+    pub fn logs(&self, profile_id: &str, since: Option<u64>) -> Vec<LogLine> {
+        let servers = self.servers();
+        let Some(instance) = servers.instances.get(profile_id) else {
+            return Vec::new();
+        };
+        instance
+            .logs
+            .iter()
+            .filter(|line| since.is_none_or(|since| line.seq > since))
+            .cloned()
+            .collect()
+    }
+
+    fn log<R: tauri::Runtime>(&self, app: &tauri::AppHandle<R>, profile_id: &str, stream: LogStream, text: String) {
+        let line = {
+            let mut servers = self.servers();
+            let Some(instance) = servers.instances.get_mut(profile_id) else {
+                return;
+            };
+            let line = LogLine {
+                seq: instance.next_seq,
+                stream,
+                text,
+                at: chrono::Utc::now().to_rfc3339(),
+            };
+            instance.next_seq += 1;
+            if instance.logs.len() >= MAX_LOG_LINES {
+                instance.logs.pop_front();
+            }
+            instance.logs.push_back(line.clone());
+            line
+        };
+        let _ = app.emit(LOG_EVENT, LogEvent { profile_id, line: &line });
+    }
+
+    fn emit_status<R: tauri::Runtime>(app: &tauri::AppHandle<R>, status: &ServerStatus) {
+        let _ = app.emit(STATUS_EVENT, status);
+    }
+
+    // Starts a process for the profile and the tasks that watch it.
+    // `restarts` is 0 when started by hand.
+    fn launch<R: tauri::Runtime>(
+        &self,
+        app: &tauri::AppHandle<R>,
+        profile_id: &str,
+        restarts: u32,
+    ) -> Result<ServerStatus, CommandError> {
+        let mut servers = self.servers();
+        let profile = servers.profile(profile_id)?.clone();
+        let health_url = app
+            .state::<EgressPolicy>()
+            .check(&format!("{}/health", profile.base_url()), EgressPurpose::Provider)?;
+        profile.check_launchable()?;
+
+        let mut child = profile.spawn()?;
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+
+        let instance = servers.instance(profile_id)?;
+        if restarts == 0 {
+            instance.crashes = 0;
+        }
+        instance.generation += 1;
+        instance.port_conflict = false;
+        instance.status = ServerStatus {
+            state: ServerState::Starting,
+            pid: child.id(),
+            started_at: Some(chrono::Utc::now().to_rfc3339()),
+            restarts,
+            ..ServerStatus::stopped(&profile)
+        };
+        instance.child = Some(child);
+        let generation = instance.generation;
+        let status = instance.status.clone();
+        drop(servers);
+
+        eprintln!("[LlamaServer] Started {} (pid {:?}) on port {}", profile.name, status.pid, profile.port);
+        let command = format!("$ {} {}", profile.binary_path, profile.args().join(" "));
+        self.log(app, profile_id, LogStream::System, command);
+        let mut output = Vec::new();
+        if let Some(stdout) = stdout {
+            output.push(self.forward(app, profile_id, generation, LogStream::Stdout, stdout));
+        }
+        if let Some(stderr) = stderr {
+            output.push(self.forward(app, profile_id, generation, LogStream::Stderr, stderr));
+        }
+        let supervise = self.clone().supervise(app.clone(), profile_id.to_string(), generation, health_url, output);
+        tauri::async_runtime::spawn(supervise);
+
+        Self::emit_status(app, &status);
+        Ok(status)
+    }
+
+    // Copies a pipe into the log until the process closes it. Lines are read
+    // as bytes so invalid UTF-8 can't stop the reader and stall the process.
+    fn forward<R: tauri::Runtime>(
+        &self,
+        app: &tauri::AppHandle<R>,
+        profile_id: &str,
+        generation: u64,
+        stream: LogStream,
+        pipe: impl AsyncRead + Unpin + Send + 'static,
+    ) -> JoinHandle<()> {
+        let servers = self.clone();
+        let app = app.clone();
+        let profile_id = profile_id.to_string();
+        tauri::async_runtime::spawn(async move {
+            let mut reader = BufReader::new(pipe);
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                match reader.read_until(b'\n', &mut line).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {
+                        let text = String::from_utf8_lossy(&line).trim_end().to_string();
+                        if is_port_conflict(&text) {
+                            if let Some(instance) = servers.servers().current(&profile_id, generation) {
+                                instance.port_conflict = true;
+                            }
+                        }
+                        servers.log(&app, &profile_id, stream, text);
+                    }
+                }
+            }
+        })
+    }
+
+    async fn supervise<R: tauri::Runtime>(
+        self,
+        app: tauri::AppHandle<R>,
+        profile_id: String,
+        generation: u64,
+        health_url: Url,
+        output: Vec<JoinHandle<()>>,
+    ) {
+        // Without a client the process is still supervised, just never probed
+        let client = match app.state::<HttpClients>().provider_for(&health_url).await {
+            Ok(profile) => Some(profile.client),
+            Err(err) => {
+                eprintln!("[LlamaServer] Not probing {}: {}", health_url, err);
+                None
+            }
+        };
+        let mut next_probe = Instant::now();
+
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let (exit, state) = {
+                let mut servers = self.servers();
+                let Some(instance) = servers.current(&profile_id, generation) else {
+                    return;
+                };
+                let Some(child) = instance.child.as_mut() else {
+                    return;
+                };
+                match child.try_wait() {
+                    Ok(exit) => (exit, instance.status.state),
+                    Err(err) => {
+                        eprintln!("[LlamaServer] Failed to check process of {}: {}", profile_id, err);
+                        (None, instance.status.state)
+                    }
+                }
+            };
+
+            if let Some(exit) = exit {
+                // The last lines can tell a port conflict apart from a crash
+                let _ = tokio::time::timeout(OUTPUT_DRAIN_TIMEOUT, join_all(output)).await;
+                self.exited(&app, &profile_id, generation, exit.code()).await;
+                return;
+            }
+
+            let Some(client) = client.as_ref() else {
+                continue;
+            };
+            if state == ServerState::Starting && Instant::now() >= next_probe {
+                next_probe = Instant::now() + HEALTH_INTERVAL;
+                let ready = client
+                    .get(health_url.clone())
+                    .timeout(HEALTH_TIMEOUT)
+                    .send()
+                    .await
+                    .is_ok_and(|response| response.status().is_success());
+                if ready {
+                    self.ready(&app, &profile_id, generation);
+                }
+            }
+        }
+    }
+
+    fn ready<R: tauri::Runtime>(&self, app: &tauri::AppHandle<R>, profile_id: &str, generation: u64) {
+        let status = {
+            let mut servers = self.servers();
+            let Some(instance) = servers.current(profile_id, generation) else {
+                return;
+            };
+            instance.crashes = 0;
+            instance.status.state = ServerState::Ready;
+            instance.status.ready_at = Some(chrono::Utc::now().to_rfc3339());
+            instance.status.clone()
+        };
+        eprintln!("[LlamaServer] {} is ready at {}", profile_id, status.base_url);
+        self.log(app, profile_id, LogStream::System, format!("Ready at {}", status.base_url));
+        Self::emit_status(app, &status);
+    }
+
+    // Reports a process that exited without being stopped and restarts it
+    // when Servers::exited says so
+    async fn exited<R: tauri::Runtime>(&self, app: &tauri::AppHandle<R>, profile_id: &str, generation: u64, code: Option<i32>) {
+        let Some((status, delay)) = self.servers().exited(profile_id, generation, code) else {
+            return;
+        };
+
+        eprintln!("[LlamaServer] {}: {}", profile_id, status.error.as_deref().unwrap_or_default());
+        self.log(app, profile_id, LogStream::System, status.error.clone().unwrap_or_default());
+        Self::emit_status(app, &status);
+
+        let Some(delay) = delay else {
+            return;
+        };
+        tokio::time::sleep(delay).await;
+        // Started or stopped by hand in the meantime
+        if self.servers().current(profile_id, generation).is_none() {
+            return;
+        }
+        if let Err(err) = self.launch(app, profile_id, status.restarts + 1) {
+            let status = {
+                let mut servers = self.servers();
+                let Some(instance) = servers.current(profile_id, generation) else {
+                    return;
+                };
+                instance.status.error = Some(format!("Restart failed: {}", err.message));
+                instance.status.clone()
+            };
+            self.log(app, profile_id, LogStream::System, status.error.clone().unwrap_or_default());
+            Self::emit_status(app, &status);
+        }
+    }
+
+    // Starts the server unless it's already running
+    pub fn start<R: tauri::Runtime>(&self, app: &tauri::AppHandle<R>, profile_id: &str) -> Result<ServerStatus, CommandError> {
+        {
+            let mut servers = self.servers();
+            let instance = servers.instance(profile_id)?;
+            if instance.child.is_some() {
+                return Ok(instance.status.clone());
+            }
+        }
+        self.launch(app, profile_id, 0)
+    }
+
+    // Kills the process and waits for it to exit, so its port is free again
+    pub async fn stop<R: tauri::Runtime>(&self, app: &tauri::AppHandle<R>, profile_id: &str) -> Result<ServerStatus, CommandError> {
+        let child = {
+            let mut servers = self.servers();
+            let profile = servers.profile(profile_id)?.clone();
+            let instance = servers.instance(profile_id)?;
+            instance.generation += 1;
+            instance.status = ServerStatus::stopped(&profile);
+            instance.child.take()
+        };
+
+        if let Some(mut child) = child {
+            eprintln!("[LlamaServer] Stopping {}", profile_id);
+            let _ = child.start_kill();
+            let _ = child.wait().await;
+            self.log(app, profile_id, LogStream::System, "Stopped".to_string());
+        }
+
+        let status = self.servers().instance(profile_id)?.status.clone();
+        Self::emit_status(app, &status);
+        Ok(status)
+    }
+
+    pub async fn delete_profile<R: tauri::Runtime>(&self, app: &tauri::AppHandle<R>, profile_id: &str) -> Result<(), CommandError> {
+        self.stop(app, profile_id).await?;
+        let mut servers = self.servers();
+        let profiles: Vec<LlamaServerProfile> = servers
+            .profiles
+            .iter()
+            .filter(|profile| profile.id != profile_id)
+            .cloned()
+            .collect();
+        self.save(&profiles)?;
+        servers.profiles = profiles;
+        servers.instances.remove(profile_id);
+        Ok(())
+    }
+
+    // Kills every process; called when the app exits
+    pub fn shutdown(&self) {
+        let mut servers = self.servers();
+        for (profile_id, instance) in servers.instances.iter_mut() {
+            if let Some(child) = instance.child.as_mut() {
+                eprintln!("[LlamaServer] Killing {} on exit", profile_id);
+                instance.generation += 1;
+                let _ = child.start_kill();
+            }
+        }
+    }
+}
+
+#[tauri::command]
+pub fn list_llama_server_profiles(servers: State<'_, LlamaServers>) -> Vec<LlamaServerProfile> {
+    servers.profiles()
+}
+
+#[tauri::command]
+pub fn save_llama_server_profile(
+    profile: LlamaServerProfile,
+    servers: State<'_, LlamaServers>,
+) -> Result<LlamaServerProfile, CommandError> {
+    servers.save_profile(profile)
+}
+
+#[tauri::command]
+pub async fn delete_llama_server_profile(
+    profile_id: String,
+    app: tauri::AppHandle,
+    servers: State<'_, LlamaServers>,
+) -> Result<(), CommandError> {
+    servers.delete_profile(&app, &profile_id).await
+}
+
+#[tauri::command]
+pub fn get_llama_server_status(servers: State<'_, LlamaServers>) -> Vec<ServerStatus> {
+    servers.statuses()
+}
+
+// Returns once the process is running; `llama-server-status` reports when
+// the model has loaded
+#[tauri::command]
+pub async fn start_llama_server(
+    profile_id: String,
+    app: tauri::AppHandle,
+    servers: State<'_, LlamaServers>,
+) -> Result<ServerStatus, CommandError> {
+    servers.start(&app, &profile_id)
+}
+
+#[tauri::command]
+pub async fn stop_llama_server(
+    profile_id: String,
+    app: tauri::AppHandle,
+    servers: State<'_, LlamaServers>,
+) -> Result<ServerStatus, CommandError> {
+    servers.stop(&app, &profile_id).await
+}
+
+#[tauri::command]
+pub async fn restart_llama_server(
+    profile_id: String,
+    app: tauri::AppHandle,
+    servers: State<'_, LlamaServers>,
+) -> Result<ServerStatus, CommandError> {
+    servers.stop(&app, &profile_id).await?;
+    servers.start(&app, &profile_id)
+}
+
+// Buffered output, oldest first; pass the last seen `seq` as `since` to
+// get only newer lines
+#[tauri::command]
+pub fn get_llama_server_logs(
+    profile_id: String,
+    since: Option<u64>,
+    servers: State<'_, LlamaServers>,
+) -> Vec<LogLine> {
+    servers.logs(&profile_id, since)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(dir: &tempfile::TempDir) -> LlamaServerProfile {
+        let model_path = dir.path().join("model.gguf");
+        std::fs::write(&model_path, b"GGUF").unwrap();
+        LlamaServerProfile {
+            id: String::new(),
+            name: "Qwen".to_string(),
+            binary_path: stub_binary(dir).to_string_lossy().into_owned(),
+            model_path: model_path.to_string_lossy().into_owned(),
+            context_size: None,
+            threads: None,
+            port: 8081,
+            extra_args: Vec::new(),
+            restart_on_crash: true,
+        }
+    }
+
+    // Stands in for llama-server: prints its arguments and exits
+    #[cfg(unix)]
+    fn stub_binary(dir: &tempfile::TempDir) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = dir.path().join("llama-server");
+        let script = "#!/bin/sh\necho \"$@\"\necho \"couldn't bind HTTP server socket\" >&2\nexit 1\n";
+        std::fs::write(&path, script).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[cfg(not(unix))]
+    fn stub_binary(dir: &tempfile::TempDir) -> PathBuf {
+        let path = dir.path().join("llama-server.cmd");
+        std::fs::write(&path, "@echo %*\r\n@echo couldn't bind HTTP server socket 1>&2\r\n@exit /b 1\r\n").unwrap();
+        path
+    }
+
+    fn servers_with(profile: LlamaServerProfile) -> (Servers, u64) {
+        let mut servers = Servers {
+            profiles: vec![LlamaServerProfile { id: "llama_1".to_string(), ..profile }],
+            instances: HashMap::new(),
+        };
+        let instance = servers.instance("llama_1").unwrap();
+        instance.generation = 1;
+        (servers, 1)
+    }
+
+    #[test]
+    fn args_put_extra_args_last() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut profile = profile(&dir);
+        let model = profile.model_path.clone();
+        assert_eq!(profile.args(), ["--model", &model, "--host", "127.0.0.1", "--port", "8081"]);
+
+        profile.context_size = Some(8192);
+        profile.threads = Some(4);
+        profile.extra_args = vec!["--n-gpu-layers".to_string(), "0".to_string()];
+        assert_eq!(profile.args()[6..], ["--ctx-size", "8192", "--threads", "4", "--n-gpu-layers", "0"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stub_binary_gets_the_args_and_reports_the_port() {
+        let dir = tempfile::tempdir().unwrap();
+        let profile = profile(&dir);
+        profile.check_launchable().unwrap();
+
+        let child = tauri::async_runtime::block_on(async { profile.spawn().unwrap().wait_with_output().await });
+        let output = child.unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), profile.args().join(" "));
+        assert!(String::from_utf8_lossy(&output.stderr).lines().any(is_port_conflict));
+        assert!(!is_port_conflict("main: server is listening on http://127.0.0.1:8081"));
+
+        let missing_model = LlamaServerProfile { model_path: "/nonexistent/model.gguf".to_string(), ..profile.clone() };
+        assert_eq!(missing_model.check_launchable().unwrap_err().kind, ErrorKind::InvalidRequest);
+        let missing_binary = LlamaServerProfile { binary_path: "/nonexistent/llama-server".to_string(), ..profile };
+        assert_eq!(missing_binary.check_launchable().unwrap_err().kind, ErrorKind::InvalidRequest);
+        assert_eq!(missing_binary.spawn().unwrap_err().kind, ErrorKind::InvalidRequest);
+    }
+
+    #[test]
+    fn crashes_back_off_then_give_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut servers, generation) = servers_with(profile(&dir));
+        let mut delays = Vec::new();
+        for _ in 0..MAX_RESTARTS {
+            let (status, delay) = servers.exited("llama_1", generation, Some(1)).unwrap();
+            assert_eq!(status.state, ServerState::Crashed);
+            delays.push(delay.unwrap().as_secs());
+        }
+        assert_eq!(delays, [1, 2, 4, 8, 16]);
+
+        let (status, delay) = servers.exited("llama_1", generation, Some(1)).unwrap();
+        assert!(delay.is_none());
+        assert_eq!(status.error.as_deref(), Some("Exited with code 1; gave up after 5 restarts"));
+        assert_eq!(status.exit_code, Some(1));
+
+        // Another process started in the meantime
+        assert!(servers.exited("llama_1", generation - 1, Some(1)).is_none());
+        assert!(restart_delay(0).is_none());
+    }
+
+    #[test]
+    fn port_conflicts_and_disabled_restarts_arent_retried() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut servers, generation) = servers_with(profile(&dir));
+        servers.current("llama_1", generation).unwrap().port_conflict = true;
+        let (status, delay) = servers.exited("llama_1", generation, Some(1)).unwrap();
+        assert!(delay.is_none());
+        assert_eq!(status.error.as_deref(), Some("Exited with code 1; port 8081 is already in use"));
+
+        let (mut servers, generation) = servers_with(LlamaServerProfile { restart_on_crash: false, ..profile(&dir) });
+        let (status, delay) = servers.exited("llama_1", generation, None).unwrap();
+        assert!(delay.is_none());
+        assert_eq!(status.error.as_deref(), Some("Killed by a signal"));
+    }
+
+    #[test]
+    fn save_profile_validates_and_persists() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(PROFILES_FILE);
+        let servers = LlamaServers::open(Some(path.clone()));
+        let valid = profile(&dir);
+
+        for invalid in [
+            LlamaServerProfile { name: " ".to_string(), ..valid.clone() },
+            LlamaServerProfile { binary_path: String::new(), ..valid.clone() },
+            LlamaServerProfile { model_path: String::new(), ..valid.clone() },
+            LlamaServerProfile { port: 0, ..valid.clone() },
+        ] {
+            assert_eq!(servers.save_profile(invalid).unwrap_err().kind, ErrorKind::InvalidRequest);
+        }
+        assert!(servers.profiles().is_empty());
+
+        let saved = servers.save_profile(valid).unwrap();
+        assert!(saved.id.starts_with("llama_"));
+        let renamed = LlamaServerProfile { name: "Renamed".to_string(), ..saved.clone() };
+        let renamed = servers.save_profile(renamed).unwrap();
+        assert_eq!(renamed.id, saved.id);
+
+        let reloaded = LlamaServers::open(Some(path));
+        let profiles = reloaded.profiles();
+        assert_eq!(profiles.len(), 1);
+        assert_eq!(profiles[0].name, "Renamed");
+        assert_eq!(reloaded.statuses()[0].state, ServerState::Stopped);
+    }
+}
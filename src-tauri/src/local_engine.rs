@@ -0,0 +1,453 @@
+// CPU inference on GGUF models with candle, compiled with the
+// `local-inference` feature. Runs the llama (also Mistral), qwen2, qwen3,
+// phi3 and gemma3 architectures. The tokenizer comes from a tokenizer.json
+// next to the model when there is one, else it's rebuilt from the
+// vocabulary stored in the GGUF. Prompts are rendered with the model's chat
+// template (chat_template.rs).
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use candle_core::quantized::gguf_file;
+use candle_core::{Device, Tensor};
+use candle_transformers::generation::{LogitsProcessor, Sampling};
+use candle_transformers::models::{quantized_gemma3, quantized_llama, quantized_phi3, quantized_qwen2, quantized_qwen3};
+use tokenizers::decoders::byte_fallback::ByteFallback;
+use tokenizers::decoders::byte_level::ByteLevel as ByteLevelDecoder;
+use tokenizers::decoders::fuse::Fuse;
+use tokenizers::decoders::sequence::Sequence as DecoderSequence;
+use tokenizers::decoders::strip::Strip;
+use tokenizers::models::bpe::{Vocab, BPE};
+use tokenizers::normalizers::replace::Replace;
+use tokenizers::pre_tokenizers::byte_level::ByteLevel;
+use tokenizers::pre_tokenizers::metaspace::{Metaspace, PrependScheme};
+use tokenizers::{AddedToken, Tokenizer};
+
+use crate::chat::{ChatRequest, ChatStream};
+use crate::chat_template::ChatTemplate;
+use crate::error::{CommandError, ErrorKind};
+use crate::gguf::ChatTokens;
+use crate::local_inference::LoadedModel;
+
+// Used when the GGUF doesn't say
+const DEFAULT_CONTEXT_LENGTH: usize = 4096;
+const DEFAULT_TEMPERATURE: f64 = 0.8;
+// GGUF token types
+const TOKEN_TYPE_NORMAL: i32 = 1;
+const TOKEN_TYPE_CONTROL: i32 = 3;
+const TOKEN_TYPE_USER_DEFINED: i32 = 4;
+
+fn engine_error(context: &str, err: impl std::fmt::Display) -> CommandError {
+    CommandError::new(ErrorKind::Internal, format!("{}: {}", context, err))
+}
+
+enum Model {
+    Llama(quantized_llama::ModelWeights),
+    Qwen2(quantized_qwen2::ModelWeights),
+    Qwen3(quantized_qwen3::ModelWeights),
+    Phi3(quantized_phi3::ModelWeights),
+    Gemma3(quantized_gemma3::ModelWeights),
+}
+
+impl Model {
+    fn load(architecture: &str, content: gguf_file::Content, reader: &mut BufReader<File>) -> Result<Self, CommandError> {
+        let device = Device::Cpu;
+        let model = match architecture {
+            "llama" => quantized_llama::ModelWeights::from_gguf(content, reader, &device).map(Model::Llama),
+            "qwen2" => quantized_qwen2::ModelWeights::from_gguf(content, reader, &device).map(Model::Qwen2),
+            "qwen3" => quantized_qwen3::ModelWeights::from_gguf(content, reader, &device).map(Model::Qwen3),
+            "phi3" => quantized_phi3::ModelWeights::from_gguf(false, content, reader, &device).map(Model::Phi3),
+            "gemma3" => quantized_gemma3::ModelWeights::from_gguf(content, reader, &device).map(Model::Gemma3),
+            other => {
+                return Err(CommandError::new(
+                    ErrorKind::InvalidRequest,
+                    format!("The {} architecture isn't supported by local inference", other),
+                ))
+            }
+        };
+        model.map_err(|err| engine_error("Failed to load the model weights", err))
+    }
+
+    // Logits for the token after `input`, which starts at `position` in the
+    // sequence; position 0 starts over with an empty KV cache
+    fn forward(&mut self, input: &Tensor, position: usize) -> candle_core::Result<Tensor> {
+        let logits = match self {
+            Model::Llama(model) => model.forward(input, position)?,
+            Model::Qwen2(model) => model.forward(input, position)?,
+            Model::Qwen3(model) => {
+                if position == 0 {
+                    model.clear_kv_cache();
+                }
+                model.forward(input, position)?
+            }
+            Model::Phi3(model) => model.forward(input, position)?,
+            Model::Gemma3(model) => model.forward(input, position)?,
+        };
+        logits.squeeze(0)
+    }
+}
+
+fn metadata_str<'a>(content: &'a gguf_file::Content, key: &str) -> Option<&'a str> {
+    content.metadata.get(key)?.to_string().ok().map(String::as_str)
+}
+
+fn metadata_u32(content: &gguf_file::Content, key: &str) -> Option<u32> {
+    let value = content.metadata.get(key)?;
+    value.to_u32().ok().or_else(|| value.to_u64().ok().map(|v| v as u32))
+}
+
+fn metadata_strings(content: &gguf_file::Content, key: &str) -> Vec<String> {
+    let Some(values) = content.metadata.get(key).and_then(|v| v.to_vec().ok()) else {
+        return Vec::new();
+    };
+    values.iter().filter_map(|v| v.to_string().ok().cloned()).collect()
+}
+
+fn load_tokenizer(path: &Path, content: &gguf_file::Content) -> Result<Tokenizer, CommandError> {
+    let sibling = path.with_file_name("tokenizer.json");
+    if sibling.is_file() {
+        eprintln!("[Local] Using {}", sibling.display());
+        return Tokenizer::from_file(&sibling).map_err(|err| engine_error("Failed to read tokenizer.json", err));
+    }
+
+    let tokens = metadata_strings(content, "tokenizer.ggml.tokens");
+    if tokens.is_empty() {
+        return Err(CommandError::new(ErrorKind::InvalidRequest, "The model has no embedded vocabulary"));
+    }
+    let types: Vec<i32> = content
+        .metadata
+        .get("tokenizer.ggml.token_type")
+        .and_then(|v| v.to_vec().ok())
+        .map(|values| values.iter().map(|v| v.to_i32().unwrap_or(TOKEN_TYPE_NORMAL)).collect())
+        .unwrap_or_default();
+    let token_type = |id: usize| types.get(id).copied().unwrap_or(TOKEN_TYPE_NORMAL);
+    let vocab: Vocab = tokens.iter().enumerate().map(|(id, token)| (token.clone(), id as u32)).collect();
+
+    let mut tokenizer = match metadata_str(content, "tokenizer.ggml.model").unwrap_or("llama") {
+        // Byte-level BPE (Llama 3, Qwen, ...)
+        "gpt2" => {
+            let merges = metadata_strings(content, "tokenizer.ggml.merges")
+                .iter()
+                .filter_map(|merge| merge.split_once(' '))
+                .map(|(left, right)| (left.to_string(), right.to_string()))
+                .collect();
+            let bpe = BPE::builder()
+                .vocab_and_merges(vocab, merges)
+                .build()
+                .map_err(|err| engine_error("Failed to build the tokenizer", err))?;
+            let mut tokenizer = Tokenizer::new(bpe);
+            tokenizer.with_pre_tokenizer(Some(ByteLevel::new(false, true, true)));
+            tokenizer.with_decoder(Some(ByteLevelDecoder::default()));
+            tokenizer
+        }
+        // SentencePiece BPE (Llama 2, Mistral, Gemma, Phi-3). The GGUF only
+        // stores piece scores, so merges are derived from them the same way
+        // Hugging Face converts SentencePiece models: every pair of pieces
+        // that joins into another piece, best scoring result first.
+        "llama" => {
+            let scores: Vec<f32> = content
+                .metadata
+                .get("tokenizer.ggml.scores")
+                .and_then(|v| v.to_vec().ok())
+                .map(|values| values.iter().map(|v| v.to_f32().unwrap_or(0.0)).collect())
+                .unwrap_or_default();
+            let mut merges = Vec::new();
+            for (id, piece) in tokens.iter().enumerate() {
+                if token_type(id) != TOKEN_TYPE_NORMAL {
+                    continue;
+                }
+                for (split, _) in piece.char_indices().skip(1) {
+                    let (left, right) = piece.split_at(split);
+                    if let (Some(&l), Some(&r)) = (vocab.get(left), vocab.get(right)) {
+                        let score = scores.get(id).copied().unwrap_or(0.0);
+                        merges.push((score, l, r, left.to_string(), right.to_string()));
+                    }
+                }
+            }
+            merges.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+            let merges = merges.into_iter().map(|(_, _, _, left, right)| (left, right)).collect();
+
+            let mut builder = BPE::builder().vocab_and_merges(vocab, merges).byte_fallback(true);
+            if let Some(unk) = metadata_u32(content, "tokenizer.ggml.unknown_token_id").and_then(|id| tokens.get(id as usize)) {
+                builder = builder.unk_token(unk.clone());
+            }
+            let bpe = builder.build().map_err(|err| engine_error("Failed to build the tokenizer", err))?;
+            let mut tokenizer = Tokenizer::new(bpe);
+            tokenizer.with_pre_tokenizer(Some(Metaspace::new('▁', PrependScheme::First, false)));
+            let spaces = Replace::new("▁", " ").map_err(|err| engine_error("Failed to build the tokenizer", err))?;
+            tokenizer.with_decoder(Some(DecoderSequence::new(vec![
+                spaces.into(),
+                ByteFallback::new().into(),
+                Fuse::new().into(),
+                // The space the pre-tokenizer put in front of the text
+                Strip::new(' ', 1, 0).into(),
+            ])));
+            tokenizer
+        }
+        other => {
+            return Err(CommandError::new(
+                ErrorKind::InvalidRequest,
+                format!("The {} tokenizer isn't supported by local inference", other),
+            ))
+        }
+    };
+
+    // Control tokens are matched whole in the prompt, never split into pieces
+    let special: Vec<AddedToken> = tokens
+        .iter()
+        .enumerate()
+        .filter(|(id, _)| matches!(token_type(*id), TOKEN_TYPE_CONTROL | TOKEN_TYPE_USER_DEFINED))
+        .map(|(_, token)| AddedToken::from(token.clone(), true))
+        .collect();
+    tokenizer.add_special_tokens(&special);
+    Ok(tokenizer)
+}
+
+// Turns generated tokens into text. Tokens are decoded together with the
+// ones before them, since a character can span several tokens and
+// SentencePiece decoders treat the first token differently.
+struct TextDecoder {
+    tokens: Vec<u32>,
+    prev: usize,
+    current: usize,
+}
+
+impl TextDecoder {
+    fn new() -> Self {
+        Self { tokens: Vec::new(), prev: 0, current: 0 }
+    }
+
+    fn decode(tokenizer: &Tokenizer, tokens: &[u32]) -> Result<String, CommandError> {
+        tokenizer.decode(tokens, true).map_err(|err| engine_error("Failed to decode tokens", err))
+    }
+
+    // The text the token adds, once it forms whole characters
+    fn next(&mut self, tokenizer: &Tokenizer, token: u32) -> Result<Option<String>, CommandError> {
+        let before = Self::decode(tokenizer, &self.tokens[self.prev..self.current])?;
+        self.tokens.push(token);
+        let text = Self::decode(tokenizer, &self.tokens[self.prev..])?;
+        if text.len() <= before.len() || text.ends_with('\u{FFFD}') {
+            return Ok(None);
+        }
+        let added = text.get(before.len()..).unwrap_or_default().to_string();
+        self.prev = self.current;
+        self.current = self.tokens.len();
+        Ok(Some(added))
+    }
+
+    // Whatever is still held back at the end of the response
+    fn rest(&self, tokenizer: &Tokenizer) -> Result<String, CommandError> {
+        let before = Self::decode(tokenizer, &self.tokens[self.prev..self.current])?;
+        let text = Self::decode(tokenizer, &self.tokens[self.prev..])?;
+        Ok(text.get(before.len()..).unwrap_or_default().to_string())
+    }
+}
+
+// Length of the end of `text` that could be the start of a stop string
+fn stop_prefix_len(text: &str, stops: &[String]) -> usize {
+    text.char_indices()
+        .map(|(index, _)| index)
+        .find(|&index| stops.iter().any(|stop| stop.starts_with(&text[index..])))
+        .map_or(0, |index| text.len() - index)
+}
+
+fn sampling(request: &ChatRequest) -> Sampling {
+    let temperature = request.temperature.unwrap_or(DEFAULT_TEMPERATURE);
+    if temperature <= 0.0 {
+        return Sampling::ArgMax;
+    }
+    let k = request.top_k.filter(|&k| k > 0).map(|k| k as usize);
+    let p = request.top_p.filter(|&p| p > 0.0 && p < 1.0);
+    match (k, p) {
+        (None, None) => Sampling::All { temperature },
+        (Some(k), None) => Sampling::TopK { k, temperature },
+        (None, Some(p)) => Sampling::TopP { p, temperature },
+        (Some(k), Some(p)) => Sampling::TopKThenTopP { k, p, temperature },
+    }
+}
+
+pub struct Engine {
+    model: Model,
+    tokenizer: Tokenizer,
+    pool: rayon::ThreadPool,
+    chat_tokens: ChatTokens,
+    template: ChatTemplate,
+    bos: Option<u32>,
+    eos: Option<u32>,
+    info: LoadedModel,
+}
+
+impl Engine {
+    pub fn load(path: &Path, threads: usize) -> Result<Self, CommandError> {
+        let file = File::open(path).map_err(|err| {
+            CommandError::new(ErrorKind::InvalidRequest, format!("Failed to open {}: {}", path.display(), err))
+        })?;
+        let mut reader = BufReader::new(file);
+        let content = gguf_file::Content::read(&mut reader).map_err(|err| engine_error("Failed to read the GGUF header", err))?;
+
+        let architecture = metadata_str(&content, "general.architecture").unwrap_or_default().to_string();
+        let context_length = metadata_u32(&content, &format!("{}.context_length", architecture))
+            .map_or(DEFAULT_CONTEXT_LENGTH, |n| n as usize);
+        let name = metadata_str(&content, "general.name")
+            .map(str::to_string)
+            .unwrap_or_else(|| path.file_stem().unwrap_or_default().to_string_lossy().into_owned());
+
+        let tokenizer = load_tokenizer(path, &content)?;
+        let bos_id = metadata_u32(&content, "tokenizer.ggml.bos_token_id");
+        let eos = metadata_u32(&content, "tokenizer.ggml.eos_token_id");
+        let add_bos = content
+            .metadata
+            .get("tokenizer.ggml.add_bos_token")
+            .and_then(|v| v.to_bool().ok())
+            .unwrap_or(metadata_str(&content, "tokenizer.ggml.model") != Some("gpt2"));
+        let chat_tokens = ChatTokens {
+            chat_template: metadata_str(&content, "tokenizer.chat_template").map(str::to_string),
+            bos_token: bos_id.and_then(|id| tokenizer.id_to_token(id)),
+            eos_token: eos.and_then(|id| tokenizer.id_to_token(id)),
+            add_bos_token: Some(add_bos),
+            control_tokens: tokenizer
+                .get_added_tokens_decoder()
+                .into_values()
+                .filter(|token| token.special)
+                .map(|token| token.content)
+                .collect(),
+        };
+        let template = ChatTemplate::resolve(None, Some(&chat_tokens))?;
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .map_err(|err| engine_error("Failed to start the inference threads", err))?;
+        let model = pool.install(|| Model::load(&architecture, content, &mut reader))?;
+        eprintln!(
+            "[Local] Loaded {} ({}, {} chat template, {} threads)",
+            name,
+            architecture,
+            template.name.unwrap_or(template.origin),
+            threads
+        );
+
+        Ok(Self {
+            model,
+            tokenizer,
+            pool,
+            chat_tokens,
+            template,
+            bos: bos_id.filter(|_| add_bos),
+            eos,
+            info: LoadedModel {
+                path: path.to_string_lossy().into_owned(),
+                name,
+                architecture,
+                context_length,
+                threads,
+            },
+        })
+    }
+
+    pub fn info(&self) -> &LoadedModel {
+        &self.info
+    }
+
+    // Streams the response to `request` until an end-of-turn token, a stop
+    // string, max_tokens or the end of the context. `template` overrides the
+    // model's chat template.
+    pub fn generate(
+        &mut self,
+        request: &ChatRequest,
+        template: Option<&str>,
+        stream: &mut ChatStream,
+        is_cancelled: impl Fn() -> bool + Sync,
+    ) -> Result<(), CommandError> {
+        let template = match template {
+            Some(template) => ChatTemplate::resolve(Some(template), Some(&self.chat_tokens))?,
+            None => self.template.clone(),
+        };
+        let prompt = template.render(&request.messages, &request.tools, true)?;
+        // The BOS token is added by id below
+        let prompt = if self.bos.is_some() { template.strip_bos(&prompt) } else { &prompt };
+        let encoding = self
+            .tokenizer
+            .encode(prompt, false)
+            .map_err(|err| engine_error("Failed to tokenize the prompt", err))?;
+        let mut tokens: Vec<u32> = self.bos.into_iter().collect();
+        tokens.extend(encoding.get_ids());
+
+        let prompt_tokens = tokens.len();
+        let context_length = self.info.context_length;
+        if prompt_tokens >= context_length {
+            return Err(CommandError::new(
+                ErrorKind::InvalidRequest,
+                format!("The prompt is {} tokens, more than the model's context of {}", prompt_tokens, context_length),
+            ));
+        }
+        let max_tokens = request
+            .max_tokens
+            .map_or(usize::MAX, |n| n as usize)
+            .min(context_length - prompt_tokens);
+
+        let seed = request.seed.map_or_else(|| chrono::Utc::now().timestamp_nanos_opt().unwrap_or(0) as u64, |s| s as u64);
+        let mut sampler = LogitsProcessor::from_sampling(seed, sampling(request));
+        // Ends of turn that are single tokens are matched by id, the rest as text
+        let mut stop_tokens: Vec<u32> = self.eos.into_iter().collect();
+        let mut stops: Vec<String> = request.stop.iter().filter(|stop| !stop.is_empty()).cloned().collect();
+        for stop in template.stop_strings() {
+            match self.tokenizer.token_to_id(&stop) {
+                Some(id) => stop_tokens.push(id),
+                None => stops.push(stop),
+            }
+        }
+
+        let Self { model, tokenizer, pool, .. } = self;
+        pool.install(|| {
+            let mut decoder = TextDecoder::new();
+            let mut pending = String::new();
+            let mut input = tokens;
+            let mut position = 0;
+            let mut generated = 0;
+            let mut finish = "length";
+            let mut stop_string = false;
+
+            while generated < max_tokens {
+                if is_cancelled() {
+                    return Err(CommandError::cancelled());
+                }
+                let tensor = Tensor::new(input.as_slice(), &Device::Cpu)
+                    .and_then(|t| t.unsqueeze(0))
+                    .map_err(|err| engine_error("Inference failed", err))?;
+                let logits = model.forward(&tensor, position).map_err(|err| engine_error("Inference failed", err))?;
+                position += input.len();
+                let token = sampler.sample(&logits).map_err(|err| engine_error("Sampling failed", err))?;
+                if stop_tokens.contains(&token) {
+                    finish = "stop";
+                    break;
+                }
+                generated += 1;
+                input = vec![token];
+
+                let Some(text) = decoder.next(tokenizer, token)? else {
+                    continue;
+                };
+                pending.push_str(&text);
+                if let Some(end) = stops.iter().filter_map(|stop| pending.find(stop.as_str())).min() {
+                    pending.truncate(end);
+                    finish = "stop";
+                    stop_string = true;
+                    break;
+                }
+                // Hold back text that may turn out to be a stop string
+                let ready = pending.len() - stop_prefix_len(&pending, &stops);
+                stream.text(&pending[..ready])?;
+                pending.drain(..ready);
+            }
+
+            if !stop_string {
+                pending.push_str(&decoder.rest(tokenizer)?);
+            }
+            stream.text(&pending)?;
+            stream.usage(prompt_tokens as u64, generated as u64)?;
+            stream.finish_reason(finish);
+            Ok(())
+        })
+    }
+}
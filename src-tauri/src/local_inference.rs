@@ -0,0 +1,459 @@
+// The built-in `local` provider: runs GGUF models on the CPU inside the app,
+// without Ollama or a llama.cpp server. The engine (local_engine.rs) is only
+// compiled with the `local-inference` cargo feature; without it these
+// commands report that local inference isn't available. Models are looked
+// up in the models folder of the app data dir unless given by absolute
+// path, and stay loaded between requests.
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tauri::ipc::Channel;
+use tauri::{Manager, State};
+
+use crate::cancellation::RequestRegistry;
+use crate::chat::{ChatCompletion, ChatEvent, ChatRequest, ChatStream};
+use crate::error::{CommandError, ErrorKind};
+use crate::gguf::{self, GgufSummary};
+use crate::tool_emulation;
+
+#[cfg(feature = "local-inference")]
+use crate::local_engine::Engine;
+
+const MODELS_DIR: &str = "models";
+
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoadedModel {
+    pub path: String,
+    pub name: String,
+    pub architecture: String,
+    pub context_length: usize,
+    pub threads: usize,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LocalInferenceStatus {
+    // False when the app was built without the `local-inference` feature
+    pub available: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub models_dir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub loaded: Option<LoadedModel>,
+}
+
+// Without the feature there's never an engine to load
+#[cfg(not(feature = "local-inference"))]
+enum Engine {}
+
+#[cfg(not(feature = "local-inference"))]
+impl Engine {
+    fn load(_path: &Path, _threads: usize) -> Result<Self, CommandError> {
+        Err(CommandError::new(
+            ErrorKind::InvalidRequest,
+            "This build doesn't include local inference (cargo feature `local-inference`)",
+        ))
+    }
+
+    fn info(&self) -> &LoadedModel {
+        match *self {}
+    }
+
+    fn generate(
+        &mut self,
+        _request: &ChatRequest,
+        _template: Option<&str>,
+        _stream: &mut ChatStream,
+        _is_cancelled: impl Fn() -> bool + Sync,
+    ) -> Result<(), CommandError> {
+        match *self {}
+    }
+}
+
+#[derive(Clone)]
+pub struct LocalModels {
+    engine: Arc<Mutex<Option<Engine>>>,
+    // Kept apart from the engine, which stays locked while it generates
+    loaded: Arc<Mutex<Option<LoadedModel>>>,
+    // Unloads waiting for the engine; a running generation stops for them
+    unloads: Arc<AtomicUsize>,
+    models_dir: Option<PathBuf>,
+}
+
+impl LocalModels {
+    pub fn new<R: tauri::Runtime>(app: &tauri::AppHandle<R>) -> Self {
+        Self::open_dir(app.path().app_data_dir().ok())
+    }
+
+    fn open_dir(app_data_dir: Option<PathBuf>) -> Self {
+        Self {
+            engine: Arc::new(Mutex::new(None)),
+            loaded: Arc::new(Mutex::new(None)),
+            unloads: Arc::new(AtomicUsize::new(0)),
+            models_dir: app_data_dir.map(|dir| dir.join(MODELS_DIR)),
+        }
+    }
+
+    fn models_dir(&self) -> Result<&Path, CommandError> {
+        self.models_dir
+            .as_deref()
+            .ok_or_else(|| CommandError::new(ErrorKind::Internal, "No app data directory for local models"))
+    }
+
+    // A model name from local_list_models, or an absolute path
+    fn resolve(&self, model: &str) -> Result<PathBuf, CommandError> {
+        let path = Path::new(model);
+        let path = if path.is_absolute() { path.to_path_buf() } else { self.models_dir()?.join(path) };
+        if !path.is_file() {
+            return Err(CommandError::new(ErrorKind::InvalidRequest, format!("Model not found: {}", model)));
+        }
+        let info = gguf::inspect(&path)?;
+        if info.kind != "model" {
+            return Err(CommandError::new(
+                ErrorKind::InvalidRequest,
+                format!("{} is a {} file, not a model", info.file_name, info.kind),
+            ));
+        }
+        if info.split_count > 1 {
+            return Err(CommandError::new(
+                ErrorKind::InvalidRequest,
+                "Split models can't be run locally yet; merge the parts with llama-gguf-split",
+            ));
+        }
+        Ok(path)
+    }
+
+    fn status(&self) -> LocalInferenceStatus {
+        LocalInferenceStatus {
+            available: cfg!(feature = "local-inference"),
+            models_dir: self.models_dir.as_ref().map(|dir| dir.to_string_lossy().into_owned()),
+            loaded: self.loaded.lock().unwrap().clone(),
+        }
+    }
+
+    // Runs `work` on the model at `path`, loading it unless it's already
+    // loaded with the same thread count. Waits while another request is
+    // generating; the engine stays locked until `work` returns.
+    fn with_model<T>(
+        &self,
+        path: &Path,
+        threads: Option<usize>,
+        work: impl FnOnce(&mut Engine) -> Result<T, CommandError>,
+    ) -> Result<T, CommandError> {
+        let threads = threads
+            .filter(|&n| n > 0)
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(4, |n| n.get()));
+        let mut engine = self.engine.lock().unwrap();
+        let current = engine
+            .take()
+            .filter(|current| Path::new(&current.info().path) == path && current.info().threads == threads);
+        let engine = match current {
+            Some(current) => engine.insert(current),
+            None => {
+                // The previous model is freed before the next one is read
+                *self.loaded.lock().unwrap() = None;
+                eprintln!("[Local] Loading {} with {} threads", path.display(), threads);
+                let loaded = Engine::load(path, threads)?;
+                *self.loaded.lock().unwrap() = Some(loaded.info().clone());
+                engine.insert(loaded)
+            }
+        };
+        work(engine)
+    }
+
+    // Whether a generation should stop to let an unload through
+    fn unload_requested(&self) -> bool {
+        self.unloads.load(Ordering::SeqCst) > 0
+    }
+
+    // Frees the loaded model. A running generation sees `unload_requested`
+    // before its next token and ends as cancelled, so this doesn't wait for
+    // the whole response.
+    fn unload(&self) {
+        self.unloads.fetch_add(1, Ordering::SeqCst);
+        let engine = self.engine.lock().unwrap().take();
+        self.unloads.fetch_sub(1, Ordering::SeqCst);
+        if let Some(engine) = engine {
+            *self.loaded.lock().unwrap() = None;
+            eprintln!("[Local] Unloaded {}", engine.info().name);
+        }
+    }
+}
+
+#[tauri::command]
+pub fn local_inference_status(models: State<'_, LocalModels>) -> LocalInferenceStatus {
+    models.status()
+}
+
+// GGUF models in the models folder, which is created when missing
+#[tauri::command]
+pub async fn local_list_models(models: State<'_, LocalModels>) -> Result<Vec<GgufSummary>, CommandError> {
+    let dir = models.models_dir()?.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        std::fs::create_dir_all(&dir).map_err(|err| {
+            CommandError::new(ErrorKind::Internal, format!("Failed to create {}: {}", dir.display(), err))
+        })?;
+        gguf::scan(&dir)
+    })
+    .await?
+}
+
+// Loads a model ahead of the first chat request. `threads` defaults to the
+// number of CPU cores.
+#[tauri::command]
+pub async fn local_load_model(
+    model: String,
+    threads: Option<usize>,
+    models: State<'_, LocalModels>,
+) -> Result<LoadedModel, CommandError> {
+    let models = models.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        let path = models.resolve(&model)?;
+        models.with_model(&path, threads, |engine| Ok(engine.info().clone()))
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn local_unload_model(models: State<'_, LocalModels>) -> Result<(), CommandError> {
+    let models = models.inner().clone();
+    tokio::task::spawn_blocking(move || models.unload()).await?;
+    Ok(())
+}
+
+// Streams a chat completion from a local model over `on_event`, loading the
+// model first if needed. Requests run one at a time. `chat_template` (a
+// built-in name or Jinja source) overrides the model's template.
+#[tauri::command]
+pub async fn local_chat(
+    request: ChatRequest,
+    request_id: Option<String>,
+    threads: Option<usize>,
+    chat_template: Option<String>,
+    on_event: Channel<ChatEvent>,
+    requests: State<'_, RequestRegistry>,
+    models: State<'_, LocalModels>,
+) -> Result<ChatCompletion, CommandError> {
+    eprintln!("[Local] Chat with {} ({} messages)", request.model, request.messages.len());
+    let models = models.inner().clone();
+
+    let result = requests
+        .run(request_id, |token| {
+            let on_event = on_event.clone();
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    let path = models.resolve(&request.model)?;
+                    let dialect = tool_emulation::dialect_for(&request, None);
+                    let prepared = dialect.map(|dialect| tool_emulation::prepare(&request, dialect));
+                    models.with_model(&path, threads, |engine| {
+                        let mut stream = ChatStream::new(&on_event);
+                        stream.model(Some(request.model.clone()));
+                        if let Some(dialect) = dialect {
+                            stream.emulate_tools(dialect, &request.tools);
+                        }
+                        let is_cancelled =
+                            || token.as_ref().is_some_and(|t| t.is_cancelled()) || models.unload_requested();
+                        let request = prepared.as_ref().unwrap_or(&request);
+                        engine.generate(request, chat_template.as_deref(), &mut stream, is_cancelled)?;
+                        stream.finish()
+                    })
+                })
+                .await?
+            }
+        })
+        .await;
+
+    if let Err(err) = &result {
+        let _ = on_event.send(ChatEvent::Error(err.clone()));
+    }
+    result
+}
+
+#[cfg(all(test, feature = "local-inference"))]
+mod tests {
+    use candle_core::quantized::{gguf_file, GgmlDType, QTensor};
+    use candle_core::{Device, Tensor};
+    use serde_json::json;
+
+    use super::*;
+
+    const DIM: usize = 16;
+    // Control tokens first, then the text pieces
+    const TOKENS: [(&str, i32); 8] = [
+        ("<unk>", 2),
+        ("<s>", 3),
+        ("</s>", 3),
+        ("<|im_start|>", 3),
+        ("<|im_end|>", 3),
+        ("▁Hello", 1),
+        ("▁world", 1),
+        ("!", 1),
+    ];
+    // Token that follows each token. Most of a chat prompt isn't in the
+    // vocabulary and ends up as <unk>, so a reply reads "Hello world!" and
+    // ends the turn.
+    const NEXT: [(usize, usize); 4] = [(0, 5), (5, 6), (6, 7), (7, 4)];
+
+    fn tensor(values: Vec<f32>, shape: &[usize]) -> QTensor {
+        let tensor = Tensor::from_vec(values, shape, &Device::Cpu).unwrap();
+        QTensor::quantize(&tensor, GgmlDType::F32).unwrap()
+    }
+
+    // A one-layer llama whose attention and feed-forward weights are zero,
+    // so each token's logits only depend on its own embedding: greedy
+    // decoding walks the NEXT chain
+    fn tiny_model(dir: &Path) -> PathBuf {
+        let vocab = TOKENS.len();
+        let mut embeddings = vec![0f32; vocab * DIM];
+        for token in 0..vocab {
+            embeddings[token * DIM + token] = 1.0;
+        }
+        let mut output = vec![0f32; vocab * DIM];
+        for (from, to) in NEXT {
+            output[to * DIM + from] = 1.0;
+        }
+
+        let tensors = [
+            ("token_embd.weight", tensor(embeddings, &[vocab, DIM])),
+            ("output.weight", tensor(output, &[vocab, DIM])),
+            ("output_norm.weight", tensor(vec![1.0; DIM], &[DIM])),
+            ("blk.0.attn_norm.weight", tensor(vec![1.0; DIM], &[DIM])),
+            ("blk.0.ffn_norm.weight", tensor(vec![1.0; DIM], &[DIM])),
+            ("blk.0.attn_q.weight", tensor(vec![0.0; DIM * DIM], &[DIM, DIM])),
+            ("blk.0.attn_k.weight", tensor(vec![0.0; DIM * DIM], &[DIM, DIM])),
+            ("blk.0.attn_v.weight", tensor(vec![0.0; DIM * DIM], &[DIM, DIM])),
+            ("blk.0.attn_output.weight", tensor(vec![0.0; DIM * DIM], &[DIM, DIM])),
+            ("blk.0.ffn_gate.weight", tensor(vec![0.0; DIM * DIM], &[DIM, DIM])),
+            ("blk.0.ffn_up.weight", tensor(vec![0.0; DIM * DIM], &[DIM, DIM])),
+            ("blk.0.ffn_down.weight", tensor(vec![0.0; DIM * DIM], &[DIM, DIM])),
+        ];
+
+        use gguf_file::Value;
+        let metadata = [
+            ("general.architecture", Value::String("llama".to_string())),
+            ("general.name", Value::String("Tiny".to_string())),
+            ("llama.context_length", Value::U32(4096)),
+            ("llama.embedding_length", Value::U32(DIM as u32)),
+            ("llama.block_count", Value::U32(1)),
+            ("llama.feed_forward_length", Value::U32(DIM as u32)),
+            ("llama.attention.head_count", Value::U32(2)),
+            ("llama.attention.head_count_kv", Value::U32(2)),
+            ("llama.rope.dimension_count", Value::U32(8)),
+            ("llama.attention.layer_norm_rms_epsilon", Value::F32(1e-6)),
+            ("tokenizer.ggml.model", Value::String("llama".to_string())),
+            (
+                "tokenizer.ggml.tokens",
+                Value::Array(TOKENS.iter().map(|(token, _)| Value::String(token.to_string())).collect()),
+            ),
+            ("tokenizer.ggml.token_type", Value::Array(TOKENS.iter().map(|(_, kind)| Value::I32(*kind)).collect())),
+            ("tokenizer.ggml.scores", Value::Array(TOKENS.iter().map(|_| Value::F32(0.0)).collect())),
+            ("tokenizer.ggml.unknown_token_id", Value::U32(0)),
+            ("tokenizer.ggml.bos_token_id", Value::U32(1)),
+            ("tokenizer.ggml.eos_token_id", Value::U32(2)),
+        ];
+
+        let path = dir.join("tiny.gguf");
+        let mut file = std::fs::File::create(&path).unwrap();
+        let metadata: Vec<(&str, &Value)> = metadata.iter().map(|(key, value)| (*key, value)).collect();
+        let tensors: Vec<(&str, &QTensor)> = tensors.iter().map(|(name, tensor)| (*name, tensor)).collect();
+        gguf_file::write(&mut file, &metadata, &tensors).unwrap();
+        path
+    }
+
+    fn request(content: &str, fields: serde_json::Value) -> ChatRequest {
+        let mut request = json!({
+            "model": "tiny.gguf",
+            "messages": [{ "role": "user", "content": content }],
+            "temperature": 0.0,
+        });
+        request.as_object_mut().unwrap().extend(fields.as_object().unwrap().clone());
+        serde_json::from_value(request).unwrap()
+    }
+
+    fn chat(
+        models: &LocalModels,
+        path: &Path,
+        request: &ChatRequest,
+        is_cancelled: impl Fn() -> bool + Sync,
+    ) -> Result<ChatCompletion, CommandError> {
+        let channel = Channel::new(|_| Ok(()));
+        models.with_model(path, Some(1), |engine| {
+            let mut stream = ChatStream::new(&channel);
+            engine.generate(request, None, &mut stream, is_cancelled)?;
+            stream.finish()
+        })
+    }
+
+    #[test]
+    fn streams_until_the_end_of_the_turn() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = tiny_model(dir.path());
+        let models = LocalModels::open_dir(None);
+
+        let completion = chat(&models, &path, &request("Hi", json!({})), || false).unwrap();
+        assert_eq!(completion.content, "Hello world!");
+        assert_eq!(completion.finish_reason, crate::chat::FinishReason::Stop);
+        assert_eq!(completion.usage.unwrap().output_tokens, 3);
+        assert_eq!(models.status().loaded.unwrap().name, "Tiny");
+
+        let completion = chat(&models, &path, &request("Hi", json!({ "maxTokens": 2 })), || false).unwrap();
+        assert_eq!(completion.content, "Hello world");
+        assert_eq!(completion.finish_reason, crate::chat::FinishReason::Length);
+    }
+
+    #[test]
+    fn stops_at_stop_strings() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = tiny_model(dir.path());
+        let models = LocalModels::open_dir(None);
+
+        let request = request("Hi", json!({ "stop": ["orld"] }));
+        let completion = chat(&models, &path, &request, || false).unwrap();
+        assert_eq!(completion.content, "Hello w");
+        assert_eq!(completion.finish_reason, crate::chat::FinishReason::Stop);
+    }
+
+    #[test]
+    fn cancellation_stops_generating() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = tiny_model(dir.path());
+        let models = LocalModels::open_dir(None);
+
+        let steps = AtomicUsize::new(0);
+        let is_cancelled = || steps.fetch_add(1, Ordering::SeqCst) >= 2;
+        let err = chat(&models, &path, &request("Hi", json!({})), is_cancelled).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Cancelled);
+        assert_eq!(steps.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn unload_stops_a_running_generation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = tiny_model(dir.path());
+        let models = LocalModels::open_dir(None);
+
+        // The generation holds off until the unload is waiting for the engine
+        let (started, generating) = std::sync::mpsc::channel();
+        let chatting = {
+            let models = models.clone();
+            std::thread::spawn(move || {
+                let is_cancelled = || {
+                    let _ = started.send(());
+                    let waited = std::time::Instant::now();
+                    while !models.unload_requested() && waited.elapsed() < std::time::Duration::from_secs(5) {
+                        std::thread::yield_now();
+                    }
+                    models.unload_requested()
+                };
+                chat(&models, &path, &request("Hi", json!({})), is_cancelled)
+            })
+        };
+        generating.recv().unwrap();
+
+        models.unload();
+        assert!(models.status().loaded.is_none());
+        let err = chatting.join().unwrap().unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Cancelled);
+    }
+}
@@ -0,0 +1,422 @@
+// Upstream proxy, extra CA roots and certificate exceptions for every
+// outgoing backend request. Persisted to network.json in the app data dir,
+// except the proxy password, which is kept in the secrets vault.
+use std::ffi::OsString;
+use std::path::Path;
+
+use reqwest::Url;
+
+use crate::app_data;
+use crate::error::{CommandError, ErrorKind};
+use crate::http_client::HttpClients;
+use crate::recorder::Recorder;
+use crate::vault::Vault;
+
+const SETTINGS_FILE: &str = "network.json";
+const PASSWORD_ENTRY: &str = "proxyPassword";
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProxySettings {
+    // http://, https://, socks5:// or socks5h:// (DNS resolved by the proxy)
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    // Received from the UI (and read from files of earlier versions), but
+    // never written out or sent back; it lives in the vault
+    #[serde(default, skip_serializing)]
+    pub password: Option<String>,
+    // Whether the vault holds a password, which the UI keeps by sending
+    // this back without a new password
+    #[serde(default)]
+    pub has_password: bool,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<ProxySettings>,
+    // Hosts, domains (".corp.example") or CIDR ranges that bypass the proxy
+    #[serde(default = "default_no_proxy")]
+    pub no_proxy: Vec<String>,
+    // PEM blocks trusted in addition to the system roots
+    #[serde(default)]
+    pub extra_root_certs: Vec<String>,
+    // Hosts whose invalid or self-signed certificates are accepted
+    #[serde(default)]
+    pub insecure_hosts: Vec<String>,
+}
+
+fn default_no_proxy() -> Vec<String> {
+    // Local providers never go through the proxy
+    vec!["localhost".to_string(), "127.0.0.1".to_string(), "::1".to_string()]
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        Self {
+            proxy: None,
+            no_proxy: default_no_proxy(),
+            extra_root_certs: Vec::new(),
+            insecure_hosts: Vec::new(),
+        }
+    }
+}
+
+fn invalid(message: impl Into<String>) -> CommandError {
+    CommandError::new(ErrorKind::InvalidRequest, message)
+}
+
+impl NetworkSettings {
+    // Proxy URL with the credentials embedded, as reqwest and Chrome expect
+    fn proxy_url(&self) -> Result<Option<Url>, CommandError> {
+        let Some(proxy) = &self.proxy else {
+            return Ok(None);
+        };
+
+        let mut url = Url::parse(proxy.url.trim()).map_err(|err| invalid(format!("Invalid proxy URL: {err}")))?;
+        match url.scheme() {
+            "http" | "https" | "socks5" | "socks5h" => {}
+            scheme => return Err(invalid(format!("Unsupported proxy scheme: {}", scheme))),
+        }
+        if url.host_str().is_none() {
+            return Err(invalid("Proxy URL has no host"));
+        }
+
+        if let Some(username) = proxy.username.as_deref().filter(|u| !u.is_empty()) {
+            url.set_username(username)
+                .map_err(|_| invalid("Proxy URL can't carry credentials"))?;
+            url.set_password(proxy.password.as_deref())
+                .map_err(|_| invalid("Proxy URL can't carry credentials"))?;
+        }
+        Ok(Some(url))
+    }
+
+    // Proxy for the reqwest clients. The proxy resolves target hosts itself,
+    // so HttpClients resolves and checks them before handing a request over.
+    pub fn reqwest_proxy(&self) -> Result<Option<reqwest::Proxy>, CommandError> {
+        let Some(url) = self.proxy_url()? else {
+            return Ok(None);
+        };
+        let proxy = reqwest::Proxy::all(url.as_str())
+            .map_err(|err| invalid(format!("Invalid proxy URL: {err}")))?
+            .no_proxy(reqwest::NoProxy::from_string(&self.no_proxy.join(",")));
+        Ok(Some(proxy))
+    }
+
+    pub fn root_certificates(&self) -> Result<Vec<reqwest::Certificate>, CommandError> {
+        let mut certificates = Vec::new();
+        for (index, pem) in self.extra_root_certs.iter().enumerate() {
+            let bundle = reqwest::Certificate::from_pem_bundle(pem.as_bytes())
+                .map_err(|err| invalid(format!("Invalid root certificate #{}: {err}", index + 1)))?;
+            if bundle.is_empty() {
+                return Err(invalid(format!("Root certificate #{} contains no PEM certificate", index + 1)));
+            }
+            certificates.extend(bundle);
+        }
+        Ok(certificates)
+    }
+
+    pub fn is_insecure_host(&self, url: &Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        self.insecure_hosts
+            .iter()
+            .any(|insecure| insecure.trim().eq_ignore_ascii_case(host))
+    }
+
+    // Launch settings for a headless Chrome instance that will load `url`
+    pub fn browser_launch(&self, url: &Url) -> BrowserNetwork {
+        let mut launch = BrowserNetwork {
+            // Chrome's certificate checks are all or nothing, and every fetch
+            // starts its own browser, so this follows the page's host
+            ignore_certificate_errors: self.is_insecure_host(url),
+            ..Default::default()
+        };
+
+        if let Ok(Some(proxy)) = self.proxy_url() {
+            let mut server = proxy.clone();
+            let _ = server.set_username("");
+            let _ = server.set_password(None);
+            launch.proxy_server = Some(server.as_str().trim_end_matches('/').to_string());
+
+            if !self.no_proxy.is_empty() {
+                let mut arg = OsString::from("--proxy-bypass-list=");
+                arg.push(self.no_proxy.join(";"));
+                launch.args.push(arg);
+            }
+
+            // Chrome asks for proxy credentials through the Fetch domain
+            if !proxy.username().is_empty() {
+                launch.proxy_auth = Some((
+                    urlencoding::decode(proxy.username()).map(|u| u.into_owned()).unwrap_or_default(),
+                    proxy
+                        .password()
+                        .and_then(|p| urlencoding::decode(p).ok())
+                        .map(|p| p.into_owned()),
+                ));
+            }
+        }
+
+        launch
+    }
+}
+
+#[derive(Default)]
+pub struct BrowserNetwork {
+    pub proxy_server: Option<String>,
+    pub args: Vec<OsString>,
+    pub ignore_certificate_errors: bool,
+    pub proxy_auth: Option<(String, Option<String>)>,
+}
+
+fn stored_password(vault: &Vault) -> Result<Option<String>, CommandError> {
+    Ok(vault.get::<Option<String>>(PASSWORD_ENTRY)?.flatten())
+}
+
+fn store_password(vault: &Vault, password: Option<String>) -> Result<(), CommandError> {
+    // Nothing to clear without a vault; don't create one for it
+    if password.is_none() && !vault.status().exists {
+        return Ok(());
+    }
+    vault.update(PASSWORD_ENTRY, |stored: &mut Option<String>| *stored = password)
+}
+
+// Reads the persisted settings; a missing or unreadable file means defaults.
+// The proxy password comes from the vault, and a plain-text one written by
+// earlier versions is moved there. While the vault is locked the password
+// is missing until refresh_password picks it up.
+pub fn load<R: tauri::Runtime>(app: &tauri::AppHandle<R>, vault: &Vault) -> NetworkSettings {
+    read(app_data::path(app, SETTINGS_FILE).as_deref(), vault)
+}
+
+fn read(path: Option<&Path>, vault: &Vault) -> NetworkSettings {
+    let mut settings: NetworkSettings = app_data::read(path);
+    let Some(proxy) = settings.proxy.as_mut() else {
+        return settings;
+    };
+
+    if let Some(password) = proxy.password.clone().filter(|password| !password.is_empty()) {
+        match store_password(vault, Some(password)) {
+            Ok(()) => {
+                proxy.has_password = true;
+                match app_data::write(path, &settings) {
+                    Ok(()) => eprintln!("[Network] Moved the proxy password into the vault"),
+                    Err(err) => eprintln!("[Network] Failed to rewrite network settings: {}", err),
+                }
+            }
+            Err(err) => eprintln!("[Network] Proxy password stays in {} for now: {}", SETTINGS_FILE, err),
+        }
+    } else if proxy.has_password {
+        match stored_password(vault) {
+            Ok(password) => proxy.password = password,
+            Err(err) => eprintln!("[Network] Proxy password not available yet: {}", err),
+        }
+    }
+    settings
+}
+
+// Keeps the proxy password out of recorded request bodies
+pub fn redact_password(recorder: &Recorder, settings: &NetworkSettings) {
+    recorder.set_secret(PASSWORD_ENTRY, settings.proxy.as_ref().and_then(|proxy| proxy.password.as_deref()));
+}
+
+// Rebuilds the clients with the proxy password once the vault is unlocked
+pub fn refresh_password(clients: &HttpClients, recorder: &Recorder, vault: &Vault) {
+    let mut network = clients.network();
+    let Some(proxy) = network.proxy.as_mut().filter(|proxy| proxy.has_password && proxy.password.is_none()) else {
+        return;
+    };
+    match stored_password(vault) {
+        Ok(Some(password)) => {
+            proxy.password = Some(password);
+            redact_password(recorder, &network);
+            if let Err(err) = clients.update_network(network) {
+                eprintln!("[Network] Failed to apply the proxy password: {}", err);
+            }
+        }
+        Ok(None) => {}
+        Err(err) => eprintln!("[Network] Proxy password not available: {}", err),
+    }
+}
+
+// The proxy password is never serialized; the UI only sees hasPassword
+#[tauri::command]
+pub fn get_network_settings(clients: tauri::State<'_, HttpClients>) -> NetworkSettings {
+    clients.network()
+}
+
+// Checks the new settings by building clients for them, then stores the
+// password and the settings, and only then swaps the clients in. A proxy
+// sent with `hasPassword` and no password keeps the stored one.
+#[tauri::command]
+pub fn update_network_settings(
+    settings: NetworkSettings,
+    app: tauri::AppHandle,
+    clients: tauri::State<'_, HttpClients>,
+    recorder: tauri::State<'_, Recorder>,
+    vault: tauri::State<'_, Vault>,
+) -> Result<(), CommandError> {
+    eprintln!(
+        "[Network] Updating settings: proxy {}, {} extra roots, {} insecure hosts",
+        if settings.proxy.is_some() { "on" } else { "off" },
+        settings.extra_root_certs.len(),
+        settings.insecure_hosts.len()
+    );
+    update(settings, app_data::path(&app, SETTINGS_FILE).as_deref(), &clients, &recorder, &vault)
+}
+
+fn update(
+    mut settings: NetworkSettings,
+    path: Option<&Path>,
+    clients: &HttpClients,
+    recorder: &Recorder,
+    vault: &Vault,
+) -> Result<(), CommandError> {
+    // None leaves the vault alone, Some(None) clears the stored password
+    let mut new_password = Some(None);
+    if let Some(proxy) = settings.proxy.as_mut() {
+        match proxy.password.take().filter(|password| !password.is_empty()) {
+            Some(password) => {
+                new_password = Some(Some(password.clone()));
+                proxy.password = Some(password);
+                proxy.has_password = true;
+            }
+            None if proxy.has_password => {
+                new_password = None;
+                proxy.password = clients.network().proxy.and_then(|current| current.password);
+            }
+            None => {}
+        }
+    }
+
+    let prepared = clients.prepare_network(settings.clone())?;
+    if let Some(password) = new_password {
+        store_password(vault, password)?;
+    }
+    app_data::write(path, &settings)?;
+    redact_password(recorder, &settings);
+    clients.install(prepared);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::egress::{EgressPolicy, EgressPolicySettings};
+    use crate::http_client::HttpClientSettings;
+    use crate::recorder::RecorderSettings;
+
+    struct Fixture {
+        dir: tempfile::TempDir,
+        clients: HttpClients,
+        recorder: Recorder,
+        vault: Vault,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let policy = EgressPolicy::new(EgressPolicySettings::default()).unwrap();
+            Self {
+                clients: HttpClients::new(HttpClientSettings::default(), NetworkSettings::default(), policy).unwrap(),
+                recorder: Recorder::new(RecorderSettings::default()),
+                vault: Vault::open_dir(Some(dir.path().to_path_buf())),
+                dir,
+            }
+        }
+
+        fn path(&self) -> std::path::PathBuf {
+            self.dir.path().join(SETTINGS_FILE)
+        }
+
+        fn update(&self, settings: NetworkSettings) -> Result<(), CommandError> {
+            update(settings, Some(&self.path()), &self.clients, &self.recorder, &self.vault)
+        }
+    }
+
+    fn with_proxy(password: Option<&str>, has_password: bool) -> NetworkSettings {
+        NetworkSettings {
+            proxy: Some(ProxySettings {
+                url: "http://proxy.corp.example:3128".to_string(),
+                username: Some("alice".to_string()),
+                password: password.map(str::to_string),
+                has_password,
+            }),
+            insecure_hosts: vec!["gpu-box.lan".to_string()],
+            ..NetworkSettings::default()
+        }
+    }
+
+    #[test]
+    fn settings_round_trip_without_the_password_on_disk() {
+        let fixture = Fixture::new();
+        fixture.update(with_proxy(Some("hunter2"), false)).unwrap();
+
+        let text = std::fs::read_to_string(fixture.path()).unwrap();
+        assert!(!text.contains("hunter2"));
+        assert!(text.contains("\"hasPassword\": true"));
+        assert_eq!(stored_password(&fixture.vault).unwrap().as_deref(), Some("hunter2"));
+        assert_eq!(fixture.clients.network().proxy.unwrap().password.as_deref(), Some("hunter2"));
+
+        let loaded = read(Some(&fixture.path()), &fixture.vault);
+        let proxy = loaded.proxy.unwrap();
+        assert_eq!(proxy.url, "http://proxy.corp.example:3128");
+        assert_eq!(proxy.username.as_deref(), Some("alice"));
+        assert_eq!(proxy.password.as_deref(), Some("hunter2"));
+        assert_eq!(loaded.insecure_hosts, ["gpu-box.lan"]);
+        assert_eq!(loaded.no_proxy, default_no_proxy());
+
+        // hasPassword without a password keeps the stored one
+        fixture.update(with_proxy(None, true)).unwrap();
+        assert_eq!(fixture.clients.network().proxy.unwrap().password.as_deref(), Some("hunter2"));
+        assert_eq!(stored_password(&fixture.vault).unwrap().as_deref(), Some("hunter2"));
+
+        // Neither clears it
+        fixture.update(with_proxy(None, false)).unwrap();
+        assert_eq!(stored_password(&fixture.vault).unwrap(), None);
+        assert!(read(Some(&fixture.path()), &fixture.vault).proxy.unwrap().password.is_none());
+    }
+
+    #[test]
+    fn plain_text_passwords_move_into_the_vault() {
+        let fixture = Fixture::new();
+        let legacy = r#"{"proxy":{"url":"http://proxy.corp.example:3128","password":"hunter2"}}"#;
+        std::fs::write(fixture.path(), legacy).unwrap();
+
+        let loaded = read(Some(&fixture.path()), &fixture.vault);
+        let proxy = loaded.proxy.unwrap();
+        assert_eq!(proxy.password.as_deref(), Some("hunter2"));
+        assert!(proxy.has_password);
+        assert_eq!(stored_password(&fixture.vault).unwrap().as_deref(), Some("hunter2"));
+        assert!(!std::fs::read_to_string(fixture.path()).unwrap().contains("hunter2"));
+    }
+
+    #[test]
+    fn a_locked_vault_leaves_everything_unchanged() {
+        let fixture = Fixture::new();
+        fixture.vault.create(Some("correct horse")).unwrap();
+        fixture.vault.lock().unwrap();
+
+        let err = fixture.update(with_proxy(Some("hunter2"), false)).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Locked);
+        assert!(fixture.clients.network().proxy.is_none());
+        assert!(fixture.clients.network().insecure_hosts.is_empty());
+        assert!(!fixture.path().exists());
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected_before_anything_is_stored() {
+        let fixture = Fixture::new();
+        let mut settings = with_proxy(Some("hunter2"), false);
+        settings.proxy.as_mut().unwrap().url = "ftp://proxy.corp.example".to_string();
+
+        assert_eq!(fixture.update(settings).unwrap_err().kind, ErrorKind::InvalidRequest);
+        assert!(fixture.clients.network().proxy.is_none());
+        assert!(!fixture.vault.status().exists);
+        assert!(!fixture.path().exists());
+    }
+}
@@ -0,0 +1,824 @@
+// Native Ollama client: typed wrappers around /api/tags, /api/show,
+// /api/chat, /api/pull, /api/delete and /api/copy. Streaming endpoints
+// answer with NDJSON, which is parsed here and forwarded as typed events.
+// Request and response types mirror Ollama's wire format (snake_case).
+use reqwest::Method;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Map, Value};
+use tauri::ipc::Channel;
+use tauri::State;
+
+use crate::cancellation::RequestRegistry;
+use crate::chat::{ChatCompletion, ChatEvent, ChatMessage, ChatRequest, ChatStream};
+use crate::egress::{EgressPolicy, EgressPurpose};
+use crate::error::{CommandError, ErrorKind};
+use crate::http_client::{HttpClients, HttpProfile};
+use crate::recorder::{ExchangeSource, Recorder, Recording};
+use crate::tool_emulation;
+
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct OllamaModelDetails {
+    pub parent_model: String,
+    pub format: String,
+    pub family: String,
+    pub families: Option<Vec<String>>,
+    pub parameter_size: String,
+    pub quantization_level: String,
+}
+
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct OllamaModel {
+    pub name: String,
+    pub model: String,
+    pub modified_at: String,
+    pub size: u64,
+    pub digest: String,
+    pub details: OllamaModelDetails,
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+struct TagsResponse {
+    models: Vec<OllamaModel>,
+}
+
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct OllamaModelInfo {
+    pub license: String,
+    pub modelfile: String,
+    pub parameters: String,
+    pub template: String,
+    pub system: String,
+    pub details: OllamaModelDetails,
+    // Architecture keys such as "llama.context_length"
+    pub model_info: Option<Value>,
+    // "completion", "tools", "vision", "thinking", ...
+    pub capabilities: Vec<String>,
+    pub modified_at: String,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct OllamaFunctionCall {
+    pub name: String,
+    #[serde(default)]
+    pub arguments: Value,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct OllamaToolCall {
+    pub function: OllamaFunctionCall,
+}
+
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct OllamaMessage {
+    pub role: String,
+    #[serde(default)]
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<String>,
+    // Base64 encoded images, without a data: prefix
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub images: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<OllamaToolCall>>,
+    // Name of the tool a "tool" message answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_name: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct StreamingBody<'a, T> {
+    #[serde(flatten)]
+    request: &'a T,
+    stream: bool,
+}
+
+#[derive(serde::Deserialize)]
+struct ChatChunk {
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    message: Option<OllamaMessage>,
+    #[serde(default)]
+    done: bool,
+    #[serde(default)]
+    done_reason: Option<String>,
+    #[serde(default)]
+    prompt_eval_count: Option<u64>,
+    #[serde(default)]
+    eval_count: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct OllamaPullProgress {
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completed: Option<u64>,
+}
+
+fn parse_error(err: serde_json::Error) -> CommandError {
+    CommandError::new(ErrorKind::Parse, format!("Invalid response from Ollama: {err}"))
+}
+
+// Ollama reports failures as {"error": "..."}, both as the body of a failed
+// request and as a line in the middle of a stream
+fn error_message(text: &str) -> Option<String> {
+    serde_json::from_str::<Value>(text)
+        .ok()?
+        .get("error")?
+        .as_str()
+        .map(|message| message.to_string())
+}
+
+// Adds a chunk to an NDJSON buffer and returns the lines it completed,
+// trimmed and without blank ones. `None` ends the stream, where a last line
+// without a trailing newline still counts.
+fn complete_lines(pending: &mut Vec<u8>, chunk: Option<&[u8]>) -> Vec<String> {
+    match chunk {
+        Some(chunk) => pending.extend_from_slice(chunk),
+        None if !pending.is_empty() => pending.push(b'\n'),
+        None => {}
+    }
+
+    let mut lines = Vec::new();
+    while let Some(end) = pending.iter().position(|&byte| byte == b'\n') {
+        let line: Vec<u8> = pending.drain(..=end).collect();
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim();
+        if !line.is_empty() {
+            lines.push(line.to_string());
+        }
+    }
+    lines
+}
+
+fn convert_message(message: &ChatMessage, messages: &[ChatMessage]) -> OllamaMessage {
+    let tool_calls = message
+        .tool_calls
+        .iter()
+        .map(|call| OllamaToolCall {
+            function: OllamaFunctionCall {
+                name: call.function.name.clone(),
+                arguments: serde_json::from_str(&call.function.arguments).unwrap_or_else(|_| json!({})),
+            },
+        })
+        .collect::<Vec<_>>();
+
+    // Ollama matches tool results by function name rather than call id
+    let tool_name = message.tool_call_id.as_ref().and_then(|id| {
+        messages
+            .iter()
+            .flat_map(|m| &m.tool_calls)
+            .find(|call| &call.id == id)
+            .map(|call| call.function.name.clone())
+    });
+
+    OllamaMessage {
+        role: message.role.clone(),
+        content: message.content.clone(),
+        thinking: None,
+        images: (!message.images.is_empty())
+            .then(|| message.images.iter().map(|image| image.data.clone()).collect()),
+        tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+        tool_name,
+    }
+}
+
+fn chat_body(request: &ChatRequest) -> Value {
+    let messages: Vec<OllamaMessage> = request
+        .messages
+        .iter()
+        .map(|message| convert_message(message, &request.messages))
+        .collect();
+
+    let mut options = Map::new();
+    let sampling = [
+        ("num_predict", request.max_tokens.map(|v| json!(v))),
+        ("temperature", request.temperature.map(|v| json!(v))),
+        ("top_p", request.top_p.map(|v| json!(v))),
+        ("top_k", request.top_k.map(|v| json!(v))),
+        ("frequency_penalty", request.frequency_penalty.map(|v| json!(v))),
+        ("presence_penalty", request.presence_penalty.map(|v| json!(v))),
+        ("seed", request.seed.map(|v| json!(v))),
+    ];
+    for (key, value) in sampling {
+        if let Some(value) = value {
+            options.insert(key.into(), value);
+        }
+    }
+    if !request.stop.is_empty() {
+        options.insert("stop".into(), json!(request.stop));
+    }
+
+    let mut body = Map::new();
+    body.insert("model".into(), json!(request.model));
+    body.insert("messages".into(), json!(messages));
+    if !request.tools.is_empty() {
+        body.insert("tools".into(), json!(request.tools));
+    }
+    if !options.is_empty() {
+        body.insert("options".into(), Value::Object(options));
+    }
+    if request.thinking_budget.is_some() {
+        body.insert("think".into(), json!(true));
+    }
+    // Ollama constrains the reply to a schema given as `format`
+    if let Some(schema) = &request.response_schema {
+        body.insert("format".into(), schema.clone());
+    }
+    // keep_alive, ...
+    for (key, value) in &request.extra {
+        body.insert(key.clone(), value.clone());
+    }
+    Value::Object(body)
+}
+
+pub(crate) struct Ollama {
+    base_url: String,
+    profile: HttpProfile,
+    policy: EgressPolicy,
+    recorder: Recorder,
+}
+
+impl Ollama {
+    pub(crate) async fn new(
+        base_url: &str,
+        clients: &HttpClients,
+        policy: &EgressPolicy,
+        recorder: &Recorder,
+    ) -> Result<Self, CommandError> {
+        let base_url = base_url.trim().trim_end_matches('/').to_string();
+        let parsed = policy.check(&base_url, EgressPurpose::Provider)?;
+        Ok(Self {
+            profile: clients.provider_for(&parsed).await?,
+            base_url,
+            policy: policy.clone(),
+            recorder: recorder.clone(),
+        })
+    }
+
+    // `timeout` is off for streams, which can legitimately run for minutes
+    fn request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&impl serde::Serialize>,
+        timeout: bool,
+    ) -> Result<reqwest::Request, CommandError> {
+        let url = self
+            .policy
+            .check(&format!("{}{}", self.base_url, path), EgressPurpose::Provider)?;
+        let mut request = self.profile.client.request(method, url);
+        if let Some(body) = body {
+            let body = serde_json::to_vec(body)
+                .map_err(|err| CommandError::new(ErrorKind::InvalidRequest, format!("Invalid request body: {err}")))?;
+            request = request
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body);
+        }
+        if timeout {
+            request = request.timeout(self.profile.request_timeout);
+        }
+        Ok(request.build()?)
+    }
+
+    // Sends the request and returns the response if it succeeded
+    async fn send(&self, request: reqwest::Request, recording: &mut Recording) -> Result<reqwest::Response, CommandError> {
+        let url = request.url().to_string();
+        let response = self.profile.client.execute(request).await?;
+        recording.response(&response);
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let text = response.text().await.unwrap_or_default();
+        recording.body(&text);
+        let mut err = CommandError::http_status(status).with_url(url);
+        if let Some(message) = error_message(&text) {
+            err.message = format!("Ollama: {}", message);
+        }
+        Err(err)
+    }
+
+    // Runs a non-streaming request and returns the response body
+    async fn call(&self, method: Method, path: &str, body: Option<&impl serde::Serialize>) -> Result<String, CommandError> {
+        let request = self.request(method, path, body, true)?;
+        let mut recording = self.recorder.start(ExchangeSource::Provider, &request);
+
+        let result = async {
+            let response = self.send(request, &mut recording).await?;
+            let text = response
+                .text()
+                .await
+                .map_err(|err| CommandError::from_reqwest(err, "Failed to read response"))?;
+            recording.body(&text);
+            Ok(text)
+        }
+        .await;
+
+        recording.finish_with(&result);
+        result
+    }
+
+    async fn call_json<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&impl serde::Serialize>,
+    ) -> Result<T, CommandError> {
+        let text = self.call(method, path, body).await?;
+        serde_json::from_str(&text).map_err(parse_error)
+    }
+
+    // Streams an NDJSON endpoint, handing each parsed line to `on_line`
+    async fn stream<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &impl serde::Serialize,
+        mut on_line: impl FnMut(T) -> Result<(), CommandError>,
+    ) -> Result<(), CommandError> {
+        let request = self.request(Method::POST, path, Some(&StreamingBody { request: body, stream: true }), false)?;
+        let mut recording = self.recorder.start(ExchangeSource::Provider, &request);
+
+        let result = async {
+            let mut response = self.send(request, &mut recording).await?;
+            let mut pending = Vec::new();
+
+            loop {
+                let chunk = response
+                    .chunk()
+                    .await
+                    .map_err(|err| CommandError::from_reqwest(err, "Failed to read response"))?;
+                let finished = chunk.is_none();
+
+                for line in complete_lines(&mut pending, chunk.as_deref()) {
+                    recording.body(&line);
+                    recording.body("\n");
+
+                    if let Some(message) = error_message(&line) {
+                        return Err(CommandError::new(ErrorKind::Network, format!("Ollama: {}", message)));
+                    }
+                    on_line(serde_json::from_str(&line).map_err(parse_error)?)?;
+                }
+
+                if finished {
+                    return Ok(());
+                }
+            }
+        }
+        .await;
+
+        recording.finish_with(&result);
+        result
+    }
+
+    // Streams a chat completion over `on_event` and returns it, including
+    // any tool calls, once Ollama reports it's done
+    pub(crate) async fn chat(
+        &self,
+        request: &ChatRequest,
+        on_event: &Channel<ChatEvent>,
+    ) -> Result<ChatCompletion, CommandError> {
+        let dialect = tool_emulation::dialect_for(request, None);
+        let body = match dialect {
+            Some(dialect) => chat_body(&tool_emulation::prepare(request, dialect)),
+            None => chat_body(request),
+        };
+
+        let mut stream = ChatStream::new(on_event);
+        if let Some(dialect) = dialect {
+            stream.emulate_tools(dialect, &request.tools);
+        }
+        // Ollama doesn't give tool calls ids, so they're made up per response
+        let response_id = chrono::Utc::now().timestamp_millis();
+        let mut done = false;
+
+        self.stream("/api/chat", &body, |chunk: ChatChunk| {
+            stream.model(chunk.model);
+            if let Some(delta) = chunk.message {
+                stream.reasoning(delta.thinking.as_deref().unwrap_or_default())?;
+                stream.text(&delta.content)?;
+                // Tool calls arrive whole, never split across chunks
+                for call in delta.tool_calls.unwrap_or_default() {
+                    let id = format!("call_{}_{}", response_id, stream.tool_call_count());
+                    let arguments = call.function.arguments.to_string();
+                    stream.tool_call(&id, &call.function.name, &arguments)?;
+                }
+            }
+
+            if chunk.done {
+                done = true;
+                if let Some(reason) = chunk.done_reason {
+                    stream.finish_reason(&reason);
+                }
+                if chunk.prompt_eval_count.is_some() || chunk.eval_count.is_some() {
+                    stream.usage(chunk.prompt_eval_count.unwrap_or(0), chunk.eval_count.unwrap_or(0))?;
+                }
+            }
+            Ok(())
+        })
+        .await?;
+
+        if !done {
+            return Err(CommandError::new(ErrorKind::Network, "Ollama closed the stream before it was done"));
+        }
+        stream.finish()
+    }
+
+    // Streams /api/pull until Ollama reports success. Ollama keeps the layers
+    // it already downloaded, so pulling again after a failure resumes them.
+    pub(crate) async fn pull(
+        &self,
+        model: &str,
+        insecure: bool,
+        mut on_progress: impl FnMut(OllamaPullProgress) -> Result<(), CommandError>,
+    ) -> Result<(), CommandError> {
+        let body = json!({ "model": model, "insecure": insecure });
+        let mut status = String::new();
+        self.stream("/api/pull", &body, |progress: OllamaPullProgress| {
+            status.clone_from(&progress.status);
+            on_progress(progress)
+        })
+        .await?;
+
+        if status != "success" {
+            return Err(CommandError::new(
+                ErrorKind::Network,
+                format!("Pull of {} ended without success (last status: {})", model, status),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[tauri::command]
+pub async fn ollama_list_models(
+    base_url: String,
+    clients: State<'_, HttpClients>,
+    policy: State<'_, EgressPolicy>,
+    recorder: State<'_, Recorder>,
+) -> Result<Vec<OllamaModel>, CommandError> {
+    let ollama = Ollama::new(&base_url, &clients, &policy, &recorder).await?;
+    let tags: TagsResponse = ollama.call_json(Method::GET, "/api/tags", None::<&()>).await?;
+    Ok(tags.models)
+}
+
+#[tauri::command]
+pub async fn ollama_show_model(
+    base_url: String,
+    model: String,
+    verbose: Option<bool>,
+    clients: State<'_, HttpClients>,
+    policy: State<'_, EgressPolicy>,
+    recorder: State<'_, Recorder>,
+) -> Result<OllamaModelInfo, CommandError> {
+    let ollama = Ollama::new(&base_url, &clients, &policy, &recorder).await?;
+    let body = serde_json::json!({ "model": model, "verbose": verbose.unwrap_or(false) });
+    ollama.call_json(Method::POST, "/api/show", Some(&body)).await
+}
+
+// Chat over Ollama's native API; failures are sent over `on_event` too
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn ollama_chat(
+    base_url: String,
+    request: ChatRequest,
+    request_id: Option<String>,
+    on_event: Channel<ChatEvent>,
+    requests: State<'_, RequestRegistry>,
+    clients: State<'_, HttpClients>,
+    policy: State<'_, EgressPolicy>,
+    recorder: State<'_, Recorder>,
+) -> Result<ChatCompletion, CommandError> {
+    eprintln!("[Ollama] Chat with {} ({} messages)", request.model, request.messages.len());
+    let ollama = Ollama::new(&base_url, &clients, &policy, &recorder).await?;
+    let result = requests.run(request_id, |_| ollama.chat(&request, &on_event)).await;
+
+    if let Err(err) = &result {
+        let _ = on_event.send(ChatEvent::Error(err.clone()));
+    }
+    result
+}
+
+// Downloads a model, forwarding Ollama's progress lines over `on_progress`
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn ollama_pull_model(
+    base_url: String,
+    model: String,
+    insecure: Option<bool>,
+    request_id: Option<String>,
+    on_progress: Channel<OllamaPullProgress>,
+    requests: State<'_, RequestRegistry>,
+    clients: State<'_, HttpClients>,
+    policy: State<'_, EgressPolicy>,
+    recorder: State<'_, Recorder>,
+) -> Result<(), CommandError> {
+    eprintln!("[Ollama] Pulling {}", model);
+    let ollama = Ollama::new(&base_url, &clients, &policy, &recorder).await?;
+
+    requests
+        .run(request_id, |_| async {
+            ollama
+                .pull(&model, insecure.unwrap_or(false), |progress| {
+                    on_progress.send(progress)?;
+                    Ok(())
+                })
+                .await?;
+            eprintln!("[Ollama] Pulled {}", model);
+            Ok(())
+        })
+        .await
+}
+
+#[tauri::command]
+pub async fn ollama_delete_model(
+    base_url: String,
+    model: String,
+    clients: State<'_, HttpClients>,
+    policy: State<'_, EgressPolicy>,
+    recorder: State<'_, Recorder>,
+) -> Result<(), CommandError> {
+    let ollama = Ollama::new(&base_url, &clients, &policy, &recorder).await?;
+    let body = serde_json::json!({ "model": model });
+    ollama.call(Method::DELETE, "/api/delete", Some(&body)).await?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn ollama_copy_model(
+    base_url: String,
+    source: String,
+    destination: String,
+    clients: State<'_, HttpClients>,
+    policy: State<'_, EgressPolicy>,
+    recorder: State<'_, Recorder>,
+) -> Result<(), CommandError> {
+    let ollama = Ollama::new(&base_url, &clients, &policy, &recorder).await?;
+    let body = serde_json::json!({ "source": source, "destination": destination });
+    ollama.call(Method::POST, "/api/copy", Some(&body)).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::chat::FinishReason;
+    use crate::egress::EgressPolicySettings;
+    use crate::http_client::HttpClientSettings;
+    use crate::network::NetworkSettings;
+    use crate::recorder::RecorderSettings;
+
+    fn decode(chunks: &[&[u8]]) -> Vec<String> {
+        let mut pending = Vec::new();
+        let mut lines = Vec::new();
+        for chunk in chunks {
+            lines.extend(complete_lines(&mut pending, Some(chunk)));
+        }
+        lines.extend(complete_lines(&mut pending, None));
+        lines
+    }
+
+    #[test]
+    fn lines_split_across_chunks() {
+        let lines = decode(&[b"{\"status\":\"pul", b"ling\"}\n{\"status\"", b":\"success\"}\n"]);
+        assert_eq!(lines, ["{\"status\":\"pulling\"}", "{\"status\":\"success\"}"]);
+    }
+
+    #[test]
+    fn last_line_without_newline() {
+        let lines = decode(&[b"{\"done\":false}\n", b"{\"done\":true}"]);
+        assert_eq!(lines, ["{\"done\":false}", "{\"done\":true}"]);
+        assert_eq!(decode(&[b"{\"done\":true}\n"]).len(), 1);
+    }
+
+    #[test]
+    fn blank_lines_and_crlf() {
+        let lines = decode(&[b"\r\n{\"a\":1}\r", b"\n\n  \n{\"b\":2}\r\n"]);
+        assert_eq!(lines, ["{\"a\":1}", "{\"b\":2}"]);
+    }
+
+    #[test]
+    fn characters_split_across_chunks() {
+        let text = "{\"message\":{\"role\":\"assistant\",\"content\":\"h\u{e9}\u{1f600}\"}}\n".as_bytes();
+        let chunks: Vec<&[u8]> = text.chunks(1).collect();
+        let lines = decode(&chunks);
+        let chunk: ChatChunk = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(chunk.message.unwrap().content, "h\u{e9}\u{1f600}");
+    }
+
+    #[test]
+    fn errors_in_the_stream() {
+        let lines = decode(&[
+            b"{\"status\":\"pulling manifest\"}\n",
+            b"{\"error\":\"pull model manifest: file does not exist\"}",
+        ]);
+        assert_eq!(error_message(&lines[0]), None);
+        assert_eq!(error_message(&lines[1]).as_deref(), Some("pull model manifest: file does not exist"));
+    }
+
+    // Answers one request with `status` and an NDJSON `body`, and returns
+    // the request line and body it got
+    fn serve_once(status: &'static str, body: String) -> (String, std::thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 4096];
+            let complete = |request: &[u8]| {
+                let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") else {
+                    return false;
+                };
+                let head = String::from_utf8_lossy(&request[..end]).to_lowercase();
+                let length = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length:"))
+                    .map_or(0, |value| value.trim().parse::<usize>().unwrap());
+                request.len() >= end + 4 + length
+            };
+            while !complete(&request) {
+                let read = stream.read(&mut buffer).unwrap();
+                if read == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buffer[..read]);
+            }
+            let response = format!(
+                "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+            let request = String::from_utf8_lossy(&request).into_owned();
+            let (head, body) = request.split_once("\r\n\r\n").unwrap();
+            format!("{}\n{}", head.lines().next().unwrap(), body)
+        });
+        (base_url, server)
+    }
+
+    fn ndjson(lines: &[Value]) -> String {
+        lines.iter().map(|line| format!("{line}\n")).collect()
+    }
+
+    fn ollama(base_url: &str) -> Ollama {
+        let policy = EgressPolicy::new(EgressPolicySettings::default()).unwrap();
+        let clients =
+            HttpClients::new(HttpClientSettings::default(), NetworkSettings::default(), policy.clone()).unwrap();
+        let recorder = Recorder::new(RecorderSettings::default());
+        tauri::async_runtime::block_on(Ollama::new(base_url, &clients, &policy, &recorder)).unwrap()
+    }
+
+    fn chat(status: &'static str, lines: &[Value]) -> (Result<ChatCompletion, CommandError>, String) {
+        let (base_url, server) = serve_once(status, ndjson(lines));
+        let request: ChatRequest = serde_json::from_value(json!({
+            "model": "llama3.2",
+            "messages": [{ "role": "user", "content": "Weather in Paris?" }],
+            "tools": [{ "type": "function", "function": { "name": "get_weather", "parameters": {} } }],
+        }))
+        .unwrap();
+        let channel = Channel::new(|_| Ok(()));
+        let result = tauri::async_runtime::block_on(ollama(&base_url).chat(&request, &channel));
+        (result, server.join().unwrap())
+    }
+
+    fn message(content: &str) -> Value {
+        json!({ "model": "llama3.2", "message": { "role": "assistant", "content": content }, "done": false })
+    }
+
+    #[test]
+    fn chat_streams_text_reasoning_and_usage() {
+        let (result, request) = chat(
+            "200 OK",
+            &[
+                json!({ "model": "llama3.2", "message": { "role": "assistant", "content": "", "thinking": "Hmm" } }),
+                message("Hel"),
+                message("lo"),
+                json!({
+                    "model": "llama3.2",
+                    "done": true,
+                    "done_reason": "length",
+                    "prompt_eval_count": 12,
+                    "eval_count": 5,
+                }),
+            ],
+        );
+
+        assert!(request.starts_with("POST /api/chat HTTP/1.1\n"), "{request}");
+        let body: Value = serde_json::from_str(request.split_once('\n').unwrap().1).unwrap();
+        assert_eq!(body["model"], "llama3.2");
+        assert_eq!(body["stream"], true);
+        assert_eq!(body["tools"][0]["function"]["name"], "get_weather");
+
+        let completion = result.unwrap();
+        assert_eq!(completion.model.as_deref(), Some("llama3.2"));
+        assert_eq!(completion.content, "Hello");
+        assert_eq!(completion.reasoning.as_deref(), Some("Hmm"));
+        assert_eq!(completion.finish_reason, FinishReason::Length);
+        let usage = completion.usage.unwrap();
+        assert_eq!((usage.input_tokens, usage.output_tokens, usage.total_tokens), (12, 5, 17));
+    }
+
+    #[test]
+    fn chat_returns_tool_calls() {
+        let calls = json!([
+            { "function": { "name": "get_weather", "arguments": { "city": "Paris" } } },
+            { "function": { "name": "get_weather", "arguments": { "city": "Lyon" } } },
+        ]);
+        let (result, _) = chat(
+            "200 OK",
+            &[
+                json!({ "message": { "role": "assistant", "content": "", "tool_calls": calls } }),
+                json!({ "message": { "role": "assistant", "content": "" }, "done": true, "done_reason": "stop" }),
+            ],
+        );
+
+        let completion = result.unwrap();
+        // Ollama says "stop" for tool calls too
+        assert_eq!(completion.finish_reason, FinishReason::ToolCalls);
+        assert_eq!(completion.tool_calls.len(), 2);
+        assert_eq!(completion.tool_calls[0].name, "get_weather");
+        assert_eq!(completion.tool_calls[0].arguments, r#"{"city":"Paris"}"#);
+        assert_eq!(completion.tool_calls[1].arguments, r#"{"city":"Lyon"}"#);
+        assert_ne!(completion.tool_calls[0].id, completion.tool_calls[1].id);
+    }
+
+    #[test]
+    fn chat_errors() {
+        // An error line in the middle of the stream
+        let (result, _) = chat("200 OK", &[message("Hi"), json!({ "error": "model runner has unexpectedly stopped" })]);
+        let err = result.unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Network);
+        assert_eq!(err.message, "Ollama: model runner has unexpectedly stopped");
+
+        // An error body on a failed request
+        let not_found = json!({ "error": "model \"llama3.2\" not found, try pulling it first" });
+        let (result, _) = chat("404 Not Found", &[not_found]);
+        let err = result.unwrap_err();
+        assert_eq!((err.kind, err.status), (ErrorKind::HttpStatus, Some(404)));
+        assert!(err.message.starts_with("Ollama: model \"llama3.2\" not found"), "{}", err.message);
+
+        // A stream that ends without its done line
+        let (result, _) = chat("200 OK", &[message("Hi")]);
+        assert!(result.unwrap_err().message.contains("before it was done"));
+    }
+
+    fn pull(lines: &[Value]) -> (Result<(), CommandError>, Vec<OllamaPullProgress>, String) {
+        let (base_url, server) = serve_once("200 OK", ndjson(lines));
+        let mut progress = Vec::new();
+        let result = tauri::async_runtime::block_on(ollama(&base_url).pull("llama3.2", true, |update| {
+            progress.push(update);
+            Ok(())
+        }));
+        (result, progress, server.join().unwrap())
+    }
+
+    #[test]
+    fn pull_reports_progress() {
+        let layer = |completed: u64| {
+            json!({ "status": "pulling 6a07", "digest": "sha256:6a07", "total": 2000, "completed": completed })
+        };
+        let (result, progress, request) = pull(&[
+            json!({ "status": "pulling manifest" }),
+            layer(500),
+            layer(2000),
+            json!({ "status": "verifying sha256 digest" }),
+            json!({ "status": "success" }),
+        ]);
+        result.unwrap();
+        assert!(request.starts_with("POST /api/pull HTTP/1.1\n"), "{request}");
+        assert!(request.contains(r#""insecure":true"#) && request.contains(r#""model":"llama3.2""#));
+
+        assert_eq!(progress.len(), 5);
+        assert_eq!(progress[0].status, "pulling manifest");
+        assert_eq!(progress[0].total, None);
+        assert_eq!(progress[1].digest.as_deref(), Some("sha256:6a07"));
+        assert_eq!((progress[1].total, progress[1].completed), (Some(2000), Some(500)));
+        assert_eq!(progress[4].status, "success");
+    }
+
+    #[test]
+    fn pulls_must_end_in_success() {
+        let (result, progress, _) = pull(&[json!({ "status": "pulling manifest" })]);
+        assert_eq!(progress.len(), 1);
+        assert!(result.unwrap_err().message.contains("last status: pulling manifest"));
+
+        let (result, _, _) = pull(&[
+            json!({ "status": "pulling manifest" }),
+            json!({ "error": "pull model manifest: file does not exist" }),
+        ]);
+        assert_eq!(result.unwrap_err().message, "Ollama: pull model manifest: file does not exist");
+    }
+}
@@ -0,0 +1,512 @@
+// Streaming client for OpenAI-compatible chat completion APIs (OpenAI,
+// OpenRouter, LM Studio, llama.cpp server, ...). The SSE stream is decoded
+// here and its deltas are assembled into ChatEvents, so every provider gets
+// the same handling of split tool call arguments, reasoning and usage.
+use std::collections::HashMap;
+
+use serde_json::{json, Map, Value};
+use tauri::ipc::Channel;
+use tauri::State;
+
+use crate::cancellation::RequestRegistry;
+use crate::chat::{ChatCompletion, ChatEvent, ChatImage, ChatMessage, ChatRequest, ChatStream};
+use crate::credentials::CredentialStore;
+use crate::egress::{EgressPolicy, EgressPurpose};
+use crate::error::{CommandError, ErrorKind};
+use crate::gbnf;
+use crate::http_client::HttpClients;
+use crate::recorder::{ExchangeSource, Recorder, Recording};
+use crate::sse;
+use crate::tool_emulation::{self, ToolDialect};
+
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+struct Usage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+struct FunctionDelta {
+    name: Option<String>,
+    arguments: Option<String>,
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+struct ToolCallDelta {
+    index: Option<usize>,
+    id: Option<String>,
+    function: FunctionDelta,
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+struct Delta {
+    content: Option<String>,
+    // DeepSeek, LM Studio and llama.cpp use reasoning_content; OpenRouter uses reasoning
+    reasoning_content: Option<String>,
+    reasoning: Option<String>,
+    tool_calls: Vec<ToolCallDelta>,
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+struct ChoiceDelta {
+    index: usize,
+    delta: Delta,
+    finish_reason: Option<String>,
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+struct CompletionChunk {
+    model: Option<String>,
+    choices: Vec<ChoiceDelta>,
+    usage: Option<Usage>,
+    // OpenRouter reports upstream failures inside the stream
+    error: Option<Value>,
+}
+
+// Translates stream chunks into ChatStream calls. Only the first choice is
+// used; chat requests never ask for more than one.
+struct Assembler<'a> {
+    stream: ChatStream<'a>,
+    // (tool call index from the server or its id, ChatStream index)
+    tool_calls: Vec<(String, usize)>,
+}
+
+impl<'a> Assembler<'a> {
+    fn new(stream: ChatStream<'a>) -> Self {
+        Self { stream, tool_calls: Vec::new() }
+    }
+
+    fn apply(&mut self, chunk: CompletionChunk) -> Result<(), CommandError> {
+        if let Some(error) = chunk.error {
+            return Err(CommandError::new(ErrorKind::Network, provider_message(&error)));
+        }
+        self.stream.model(chunk.model);
+
+        for choice in chunk.choices.into_iter().filter(|choice| choice.index == 0) {
+            let delta = choice.delta;
+            if let Some(text) = delta.reasoning_content.or(delta.reasoning) {
+                self.stream.reasoning(&text)?;
+            }
+            if let Some(text) = delta.content {
+                self.stream.text(&text)?;
+            }
+            for call in delta.tool_calls {
+                self.tool_call(call)?;
+            }
+            if let Some(reason) = choice.finish_reason {
+                self.stream.finish_reason(&reason);
+            }
+        }
+
+        // Sent in a final chunk with no choices when include_usage is set
+        if let Some(usage) = chunk.usage {
+            self.stream.usage(usage.prompt_tokens, usage.completion_tokens)?;
+        }
+        Ok(())
+    }
+
+    fn tool_call(&mut self, delta: ToolCallDelta) -> Result<(), CommandError> {
+        // Some servers leave out the index; fall back to the id, then to a
+        // continuation of the last call
+        let key = match (delta.index, delta.id.as_deref().filter(|id| !id.is_empty())) {
+            (Some(index), _) => Some(format!("#{}", index)),
+            (None, Some(id)) => Some(id.to_string()),
+            (None, None) => None,
+        };
+        let known = match &key {
+            Some(key) => self.tool_calls.iter().find(|(k, _)| k == key).map(|(_, index)| *index),
+            None => self.tool_calls.last().map(|(_, index)| *index),
+        };
+
+        let index = match known {
+            Some(index) => {
+                if let Some(name) = delta.function.name.as_deref().filter(|name| !name.is_empty()) {
+                    self.stream.tool_call_name(index, name);
+                }
+                index
+            }
+            None => {
+                let id = delta.id.clone().unwrap_or_default();
+                let name = delta.function.name.clone().unwrap_or_default();
+                let index = self.stream.tool_call_start(&id, &name)?;
+                self.tool_calls.push((key.unwrap_or_default(), index));
+                index
+            }
+        };
+        if let Some(arguments) = delta.function.arguments {
+            self.stream.tool_call_args(index, &arguments)?;
+        }
+        Ok(())
+    }
+}
+
+fn image_url(image: &ChatImage) -> Value {
+    json!({
+        "type": "image_url",
+        "image_url": { "url": format!("data:{};base64,{}", image.mime_type, image.data) },
+    })
+}
+
+fn convert_message(message: &ChatMessage) -> Value {
+    match message.role.as_str() {
+        "tool" => json!({
+            "role": "tool",
+            "tool_call_id": message.tool_call_id.clone().unwrap_or_default(),
+            "content": message.content,
+        }),
+        "assistant" if !message.tool_calls.is_empty() => {
+            let calls: Vec<Value> = message
+                .tool_calls
+                .iter()
+                .map(|call| {
+                    json!({
+                        "id": call.id,
+                        "type": "function",
+                        "function": { "name": call.function.name, "arguments": call.function.arguments },
+                    })
+                })
+                .collect();
+            json!({ "role": "assistant", "content": message.content, "tool_calls": calls })
+        }
+        // Images turn the content into a list of parts
+        role if !message.images.is_empty() => {
+            let mut parts = vec![json!({ "type": "text", "text": message.content })];
+            parts.extend(message.images.iter().map(image_url));
+            json!({ "role": role, "content": parts })
+        }
+        role => json!({ "role": role, "content": message.content }),
+    }
+}
+
+// `tool_grammar` is set when llama.cpp gets its tools in the prompt: it
+// won't take a grammar together with native tools
+fn request_body(
+    request: &ChatRequest,
+    kind: Option<&str>,
+    tool_grammar: Option<String>,
+    include_usage: bool,
+) -> Result<Value, CommandError> {
+    let mut body = Map::new();
+    body.insert("model".into(), json!(request.model));
+    body.insert("messages".into(), request.messages.iter().map(convert_message).collect());
+    if !request.tools.is_empty() {
+        body.insert("tools".into(), json!(request.tools));
+    }
+    body.insert("stream".into(), json!(true));
+    // Not every server accepts stream_options, so providers opt in
+    if include_usage {
+        body.insert("stream_options".into(), json!({ "include_usage": true }));
+    }
+    // Tool call grammars take precedence over a response schema
+    match (&request.response_schema, tool_grammar) {
+        (_, Some(grammar)) => {
+            body.insert("grammar".into(), json!(grammar));
+        }
+        (Some(schema), None) if kind == Some("llamacpp") => match gbnf::from_schema(schema) {
+            Ok(grammar) => {
+                body.insert("grammar".into(), json!(grammar));
+            }
+            // The reply just isn't constrained then
+            Err(err) => eprintln!("[OpenAI compat] Sending the response schema without a grammar: {}", err.message),
+        },
+        (Some(schema), None) => {
+            body.insert(
+                "response_format".into(),
+                json!({ "type": "json_schema", "json_schema": { "name": "response", "schema": schema } }),
+            );
+        }
+        (None, None) => {}
+    }
+    if !request.stop.is_empty() {
+        body.insert("stop".into(), json!(request.stop));
+    }
+
+    let optional = [
+        ("max_tokens", request.max_tokens.map(|v| json!(v))),
+        ("temperature", request.temperature.map(|v| json!(v))),
+        ("top_p", request.top_p.map(|v| json!(v))),
+        ("frequency_penalty", request.frequency_penalty.map(|v| json!(v))),
+        ("presence_penalty", request.presence_penalty.map(|v| json!(v))),
+        ("seed", request.seed.map(|v| json!(v))),
+    ];
+    for (key, value) in optional {
+        if let Some(value) = value {
+            body.insert(key.into(), value);
+        }
+    }
+    // top_k isn't part of the OpenAI API; servers that support it take it as is
+    if let Some(top_k) = request.top_k {
+        body.insert("top_k".into(), json!(top_k));
+    }
+
+    for (key, value) in &request.extra {
+        body.insert(key.clone(), value.clone());
+    }
+    Ok(Value::Object(body))
+}
+
+// Error text from {"error": {"message": ...}}, {"error": "..."} or {"message": ...}
+fn provider_message(error: &Value) -> String {
+    error
+        .get("message")
+        .and_then(Value::as_str)
+        .or_else(|| error.as_str())
+        .map(|message| message.to_string())
+        .unwrap_or_else(|| error.to_string())
+}
+
+pub fn error_body_message(text: &str) -> Option<String> {
+    let body: Value = serde_json::from_str(text).ok()?;
+    let error = body.get("error").unwrap_or(&body);
+    let message = provider_message(error);
+    (!message.is_empty()).then_some(message)
+}
+
+// Passes a successful response through; otherwise reads the body and turns
+// the provider's error message into the command error
+pub async fn ensure_success(
+    response: reqwest::Response,
+    recording: &mut Recording,
+) -> Result<reqwest::Response, CommandError> {
+    recording.response(&response);
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let url = response.url().to_string();
+    let text = response.text().await.unwrap_or_default();
+    recording.body(&text);
+    let mut err = CommandError::http_status(status).with_url(url);
+    if let Some(message) = error_body_message(&text) {
+        err.message = format!("{} ({})", message, status);
+    }
+    Err(err)
+}
+
+// Streams a chat completion from `base_url`, e.g. "https://api.openai.com/v1".
+// The API key stored for `provider_id` is sent as a bearer token; local
+// servers without keys leave it out. `kind` is the provider type; llama.cpp
+// ("llamacpp") gets GBNF grammars for tool calls and response schemas.
+// `include_usage` asks for token usage at the end of the stream.
+// Requests with a `tool_dialect` get their tools in the prompt instead.
+// Events go over `on_event`; the assembled completion is returned once the
+// stream ends.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn openai_compat_chat(
+    base_url: String,
+    provider_id: Option<String>,
+    kind: Option<String>,
+    headers: Option<HashMap<String, String>>,
+    include_usage: Option<bool>,
+    request: ChatRequest,
+    request_id: Option<String>,
+    on_event: Channel<ChatEvent>,
+    requests: State<'_, RequestRegistry>,
+    clients: State<'_, HttpClients>,
+    credentials: State<'_, CredentialStore>,
+    policy: State<'_, EgressPolicy>,
+    recorder: State<'_, Recorder>,
+) -> Result<ChatCompletion, CommandError> {
+    let url = format!("{}/chat/completions", base_url.trim().trim_end_matches('/'));
+    let url = policy.check(&url, EgressPurpose::Provider)?;
+    eprintln!("[OpenAI compat] Stream request to {} for {}", url, request.model);
+
+    // llama.cpp emulates tools as grammar-constrained JSON unless the request
+    // picks another dialect
+    let llamacpp = kind.as_deref() == Some("llamacpp");
+    let dialect = tool_emulation::dialect_for(&request, llamacpp.then_some(ToolDialect::Json));
+    let tool_grammar = match dialect {
+        Some(ToolDialect::Json) if llamacpp => gbnf::for_tools(&request.tools)
+            .inspect_err(|err| eprintln!("[OpenAI compat] Emulating tools without a grammar: {}", err.message))
+            .ok(),
+        _ => None,
+    };
+    let include_usage = include_usage.unwrap_or(false);
+    let body = match dialect {
+        Some(dialect) => {
+            let prepared = tool_emulation::prepare(&request, dialect);
+            request_body(&prepared, kind.as_deref(), tool_grammar, include_usage)?
+        }
+        None => request_body(&request, kind.as_deref(), None, include_usage)?,
+    };
+    // No overall timeout: generation can legitimately take minutes
+    let client = clients.provider_for(&url).await?.client;
+    let mut http_request = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(reqwest::header::ACCEPT, "text/event-stream")
+        .body(body.to_string());
+    for (name, value) in headers.unwrap_or_default() {
+        http_request = http_request.header(name, value);
+    }
+    let mut http_request = http_request.build()?;
+    if let Some(provider_id) = &provider_id {
+        credentials.authorize(provider_id, &mut http_request)?;
+    }
+    let mut recording = recorder.start(ExchangeSource::Provider, &http_request);
+
+    let result = requests
+        .run(request_id, |_| async {
+            let result = async {
+                let response = client.execute(http_request).await?;
+                let mut response = ensure_success(response, &mut recording).await?;
+
+                let mut stream = ChatStream::new(&on_event);
+                if let Some(dialect) = dialect {
+                    stream.emulate_tools(dialect, &request.tools);
+                }
+                let mut assembler = Assembler::new(stream);
+                sse::read_events(&mut response, &mut recording, |event| {
+                    let data = event.data.trim();
+                    if data.is_empty() {
+                        return Ok(true);
+                    }
+                    // Some servers keep the connection open after [DONE]
+                    if data == "[DONE]" {
+                        return Ok(false);
+                    }
+                    if event.event == "error" {
+                        let message = error_body_message(data).unwrap_or_else(|| data.to_string());
+                        return Err(CommandError::new(ErrorKind::Network, message));
+                    }
+
+                    let chunk: CompletionChunk = serde_json::from_str(data).map_err(|err| {
+                        CommandError::new(ErrorKind::Parse, format!("Invalid stream chunk: {err}"))
+                    })?;
+                    assembler.apply(chunk)?;
+                    Ok(true)
+                })
+                .await?;
+
+                let completion = assembler.stream.finish()?;
+                eprintln!(
+                    "[OpenAI compat] Stream finished: {} chars, {} tool calls, finish reason {:?}",
+                    completion.content.len(),
+                    completion.tool_calls.len(),
+                    completion.finish_reason
+                );
+                Ok(completion)
+            }
+            .await;
+            recording.finish_with(&result);
+            result
+        })
+        .await;
+
+    if let Err(err) = &result {
+        let _ = on_event.send(ChatEvent::Error(err.clone()));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::FinishReason;
+
+    fn assemble(chunks: &[Value]) -> ChatCompletion {
+        let channel = Channel::new(|_| Ok(()));
+        let mut assembler = Assembler::new(ChatStream::new(&channel));
+        for chunk in chunks {
+            assembler.apply(serde_json::from_value(chunk.clone()).unwrap()).unwrap();
+        }
+        assembler.stream.finish().unwrap()
+    }
+
+    fn tool_delta(call: Value) -> Value {
+        json!({ "choices": [{ "index": 0, "delta": { "tool_calls": [call] } }] })
+    }
+
+    fn calls(completion: &ChatCompletion) -> Vec<(&str, &str, &str)> {
+        let calls = completion.tool_calls.iter();
+        calls.map(|call| (call.id.as_str(), call.name.as_str(), call.arguments.as_str())).collect()
+    }
+
+    #[test]
+    fn tool_calls_are_keyed_by_index() {
+        let completion = assemble(&[
+            tool_delta(json!({ "index": 0, "id": "call_1", "function": { "name": "search", "arguments": "" } })),
+            tool_delta(json!({ "index": 0, "function": { "arguments": "{\"q\":" } })),
+            tool_delta(json!({ "index": 0, "function": { "arguments": "\"rust\"}" } })),
+            json!({ "choices": [{ "index": 0, "delta": {}, "finish_reason": "tool_calls" }] }),
+        ]);
+        assert_eq!(calls(&completion), [("call_1", "search", "{\"q\":\"rust\"}")]);
+        assert_eq!(completion.finish_reason, FinishReason::ToolCalls);
+    }
+
+    #[test]
+    fn tool_calls_without_an_index_are_keyed_by_id() {
+        let completion = assemble(&[
+            tool_delta(json!({ "id": "a", "function": { "name": "search", "arguments": "{\"q\":" } })),
+            tool_delta(json!({ "id": "b", "function": { "name": "fetch", "arguments": "{}" } })),
+            tool_delta(json!({ "id": "a", "function": { "arguments": "1}" } })),
+        ]);
+        assert_eq!(calls(&completion), [("a", "search", "{\"q\":1}"), ("b", "fetch", "{}")]);
+    }
+
+    #[test]
+    fn tool_calls_without_index_or_id_continue_the_last_call() {
+        let completion = assemble(&[
+            tool_delta(json!({ "id": "a", "function": { "name": "search", "arguments": "{\"q\"" } })),
+            tool_delta(json!({ "id": "", "function": { "arguments": ":2}" } })),
+        ]);
+        assert_eq!(calls(&completion), [("a", "search", "{\"q\":2}")]);
+    }
+
+    #[test]
+    fn parallel_tool_calls_interleave() {
+        let completion = assemble(&[json!({ "choices": [{ "index": 0, "delta": { "tool_calls": [
+            { "index": 0, "id": "a", "function": { "name": "search", "arguments": "{\"q\":" } },
+            { "index": 1, "id": "b", "function": { "name": "fetch", "arguments": "{\"url\":" } },
+            { "index": 0, "function": { "arguments": "1}" } },
+            { "index": 1, "function": { "arguments": "\"x\"}" } },
+        ] } }] })]);
+        assert_eq!(calls(&completion), [("a", "search", "{\"q\":1}"), ("b", "fetch", "{\"url\":\"x\"}")]);
+    }
+
+    #[test]
+    fn a_late_tool_name_is_kept() {
+        let completion = assemble(&[
+            tool_delta(json!({ "index": 0, "id": "a", "function": { "arguments": "" } })),
+            tool_delta(json!({ "index": 0, "function": { "name": "search", "arguments": "{}" } })),
+            tool_delta(json!({ "index": 0, "function": { "name": "other" } })),
+        ]);
+        assert_eq!(calls(&completion), [("a", "search", "{}")]);
+    }
+
+    #[test]
+    fn text_reasoning_and_usage_are_assembled() {
+        let completion = assemble(&[
+            json!({ "model": "m", "choices": [{ "index": 0, "delta": { "reasoning_content": "Hmm" } }] }),
+            json!({ "choices": [
+                { "index": 0, "delta": { "content": "Hi" } },
+                { "index": 1, "delta": { "content": "x" } },
+            ] }),
+            json!({ "choices": [{ "index": 0, "delta": {}, "finish_reason": "stop" }] }),
+            json!({ "choices": [], "usage": { "prompt_tokens": 3, "completion_tokens": 2 } }),
+        ]);
+        assert_eq!(completion.model.as_deref(), Some("m"));
+        assert_eq!(completion.content, "Hi");
+        assert_eq!(completion.reasoning.as_deref(), Some("Hmm"));
+        assert_eq!(completion.usage.unwrap().total_tokens, 5);
+        assert_eq!(completion.finish_reason, FinishReason::Stop);
+    }
+
+    #[test]
+    fn usage_is_only_requested_when_enabled() {
+        let request: ChatRequest =
+            serde_json::from_value(json!({ "model": "m", "messages": [{ "role": "user", "content": "Hi" }] })).unwrap();
+        let body = request_body(&request, None, None, false).unwrap();
+        assert!(body.get("stream_options").is_none());
+        let body = request_body(&request, None, None, true).unwrap();
+        assert_eq!(body["stream_options"]["include_usage"], true);
+    }
+}
@@ -0,0 +1,541 @@
+// Background queue for Ollama model pulls. One task, started with the app,
+// runs the queued pulls one at a time and emits `model-pull-progress` to all
+// windows, so downloads keep going while windows reload. The queue is
+// persisted to pulls.json in the app data dir; pulls that were unfinished
+// when the app quit are queued again on start and resume where Ollama left off.
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use futures::future::{AbortHandle, AbortRegistration, Abortable};
+use tauri::{Emitter, Manager, State};
+use tokio::sync::Notify;
+
+use crate::app_data;
+use crate::egress::EgressPolicy;
+use crate::error::{CommandError, ErrorKind};
+use crate::http_client::HttpClients;
+use crate::ollama::{Ollama, OllamaPullProgress};
+use crate::recorder::Recorder;
+
+const PULLS_FILE: &str = "pulls.json";
+const PROGRESS_EVENT: &str = "model-pull-progress";
+// Finished pulls kept for the download list; older ones are dropped
+const MAX_FINISHED: usize = 50;
+// Ollama reports progress many times a second; status changes are always sent
+const EMIT_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PullState {
+    Queued,
+    Active,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl PullState {
+    fn is_finished(self) -> bool {
+        matches!(self, PullState::Completed | PullState::Failed | PullState::Cancelled)
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LayerProgress {
+    pub digest: String,
+    pub total: u64,
+    pub completed: u64,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelPull {
+    pub id: String,
+    pub base_url: String,
+    pub model: String,
+    pub insecure: bool,
+    pub state: PullState,
+    // Ollama's latest status line, e.g. "pulling manifest" or "verifying sha256 digest"
+    pub status: String,
+    #[serde(default)]
+    pub layers: Vec<LayerProgress>,
+    // Sums over the layers
+    pub completed: u64,
+    pub total: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<CommandError>,
+    pub attempts: u32,
+    pub created_at: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<String>,
+}
+
+impl ModelPull {
+    // Folds a progress line in; true when the status line changed
+    fn apply(&mut self, progress: OllamaPullProgress) -> bool {
+        if let (Some(digest), Some(total)) = (progress.digest, progress.total) {
+            let completed = progress.completed.unwrap_or(0);
+            match self.layers.iter_mut().find(|layer| layer.digest == digest) {
+                Some(layer) => {
+                    layer.total = total;
+                    layer.completed = completed;
+                }
+                None => self.layers.push(LayerProgress { digest, total, completed }),
+            }
+            self.total = self.layers.iter().map(|layer| layer.total).sum();
+            self.completed = self.layers.iter().map(|layer| layer.completed).sum();
+        }
+        let changed = self.status != progress.status;
+        self.status = progress.status;
+        changed
+    }
+
+    fn reset(&mut self) {
+        self.state = PullState::Queued;
+        self.status = String::new();
+        self.error = None;
+        self.finished_at = None;
+    }
+}
+
+struct Queue {
+    pulls: Vec<ModelPull>,
+    // Aborts the active pull
+    abort: Option<(String, AbortHandle)>,
+    // Counts changes, so a copy saved late can't overwrite a newer one
+    revision: u64,
+}
+
+impl Queue {
+    // A copy of the changed list, written once the queue lock is released
+    fn changed(&mut self) -> (u64, Vec<ModelPull>) {
+        self.revision += 1;
+        (self.revision, self.pulls.clone())
+    }
+}
+
+#[derive(Clone)]
+pub struct PullManager {
+    queue: Arc<Mutex<Queue>>,
+    wake: Arc<Notify>,
+    path: Option<PathBuf>,
+    // Revision of the queue in the file; held while writing it
+    saved: Arc<Mutex<u64>>,
+}
+
+impl PullManager {
+    pub fn load<R: tauri::Runtime>(app: &tauri::AppHandle<R>) -> Self {
+        Self::open(app_data::path(app, PULLS_FILE))
+    }
+
+    // Reads the persisted queue; pulls that were running are queued again
+    fn open(path: Option<PathBuf>) -> Self {
+        let mut pulls: Vec<ModelPull> = app_data::read(path.as_deref());
+
+        let mut resumed = 0;
+        for pull in pulls.iter_mut().filter(|pull| !pull.state.is_finished()) {
+            pull.state = PullState::Queued;
+            resumed += 1;
+        }
+        if resumed > 0 {
+            eprintln!("[Pulls] Resuming {} unfinished pulls", resumed);
+        }
+
+        Self {
+            queue: Arc::new(Mutex::new(Queue { pulls, abort: None, revision: 0 })),
+            wake: Arc::new(Notify::new()),
+            path,
+            saved: Arc::new(Mutex::new(0)),
+        }
+    }
+
+    fn queue(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap()
+    }
+
+    // Writes a copy from Queue::changed; failures are only logged since
+    // pulls work without the file
+    fn save(&self, (revision, pulls): (u64, Vec<ModelPull>)) {
+        if self.path.is_none() {
+            return;
+        }
+        let mut saved = self.saved.lock().unwrap();
+        if *saved >= revision {
+            return;
+        }
+        match app_data::write(self.path.as_deref(), &pulls) {
+            Ok(()) => *saved = revision,
+            Err(err) => eprintln!("[Pulls] {}", err),
+        }
+    }
+
+    pub fn snapshot(&self) -> Vec<ModelPull> {
+        self.queue().pulls.clone()
+    }
+
+    // Adds a pull to the queue, or returns the queued or running pull of the
+    // same model from the same server
+    pub fn enqueue(&self, base_url: String, model: String, insecure: bool) -> ModelPull {
+        let base_url = base_url.trim().trim_end_matches('/').to_string();
+        let mut queue = self.queue();
+        if let Some(existing) = queue
+            .pulls
+            .iter()
+            .find(|pull| !pull.state.is_finished() && pull.base_url == base_url && pull.model == model)
+        {
+            return existing.clone();
+        }
+
+        let now = chrono::Utc::now();
+        let mut id = format!("pull_{}", now.timestamp_millis());
+        while queue.pulls.iter().any(|pull| pull.id == id) {
+            id.push('_');
+        }
+        let pull = ModelPull {
+            id,
+            base_url,
+            model,
+            insecure,
+            state: PullState::Queued,
+            status: String::new(),
+            layers: Vec::new(),
+            completed: 0,
+            total: 0,
+            error: None,
+            attempts: 0,
+            created_at: now.to_rfc3339(),
+            finished_at: None,
+        };
+        queue.pulls.push(pull.clone());
+        let changed = queue.changed();
+        drop(queue);
+
+        self.save(changed);
+        self.wake.notify_one();
+        pull
+    }
+
+    fn find<'a>(queue: &'a mut Queue, id: &str) -> Result<&'a mut ModelPull, CommandError> {
+        queue
+            .pulls
+            .iter_mut()
+            .find(|pull| pull.id == id)
+            .ok_or_else(|| CommandError::new(ErrorKind::InvalidRequest, format!("Unknown pull {}", id)))
+    }
+
+    // Queued pulls are cancelled right away; a running one once its stream
+    // has been aborted, which the worker then records
+    pub fn cancel(&self, id: &str) -> Result<ModelPull, CommandError> {
+        let mut queue = self.queue();
+        if let Some((active, abort)) = &queue.abort {
+            if active == id {
+                abort.abort();
+            }
+        }
+        let pull = Self::find(&mut queue, id)?;
+        if pull.state == PullState::Queued {
+            pull.state = PullState::Cancelled;
+            pull.finished_at = Some(chrono::Utc::now().to_rfc3339());
+        }
+        let pull = pull.clone();
+        let changed = queue.changed();
+        drop(queue);
+
+        self.save(changed);
+        Ok(pull)
+    }
+
+    pub fn retry(&self, id: &str) -> Result<ModelPull, CommandError> {
+        let mut queue = self.queue();
+        let pull = Self::find(&mut queue, id)?;
+        if !matches!(pull.state, PullState::Failed | PullState::Cancelled) {
+            return Err(CommandError::new(
+                ErrorKind::InvalidRequest,
+                format!("Pull of {} hasn't failed or been cancelled", pull.model),
+            ));
+        }
+        pull.reset();
+        let pull = pull.clone();
+        let changed = queue.changed();
+        drop(queue);
+
+        self.save(changed);
+        self.wake.notify_one();
+        Ok(pull)
+    }
+
+    // Removes completed, failed and cancelled pulls from the list
+    pub fn clear_finished(&self) -> Vec<ModelPull> {
+        let mut queue = self.queue();
+        queue.pulls.retain(|pull| !pull.state.is_finished());
+        let changed = queue.changed();
+        drop(queue);
+
+        let pulls = changed.1.clone();
+        self.save(changed);
+        pulls
+    }
+
+    // Marks the oldest queued pull active and returns it with its abort registration
+    fn take_next(&self) -> Option<(ModelPull, AbortRegistration)> {
+        let mut queue = self.queue();
+        let pull = queue.pulls.iter_mut().find(|pull| pull.state == PullState::Queued)?;
+        pull.state = PullState::Active;
+        pull.attempts += 1;
+        let pull = pull.clone();
+
+        let (abort, registration) = AbortHandle::new_pair();
+        queue.abort = Some((pull.id.clone(), abort));
+        let changed = queue.changed();
+        drop(queue);
+
+        self.save(changed);
+        Some((pull, registration))
+    }
+
+    fn progress(&self, id: &str, progress: OllamaPullProgress) -> Option<(ModelPull, bool)> {
+        let mut queue = self.queue();
+        let pull = Self::find(&mut queue, id).ok()?;
+        let changed = pull.apply(progress);
+        Some((pull.clone(), changed))
+    }
+
+    // Records how a pull ended; None if it was cleared in the meantime
+    fn finish(&self, id: &str, result: Result<(), CommandError>) -> Option<ModelPull> {
+        let mut queue = self.queue();
+        queue.abort = None;
+        let pull = Self::find(&mut queue, id).ok()?;
+        pull.state = match &result {
+            Ok(()) => PullState::Completed,
+            Err(err) if err.kind == ErrorKind::Cancelled => PullState::Cancelled,
+            Err(_) => PullState::Failed,
+        };
+        pull.error = result.err();
+        pull.finished_at = Some(chrono::Utc::now().to_rfc3339());
+        let pull = pull.clone();
+
+        // Keep the newest finished pulls
+        let finished = queue.pulls.iter().filter(|pull| pull.state.is_finished()).count();
+        let mut excess = finished.saturating_sub(MAX_FINISHED);
+        queue.pulls.retain(|pull| {
+            if excess > 0 && pull.state.is_finished() {
+                excess -= 1;
+                return false;
+            }
+            true
+        });
+        let changed = queue.changed();
+        drop(queue);
+
+        self.save(changed);
+        Some(pull)
+    }
+
+    async fn run<R: tauri::Runtime>(&self, app: &tauri::AppHandle<R>, pull: ModelPull, registration: AbortRegistration) {
+        eprintln!("[Pulls] Pulling {} from {}", pull.model, pull.base_url);
+        let _ = app.emit(PROGRESS_EVENT, &pull);
+
+        let clients = app.state::<HttpClients>();
+        let policy = app.state::<EgressPolicy>();
+        let recorder = app.state::<Recorder>();
+        let work = async {
+            let ollama = Ollama::new(&pull.base_url, clients.inner(), policy.inner(), recorder.inner()).await?;
+            let mut last_emit = Instant::now();
+            ollama
+                .pull(&pull.model, pull.insecure, |progress| {
+                    if let Some((update, changed)) = self.progress(&pull.id, progress) {
+                        if changed || last_emit.elapsed() >= EMIT_INTERVAL {
+                            last_emit = Instant::now();
+                            let _ = app.emit(PROGRESS_EVENT, &update);
+                        }
+                    }
+                    Ok(())
+                })
+                .await
+        };
+        let result = match Abortable::new(work, registration).await {
+            Ok(result) => result,
+            Err(_) => Err(CommandError::cancelled()),
+        };
+
+        match &result {
+            Ok(()) => eprintln!("[Pulls] Pulled {}", pull.model),
+            Err(err) => eprintln!("[Pulls] Pull of {} stopped: {}", pull.model, err.message),
+        }
+        if let Some(done) = self.finish(&pull.id, result) {
+            let _ = app.emit(PROGRESS_EVENT, &done);
+        }
+    }
+}
+
+pub fn spawn_worker<R: tauri::Runtime>(app: tauri::AppHandle<R>, manager: PullManager) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            match manager.take_next() {
+                Some((pull, registration)) => manager.run(&app, pull, registration).await,
+                None => manager.wake.notified().await,
+            }
+        }
+    });
+}
+
+#[tauri::command]
+pub fn list_model_pulls(manager: State<'_, PullManager>) -> Vec<ModelPull> {
+    manager.snapshot()
+}
+
+#[tauri::command]
+pub fn queue_model_pull(
+    base_url: String,
+    model: String,
+    insecure: Option<bool>,
+    manager: State<'_, PullManager>,
+) -> Result<ModelPull, CommandError> {
+    if model.trim().is_empty() {
+        return Err(CommandError::new(ErrorKind::InvalidRequest, "Model name is empty"));
+    }
+    eprintln!("[Pulls] Queueing {}", model);
+    Ok(manager.enqueue(base_url, model.trim().to_string(), insecure.unwrap_or(false)))
+}
+
+#[tauri::command]
+pub fn cancel_model_pull(id: String, manager: State<'_, PullManager>) -> Result<ModelPull, CommandError> {
+    manager.cancel(&id)
+}
+
+// Queues a failed or cancelled pull again; layers Ollama already has aren't
+// downloaded twice
+#[tauri::command]
+pub fn retry_model_pull(id: String, manager: State<'_, PullManager>) -> Result<ModelPull, CommandError> {
+    manager.retry(&id)
+}
+
+#[tauri::command]
+pub fn clear_finished_model_pulls(manager: State<'_, PullManager>) -> Vec<ModelPull> {
+    manager.clear_finished()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manager(dir: &tempfile::TempDir) -> PullManager {
+        PullManager::open(Some(dir.path().join(PULLS_FILE)))
+    }
+
+    fn saved(dir: &tempfile::TempDir) -> Vec<ModelPull> {
+        app_data::read(Some(&dir.path().join(PULLS_FILE)))
+    }
+
+    #[test]
+    fn enqueue_returns_the_pending_pull_of_a_model() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = manager(&dir);
+        let first = manager.enqueue("http://localhost:11434/".into(), "llama3.2".into(), false);
+        let again = manager.enqueue(" http://localhost:11434".into(), "llama3.2".into(), false);
+        assert_eq!(again.id, first.id);
+        let other = manager.enqueue("http://localhost:11434".into(), "qwen2.5".into(), false);
+        assert_ne!(other.id, first.id);
+        assert_eq!(saved(&dir).len(), 2);
+
+        // Once finished, the model can be pulled again
+        manager.cancel(&first.id).unwrap();
+        let new = manager.enqueue("http://localhost:11434".into(), "llama3.2".into(), false);
+        assert_ne!(new.id, first.id);
+        assert_eq!(manager.snapshot().len(), 3);
+    }
+
+    #[test]
+    fn queued_pulls_cancel_at_once_and_active_ones_are_aborted() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = manager(&dir);
+        let active = manager.enqueue("http://localhost:11434".into(), "llama3.2".into(), false);
+        let queued = manager.enqueue("http://localhost:11434".into(), "qwen2.5".into(), false);
+
+        let (pull, registration) = manager.take_next().unwrap();
+        assert_eq!((pull.id.as_str(), pull.state, pull.attempts), (active.id.as_str(), PullState::Active, 1));
+
+        let cancelled = manager.cancel(&queued.id).unwrap();
+        assert_eq!(cancelled.state, PullState::Cancelled);
+        assert!(cancelled.finished_at.is_some());
+
+        // The worker records the cancellation once the stream is aborted
+        let cancelling = manager.cancel(&active.id).unwrap();
+        assert_eq!(cancelling.state, PullState::Active);
+        let aborted = tauri::async_runtime::block_on(Abortable::new(futures::future::pending::<()>(), registration));
+        assert!(aborted.is_err());
+        let done = manager.finish(&active.id, Err(CommandError::cancelled())).unwrap();
+        assert_eq!(done.state, PullState::Cancelled);
+
+        assert!(manager.take_next().is_none());
+        assert_eq!(manager.cancel("pull_unknown").unwrap_err().kind, ErrorKind::InvalidRequest);
+        assert!(saved(&dir).iter().all(|pull| pull.state == PullState::Cancelled));
+    }
+
+    #[test]
+    fn only_failed_or_cancelled_pulls_are_retried() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = manager(&dir);
+        let pull = manager.enqueue("http://localhost:11434".into(), "llama3.2".into(), false);
+        assert_eq!(manager.retry(&pull.id).unwrap_err().kind, ErrorKind::InvalidRequest);
+
+        manager.take_next().unwrap();
+        assert_eq!(manager.retry(&pull.id).unwrap_err().kind, ErrorKind::InvalidRequest);
+        let failed = CommandError::new(ErrorKind::Network, "connection reset");
+        assert_eq!(manager.finish(&pull.id, Err(failed)).unwrap().state, PullState::Failed);
+
+        let retried = manager.retry(&pull.id).unwrap();
+        assert_eq!(retried.state, PullState::Queued);
+        assert!(retried.error.is_none() && retried.finished_at.is_none());
+        let (pull, _) = manager.take_next().unwrap();
+        assert_eq!(pull.attempts, 2);
+        manager.finish(&pull.id, Ok(())).unwrap();
+        assert_eq!(manager.retry(&pull.id).unwrap_err().kind, ErrorKind::InvalidRequest);
+        assert_eq!(manager.retry("pull_unknown").unwrap_err().kind, ErrorKind::InvalidRequest);
+    }
+
+    #[test]
+    fn finish_keeps_the_newest_finished_pulls() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = manager(&dir);
+        let mut finished = Vec::new();
+        for index in 0..MAX_FINISHED + 3 {
+            manager.enqueue("http://localhost:11434".into(), format!("model{}", index), false);
+            let (pull, _) = manager.take_next().unwrap();
+            manager.finish(&pull.id, Ok(())).unwrap();
+            finished.push(pull.id);
+        }
+        let ids = |pulls: Vec<ModelPull>| pulls.into_iter().map(|pull| pull.id).collect::<Vec<_>>();
+        assert_eq!(ids(manager.snapshot()), finished[3..]);
+
+        // Pulls still to run are never dropped
+        let active = manager.enqueue("http://localhost:11434".into(), "active".into(), false);
+        let queued = manager.enqueue("http://localhost:11434".into(), "queued".into(), false);
+        manager.take_next().unwrap();
+        manager.finish(&active.id, Ok(())).unwrap();
+        let pulls = ids(manager.snapshot());
+        assert_eq!(pulls.len(), MAX_FINISHED + 1);
+        assert!(pulls.contains(&queued.id) && !pulls.contains(&finished[3]));
+        assert_eq!(ids(saved(&dir)), pulls);
+    }
+
+    #[test]
+    fn unfinished_pulls_are_queued_again_on_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = manager(&dir);
+        let active = manager.enqueue("http://localhost:11434".into(), "llama3.2".into(), false);
+        let done = manager.enqueue("http://localhost:11434".into(), "qwen2.5".into(), false);
+        manager.take_next().unwrap();
+        manager.cancel(&done.id).unwrap();
+
+        // As after a restart
+        let reloaded = PullManager::open(Some(dir.path().join(PULLS_FILE)));
+        let pulls = reloaded.snapshot();
+        assert_eq!(pulls.iter().find(|pull| pull.id == active.id).unwrap().state, PullState::Queued);
+        assert_eq!(pulls.iter().find(|pull| pull.id == done.id).unwrap().state, PullState::Cancelled);
+        let (next, _) = reloaded.take_next().unwrap();
+        assert_eq!((next.id, next.attempts), (active.id, 2));
+    }
+}
@@ -0,0 +1,608 @@
+// Per-host token buckets for search and scraping, so auto-search sessions
+// and scrape_urls batches don't hammer a single site. Provider traffic is
+// not limited here. Settings are persisted to rate_limits.json in the app
+// data dir.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+
+use crate::app_data;
+use crate::cancellation::{self, RequestToken};
+use crate::error::{CommandError, ErrorKind};
+
+const SETTINGS_FILE: &str = "rate_limits.json";
+
+// Back-off applied after a 429 that doesn't say how long to wait
+const DEFAULT_BACKOFF: Duration = Duration::from_secs(30);
+const MAX_BACKOFF: Duration = Duration::from_secs(600);
+// Full buckets nobody used for this long are dropped
+const IDLE_EVICT: Duration = Duration::from_secs(600);
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostRateLimit {
+    pub requests_per_minute: f64,
+    // Requests that may go out back to back before the rate applies
+    pub burst: u32,
+    // Minimum gap between two requests to the same host
+    pub min_delay_ms: u64,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimitSettings {
+    pub enabled: bool,
+    pub default_limit: HostRateLimit,
+    // Overrides keyed by host; "duckduckgo.com" also covers its subdomains
+    pub hosts: HashMap<String, HostRateLimit>,
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        let mut hosts = HashMap::new();
+        hosts.insert(
+            "duckduckgo.com".to_string(),
+            HostRateLimit {
+                requests_per_minute: 10.0,
+                burst: 2,
+                min_delay_ms: 2000,
+            },
+        );
+
+        Self {
+            enabled: true,
+            default_limit: HostRateLimit {
+                requests_per_minute: 30.0,
+                burst: 4,
+                min_delay_ms: 1000,
+            },
+            hosts,
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostRateLimitStatus {
+    pub host: String,
+    pub limit: HostRateLimit,
+    pub tokens_available: f64,
+    // Requests currently sleeping until their slot
+    pub waiting: usize,
+    // How long a request made now would wait
+    pub wait_ms: u64,
+    pub limited: bool,
+    pub total_requests: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_request_at: Option<String>,
+}
+
+struct Bucket {
+    limit: HostRateLimit,
+    tokens: f64,
+    last_refill: Instant,
+    // Earliest start of the next request (minimum delay and back-off)
+    next_allowed: Instant,
+    waiting: usize,
+    total_requests: u64,
+    last_request_at: Option<DateTime<Utc>>,
+}
+
+impl Bucket {
+    fn new(limit: HostRateLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst as f64,
+            limit,
+            last_refill: now,
+            next_allowed: now,
+            waiting: 0,
+            total_requests: 0,
+            last_request_at: None,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.requests_per_minute / 60.0).min(self.limit.burst as f64);
+        self.last_refill = now;
+    }
+
+    // When a request made at `now` may start. Tokens go negative while
+    // requests are queued, so later callers line up behind earlier ones.
+    fn start_at(&self, now: Instant) -> Instant {
+        let token_at = if self.tokens >= 1.0 {
+            now
+        } else {
+            let per_second = self.limit.requests_per_minute / 60.0;
+            now + Duration::from_secs_f64((1.0 - self.tokens) / per_second)
+        };
+        token_at.max(self.next_allowed)
+    }
+
+    // A refilled bucket with no one waiting is the same as a new one
+    fn is_idle(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.waiting == 0
+            && self.tokens >= self.limit.burst as f64
+            && now.saturating_duration_since(self.next_allowed) >= IDLE_EVICT
+    }
+}
+
+struct LimiterState {
+    settings: RwLock<RateLimitSettings>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+#[derive(Clone)]
+pub struct RateLimiter {
+    state: Arc<LimiterState>,
+}
+
+fn limit_for(settings: &RateLimitSettings, host: &str) -> HostRateLimit {
+    // The most specific matching override wins
+    settings
+        .hosts
+        .iter()
+        .filter(|(pattern, _)| host == pattern.as_str() || host.ends_with(&format!(".{}", pattern)))
+        .max_by_key(|(pattern, _)| pattern.len())
+        .map(|(_, limit)| limit.clone())
+        .unwrap_or_else(|| settings.default_limit.clone())
+}
+
+fn validate(settings: &RateLimitSettings) -> Result<(), CommandError> {
+    let limits = std::iter::once(("default", &settings.default_limit))
+        .chain(settings.hosts.iter().map(|(host, limit)| (host.as_str(), limit)));
+    for (host, limit) in limits {
+        if !limit.requests_per_minute.is_finite() || limit.requests_per_minute <= 0.0 || limit.burst == 0 {
+            return Err(CommandError::new(
+                ErrorKind::InvalidRequest,
+                format!("Invalid rate limit for {}: requests per minute and burst must be positive", host),
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Retry-After in seconds; HTTP dates are treated as the default back-off
+fn retry_after(headers: &reqwest::header::HeaderMap) -> Duration {
+    headers
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_BACKOFF)
+        .min(MAX_BACKOFF)
+}
+
+// A slot taken for a request. Dropped before `used` (the wait was cancelled)
+// it hands its token back, and its delay too if no one queued behind it.
+struct Reservation<'a> {
+    limiter: &'a RateLimiter,
+    host: String,
+    wait: Duration,
+    // The bucket's next_allowed before and after the slot was taken
+    previous_next: Instant,
+    next_allowed: Instant,
+    used: bool,
+}
+
+impl Reservation<'_> {
+    fn used(mut self) {
+        self.used = true;
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        let mut buckets = self.limiter.state.buckets.lock().unwrap();
+        let Some(bucket) = buckets.get_mut(&self.host) else {
+            return;
+        };
+        if !self.wait.is_zero() {
+            bucket.waiting = bucket.waiting.saturating_sub(1);
+        }
+        if self.used {
+            return;
+        }
+        bucket.tokens = (bucket.tokens + 1.0).min(bucket.limit.burst as f64);
+        bucket.total_requests = bucket.total_requests.saturating_sub(1);
+        if bucket.next_allowed == self.next_allowed {
+            bucket.next_allowed = self.previous_next;
+        }
+    }
+}
+
+impl RateLimiter {
+    pub fn new(settings: RateLimitSettings) -> Result<Self, CommandError> {
+        validate(&settings)?;
+        Ok(Self {
+            state: Arc::new(LimiterState {
+                settings: RwLock::new(settings),
+                buckets: Mutex::new(HashMap::new()),
+            }),
+        })
+    }
+
+    pub fn settings(&self) -> RateLimitSettings {
+        self.state.settings.read().unwrap().clone()
+    }
+
+    pub fn update(&self, settings: RateLimitSettings) -> Result<(), CommandError> {
+        validate(&settings)?;
+        // Same lock order as reserve: settings, then buckets
+        let mut current = self.state.settings.write().unwrap();
+        let now = Instant::now();
+        for (host, bucket) in self.state.buckets.lock().unwrap().iter_mut() {
+            bucket.refill(now);
+            bucket.limit = limit_for(&settings, host);
+            bucket.tokens = bucket.tokens.min(bucket.limit.burst as f64);
+        }
+        *current = settings;
+        Ok(())
+    }
+
+    // The host's bucket, created on first use. Idle full buckets of other
+    // hosts are dropped on the way so the map doesn't grow forever.
+    fn bucket<'a>(
+        buckets: &'a mut HashMap<String, Bucket>,
+        settings: &RateLimitSettings,
+        host: &str,
+        now: Instant,
+    ) -> &'a mut Bucket {
+        if !buckets.contains_key(host) {
+            buckets.retain(|_, bucket| !bucket.is_idle(now));
+        }
+        let bucket = buckets
+            .entry(host.to_string())
+            .or_insert_with(|| Bucket::new(limit_for(settings, host), now));
+        bucket.refill(now);
+        bucket
+    }
+
+    // Takes the next slot for the URL's host; the reservation says how long
+    // to wait for it
+    fn reserve(&self, url: &Url) -> Option<Reservation<'_>> {
+        let settings = self.state.settings.read().unwrap();
+        if !settings.enabled {
+            return None;
+        }
+        let host = url.host_str()?.to_ascii_lowercase();
+
+        let now = Instant::now();
+        let mut buckets = self.state.buckets.lock().unwrap();
+        let bucket = Self::bucket(&mut buckets, &settings, &host, now);
+
+        let start = bucket.start_at(now);
+        let previous_next = bucket.next_allowed;
+        bucket.tokens -= 1.0;
+        bucket.next_allowed = start + Duration::from_millis(bucket.limit.min_delay_ms);
+        bucket.total_requests += 1;
+        bucket.last_request_at = Some(Utc::now());
+
+        let wait = start.duration_since(now);
+        if !wait.is_zero() {
+            bucket.waiting += 1;
+        }
+        Some(Reservation {
+            limiter: self,
+            host,
+            wait,
+            previous_next,
+            next_allowed: bucket.next_allowed,
+            used: false,
+        })
+    }
+
+    // Waits for a slot before an async request; dropping the future gives
+    // the slot back
+    pub async fn acquire(&self, url: &Url) {
+        let Some(reservation) = self.reserve(url) else {
+            return;
+        };
+        if !reservation.wait.is_zero() {
+            eprintln!("[RateLimit] Waiting {}ms for {}", reservation.wait.as_millis(), reservation.host);
+            tokio::time::sleep(reservation.wait).await;
+        }
+        reservation.used();
+    }
+
+    // Waits for a slot on a blocking scraping thread, giving up early (and
+    // giving the slot back) if the request is cancelled
+    pub fn acquire_blocking(&self, url: &Url, token: Option<&RequestToken>) -> Result<(), CommandError> {
+        let Some(reservation) = self.reserve(url) else {
+            return Ok(());
+        };
+        if !reservation.wait.is_zero() {
+            eprintln!("[RateLimit] Waiting {}ms for {}", reservation.wait.as_millis(), reservation.host);
+            cancellation::sleep_blocking(reservation.wait, token)?;
+        }
+        reservation.used();
+        Ok(())
+    }
+
+    // Pushes the host's next slot back after it told us to slow down
+    pub fn observe(&self, url: &Url, response: &reqwest::Response) {
+        self.observe_status(url, response.status(), response.headers());
+    }
+
+    fn observe_status(&self, url: &Url, status: reqwest::StatusCode, headers: &reqwest::header::HeaderMap) {
+        if status != reqwest::StatusCode::TOO_MANY_REQUESTS
+            && !(status == reqwest::StatusCode::SERVICE_UNAVAILABLE
+                && headers.contains_key(reqwest::header::RETRY_AFTER))
+        {
+            return;
+        }
+        let Some(host) = url.host_str().map(|h| h.to_ascii_lowercase()) else {
+            return;
+        };
+
+        let delay = retry_after(headers);
+        eprintln!("[RateLimit] {} answered {}, backing off for {}s", host, status, delay.as_secs());
+
+        let settings = self.state.settings.read().unwrap();
+        let now = Instant::now();
+        let mut buckets = self.state.buckets.lock().unwrap();
+        let bucket = Self::bucket(&mut buckets, &settings, &host, now);
+        bucket.tokens = bucket.tokens.min(0.0);
+        bucket.next_allowed = bucket.next_allowed.max(now + delay);
+    }
+
+    pub fn status(&self) -> Vec<HostRateLimitStatus> {
+        let now = Instant::now();
+        let mut buckets = self.state.buckets.lock().unwrap();
+        let mut status: Vec<HostRateLimitStatus> = buckets
+            .iter_mut()
+            .map(|(host, bucket)| {
+                bucket.refill(now);
+                let wait = bucket.start_at(now).duration_since(now);
+                HostRateLimitStatus {
+                    host: host.clone(),
+                    limit: bucket.limit.clone(),
+                    tokens_available: bucket.tokens.max(0.0),
+                    waiting: bucket.waiting,
+                    wait_ms: wait.as_millis() as u64,
+                    limited: !wait.is_zero(),
+                    total_requests: bucket.total_requests,
+                    last_request_at: bucket.last_request_at.map(|at| at.to_rfc3339()),
+                }
+            })
+            .collect();
+        status.sort_by(|a, b| a.host.cmp(&b.host));
+        status
+    }
+}
+
+// Reads the persisted settings; a missing or unreadable file means defaults
+pub fn load<R: tauri::Runtime>(app: &tauri::AppHandle<R>) -> RateLimitSettings {
+    app_data::load(app, SETTINGS_FILE)
+}
+
+#[tauri::command]
+pub fn get_rate_limit_settings(limiter: tauri::State<'_, RateLimiter>) -> RateLimitSettings {
+    limiter.settings()
+}
+
+#[tauri::command]
+pub fn update_rate_limit_settings(
+    settings: RateLimitSettings,
+    app: tauri::AppHandle,
+    limiter: tauri::State<'_, RateLimiter>,
+) -> Result<(), CommandError> {
+    limiter.update(settings.clone())?;
+    app_data::save(&app, SETTINGS_FILE, &settings)
+}
+
+// Limiter state of every host contacted so far, for "waiting for rate limit" UI
+#[tauri::command]
+pub fn get_rate_limit_status(limiter: tauri::State<'_, RateLimiter>) -> Vec<HostRateLimitStatus> {
+    limiter.status()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancellation::RequestRegistry;
+
+    fn limit(requests_per_minute: f64, burst: u32, min_delay_ms: u64) -> HostRateLimit {
+        HostRateLimit { requests_per_minute, burst, min_delay_ms }
+    }
+
+    fn limiter(default_limit: HostRateLimit) -> RateLimiter {
+        RateLimiter::new(RateLimitSettings { enabled: true, default_limit, hosts: HashMap::new() }).unwrap()
+    }
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    fn status(limiter: &RateLimiter, host: &str) -> HostRateLimitStatus {
+        limiter.status().into_iter().find(|status| status.host == host).unwrap()
+    }
+
+    #[test]
+    fn buckets_refill_at_the_configured_rate() {
+        let now = Instant::now();
+        let mut bucket = Bucket::new(limit(60.0, 2, 0), now);
+        assert_eq!(bucket.start_at(now), now);
+
+        bucket.tokens -= 2.0;
+        assert_eq!(bucket.start_at(now), now + Duration::from_secs(1));
+        // Queued requests line up one interval apart
+        bucket.tokens -= 1.0;
+        assert_eq!(bucket.start_at(now), now + Duration::from_secs(2));
+
+        bucket.refill(now + Duration::from_millis(1500));
+        assert!((bucket.tokens - 0.5).abs() < 1e-9);
+        assert_eq!(bucket.start_at(now + Duration::from_millis(1500)), now + Duration::from_secs(2));
+
+        // Never more than the burst
+        bucket.refill(now + Duration::from_secs(60));
+        assert_eq!(bucket.tokens, 2.0);
+
+        // The minimum delay and back-off apply even with tokens left
+        bucket.next_allowed = now + Duration::from_secs(70);
+        assert_eq!(bucket.start_at(now + Duration::from_secs(60)), now + Duration::from_secs(70));
+    }
+
+    #[test]
+    fn host_overrides_cover_subdomains() {
+        let mut settings = RateLimitSettings::default();
+        settings.hosts.insert("api.duckduckgo.com".to_string(), limit(1.0, 1, 0));
+        let rpm = |host: &str| limit_for(&settings, host).requests_per_minute;
+
+        assert_eq!(rpm("duckduckgo.com"), 10.0);
+        assert_eq!(rpm("html.duckduckgo.com"), 10.0);
+        // The longest matching override wins
+        assert_eq!(rpm("api.duckduckgo.com"), 1.0);
+        assert_eq!(rpm("v2.api.duckduckgo.com"), 1.0);
+        assert_eq!(rpm("notduckduckgo.com"), 30.0);
+        assert_eq!(rpm("duckduckgo.com.example"), 30.0);
+    }
+
+    #[test]
+    fn retry_after_is_read_and_clamped() {
+        let headers = |value: Option<&str>| {
+            let mut headers = reqwest::header::HeaderMap::new();
+            if let Some(value) = value {
+                headers.insert(reqwest::header::RETRY_AFTER, value.parse().unwrap());
+            }
+            headers
+        };
+        assert_eq!(retry_after(&headers(Some("120"))), Duration::from_secs(120));
+        assert_eq!(retry_after(&headers(Some(" 5 "))), Duration::from_secs(5));
+        assert_eq!(retry_after(&headers(Some("86400"))), MAX_BACKOFF);
+        assert_eq!(retry_after(&headers(Some("Wed, 21 Oct 2015 07:28:00 GMT"))), DEFAULT_BACKOFF);
+        assert_eq!(retry_after(&headers(None)), DEFAULT_BACKOFF);
+    }
+
+    #[test]
+    fn too_many_requests_backs_the_host_off() {
+        let limiter = limiter(limit(60.0, 4, 0));
+        let target = url("https://search.example.com/?q=rust");
+        let mut headers = reqwest::header::HeaderMap::new();
+
+        // A 503 without Retry-After is an outage, not a rate limit
+        limiter.observe_status(&target, reqwest::StatusCode::SERVICE_UNAVAILABLE, &headers);
+        assert!(limiter.status().is_empty());
+
+        headers.insert(reqwest::header::RETRY_AFTER, "120".parse().unwrap());
+        limiter.observe_status(&target, reqwest::StatusCode::TOO_MANY_REQUESTS, &headers);
+        let backed_off = status(&limiter, "search.example.com");
+        assert!(backed_off.limited);
+        assert!(backed_off.tokens_available < 0.01);
+        assert!(backed_off.wait_ms > 119_000 && backed_off.wait_ms <= 120_000, "{}", backed_off.wait_ms);
+
+        limiter.observe_status(&url("https://other.example.com/"), reqwest::StatusCode::OK, &headers);
+        assert_eq!(limiter.status().len(), 1);
+    }
+
+    #[test]
+    fn updates_apply_to_existing_buckets() {
+        let limiter = limiter(limit(60.0, 4, 0));
+        let target = url("https://example.com/");
+        limiter.reserve(&target).unwrap().used();
+        assert!((status(&limiter, "example.com").tokens_available - 3.0).abs() < 0.01);
+
+        let mut settings = limiter.settings();
+        settings.hosts.insert("example.com".to_string(), limit(6.0, 1, 500));
+        limiter.update(settings).unwrap();
+        let updated = status(&limiter, "example.com");
+        assert_eq!(updated.limit, limit(6.0, 1, 500));
+        assert_eq!(updated.tokens_available, 1.0);
+
+        let mut settings = limiter.settings();
+        settings.default_limit.burst = 0;
+        assert_eq!(limiter.update(settings).unwrap_err().kind, ErrorKind::InvalidRequest);
+        assert_eq!(limiter.settings().default_limit.burst, 4);
+    }
+
+    #[test]
+    fn idle_full_buckets_are_evicted() {
+        let limiter = limiter(limit(60.0, 2, 0));
+        limiter.reserve(&url("https://idle.example.com/")).unwrap().used();
+        limiter.reserve(&url("https://busy.example.com/")).unwrap().used();
+        {
+            let mut buckets = limiter.state.buckets.lock().unwrap();
+            let long_ago = Instant::now() - IDLE_EVICT - Duration::from_secs(1);
+            for bucket in buckets.values_mut() {
+                bucket.last_refill = long_ago;
+                bucket.next_allowed = long_ago;
+            }
+            // A request is still waiting for its slot
+            buckets.get_mut("busy.example.com").unwrap().waiting = 1;
+        }
+
+        limiter.reserve(&url("https://new.example.com/")).unwrap().used();
+        let hosts: Vec<String> = limiter.status().into_iter().map(|status| status.host).collect();
+        assert_eq!(hosts, ["busy.example.com", "new.example.com"]);
+    }
+
+    #[test]
+    fn cancelled_waits_give_their_slot_back() {
+        let limiter = limiter(limit(60.0, 1, 0));
+        let target = url("https://example.com/");
+        tauri::async_runtime::block_on(limiter.acquire(&target));
+
+        // The second request would wait about a second; give up after 50ms
+        let waited = tauri::async_runtime::block_on(async {
+            let waiting = async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                status(&limiter, "example.com").waiting
+            };
+            tokio::select! {
+                _ = limiter.acquire(&target) => None,
+                waiting = waiting => Some(waiting),
+            }
+        });
+        assert_eq!(waited, Some(1));
+
+        let after = status(&limiter, "example.com");
+        assert_eq!(after.waiting, 0);
+        assert_eq!(after.total_requests, 1);
+        assert!(after.wait_ms <= 1000, "{}", after.wait_ms);
+    }
+
+    #[test]
+    fn cancelled_blocking_waits_give_their_slot_back() {
+        let limiter = limiter(limit(60.0, 1, 0));
+        let target = url("https://example.com/");
+        limiter.acquire_blocking(&target, None).unwrap();
+
+        let registry = RequestRegistry::default();
+        let (token_tx, token_rx) = std::sync::mpsc::channel();
+        let work = registry.run(Some("scrape".into()), |token| {
+            let _ = token_tx.send(token.unwrap());
+            std::future::pending::<Result<(), CommandError>>()
+        });
+        let wait = async {
+            let token = token_rx.recv().unwrap();
+            let waiter = {
+                let (limiter, target) = (limiter.clone(), target.clone());
+                std::thread::spawn(move || limiter.acquire_blocking(&target, Some(&token)))
+            };
+            std::thread::sleep(Duration::from_millis(50));
+            assert!(registry.cancel("scrape"));
+            waiter.join().unwrap()
+        };
+        let (_, waited) = tauri::async_runtime::block_on(async { futures::join!(work, wait) });
+        assert_eq!(waited.unwrap_err().kind, ErrorKind::Cancelled);
+
+        let after = status(&limiter, "example.com");
+        assert_eq!((after.waiting, after.total_requests), (0, 1));
+        assert!(after.wait_ms <= 1000, "{}", after.wait_ms);
+    }
+
+    #[test]
+    fn disabled_limits_never_wait() {
+        let limiter = limiter(limit(1.0, 1, 60_000));
+        let mut settings = limiter.settings();
+        settings.enabled = false;
+        limiter.update(settings).unwrap();
+        for _ in 0..3 {
+            assert!(limiter.reserve(&url("https://example.com/")).is_none());
+        }
+    }
+}
@@ -0,0 +1,1081 @@
+// Opt-in recorder of proxied and scraped requests for provider debugging.
+// Exchanges go into a ring buffer, can be exported as HAR 1.2 and replayed
+// by a local mock server so bugs reproduce without the original server.
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use futures::future::{AbortHandle, Abortable};
+use reqwest::Url;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::error::{CommandError, ErrorKind};
+use crate::take_utf8_prefix;
+
+const REDACTED: &str = "[redacted]";
+const MAX_REQUEST_HEAD: usize = 64 * 1024;
+
+// Header, query parameter and body field names whose values never reach
+// the buffer
+const SECRET_NAMES: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "x-api-key",
+    "api-key",
+    "x-goog-api-key",
+    "key",
+    "api_key",
+    "apikey",
+    "access_token",
+    "token",
+    "password",
+    "client_secret",
+];
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecorderSettings {
+    pub enabled: bool,
+    // Number of exchanges kept; the oldest are dropped first
+    pub capacity: usize,
+    // Request and response bodies are cut off after this many bytes
+    pub max_body_bytes: usize,
+}
+
+impl Default for RecorderSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            capacity: 200,
+            max_body_bytes: 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExchangeSource {
+    Proxy,
+    ProxyStream,
+    Fetch,
+    Browser,
+    Search,
+    Scrape,
+    // Native provider clients (Ollama, ...)
+    Provider,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct HeaderEntry {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<HeaderEntry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    pub body_truncated: bool,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedResponse {
+    // 0 when the status isn't known, e.g. pages loaded by the headless browser
+    pub status: u16,
+    pub status_text: String,
+    pub headers: Vec<HeaderEntry>,
+    pub body: String,
+    pub body_truncated: bool,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedExchange {
+    pub id: u64,
+    pub started_at: String,
+    pub source: ExchangeSource,
+    pub duration_ms: u64,
+    // Time until the response headers arrived
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wait_ms: Option<u64>,
+    pub request: RecordedRequest,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<RecordedResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<CommandError>,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayServerInfo {
+    pub url: String,
+    pub entries: usize,
+}
+
+struct ReplayServer {
+    url: String,
+    abort: AbortHandle,
+}
+
+struct RecorderState {
+    settings: RwLock<RecorderSettings>,
+    exchanges: Mutex<VecDeque<RecordedExchange>>,
+    next_id: AtomicU64,
+    replay: Mutex<Option<ReplayServer>>,
+    // Known secret values, such as the proxy password, by name
+    secrets: RwLock<HashMap<&'static str, String>>,
+}
+
+#[derive(Clone)]
+pub struct Recorder {
+    state: Arc<RecorderState>,
+}
+
+fn is_secret(name: &str) -> bool {
+    SECRET_NAMES.iter().any(|secret| name.eq_ignore_ascii_case(secret))
+}
+
+fn redact_headers(headers: &reqwest::header::HeaderMap) -> Vec<HeaderEntry> {
+    headers
+        .iter()
+        .map(|(name, value)| HeaderEntry {
+            name: name.as_str().to_string(),
+            value: if is_secret(name.as_str()) {
+                REDACTED.to_string()
+            } else {
+                String::from_utf8_lossy(value.as_bytes()).into_owned()
+            },
+        })
+        .collect()
+}
+
+// Replaces the values of credential-like query parameters (?key=...)
+fn redact_url(url: &Url) -> String {
+    if !url.query_pairs().any(|(name, _)| is_secret(&name)) {
+        return url.to_string();
+    }
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(name, value)| {
+            let value = if is_secret(&name) { REDACTED.to_string() } else { value.into_owned() };
+            (name.into_owned(), value)
+        })
+        .collect();
+    let mut redacted = url.clone();
+    redacted.query_pairs_mut().clear().extend_pairs(pairs);
+    redacted.to_string()
+}
+
+// Replaces the values of credential-like fields in a JSON value, at any
+// depth; returns whether anything was replaced
+fn redact_json(value: &mut serde_json::Value) -> bool {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut redacted = false;
+            for (name, value) in map.iter_mut() {
+                if is_secret(name) && !value.is_null() {
+                    *value = serde_json::Value::String(REDACTED.to_string());
+                    redacted = true;
+                } else {
+                    redacted |= redact_json(value);
+                }
+            }
+            redacted
+        }
+        serde_json::Value::Array(items) => items.iter_mut().fold(false, |redacted, item| redact_json(item) | redacted),
+        _ => false,
+    }
+}
+
+// Request bodies with credential-like JSON or form fields get those values
+// replaced; known secret values are replaced wherever they appear
+fn redact_body(text: &str, form: bool, secrets: &[String]) -> String {
+    let mut text = match serde_json::from_str::<serde_json::Value>(text) {
+        Ok(mut value) => {
+            if redact_json(&mut value) {
+                value.to_string()
+            } else {
+                text.to_string()
+            }
+        }
+        Err(_) if form && url::form_urlencoded::parse(text.as_bytes()).any(|(name, _)| is_secret(&name)) => {
+            let pairs = url::form_urlencoded::parse(text.as_bytes()).map(|(name, value)| {
+                let value = if is_secret(&name) { REDACTED.into() } else { value };
+                (name, value)
+            });
+            url::form_urlencoded::Serializer::new(String::new()).extend_pairs(pairs).finish()
+        }
+        Err(_) => text.to_string(),
+    };
+    for secret in secrets {
+        text = text.replace(secret.as_str(), REDACTED);
+    }
+    text
+}
+
+// Cuts `text` to at most `max` bytes on a character boundary
+fn truncate(text: &str, max: usize) -> (String, bool) {
+    if text.len() <= max {
+        return (text.to_string(), false);
+    }
+    let mut end = max;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    (text[..end].to_string(), true)
+}
+
+impl Recorder {
+    pub fn new(settings: RecorderSettings) -> Self {
+        Self {
+            state: Arc::new(RecorderState {
+                settings: RwLock::new(settings),
+                exchanges: Mutex::new(VecDeque::new()),
+                next_id: AtomicU64::new(1),
+                replay: Mutex::new(None),
+                secrets: RwLock::new(HashMap::new()),
+            }),
+        }
+    }
+
+    pub fn settings(&self) -> RecorderSettings {
+        self.state.settings.read().unwrap().clone()
+    }
+
+    pub fn update(&self, settings: RecorderSettings) {
+        let mut exchanges = self.state.exchanges.lock().unwrap();
+        while exchanges.len() > settings.capacity {
+            exchanges.pop_front();
+        }
+        *self.state.settings.write().unwrap() = settings;
+    }
+
+    pub fn exchanges(&self) -> Vec<RecordedExchange> {
+        self.state.exchanges.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.state.exchanges.lock().unwrap().clear();
+    }
+
+    // Registers a secret value that must never show up in a recorded request
+    // body; None forgets it
+    pub fn set_secret(&self, name: &'static str, value: Option<&str>) {
+        let mut secrets = self.state.secrets.write().unwrap();
+        match value.filter(|value| !value.is_empty()) {
+            Some(value) => secrets.insert(name, value.to_string()),
+            None => secrets.remove(name),
+        };
+    }
+
+    // Starts recording a request built with reqwest; a no-op when disabled
+    pub fn start(&self, source: ExchangeSource, request: &reqwest::Request) -> Recording {
+        let settings = self.settings();
+        if !settings.enabled {
+            return Recording { active: None };
+        }
+
+        let (body, body_truncated) = match request.body().and_then(|body| body.as_bytes()) {
+            Some(bytes) => {
+                let form = request
+                    .headers()
+                    .get(reqwest::header::CONTENT_TYPE)
+                    .is_some_and(|value| value.as_bytes().starts_with(b"application/x-www-form-urlencoded"));
+                let secrets: Vec<String> = self.state.secrets.read().unwrap().values().cloned().collect();
+                let text = redact_body(&String::from_utf8_lossy(bytes), form, &secrets);
+                let (body, truncated) = truncate(&text, settings.max_body_bytes);
+                (Some(body), truncated)
+            }
+            None => (None, false),
+        };
+
+        self.begin(
+            source,
+            settings.max_body_bytes,
+            RecordedRequest {
+                method: request.method().to_string(),
+                url: redact_url(request.url()),
+                headers: redact_headers(request.headers()),
+                body,
+                body_truncated,
+            },
+        )
+    }
+
+    // Starts recording a page load by the headless browser, which doesn't
+    // expose the raw request
+    pub fn start_browser(&self, url: &str) -> Recording {
+        let settings = self.settings();
+        if !settings.enabled {
+            return Recording { active: None };
+        }
+
+        let url = Url::parse(url).map(|u| redact_url(&u)).unwrap_or_else(|_| url.to_string());
+        self.begin(
+            ExchangeSource::Browser,
+            settings.max_body_bytes,
+            RecordedRequest {
+                method: "GET".to_string(),
+                url,
+                headers: Vec::new(),
+                body: None,
+                body_truncated: false,
+            },
+        )
+    }
+
+    fn begin(&self, source: ExchangeSource, max_body_bytes: usize, request: RecordedRequest) -> Recording {
+        Recording {
+            active: Some(Box::new(ActiveRecording {
+                recorder: self.clone(),
+                id: self.state.next_id.fetch_add(1, Ordering::SeqCst),
+                started_at: Utc::now(),
+                started: Instant::now(),
+                source,
+                max_body_bytes,
+                request,
+                wait: None,
+                response: None,
+                pending: Vec::new(),
+            })),
+        }
+    }
+
+    fn push(&self, exchange: RecordedExchange) {
+        let capacity = self.state.settings.read().unwrap().capacity;
+        let mut exchanges = self.state.exchanges.lock().unwrap();
+        exchanges.push_back(exchange);
+        while exchanges.len() > capacity {
+            exchanges.pop_front();
+        }
+    }
+
+    pub fn export_har(&self) -> Har {
+        Har {
+            log: HarLog {
+                version: "1.2".to_string(),
+                creator: HarCreator {
+                    name: "OpenChat".to_string(),
+                    version: env!("CARGO_PKG_VERSION").to_string(),
+                },
+                entries: self.exchanges().iter().map(HarEntry::from).collect(),
+            },
+        }
+    }
+
+    // Serves the given HAR (or the current buffer) on a loopback port
+    pub async fn start_replay(&self, har: Option<Har>, port: Option<u16>) -> Result<ReplayServerInfo, CommandError> {
+        let har = har.unwrap_or_else(|| self.export_har());
+        let entries: Vec<ReplayEntry> = har.log.entries.iter().filter_map(ReplayEntry::from_har).collect();
+        if entries.is_empty() {
+            return Err(CommandError::new(ErrorKind::InvalidRequest, "No recorded responses to replay"));
+        }
+
+        self.stop_replay();
+
+        let listener = TcpListener::bind(("127.0.0.1", port.unwrap_or(0)))
+            .await
+            .map_err(|err| CommandError::new(ErrorKind::Internal, format!("Failed to start replay server: {err}")))?;
+        let addr = listener
+            .local_addr()
+            .map_err(|err| CommandError::new(ErrorKind::Internal, format!("Failed to start replay server: {err}")))?;
+        let url = format!("http://{}", addr);
+
+        let info = ReplayServerInfo { url: url.clone(), entries: entries.len() };
+        let (abort, registration) = AbortHandle::new_pair();
+        tauri::async_runtime::spawn(Abortable::new(serve_replay(listener, Arc::new(entries)), registration));
+
+        eprintln!("[Recorder] Replaying {} exchanges on {}", info.entries, url);
+        *self.state.replay.lock().unwrap() = Some(ReplayServer { url, abort });
+        Ok(info)
+    }
+
+    pub fn stop_replay(&self) -> bool {
+        match self.state.replay.lock().unwrap().take() {
+            Some(server) => {
+                eprintln!("[Recorder] Stopping replay server on {}", server.url);
+                server.abort.abort();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+struct ActiveRecording {
+    recorder: Recorder,
+    id: u64,
+    started_at: DateTime<Utc>,
+    started: Instant,
+    source: ExchangeSource,
+    max_body_bytes: usize,
+    request: RecordedRequest,
+    wait: Option<Duration>,
+    response: Option<RecordedResponse>,
+    // Bytes of a character split across body chunks
+    pending: Vec<u8>,
+}
+
+// One exchange being recorded. Every method is a no-op while the recorder
+// is disabled, so call sites don't need to check.
+pub struct Recording {
+    active: Option<Box<ActiveRecording>>,
+}
+
+impl Recording {
+    pub fn response(&mut self, response: &reqwest::Response) {
+        let status = response.status();
+        let status_text = status.canonical_reason().unwrap_or("").to_string();
+        self.response_parts(status.as_u16(), status_text, redact_headers(response.headers()));
+    }
+
+    // Browser loads have no status or headers to capture
+    pub fn browser_response(&mut self) {
+        self.response_parts(0, "captured by headless browser".to_string(), Vec::new());
+    }
+
+    fn response_parts(&mut self, status: u16, status_text: String, headers: Vec<HeaderEntry>) {
+        if let Some(active) = self.active.as_mut() {
+            active.wait = Some(active.started.elapsed());
+            active.response = Some(RecordedResponse {
+                status,
+                status_text,
+                headers,
+                body: String::new(),
+                body_truncated: false,
+            });
+        }
+    }
+
+    // Appends response text; streamed bodies are appended chunk by chunk
+    pub fn body(&mut self, text: &str) {
+        let Some(active) = self.active.as_mut() else {
+            return;
+        };
+        let max = active.max_body_bytes;
+        if let Some(response) = active.response.as_mut() {
+            if response.body_truncated {
+                return;
+            }
+            let (text, truncated) = truncate(text, max.saturating_sub(response.body.len()));
+            response.body.push_str(&text);
+            response.body_truncated = truncated;
+        }
+    }
+
+    // Appends raw response bytes; a character split across chunks is kept
+    // until the rest of it arrives
+    pub fn body_bytes(&mut self, bytes: &[u8]) {
+        let Some(active) = self.active.as_mut() else {
+            return;
+        };
+        active.pending.extend_from_slice(bytes);
+        let text = take_utf8_prefix(&mut active.pending);
+        self.body(&text);
+    }
+
+    // Records the outcome of a whole request
+    pub fn finish_with<T>(mut self, result: &Result<T, CommandError>) {
+        self.complete(result.as_ref().err().cloned());
+    }
+
+    fn complete(&mut self, error: Option<CommandError>) {
+        // A stream that ended mid-character
+        let pending = self.active.as_mut().map(|active| std::mem::take(&mut active.pending)).unwrap_or_default();
+        if !pending.is_empty() {
+            self.body(&String::from_utf8_lossy(&pending));
+        }
+        let Some(active) = self.active.take() else {
+            return;
+        };
+        let active = *active;
+        active.recorder.push(RecordedExchange {
+            id: active.id,
+            started_at: active.started_at.to_rfc3339(),
+            source: active.source,
+            duration_ms: active.started.elapsed().as_millis() as u64,
+            wait_ms: active.wait.map(|wait| wait.as_millis() as u64),
+            request: active.request,
+            response: active.response,
+            error,
+        });
+    }
+}
+
+// A recording dropped before it finished belongs to a cancelled request
+impl Drop for Recording {
+    fn drop(&mut self) {
+        self.complete(Some(CommandError::cancelled()));
+    }
+}
+
+// HAR 1.2 (http://www.softwareishard.com/blog/har-12-spec/). Fields other
+// tools leave out are defaulted so their exports can be replayed too.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Har {
+    pub log: HarLog,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct HarLog {
+    pub version: String,
+    pub creator: HarCreator,
+    pub entries: Vec<HarEntry>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct HarCreator {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarEntry {
+    pub started_date_time: String,
+    pub time: f64,
+    pub request: HarRequest,
+    pub response: HarResponse,
+    #[serde(default)]
+    pub cache: serde_json::Map<String, serde_json::Value>,
+    #[serde(default)]
+    pub timings: HarTimings,
+    #[serde(rename = "_source", default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(rename = "_error", default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarRequest {
+    pub method: String,
+    pub url: String,
+    #[serde(default)]
+    pub http_version: String,
+    #[serde(default)]
+    pub cookies: Vec<serde_json::Value>,
+    #[serde(default)]
+    pub headers: Vec<HeaderEntry>,
+    #[serde(default)]
+    pub query_string: Vec<HeaderEntry>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_data: Option<HarPostData>,
+    #[serde(default)]
+    pub headers_size: i64,
+    #[serde(default)]
+    pub body_size: i64,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarPostData {
+    #[serde(default)]
+    pub mime_type: String,
+    #[serde(default)]
+    pub text: String,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarResponse {
+    pub status: u16,
+    #[serde(default)]
+    pub status_text: String,
+    #[serde(default)]
+    pub http_version: String,
+    #[serde(default)]
+    pub cookies: Vec<serde_json::Value>,
+    #[serde(default)]
+    pub headers: Vec<HeaderEntry>,
+    pub content: HarContent,
+    #[serde(rename = "redirectURL", default)]
+    pub redirect_url: String,
+    #[serde(default)]
+    pub headers_size: i64,
+    #[serde(default)]
+    pub body_size: i64,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarContent {
+    pub size: i64,
+    #[serde(default)]
+    pub mime_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct HarTimings {
+    pub send: f64,
+    pub wait: f64,
+    pub receive: f64,
+}
+
+fn header_value<'a>(headers: &'a [HeaderEntry], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|h| h.name.eq_ignore_ascii_case(name))
+        .map(|h| h.value.as_str())
+}
+
+impl From<&RecordedExchange> for HarEntry {
+    fn from(exchange: &RecordedExchange) -> Self {
+        let request = &exchange.request;
+        let query_string = Url::parse(&request.url)
+            .map(|url| {
+                url.query_pairs()
+                    .map(|(name, value)| HeaderEntry { name: name.into_owned(), value: value.into_owned() })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let post_data = request.body.as_ref().map(|body| HarPostData {
+            mime_type: header_value(&request.headers, "content-type").unwrap_or("").to_string(),
+            text: body.clone(),
+        });
+
+        // A failed request is exported with status 0, as browsers do
+        let response = match &exchange.response {
+            Some(response) => HarResponse {
+                status: response.status,
+                status_text: response.status_text.clone(),
+                http_version: "HTTP/1.1".to_string(),
+                cookies: Vec::new(),
+                headers: response.headers.clone(),
+                content: HarContent {
+                    size: response.body.len() as i64,
+                    mime_type: header_value(&response.headers, "content-type").unwrap_or("").to_string(),
+                    text: Some(response.body.clone()),
+                    comment: response.body_truncated.then(|| "body truncated by the recorder".to_string()),
+                },
+                redirect_url: String::new(),
+                headers_size: -1,
+                body_size: response.body.len() as i64,
+            },
+            None => HarResponse {
+                status: 0,
+                status_text: String::new(),
+                http_version: "HTTP/1.1".to_string(),
+                cookies: Vec::new(),
+                headers: Vec::new(),
+                content: HarContent { size: 0, mime_type: String::new(), text: None, comment: None },
+                redirect_url: String::new(),
+                headers_size: -1,
+                body_size: -1,
+            },
+        };
+
+        let wait = exchange.wait_ms.unwrap_or(exchange.duration_ms) as f64;
+        HarEntry {
+            started_date_time: exchange.started_at.clone(),
+            time: exchange.duration_ms as f64,
+            request: HarRequest {
+                method: request.method.clone(),
+                url: request.url.clone(),
+                http_version: "HTTP/1.1".to_string(),
+                cookies: Vec::new(),
+                headers: request.headers.clone(),
+                query_string,
+                post_data,
+                headers_size: -1,
+                body_size: request.body.as_ref().map_or(0, |body| body.len() as i64),
+            },
+            response,
+            cache: serde_json::Map::new(),
+            timings: HarTimings {
+                send: 0.0,
+                wait,
+                receive: exchange.duration_ms as f64 - wait,
+            },
+            source: serde_json::to_value(exchange.source)
+                .ok()
+                .and_then(|v| v.as_str().map(|s| s.to_string())),
+            error: exchange.error.as_ref().map(|err| err.message.clone()),
+        }
+    }
+}
+
+struct ReplayEntry {
+    method: String,
+    path: String,
+    path_and_query: String,
+    status: u16,
+    status_text: String,
+    headers: Vec<HeaderEntry>,
+    body: String,
+    served: AtomicBool,
+}
+
+impl ReplayEntry {
+    // Entries without a response (failed requests) can't be replayed
+    fn from_har(entry: &HarEntry) -> Option<Self> {
+        if entry.response.status == 0 {
+            return None;
+        }
+        let url = Url::parse(&entry.request.url).ok()?;
+        let path_and_query = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        Some(Self {
+            method: entry.request.method.to_uppercase(),
+            path: url.path().to_string(),
+            path_and_query,
+            status: entry.response.status,
+            status_text: entry.response.status_text.clone(),
+            headers: entry.response.headers.clone(),
+            body: entry.response.content.text.clone().unwrap_or_default(),
+            served: AtomicBool::new(false),
+        })
+    }
+}
+
+// Matches in recording order so repeated calls get successive responses;
+// once all are served the last match keeps being returned
+fn find_replay_entry<'a>(entries: &'a [ReplayEntry], method: &str, target: &str) -> Option<&'a ReplayEntry> {
+    let path = target.split('?').next().unwrap_or(target);
+    let exact: Vec<&ReplayEntry> = entries
+        .iter()
+        .filter(|e| e.method == method && e.path_and_query == target)
+        .collect();
+    let candidates = if exact.is_empty() {
+        entries.iter().filter(|e| e.method == method && e.path == path).collect()
+    } else {
+        exact
+    };
+
+    if let Some(entry) = candidates.iter().find(|e| !e.served.swap(true, Ordering::SeqCst)) {
+        return Some(entry);
+    }
+    candidates.last().copied()
+}
+
+async fn serve_replay(listener: TcpListener, entries: Arc<Vec<ReplayEntry>>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                eprintln!("[Recorder] Replay server accept failed: {}", err);
+                continue;
+            }
+        };
+        let entries = entries.clone();
+        tauri::async_runtime::spawn(async move {
+            if let Err(err) = handle_replay_connection(stream, &entries).await {
+                eprintln!("[Recorder] Replay connection failed: {}", err);
+            }
+        });
+    }
+}
+
+// Minimal HTTP/1.1 handling: one request per connection, then close
+async fn handle_replay_connection(mut stream: TcpStream, entries: &[ReplayEntry]) -> std::io::Result<()> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 8192];
+    let head_end = loop {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+        if let Some(pos) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        if buffer.len() > MAX_REQUEST_HEAD {
+            return Ok(());
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..head_end]).into_owned();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or("").split_whitespace();
+    let method = request_line.next().unwrap_or("").to_uppercase();
+    let mut target = request_line.next().unwrap_or("/").to_string();
+    // Absolute-form targets are sent when the client goes through a proxy
+    if let Ok(url) = Url::parse(&target) {
+        target = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+    }
+
+    // Drain the request body so the client doesn't see a reset
+    let content_length = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    let mut remaining = content_length.saturating_sub(buffer.len() - head_end);
+    while remaining > 0 {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            break;
+        }
+        remaining = remaining.saturating_sub(read);
+    }
+
+    let mut response = Vec::new();
+    match find_replay_entry(entries, &method, &target) {
+        Some(entry) => {
+            response.extend_from_slice(format!("HTTP/1.1 {} {}\r\n", entry.status, entry.status_text).as_bytes());
+            for header in &entry.headers {
+                // The body is sent decoded and in one piece
+                if ["content-length", "transfer-encoding", "content-encoding", "connection"]
+                    .iter()
+                    .any(|skip| header.name.eq_ignore_ascii_case(skip))
+                {
+                    continue;
+                }
+                response.extend_from_slice(format!("{}: {}\r\n", header.name, header.value).as_bytes());
+            }
+            response.extend_from_slice(
+                format!("Content-Length: {}\r\nConnection: close\r\n\r\n", entry.body.len()).as_bytes(),
+            );
+            response.extend_from_slice(entry.body.as_bytes());
+        }
+        None => {
+            eprintln!("[Recorder] Replay {} {} -> no recorded response", method, target);
+            let body = format!("{{\"error\":\"No recorded response for {} {}\"}}", method, target.replace('"', "'"));
+            response.extend_from_slice(
+                format!(
+                    "HTTP/1.1 404 Not Found\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                )
+                .as_bytes(),
+            );
+            response.extend_from_slice(body.as_bytes());
+        }
+    }
+
+    stream.write_all(&response).await?;
+    stream.shutdown().await
+}
+
+#[tauri::command]
+pub fn get_recorder_settings(recorder: tauri::State<'_, Recorder>) -> RecorderSettings {
+    recorder.settings()
+}
+
+#[tauri::command]
+pub fn update_recorder_settings(settings: RecorderSettings, recorder: tauri::State<'_, Recorder>) {
+    recorder.update(settings);
+}
+
+#[tauri::command]
+pub fn get_recorded_exchanges(recorder: tauri::State<'_, Recorder>) -> Vec<RecordedExchange> {
+    recorder.exchanges()
+}
+
+#[tauri::command]
+pub fn clear_recorded_exchanges(recorder: tauri::State<'_, Recorder>) {
+    recorder.clear();
+}
+
+#[tauri::command]
+pub fn export_har(recorder: tauri::State<'_, Recorder>) -> Har {
+    recorder.export_har()
+}
+
+// Replays `har` (or the recorded buffer when omitted) on 127.0.0.1; point a
+// provider's base URL at the returned address to reproduce a session
+#[tauri::command]
+pub async fn start_replay_server(
+    har: Option<Har>,
+    port: Option<u16>,
+    recorder: tauri::State<'_, Recorder>,
+) -> Result<ReplayServerInfo, CommandError> {
+    recorder.start_replay(har, port).await
+}
+
+#[tauri::command]
+pub fn stop_replay_server(recorder: tauri::State<'_, Recorder>) -> bool {
+    recorder.stop_replay()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recorder(max_body_bytes: usize) -> Recorder {
+        Recorder::new(RecorderSettings { enabled: true, capacity: 10, max_body_bytes })
+    }
+
+    fn post(url: &str, content_type: &str, body: &str) -> reqwest::Request {
+        reqwest::Client::new()
+            .post(url)
+            .header("Authorization", "Bearer sk-secret")
+            .header("X-Api-Key", "sk-secret")
+            .header("Content-Type", content_type)
+            .body(body.to_string())
+            .build()
+            .unwrap()
+    }
+
+    fn record(recorder: &Recorder, request: &reqwest::Request, chunks: &[&[u8]]) -> RecordedExchange {
+        let mut recording = recorder.start(ExchangeSource::Proxy, request);
+        recording.response_parts(
+            200,
+            "OK".to_string(),
+            vec![HeaderEntry { name: "content-type".to_string(), value: "text/event-stream".to_string() }],
+        );
+        for chunk in chunks {
+            recording.body_bytes(chunk);
+        }
+        recording.finish_with(&Ok::<(), CommandError>(()));
+        recorder.exchanges().pop().unwrap()
+    }
+
+    #[test]
+    fn secrets_are_redacted_from_headers_and_urls() {
+        let recorder = recorder(1024);
+        let request = post("https://api.example.com/v1?key=sk-secret&alt=sse", "application/json", "{}");
+        let exchange = record(&recorder, &request, &[]);
+
+        let header = |name: &str| header_value(&exchange.request.headers, name).unwrap().to_string();
+        assert_eq!(header("authorization"), REDACTED);
+        assert_eq!(header("x-api-key"), REDACTED);
+        assert_eq!(header("content-type"), "application/json");
+        assert_eq!(exchange.request.url, "https://api.example.com/v1?key=%5Bredacted%5D&alt=sse");
+    }
+
+    #[test]
+    fn secrets_are_redacted_from_request_bodies() {
+        let recorder = recorder(1024);
+        recorder.set_secret("proxyPassword", Some("hunter2"));
+
+        let body = r#"{"model":"m","apiKey":"sk-secret","auth":[{"password":"p"}],"messages":[{"content":"hunter2"}]}"#;
+        let exchange = record(&recorder, &post("https://api.example.com/", "application/json", body), &[]);
+        let recorded = exchange.request.body.unwrap();
+        assert!(!recorded.contains("sk-secret") && !recorded.contains("\"p\"") && !recorded.contains("hunter2"));
+        let json: serde_json::Value = serde_json::from_str(&recorded).unwrap();
+        assert_eq!(json["model"], "m");
+        assert_eq!(json["apiKey"], REDACTED);
+        assert_eq!(json["auth"][0]["password"], REDACTED);
+
+        let form = "grant_type=password&client_secret=abc&api_key=sk-secret";
+        let request = post("https://auth.example.com/", "application/x-www-form-urlencoded", form);
+        let recorded = record(&recorder, &request, &[]).request.body.unwrap();
+        assert_eq!(recorded, "grant_type=password&client_secret=%5Bredacted%5D&api_key=%5Bredacted%5D");
+
+        recorder.set_secret("proxyPassword", None);
+        let request = post("https://api.example.com/", "text/plain", "hunter2");
+        assert_eq!(record(&recorder, &request, &[]).request.body.unwrap(), "hunter2");
+    }
+
+    #[test]
+    fn bodies_are_truncated_on_character_boundaries() {
+        assert_eq!(truncate("h\u{e9}llo", 2), ("h".to_string(), true));
+        assert_eq!(truncate("hello", 5), ("hello".to_string(), false));
+
+        let recorder = recorder(6);
+        let request = post("https://api.example.com/", "text/plain", "0123456789");
+        let exchange = record(&recorder, &request, &[b"abcd", b"efgh", b"ijkl"]);
+        assert_eq!(exchange.request.body.as_deref(), Some("012345"));
+        assert!(exchange.request.body_truncated);
+        let response = exchange.response.unwrap();
+        assert_eq!(response.body, "abcdef");
+        assert!(response.body_truncated);
+    }
+
+    #[test]
+    fn split_characters_are_kept_whole() {
+        let recorder = recorder(1024);
+        let request = post("https://api.example.com/", "application/json", "{}");
+        let text = "data: h\u{e9}llo \u{1f600}\n\n".as_bytes();
+        let chunks: Vec<&[u8]> = text.chunks(1).collect();
+        let exchange = record(&recorder, &request, &chunks);
+        assert_eq!(exchange.response.unwrap().body, "data: h\u{e9}llo \u{1f600}\n\n");
+
+        // A stream that stops mid-character still keeps what it got
+        let exchange = record(&recorder, &request, &[b"ok \xf0\x9f"]);
+        assert_eq!(exchange.response.unwrap().body, "ok \u{fffd}");
+    }
+
+    #[test]
+    fn har_export_follows_the_1_2_shape() {
+        let recorder = recorder(1024);
+        let request = post("https://api.example.com/v1/chat?alt=sse", "application/json", r#"{"model":"m"}"#);
+        record(&recorder, &request, &[b"data: hi\n\n"]);
+        // Dropped without finishing: a cancelled request without a response
+        drop(recorder.start(ExchangeSource::Fetch, &request));
+
+        let har = serde_json::to_value(recorder.export_har()).unwrap();
+        let log = &har["log"];
+        assert_eq!(log["version"], "1.2");
+        assert_eq!(log["creator"]["name"], "OpenChat");
+        let entries = log["entries"].as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+
+        let entry = &entries[0];
+        for field in ["startedDateTime", "time", "request", "response", "cache", "timings"] {
+            assert!(entry.get(field).is_some(), "{field}");
+        }
+        assert_eq!(entry["_source"], "proxy");
+        assert_eq!(entry["request"]["method"], "POST");
+        assert_eq!(entry["request"]["httpVersion"], "HTTP/1.1");
+        assert_eq!(entry["request"]["queryString"][0]["name"], "alt");
+        assert_eq!(entry["request"]["postData"]["mimeType"], "application/json");
+        assert_eq!(entry["request"]["postData"]["text"], r#"{"model":"m"}"#);
+        assert_eq!(entry["response"]["status"], 200);
+        assert_eq!(entry["response"]["content"]["mimeType"], "text/event-stream");
+        assert_eq!(entry["response"]["content"]["text"], "data: hi\n\n");
+        assert_eq!(entry["response"]["content"]["size"], 10);
+        assert_eq!(entry["response"]["redirectURL"], "");
+        assert!(entry["timings"]["wait"].is_number());
+
+        let cancelled = &entries[1];
+        assert_eq!(cancelled["response"]["status"], 0);
+        assert_eq!(cancelled["_source"], "fetch");
+        assert!(cancelled["_error"].is_string());
+
+        // What was exported can be read back for replay
+        let har: Har = serde_json::from_value(har).unwrap();
+        assert_eq!(har.log.entries.iter().filter_map(ReplayEntry::from_har).count(), 1);
+    }
+
+    fn replay_entry(method: &str, url: &str, body: &str) -> ReplayEntry {
+        let entry: HarEntry = serde_json::from_value(serde_json::json!({
+            "startedDateTime": "2024-01-01T00:00:00Z",
+            "time": 1.0,
+            "request": { "method": method, "url": url },
+            "response": { "status": 200, "content": { "size": body.len(), "text": body } },
+        }))
+        .unwrap();
+        ReplayEntry::from_har(&entry).unwrap()
+    }
+
+    #[test]
+    fn replay_matches_in_recording_order() {
+        let entries = vec![
+            replay_entry("post", "http://x/v1/chat?alt=sse", "sse"),
+            replay_entry("POST", "http://x/v1/chat", "first"),
+            replay_entry("POST", "http://x/v1/chat", "second"),
+            replay_entry("GET", "http://x/v1/models", "models"),
+        ];
+        let body = |method: &str, target: &str| find_replay_entry(&entries, method, target).map(|e| e.body.as_str());
+
+        // An exact path and query match wins over a path match
+        assert_eq!(body("POST", "/v1/chat?alt=sse"), Some("sse"));
+        // Path matches are served in order; the last one repeats
+        assert_eq!(body("POST", "/v1/chat"), Some("first"));
+        assert_eq!(body("POST", "/v1/chat?other=1"), Some("second"));
+        assert_eq!(body("POST", "/v1/chat"), Some("second"));
+        assert_eq!(body("GET", "/v1/models"), Some("models"));
+        assert_eq!(body("GET", "/v1/chat"), None);
+        assert_eq!(body("POST", "/v1/embeddings"), None);
+    }
+}
@@ -0,0 +1,251 @@
+// Incremental Server-Sent Events decoder
+// (https://html.spec.whatwg.org/multipage/server-sent-events.html).
+// Bytes go in as they arrive; complete events come out. Handles LF, CRLF and
+// CR line endings split across chunks, multi-line data fields and comments.
+use crate::error::CommandError;
+use crate::recorder::Recording;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SseEvent {
+    // "message" unless the server named the event
+    pub event: String,
+    pub data: String,
+    pub id: Option<String>,
+}
+
+#[derive(Default)]
+pub struct SseDecoder {
+    buffer: Vec<u8>,
+    event: Option<String>,
+    data: Option<String>,
+    id: Option<String>,
+    // The first line of a stream may start with a byte order mark
+    started: bool,
+}
+
+impl SseDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Feeds a chunk and returns the events it completed
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+        self.drain(false)
+    }
+
+    // Ends the stream. A trailing event without its blank line is dispatched
+    // anyway; servers that drop the connection early would otherwise lose it.
+    pub fn finish(&mut self) -> Vec<SseEvent> {
+        let mut events = self.drain(true);
+        if !self.buffer.is_empty() {
+            let line = std::mem::take(&mut self.buffer);
+            self.line(&line, &mut events);
+        }
+        events.extend(self.dispatch());
+        events
+    }
+
+    fn drain(&mut self, at_end: bool) -> Vec<SseEvent> {
+        let mut events = Vec::new();
+        let mut start = 0;
+        let mut index = 0;
+
+        while index < self.buffer.len() {
+            match self.buffer[index] {
+                b'\n' => {
+                    let line = self.buffer[start..index].to_vec();
+                    self.line(&line, &mut events);
+                    index += 1;
+                    start = index;
+                }
+                b'\r' => {
+                    // A CR at the end of a chunk may be the first half of a CRLF
+                    if index + 1 == self.buffer.len() && !at_end {
+                        break;
+                    }
+                    let line = self.buffer[start..index].to_vec();
+                    self.line(&line, &mut events);
+                    index += if self.buffer.get(index + 1) == Some(&b'\n') { 2 } else { 1 };
+                    start = index;
+                }
+                _ => index += 1,
+            }
+        }
+
+        self.buffer.drain(..start);
+        events
+    }
+
+    fn line(&mut self, line: &[u8], events: &mut Vec<SseEvent>) {
+        let mut line = String::from_utf8_lossy(line).into_owned();
+        if !self.started {
+            self.started = true;
+            if let Some(rest) = line.strip_prefix('\u{feff}') {
+                line = rest.to_string();
+            }
+        }
+
+        if line.is_empty() {
+            events.extend(self.dispatch());
+            return;
+        }
+        // Comments, used by most servers as keep-alives
+        if line.starts_with(':') {
+            return;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line.as_str(), ""),
+        };
+        match field {
+            "event" => self.event = Some(value.to_string()),
+            // Consecutive data lines form one value, joined by newlines
+            "data" => match self.data.as_mut() {
+                Some(data) => {
+                    data.push('\n');
+                    data.push_str(value);
+                }
+                None => self.data = Some(value.to_string()),
+            },
+            "id" if !value.contains('\0') => self.id = Some(value.to_string()),
+            // "retry" only matters to EventSource reconnects
+            _ => {}
+        }
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = self.event.take();
+        let data = self.data.take()?;
+        Some(SseEvent {
+            event: event.filter(|e| !e.is_empty()).unwrap_or_else(|| "message".to_string()),
+            data,
+            id: self.id.clone(),
+        })
+    }
+}
+
+// Reads an event stream response to the end, handing each event to
+// `on_event`. Returning Ok(false) stops reading, for servers that keep the
+// connection open after their final event.
+pub async fn read_events(
+    response: &mut reqwest::Response,
+    recording: &mut Recording,
+    mut on_event: impl FnMut(SseEvent) -> Result<bool, CommandError>,
+) -> Result<(), CommandError> {
+    let mut decoder = SseDecoder::new();
+    loop {
+        let chunk = response
+            .chunk()
+            .await
+            .map_err(|err| CommandError::from_reqwest(err, "Failed to read response"))?;
+        let finished = chunk.is_none();
+        let events = match &chunk {
+            Some(bytes) => {
+                recording.body_bytes(bytes);
+                decoder.push(bytes)
+            }
+            None => decoder.finish(),
+        };
+
+        for event in events {
+            if !on_event(event)? {
+                return Ok(());
+            }
+        }
+        if finished {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(chunks: &[&[u8]]) -> Vec<SseEvent> {
+        let mut decoder = SseDecoder::new();
+        let mut events = Vec::new();
+        for chunk in chunks {
+            events.extend(decoder.push(chunk));
+        }
+        events.extend(decoder.finish());
+        events
+    }
+
+    fn data(events: &[SseEvent]) -> Vec<&str> {
+        events.iter().map(|event| event.data.as_str()).collect()
+    }
+
+    #[test]
+    fn line_endings() {
+        for text in ["data: a\n\ndata: b\n\n", "data: a\r\n\r\ndata: b\r\n\r\n", "data: a\r\rdata: b\r\r"] {
+            assert_eq!(data(&decode(&[text.as_bytes()])), ["a", "b"], "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn crlf_split_across_chunks() {
+        // The CR ending one chunk and the LF starting the next are one line break
+        let events = decode(&[b"data: a\r", b"\n\r", b"\ndata: b\r\n", b"\r\n"]);
+        assert_eq!(data(&events), ["a", "b"]);
+    }
+
+    #[test]
+    fn byte_order_mark() {
+        let events = decode(&[b"\xef\xbb", b"\xbfdata: a\n\n"]);
+        assert_eq!(data(&events), ["a"]);
+        // Only at the very start of the stream
+        let events = decode(&[b"data: a\n\n\xef\xbb\xbfdata: b\n\n"]);
+        assert_eq!(data(&events), ["a"]);
+    }
+
+    #[test]
+    fn comments_are_skipped() {
+        let events = decode(&[b": keep-alive\n\n:\ndata: a\n: between\ndata: b\n\n"]);
+        assert_eq!(data(&events), ["a\nb"]);
+    }
+
+    #[test]
+    fn fields() {
+        let events = decode(&[b"event: message_start\nid: 7\ndata:{\"a\":1}\nretry: 100\n\ndata\n\n"]);
+        assert_eq!(
+            events,
+            [
+                SseEvent {
+                    event: "message_start".to_string(),
+                    data: "{\"a\":1}".to_string(),
+                    id: Some("7".to_string()),
+                },
+                // The id carries over; the event name doesn't
+                SseEvent {
+                    event: "message".to_string(),
+                    data: String::new(),
+                    id: Some("7".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn events_split_across_chunks() {
+        let text = "event: delta\ndata: {\"text\":\"h\u{e9}llo\"}\n\ndata: [DONE]\n\n".as_bytes();
+        let chunks: Vec<&[u8]> = text.chunks(3).collect();
+        let events = decode(&chunks);
+        assert_eq!(data(&events), ["{\"text\":\"h\u{e9}llo\"}", "[DONE]"]);
+        assert_eq!(events[0].event, "delta");
+    }
+
+    #[test]
+    fn events_without_data_are_dropped() {
+        assert!(decode(&[b"event: ping\n\n"]).is_empty());
+    }
+
+    #[test]
+    fn trailing_event_without_blank_line() {
+        let mut decoder = SseDecoder::new();
+        assert!(decoder.push(b"data: a\ndata: b").is_empty());
+        assert_eq!(data(&decoder.finish()), ["a\nb"]);
+    }
+}
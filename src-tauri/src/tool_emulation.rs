@@ -0,0 +1,620 @@
+// Tool calling for models without native support. The tool definitions and
+// a calling convention go into the system prompt, earlier calls and results
+// are written out as text, and calls are parsed out of the streamed reply
+// and reported as ordinary tool call events. Dialects:
+//   hermes       <tool_call>{"name": ..., "arguments": {...}}</tool_call>
+//   xml          <invoke name="..."><parameter name="...">...</parameter></invoke>
+//   fenced_json  {"name": ..., "arguments": {...}} in a ```tool_call block
+//   json         the whole reply is one {"name": ..., "arguments": {...}}
+//                object; llama.cpp enforces it with a grammar (gbnf.rs)
+// `auto` picks one from the model name.
+use serde_json::{json, Value};
+
+use crate::chat::{ChatMessage, ChatRequest};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolDialect {
+    Auto,
+    Hermes,
+    Xml,
+    FencedJson,
+    Json,
+}
+
+// Where a call starts and ends in the reply. Markers without an end are
+// wrappers that are dropped from the text.
+struct Marker {
+    open: &'static str,
+    close: Option<&'static str>,
+}
+
+const HERMES_MARKERS: &[Marker] = &[Marker { open: "<tool_call>", close: Some("</tool_call>") }];
+const XML_MARKERS: &[Marker] = &[
+    Marker { open: "<invoke", close: Some("</invoke>") },
+    Marker { open: "<function_calls>", close: None },
+    Marker { open: "</function_calls>", close: None },
+];
+// Models often label the block json instead; those only count as calls
+// when they name a known tool
+const FENCED_MARKERS: &[Marker] = &[
+    Marker { open: "```tool_call", close: Some("```") },
+    Marker { open: "```json", close: Some("```") },
+];
+
+impl ToolDialect {
+    // Models trained on a format do best with it
+    pub fn resolve(self, model: &str) -> Self {
+        if self != Self::Auto {
+            return self;
+        }
+        let model = model.to_lowercase();
+        if ["qwen", "hermes", "nous"].iter().any(|family| model.contains(family)) {
+            Self::Hermes
+        } else {
+            Self::FencedJson
+        }
+    }
+
+    fn markers(self) -> &'static [Marker] {
+        match self {
+            Self::Hermes => HERMES_MARKERS,
+            Self::Xml => XML_MARKERS,
+            Self::Auto | Self::FencedJson => FENCED_MARKERS,
+            Self::Json => &[],
+        }
+    }
+
+    fn instructions(self) -> &'static str {
+        match self {
+            Self::Hermes => {
+                "To call a tool, write a JSON object with its name and arguments inside <tool_call></tool_call> tags:\n\
+                 <tool_call>\n{\"name\": \"<tool name>\", \"arguments\": {<arguments>}}\n</tool_call>\n\
+                 You can make several calls in one reply. Results come back inside <tool_response></tool_response> tags."
+            }
+            Self::Xml => {
+                "To call a tool, write an invoke block with one parameter element per argument:\n\
+                 <invoke name=\"<tool name>\">\n<parameter name=\"<argument name>\"><value></parameter>\n</invoke>\n\
+                 You can make several calls in one reply. Results come back inside <result></result> tags."
+            }
+            Self::Auto | Self::FencedJson => {
+                "To call a tool, write a JSON object with its name and arguments in a tool_call code block:\n\
+                 ```tool_call\n{\"name\": \"<tool name>\", \"arguments\": {<arguments>}}\n```\n\
+                 You can make several calls in one reply. Results come back in messages starting with \"Tool result for\"."
+            }
+            Self::Json => {
+                "To call a tool, reply with only a JSON object of the form \
+                 {\"name\": <tool name>, \"arguments\": <arguments object>}, with nothing before or after it. \
+                 Results come back in messages starting with \"Tool result for\"."
+            }
+        }
+    }
+
+    fn format_call(self, name: &str, arguments: &str) -> String {
+        let arguments: Value = serde_json::from_str(arguments).unwrap_or_else(|_| json!({}));
+        let call = json!({ "name": name, "arguments": arguments }).to_string();
+        match self {
+            Self::Hermes => format!("<tool_call>\n{}\n</tool_call>", call),
+            Self::Xml => {
+                let mut block = format!("<invoke name=\"{}\">\n", name);
+                for (key, value) in arguments.as_object().into_iter().flatten() {
+                    let value = value.as_str().map_or_else(|| value.to_string(), str::to_string);
+                    block.push_str(&format!("<parameter name=\"{}\">{}</parameter>\n", key, value));
+                }
+                block.push_str("</invoke>");
+                block
+            }
+            Self::Auto | Self::FencedJson => format!("```tool_call\n{}\n```", call),
+            Self::Json => call,
+        }
+    }
+
+    fn format_result(self, name: &str, content: &str) -> String {
+        match self {
+            Self::Hermes => format!(
+                "<tool_response>\n{}\n</tool_response>",
+                json!({ "name": name, "content": content })
+            ),
+            Self::Xml => format!("<result name=\"{}\">\n{}\n</result>", name, content),
+            Self::Auto | Self::FencedJson | Self::Json => format!("Tool result for {}:\n{}", name, content),
+        }
+    }
+}
+
+// The dialect to emulate tool calls in, if the request has tools: the one
+// it asks for, else `default`
+pub fn dialect_for(request: &ChatRequest, default: Option<ToolDialect>) -> Option<ToolDialect> {
+    if request.tools.is_empty() {
+        return None;
+    }
+    request.tool_dialect.or(default).map(|dialect| dialect.resolve(&request.model))
+}
+
+fn system_prompt(dialect: ToolDialect, tools: &[Value]) -> String {
+    let mut prompt = format!("You can call the tools listed below. {}\n\nTools:", dialect.instructions());
+    for tool in tools {
+        let (name, description, parameters) = ChatRequest::tool_parts(tool);
+        prompt.push('\n');
+        prompt.push_str(&json!({ "name": name, "description": description, "parameters": parameters }).to_string());
+    }
+    prompt
+}
+
+fn text_message(role: &str, content: String) -> ChatMessage {
+    ChatMessage {
+        role: role.to_string(),
+        content,
+        images: Vec::new(),
+        tool_calls: Vec::new(),
+        tool_call_id: None,
+        reasoning_blocks: Vec::new(),
+    }
+}
+
+// The request without native tools: they're described in the system prompt
+// instead, and earlier calls and results become plain messages
+pub fn prepare(request: &ChatRequest, dialect: ToolDialect) -> ChatRequest {
+    let prompt = system_prompt(dialect, &request.tools);
+    let mut messages = Vec::new();
+    if request.messages.first().is_none_or(|message| message.role != "system") {
+        messages.push(text_message("system", prompt.clone()));
+    }
+
+    // Results of consecutive calls are sent as one message
+    let mut last_was_result = false;
+    for (i, message) in request.messages.iter().enumerate() {
+        let is_result = message.role == "tool";
+        let mut message = message.clone();
+        match message.role.as_str() {
+            "system" if i == 0 => message.content = format!("{}\n\n{}", message.content, prompt),
+            "assistant" if !message.tool_calls.is_empty() => {
+                let calls: Vec<String> = message
+                    .tool_calls
+                    .iter()
+                    .map(|call| dialect.format_call(&call.function.name, &call.function.arguments))
+                    .collect();
+                let calls = calls.join("\n");
+                message.content = if message.content.trim().is_empty() {
+                    calls
+                } else {
+                    format!("{}\n\n{}", message.content, calls)
+                };
+                message.tool_calls.clear();
+            }
+            "tool" => {
+                let name = request
+                    .messages
+                    .iter()
+                    .flat_map(|m| &m.tool_calls)
+                    .find(|call| message.tool_call_id.as_ref() == Some(&call.id))
+                    .map_or("the tool", |call| call.function.name.as_str());
+                let result = dialect.format_result(name, &message.content);
+                if let (true, Some(previous)) = (last_was_result, messages.last_mut()) {
+                    previous.content = format!("{}\n\n{}", previous.content, result);
+                    continue;
+                }
+                message = text_message("user", result);
+            }
+            _ => {}
+        }
+        last_was_result = is_result;
+        messages.push(message);
+    }
+
+    ChatRequest {
+        messages,
+        tools: Vec::new(),
+        tool_dialect: None,
+        ..request.clone()
+    }
+}
+
+pub enum Segment {
+    Text(String),
+    Call { id: String, name: String, arguments: String },
+}
+
+// Splits streamed reply text into text and tool calls. Text that could be
+// the start of a marker is held back until the next delta settles it.
+pub struct ToolCallParser {
+    dialect: ToolDialect,
+    // Tool names and parameter schemas
+    tools: Vec<(String, Value)>,
+    buffer: String,
+    // The marker of the call being read
+    open: Option<&'static Marker>,
+    // For the json dialect: whether the reply starts with `{`
+    json_reply: Option<bool>,
+    // Calls have no ids in the text, so they're made up per response
+    response_id: i64,
+    calls: usize,
+}
+
+// Length of the longest end of `text` that starts one of the markers
+fn partial_marker(text: &str, markers: &[Marker]) -> usize {
+    markers
+        .iter()
+        .filter_map(|marker| (1..marker.open.len()).rev().find(|&n| text.ends_with(&marker.open[..n])))
+        .max()
+        .unwrap_or(0)
+}
+
+// Value of the `name="..."` attribute in a tag
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.find(&format!("{}=\"", name))? + name.len() + 2;
+    let end = start + tag[start..].find('"')?;
+    Some(&tag[start..end])
+}
+
+impl ToolCallParser {
+    pub fn new(dialect: ToolDialect, tools: &[Value]) -> Self {
+        let tools = tools
+            .iter()
+            .filter_map(|tool| {
+                let (name, _, parameters) = ChatRequest::tool_parts(tool);
+                Some((name.as_str()?.to_string(), parameters))
+            })
+            .collect();
+        Self {
+            dialect,
+            tools,
+            buffer: String::new(),
+            open: None,
+            json_reply: None,
+            response_id: chrono::Utc::now().timestamp_millis(),
+            calls: 0,
+        }
+    }
+
+    fn is_tool(&self, name: &str) -> bool {
+        self.tools.iter().any(|(tool, _)| tool == name)
+    }
+
+    fn call(&mut self, name: String, arguments: Value) -> Segment {
+        let id = format!("call_{}_{}", self.response_id, self.calls);
+        self.calls += 1;
+        // Some models send the arguments as a JSON string
+        let arguments = match arguments {
+            Value::String(text) if serde_json::from_str::<Value>(&text).is_ok_and(|v| v.is_object()) => text,
+            Value::Null => "{}".to_string(),
+            arguments => arguments.to_string(),
+        };
+        Segment::Call { id, name, arguments }
+    }
+
+    // {"name": ..., "arguments": ...}; some models say "parameters"
+    fn json_call(&mut self, body: &str, known_only: bool) -> Option<Segment> {
+        let call: Value = serde_json::from_str(body.trim()).ok()?;
+        let name = call.get("name")?.as_str()?.to_string();
+        if known_only && !self.is_tool(&name) {
+            return None;
+        }
+        let arguments = call.get("arguments").or_else(|| call.get("parameters")).cloned().unwrap_or(Value::Null);
+        Some(self.call(name, arguments))
+    }
+
+    // ` name="..."> <parameter name="...">...</parameter> ...`, after `<invoke`
+    fn xml_call(&mut self, body: &str) -> Option<Segment> {
+        let tag_end = body.find('>')?;
+        let name = attribute(&body[..tag_end], "name")?.to_string();
+        let properties = self
+            .tools
+            .iter()
+            .find(|(tool, _)| *tool == name)
+            .and_then(|(_, parameters)| parameters.get("properties"))
+            .cloned();
+
+        let mut arguments = serde_json::Map::new();
+        let mut rest = &body[tag_end + 1..];
+        while let Some(start) = rest.find("<parameter") {
+            let tag_end = start + rest[start..].find('>')?;
+            let key = attribute(&rest[start..tag_end], "name")?.to_string();
+            let value_end = tag_end + rest[tag_end..].find("</parameter>")?;
+            let value = rest[tag_end + 1..value_end].trim_matches('\n');
+            // Values are text unless the schema says otherwise
+            let is_string = properties
+                .as_ref()
+                .and_then(|properties| properties.get(&key))
+                .is_none_or(|property| property.get("type").and_then(Value::as_str).is_none_or(|kind| kind == "string"));
+            let value = if is_string {
+                Value::String(value.to_string())
+            } else {
+                serde_json::from_str(value.trim()).unwrap_or_else(|_| Value::String(value.to_string()))
+            };
+            arguments.insert(key, value);
+            rest = &rest[value_end + "</parameter>".len()..];
+        }
+        Some(self.call(name, Value::Object(arguments)))
+    }
+
+    // A complete block between `marker`s, or its text if it isn't a call
+    fn block(&mut self, marker: &'static Marker, body: &str, closed: bool) -> Segment {
+        let call = match self.dialect {
+            ToolDialect::Xml => self.xml_call(body),
+            _ => self.json_call(body, marker.open == "```json"),
+        };
+        call.unwrap_or_else(|| {
+            let close = if closed { marker.close.unwrap_or_default() } else { "" };
+            Segment::Text(format!("{}{}{}", marker.open, body, close))
+        })
+    }
+
+    pub fn push(&mut self, text: &str) -> Vec<Segment> {
+        self.buffer.push_str(text);
+        if self.dialect == ToolDialect::Json {
+            return self.push_json();
+        }
+
+        let mut segments = Vec::new();
+        loop {
+            match self.open {
+                Some(marker) => {
+                    let close = marker.close.unwrap_or_default();
+                    let Some(end) = self.buffer.find(close) else { break };
+                    let body = self.buffer[..end].to_string();
+                    self.buffer.drain(..end + close.len());
+                    self.open = None;
+                    segments.push(self.block(marker, &body, true));
+                }
+                None => {
+                    let next = self
+                        .dialect
+                        .markers()
+                        .iter()
+                        .filter_map(|marker| self.buffer.find(marker.open).map(|at| (at, marker)))
+                        .min_by_key(|(at, _)| *at);
+                    match next {
+                        Some((at, marker)) => {
+                            let text: String = self.buffer.drain(..at).collect();
+                            segments.push(Segment::Text(text));
+                            self.buffer.drain(..marker.open.len());
+                            if marker.close.is_some() {
+                                self.open = Some(marker);
+                            }
+                        }
+                        None => {
+                            let keep = partial_marker(&self.buffer, self.dialect.markers());
+                            let text: String = self.buffer.drain(..self.buffer.len() - keep).collect();
+                            segments.push(Segment::Text(text));
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        segments
+    }
+
+    // The reply is held back once it starts with `{`
+    fn push_json(&mut self) -> Vec<Segment> {
+        if self.json_reply.is_none() {
+            let start = self.buffer.trim_start();
+            if start.is_empty() {
+                return Vec::new();
+            }
+            self.json_reply = Some(start.starts_with('{'));
+        }
+        match self.json_reply {
+            Some(true) => Vec::new(),
+            _ => vec![Segment::Text(std::mem::take(&mut self.buffer))],
+        }
+    }
+
+    // Whatever is left once the stream ends. A call cut off before its end
+    // marker (e.g. by a stop sequence) still counts if it parses.
+    pub fn finish(&mut self) -> Vec<Segment> {
+        let rest = std::mem::take(&mut self.buffer);
+        let segment = match (self.open.take(), self.json_reply) {
+            (Some(marker), _) => self.block(marker, &rest, false),
+            (None, Some(true)) => self.json_call(&rest, false).unwrap_or(Segment::Text(rest)),
+            (None, _) => Segment::Text(rest),
+        };
+        vec![segment]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tools() -> Vec<Value> {
+        vec![json!({
+            "type": "function",
+            "function": {
+                "name": "get_weather",
+                "parameters": {
+                    "type": "object",
+                    "properties": { "city": { "type": "string" }, "days": { "type": "integer" } },
+                },
+            },
+        })]
+    }
+
+    // The reply as text with calls written as [name arguments]
+    fn transcript(dialect: ToolDialect, deltas: &[&str]) -> String {
+        let mut parser = ToolCallParser::new(dialect, &tools());
+        let mut segments = Vec::new();
+        for delta in deltas {
+            segments.extend(parser.push(delta));
+        }
+        segments.extend(parser.finish());
+        segments
+            .into_iter()
+            .map(|segment| match segment {
+                Segment::Text(text) => text,
+                Segment::Call { name, arguments, .. } => format!("[{} {}]", name, arguments),
+            })
+            .collect()
+    }
+
+    // The same result however the reply is split into deltas
+    #[track_caller]
+    fn check(dialect: ToolDialect, reply: &str, expected: &str) {
+        assert_eq!(transcript(dialect, &[reply]), expected);
+        let chars: Vec<String> = reply.chars().map(String::from).collect();
+        let chars: Vec<&str> = chars.iter().map(String::as_str).collect();
+        assert_eq!(transcript(dialect, &chars), expected);
+        for (at, _) in reply.char_indices().skip(1) {
+            assert_eq!(transcript(dialect, &[&reply[..at], &reply[at..]]), expected, "split at {}", at);
+        }
+    }
+
+    #[test]
+    fn hermes() {
+        check(
+            ToolDialect::Hermes,
+            "Checking.\n<tool_call>\n{\"name\": \"get_weather\", \"arguments\": {\"city\": \"Oslo\"}}\n</tool_call>",
+            "Checking.\n[get_weather {\"city\":\"Oslo\"}]",
+        );
+        check(
+            ToolDialect::Hermes,
+            "<tool_call>{\"name\": \"get_weather\", \"arguments\": {}}</tool_call>\
+             <tool_call>{\"name\": \"get_weather\", \"parameters\": \"{\\\"city\\\": \\\"Rome\\\"}\"}</tool_call> Done.",
+            "[get_weather {}][get_weather {\"city\": \"Rome\"}] Done.",
+        );
+        // Text that only looks like the start of a tag comes through as is
+        check(ToolDialect::Hermes, "a <tool_ b < c <tool", "a <tool_ b < c <tool");
+        check(ToolDialect::Hermes, "<tool_call>not json</tool_call>", "<tool_call>not json</tool_call>");
+    }
+
+    #[test]
+    fn xml() {
+        check(
+            ToolDialect::Xml,
+            "Sure.<function_calls>\n<invoke name=\"get_weather\">\n<parameter name=\"city\">New York</parameter>\n\
+             <parameter name=\"days\">3</parameter>\n</invoke>\n</function_calls>",
+            "Sure.\n[get_weather {\"city\":\"New York\",\"days\":3}]\n",
+        );
+    }
+
+    #[test]
+    fn fenced_json() {
+        check(
+            ToolDialect::FencedJson,
+            "Let me look.\n```tool_call\n{\"name\": \"get_weather\", \"arguments\": {\"city\": \"Oslo\"}}\n```\n",
+            "Let me look.\n[get_weather {\"city\":\"Oslo\"}]\n",
+        );
+        // json blocks count when they call a known tool
+        check(
+            ToolDialect::FencedJson,
+            "```json\n{\"name\": \"get_weather\", \"arguments\": {}}\n```",
+            "[get_weather {}]",
+        );
+        let example = "Example:\n```json\n{\"name\": \"Ada\", \"age\": 36}\n```\nand ```rust\nfn main() {}\n```";
+        check(ToolDialect::FencedJson, example, example);
+    }
+
+    #[test]
+    fn json() {
+        check(
+            ToolDialect::Json,
+            "  {\"name\": \"get_weather\", \"arguments\": {\"city\": \"Oslo\"}}",
+            "[get_weather {\"city\":\"Oslo\"}]",
+        );
+        check(ToolDialect::Json, " It's sunny {in Oslo}.", " It's sunny {in Oslo}.");
+        check(ToolDialect::Json, "{\"broken\": ", "{\"broken\": ");
+    }
+
+    // Calls cut off before their end marker, e.g. by a stop string
+    #[test]
+    fn truncated_calls() {
+        check(
+            ToolDialect::Hermes,
+            "<tool_call>\n{\"name\": \"get_weather\", \"arguments\": {\"city\": \"Oslo\"}}\n",
+            "[get_weather {\"city\":\"Oslo\"}]",
+        );
+        check(ToolDialect::Hermes, "<tool_call>\n{\"name\": \"get_wea", "<tool_call>\n{\"name\": \"get_wea");
+        check(
+            ToolDialect::FencedJson,
+            "```tool_call\n{\"name\": \"get_weather\", \"arguments\": {}}",
+            "[get_weather {}]",
+        );
+        check(
+            ToolDialect::Xml,
+            "<invoke name=\"get_weather\"><parameter name=\"days\">2</parameter>",
+            "[get_weather {\"days\":2}]",
+        );
+    }
+
+    #[test]
+    fn calls_get_their_own_ids() {
+        let mut parser = ToolCallParser::new(ToolDialect::Hermes, &tools());
+        let call = "<tool_call>{\"name\": \"get_weather\"}</tool_call>";
+        let ids: Vec<String> = parser
+            .push(&call.repeat(2))
+            .into_iter()
+            .filter_map(|segment| match segment {
+                Segment::Call { id, arguments, .. } => {
+                    assert_eq!(arguments, "{}");
+                    Some(id)
+                }
+                Segment::Text(_) => None,
+            })
+            .collect();
+        assert_eq!(ids.len(), 2);
+        assert_ne!(ids[0], ids[1]);
+    }
+
+    fn request(messages: Value) -> ChatRequest {
+        serde_json::from_value(json!({ "model": "qwen2.5-7b", "messages": messages, "tools": tools() })).unwrap()
+    }
+
+    #[test]
+    fn prepare_writes_out_calls_and_results() {
+        let request = request(json!([
+            { "role": "user", "content": "Weather in Oslo and Rome?" },
+            {
+                "role": "assistant",
+                "content": "",
+                "toolCalls": [
+                    { "id": "a", "function": { "name": "get_weather", "arguments": "{\"city\":\"Oslo\"}" } },
+                    { "id": "b", "function": { "name": "get_weather", "arguments": "{\"city\":\"Rome\"}" } },
+                ],
+            },
+            { "role": "tool", "toolCallId": "a", "content": "Rain" },
+            { "role": "tool", "toolCallId": "b", "content": "Sun" },
+            { "role": "assistant", "content": "Rain in Oslo, sun in Rome." },
+            { "role": "user", "content": "Thanks" },
+        ]));
+        let dialect = dialect_for(&request, Some(ToolDialect::Auto)).unwrap();
+        assert_eq!(dialect, ToolDialect::Hermes);
+
+        let prepared = prepare(&request, dialect);
+        assert!(prepared.tools.is_empty());
+        let messages: Vec<(&str, &str)> =
+            prepared.messages.iter().map(|message| (message.role.as_str(), message.content.as_str())).collect();
+        assert_eq!(messages[0].0, "system");
+        assert!(messages[0].1.contains("<tool_call>") && messages[0].1.contains("\"get_weather\""));
+        assert_eq!(
+            messages[1..],
+            [
+                ("user", "Weather in Oslo and Rome?"),
+                (
+                    "assistant",
+                    "<tool_call>\n{\"name\":\"get_weather\",\"arguments\":{\"city\":\"Oslo\"}}\n</tool_call>\n\
+                     <tool_call>\n{\"name\":\"get_weather\",\"arguments\":{\"city\":\"Rome\"}}\n</tool_call>"
+                ),
+                (
+                    "user",
+                    "<tool_response>\n{\"name\":\"get_weather\",\"content\":\"Rain\"}\n</tool_response>\n\n\
+                     <tool_response>\n{\"name\":\"get_weather\",\"content\":\"Sun\"}\n</tool_response>"
+                ),
+                ("assistant", "Rain in Oslo, sun in Rome."),
+                ("user", "Thanks"),
+            ]
+        );
+        assert!(prepared.messages.iter().all(|message| message.tool_calls.is_empty()));
+    }
+
+    #[test]
+    fn prepare_extends_the_system_prompt() {
+        let request = request(json!([
+            { "role": "system", "content": "Be brief." },
+            { "role": "user", "content": "Hi" },
+        ]));
+        let prepared = prepare(&request, ToolDialect::FencedJson);
+        assert_eq!(prepared.messages.len(), 2);
+        assert!(prepared.messages[0].content.starts_with("Be brief.\n\nYou can call the tools listed below."));
+        assert!(prepared.messages[0].content.contains("```tool_call"));
+    }
+}
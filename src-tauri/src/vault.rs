@@ -0,0 +1,856 @@
+// Encrypted secrets vault (vault.json in the app data dir). Entries are
+// sealed with ChaCha20-Poly1305 under a key derived from the user's
+// passphrase with scrypt, or under a random key kept in vault.key for users
+// who opt out of a passphrase. Neither needs an OS keyring, so the vault also
+// works on headless Linux. A passphrase vault locks itself when idle.
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tauri::{Emitter, Manager, State};
+use zeroize::Zeroizing;
+
+use crate::error::{CommandError, ErrorKind};
+
+const VAULT_FILE: &str = "vault.json";
+const KEY_FILE: &str = "vault.key";
+// A new key file waits here until the vault has been sealed with it
+const NEW_KEY_FILE: &str = "vault.key.new";
+const FORMAT_VERSION: u32 = 1;
+const DEFAULT_AUTO_LOCK_MINUTES: u32 = 15;
+const AUTO_LOCK_CHECK: Duration = Duration::from_secs(30);
+const SALT_LEN: usize = 16;
+
+type Key = Zeroizing<[u8; 32]>;
+
+// scrypt cost; memory use is 128 * r * N bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ScryptParams {
+    // N = 2^log_n
+    log_n: u8,
+    r: u32,
+    p: u32,
+}
+
+impl Default for ScryptParams {
+    // 32 MiB and roughly 100ms in a release build
+    fn default() -> Self {
+        Self { log_n: 15, r: 8, p: 1 }
+    }
+}
+
+impl ScryptParams {
+    // Bounds for parameters read from a file, so a crafted vault can't ask
+    // for gigabytes of memory
+    fn is_reasonable(&self) -> bool {
+        (1..=20).contains(&self.log_n) && (1..=32).contains(&self.r) && (1..=16).contains(&self.p)
+    }
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum KeySource {
+    // `check` is derived along with the key; it tells a wrong passphrase
+    // apart from a damaged file
+    Passphrase { salt: String, params: ScryptParams, check: String },
+    KeyFile,
+}
+
+// Authenticated together with the entries, so editing it breaks the seal
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Header {
+    version: u32,
+    key: KeySource,
+    // 0 turns automatic locking off
+    auto_lock_minutes: u32,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct VaultFile {
+    #[serde(flatten)]
+    header: Header,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VaultStatus {
+    pub exists: bool,
+    pub unlocked: bool,
+    // false when the vault is sealed with the key file
+    pub has_passphrase: bool,
+    pub auto_lock_minutes: u32,
+}
+
+struct Unlocked {
+    key: Key,
+    entries: Map<String, Value>,
+}
+
+struct VaultState {
+    // None until a vault file exists
+    header: Option<Header>,
+    unlocked: Option<Unlocked>,
+    last_used: Instant,
+}
+
+type UnlockHook = Box<dyn Fn() + Send + Sync>;
+
+struct VaultInner {
+    dir: Option<PathBuf>,
+    state: Mutex<VaultState>,
+    // Run after the vault gets unlocked, for secrets needed outside commands
+    unlock_hooks: Mutex<Vec<UnlockHook>>,
+}
+
+#[derive(Clone)]
+pub struct Vault {
+    inner: Arc<VaultInner>,
+}
+
+fn internal(message: impl Into<String>) -> CommandError {
+    CommandError::new(ErrorKind::Internal, message)
+}
+
+fn locked() -> CommandError {
+    CommandError::new(ErrorKind::Locked, "The secrets vault is locked")
+}
+
+fn no_vault() -> CommandError {
+    CommandError::new(ErrorKind::InvalidRequest, "No secrets vault has been created")
+}
+
+fn tampered() -> CommandError {
+    internal("The secrets vault is damaged or has been tampered with")
+}
+
+fn random_bytes<const N: usize>() -> Result<[u8; N], CommandError> {
+    let mut bytes = [0u8; N];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| internal("System random number generator failed"))?;
+    Ok(bytes)
+}
+
+fn decode(text: &str) -> Result<Vec<u8>, CommandError> {
+    BASE64.decode(text).map_err(|_| tampered())
+}
+
+fn derive(passphrase: &str, salt: &[u8], params: ScryptParams, out: &mut [u8]) -> Result<(), CommandError> {
+    let params = scrypt::Params::new(params.log_n, params.r, params.p, out.len())
+        .map_err(|_| internal("Invalid scrypt parameters"))?;
+    scrypt::scrypt(passphrase.as_bytes(), salt, &params, out).map_err(|_| internal("Key derivation failed"))
+}
+
+// The vault key and its check value
+fn passphrase_key(passphrase: &str, salt: &[u8], params: ScryptParams) -> Result<(Key, [u8; 32]), CommandError> {
+    let mut derived = Zeroizing::new([0u8; 64]);
+    derive(passphrase, salt, params, derived.as_mut())?;
+    let mut key = Zeroizing::new([0u8; 32]);
+    key.copy_from_slice(&derived[..32]);
+    let mut check = [0u8; 32];
+    check.copy_from_slice(&derived[32..]);
+    Ok((key, check))
+}
+
+// Key of an existing passphrase vault; fails when the passphrase is wrong
+fn open_passphrase(source: &KeySource, passphrase: &str) -> Result<Key, CommandError> {
+    let KeySource::Passphrase { salt, params, check } = source else {
+        return Err(CommandError::new(ErrorKind::InvalidRequest, "The vault has no passphrase"));
+    };
+    if !params.is_reasonable() {
+        return Err(tampered());
+    }
+    let (key, derived) = passphrase_key(passphrase, &decode(salt)?, *params)?;
+    let expected = decode(check)?;
+    // Compares every byte, whatever the first difference
+    let difference = expected
+        .iter()
+        .zip(derived.iter())
+        .fold(expected.len() ^ derived.len(), |acc, (a, b)| acc | usize::from(a ^ b));
+    if difference != 0 {
+        return Err(CommandError::new(ErrorKind::Locked, "Wrong passphrase"));
+    }
+    Ok(key)
+}
+
+fn cipher(key: &Key) -> Result<LessSafeKey, CommandError> {
+    let key = UnboundKey::new(&CHACHA20_POLY1305, key.as_ref()).map_err(|_| internal("Invalid vault key"))?;
+    Ok(LessSafeKey::new(key))
+}
+
+fn seal(key: &Key, header: &Header, entries: &Map<String, Value>) -> Result<VaultFile, CommandError> {
+    let aad = serde_json::to_vec(header).map_err(|err| internal(err.to_string()))?;
+    let nonce = random_bytes::<NONCE_LEN>()?;
+    let mut data = Zeroizing::new(serde_json::to_vec(entries).map_err(|err| internal(err.to_string()))?);
+    cipher(key)?
+        .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(aad), &mut *data)
+        .map_err(|_| internal("Failed to encrypt the vault"))?;
+    Ok(VaultFile {
+        header: header.clone(),
+        nonce: BASE64.encode(nonce),
+        ciphertext: BASE64.encode(&*data),
+    })
+}
+
+fn open(key: &Key, file: &VaultFile) -> Result<Map<String, Value>, CommandError> {
+    let aad = serde_json::to_vec(&file.header).map_err(|err| internal(err.to_string()))?;
+    let nonce: [u8; NONCE_LEN] = decode(&file.nonce)?.try_into().map_err(|_| tampered())?;
+    let mut data = Zeroizing::new(decode(&file.ciphertext)?);
+    let plaintext = cipher(key)?
+        .open_in_place(Nonce::assume_unique_for_key(nonce), Aad::from(aad), &mut data[..])
+        .map_err(|_| tampered())?;
+    serde_json::from_slice(plaintext).map_err(|_| tampered())
+}
+
+// Owner-only file, written through a temporary file so a crash never leaves
+// half of it behind
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&temp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    std::fs::rename(&temp, path)
+}
+
+impl Vault {
+    // Reads the vault header. A vault without a passphrase is unlocked right
+    // away; there is nothing to ask the user for.
+    pub fn load<R: tauri::Runtime>(app: &tauri::AppHandle<R>) -> Self {
+        Self::open_dir(app.path().app_data_dir().ok())
+    }
+
+    pub(crate) fn open_dir(dir: Option<PathBuf>) -> Self {
+        let vault = Self {
+            inner: Arc::new(VaultInner {
+                dir,
+                state: Mutex::new(VaultState {
+                    header: None,
+                    unlocked: None,
+                    last_used: Instant::now(),
+                }),
+                unlock_hooks: Mutex::new(Vec::new()),
+            }),
+        };
+
+        match vault.read_file() {
+            Ok(Some(file)) => {
+                let key_file = matches!(file.header.key, KeySource::KeyFile);
+                vault.state().header = Some(file.header);
+                if key_file {
+                    if let Err(err) = vault.unlock(None) {
+                        eprintln!("[Vault] Failed to open the vault with its key file: {}", err);
+                    }
+                }
+            }
+            Ok(None) => {}
+            Err(err) => eprintln!("[Vault] Failed to read the vault: {}", err),
+        }
+        vault
+    }
+
+    fn state(&self) -> MutexGuard<'_, VaultState> {
+        self.inner.state.lock().unwrap()
+    }
+
+    pub fn on_unlock(&self, hook: impl Fn() + Send + Sync + 'static) {
+        self.inner.unlock_hooks.lock().unwrap().push(Box::new(hook));
+    }
+
+    // Called without the state lock held; hooks read from the vault
+    fn run_unlock_hooks(&self) {
+        for hook in self.inner.unlock_hooks.lock().unwrap().iter() {
+            hook();
+        }
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf, CommandError> {
+        self.inner
+            .dir
+            .as_ref()
+            .map(|dir| dir.join(name))
+            .ok_or_else(|| internal("App data directory is not available"))
+    }
+
+    fn read_file(&self) -> Result<Option<VaultFile>, CommandError> {
+        let path = self.path(VAULT_FILE)?;
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(internal(format!("Failed to read {}: {err}", path.display()))),
+        };
+        let file: VaultFile = serde_json::from_str(&text).map_err(|_| tampered())?;
+        if file.header.version != FORMAT_VERSION {
+            return Err(internal(format!("Unsupported vault version {}", file.header.version)));
+        }
+        Ok(Some(file))
+    }
+
+    fn write_file(&self, file: &VaultFile) -> Result<(), CommandError> {
+        let path = self.path(VAULT_FILE)?;
+        let text = serde_json::to_string_pretty(file).map_err(|err| internal(err.to_string()))?;
+        write_private(&path, text.as_bytes())
+            .map_err(|err| internal(format!("Failed to write {}: {err}", path.display())))
+    }
+
+    fn read_key_file(&self, name: &str) -> Result<Key, CommandError> {
+        let path = self.path(name)?;
+        let text = Zeroizing::new(
+            std::fs::read_to_string(&path)
+                .map_err(|err| internal(format!("Failed to read the vault key file {}: {err}", path.display())))?,
+        );
+        let bytes = Zeroizing::new(BASE64.decode(text.trim()).map_err(|_| internal("The vault key file is damaged"))?);
+        let mut key = Zeroizing::new([0u8; 32]);
+        if bytes.len() != key.len() {
+            return Err(internal("The vault key file is damaged"));
+        }
+        key.copy_from_slice(&bytes);
+        Ok(key)
+    }
+
+    // Puts a new key file in place once the vault is sealed with its key
+    fn install_key_file(&self) -> Result<(), CommandError> {
+        let path = self.path(KEY_FILE)?;
+        std::fs::rename(self.path(NEW_KEY_FILE)?, &path)
+            .map_err(|err| internal(format!("Failed to write {}: {err}", path.display())))
+    }
+
+    // A new key and where it comes from. Without a passphrase the key is
+    // written to the new key file, for `install_key_file` to put in place.
+    fn new_key(&self, passphrase: Option<&str>) -> Result<(Key, KeySource), CommandError> {
+        match passphrase {
+            Some("") => Err(CommandError::new(ErrorKind::InvalidRequest, "Passphrase is empty")),
+            Some(passphrase) => {
+                let salt = random_bytes::<SALT_LEN>()?;
+                let params = ScryptParams::default();
+                let (key, check) = passphrase_key(passphrase, &salt, params)?;
+                let source = KeySource::Passphrase {
+                    salt: BASE64.encode(salt),
+                    params,
+                    check: BASE64.encode(check),
+                };
+                Ok((key, source))
+            }
+            None => {
+                let key = Zeroizing::new(random_bytes::<32>()?);
+                let path = self.path(NEW_KEY_FILE)?;
+                let text = Zeroizing::new(BASE64.encode(key.as_ref()));
+                write_private(&path, text.as_bytes())
+                    .map_err(|err| internal(format!("Failed to write {}: {err}", path.display())))?;
+                Ok((key, KeySource::KeyFile))
+            }
+        }
+    }
+
+    // Key of the vault on disk, from the passphrase or the key file
+    fn existing_key(&self, file: &VaultFile, passphrase: Option<&str>) -> Result<Key, CommandError> {
+        match (&file.header.key, passphrase) {
+            (KeySource::KeyFile, _) => self.read_key_file(KEY_FILE),
+            (source, Some(passphrase)) => open_passphrase(source, passphrase),
+            (_, None) => Err(CommandError::new(ErrorKind::InvalidRequest, "A passphrase is required")),
+        }
+    }
+
+    // Key and entries of the vault on disk. When the app stopped between
+    // sealing the vault and installing its new key file, the new key file
+    // opens it and is installed now.
+    fn open_existing(
+        &self,
+        file: &VaultFile,
+        passphrase: Option<&str>,
+    ) -> Result<(Key, Map<String, Value>), CommandError> {
+        let opened = self.existing_key(file, passphrase).and_then(|key| {
+            let entries = open(&key, file)?;
+            Ok((key, entries))
+        });
+        match opened {
+            Err(err) if matches!(file.header.key, KeySource::KeyFile) && self.path(NEW_KEY_FILE)?.exists() => {
+                let key = self.read_key_file(NEW_KEY_FILE)?;
+                let entries = open(&key, file).map_err(|_| err)?;
+                self.install_key_file()?;
+                Ok((key, entries))
+            }
+            opened => opened,
+        }
+    }
+
+    fn store(&self, header: Header, key: Key, entries: Map<String, Value>) -> Result<(), CommandError> {
+        self.write_file(&seal(&key, &header, &entries)?)?;
+        let mut state = self.state();
+        state.header = Some(header);
+        state.unlocked = Some(Unlocked { key, entries });
+        state.last_used = Instant::now();
+        Ok(())
+    }
+
+    pub fn status(&self) -> VaultStatus {
+        let state = self.state();
+        VaultStatus {
+            exists: state.header.is_some(),
+            unlocked: state.unlocked.is_some(),
+            has_passphrase: matches!(
+                state.header.as_ref().map(|header| &header.key),
+                Some(KeySource::Passphrase { .. })
+            ),
+            auto_lock_minutes: state
+                .header
+                .as_ref()
+                .map_or(DEFAULT_AUTO_LOCK_MINUTES, |header| header.auto_lock_minutes),
+        }
+    }
+
+    pub fn create(&self, passphrase: Option<&str>) -> Result<(), CommandError> {
+        if self.read_file()?.is_some() {
+            return Err(CommandError::new(ErrorKind::InvalidRequest, "A secrets vault already exists"));
+        }
+        let (key, source) = self.new_key(passphrase)?;
+        let uses_key_file = matches!(source, KeySource::KeyFile);
+        let header = Header {
+            version: FORMAT_VERSION,
+            key: source,
+            auto_lock_minutes: DEFAULT_AUTO_LOCK_MINUTES,
+        };
+        self.store(header, key, Map::new())?;
+        if uses_key_file {
+            self.install_key_file()?;
+        }
+        Ok(())
+    }
+
+    pub fn unlock(&self, passphrase: Option<&str>) -> Result<(), CommandError> {
+        let file = self.read_file()?.ok_or_else(no_vault)?;
+        let (key, entries) = self.open_existing(&file, passphrase)?;
+        {
+            let mut state = self.state();
+            state.header = Some(file.header);
+            state.unlocked = Some(Unlocked { key, entries });
+            state.last_used = Instant::now();
+        }
+        self.run_unlock_hooks();
+        Ok(())
+    }
+
+    pub fn lock(&self) -> Result<(), CommandError> {
+        let mut state = self.state();
+        match state.header.as_ref().map(|header| &header.key) {
+            None => Err(no_vault()),
+            Some(KeySource::KeyFile) => Err(CommandError::new(
+                ErrorKind::InvalidRequest,
+                "A vault without a passphrase can't be locked",
+            )),
+            Some(KeySource::Passphrase { .. }) => {
+                state.unlocked = None;
+                Ok(())
+            }
+        }
+    }
+
+    // Re-encrypts the vault under a new passphrase, or under a new key file
+    // when `new_passphrase` is None. The current passphrase is checked even
+    // when the vault is unlocked. The current key file stays in place until
+    // the vault has been sealed under the new key.
+    pub fn change_passphrase(
+        &self,
+        current_passphrase: Option<&str>,
+        new_passphrase: Option<&str>,
+    ) -> Result<(), CommandError> {
+        let file = self.read_file()?.ok_or_else(no_vault)?;
+        let (_, entries) = self.open_existing(&file, current_passphrase)?;
+
+        let (key, source) = self.new_key(new_passphrase)?;
+        let uses_key_file = matches!(source, KeySource::KeyFile);
+        let header = Header { key: source, ..file.header };
+        if let Err(err) = self.store(header, key, entries) {
+            if uses_key_file {
+                let _ = std::fs::remove_file(self.path(NEW_KEY_FILE)?);
+            }
+            return Err(err);
+        }
+
+        if uses_key_file {
+            self.install_key_file()?;
+        } else {
+            // The old key file would no longer open anything
+            let _ = std::fs::remove_file(self.path(KEY_FILE)?);
+        }
+        self.run_unlock_hooks();
+        Ok(())
+    }
+
+    pub fn set_auto_lock(&self, minutes: u32) -> Result<(), CommandError> {
+        let mut state = self.state();
+        let mut header = state.header.clone().ok_or_else(no_vault)?;
+        let unlocked = state.unlocked.as_ref().ok_or_else(locked)?;
+        header.auto_lock_minutes = minutes;
+        self.write_file(&seal(&unlocked.key, &header, &unlocked.entries)?)?;
+        state.header = Some(header);
+        Ok(())
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, name: &str) -> Result<Option<T>, CommandError> {
+        self.read(name, true)
+    }
+
+    // Reads an entry for background work such as health checks. It doesn't
+    // count as activity, so it never holds off the auto-lock.
+    pub fn peek<T: DeserializeOwned>(&self, name: &str) -> Result<Option<T>, CommandError> {
+        self.read(name, false)
+    }
+
+    fn read<T: DeserializeOwned>(&self, name: &str, activity: bool) -> Result<Option<T>, CommandError> {
+        let mut state = self.state();
+        if state.header.is_none() {
+            return Ok(None);
+        }
+        if activity {
+            state.last_used = Instant::now();
+        }
+        let unlocked = state.unlocked.as_ref().ok_or_else(locked)?;
+        unlocked
+            .entries
+            .get(name)
+            .map(|value| serde_json::from_value(value.clone()))
+            .transpose()
+            .map_err(|err| internal(format!("Invalid vault entry {}: {err}", name)))
+    }
+
+    // Changes one entry and writes the vault. Without a vault yet, one is
+    // created with a key file; a passphrase can be added later.
+    pub fn update<T, R>(&self, name: &str, change: impl FnOnce(&mut T) -> R) -> Result<R, CommandError>
+    where
+        T: Serialize + DeserializeOwned + Default,
+    {
+        if self.state().header.is_none() {
+            eprintln!("[Vault] Creating a vault without a passphrase");
+            self.create(None)?;
+        }
+
+        let mut state = self.state();
+        state.last_used = Instant::now();
+        let header = state.header.clone().ok_or_else(no_vault)?;
+        let unlocked = state.unlocked.as_mut().ok_or_else(locked)?;
+
+        let mut value: T = match unlocked.entries.get(name) {
+            Some(value) => serde_json::from_value(value.clone())
+                .map_err(|err| internal(format!("Invalid vault entry {}: {err}", name)))?,
+            None => T::default(),
+        };
+        let result = change(&mut value);
+
+        // Memory only changes once the file is written
+        let mut entries = unlocked.entries.clone();
+        entries.insert(name.to_string(), serde_json::to_value(&value).map_err(|err| internal(err.to_string()))?);
+        self.write_file(&seal(&unlocked.key, &header, &entries)?)?;
+        unlocked.entries = entries;
+        Ok(result)
+    }
+
+    // Locks a passphrase vault that hasn't been used for its auto-lock time
+    fn lock_if_idle(&self) -> bool {
+        let mut state = self.state();
+        let limit = match &state.header {
+            Some(Header { key: KeySource::Passphrase { .. }, auto_lock_minutes, .. }) if *auto_lock_minutes > 0 => {
+                Duration::from_secs(u64::from(*auto_lock_minutes) * 60)
+            }
+            _ => return false,
+        };
+        if state.unlocked.is_none() || state.last_used.elapsed() < limit {
+            return false;
+        }
+        state.unlocked = None;
+        true
+    }
+}
+
+// Checks for an idle vault in the background and tells the frontend when it
+// gets locked
+pub fn spawn_auto_lock<R: tauri::Runtime>(app: tauri::AppHandle<R>, vault: Vault) {
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(AUTO_LOCK_CHECK);
+        loop {
+            interval.tick().await;
+            if vault.lock_if_idle() {
+                eprintln!("[Vault] Locked after inactivity");
+                let _ = app.emit("vault-locked", ());
+            }
+        }
+    });
+}
+
+#[tauri::command]
+pub fn vault_status(vault: State<'_, Vault>) -> VaultStatus {
+    vault.status()
+}
+
+// Key derivation takes a noticeable moment, so the commands that derive a
+// key run on the blocking pool
+
+#[tauri::command]
+pub async fn vault_create(passphrase: Option<String>, vault: State<'_, Vault>) -> Result<VaultStatus, CommandError> {
+    let vault = vault.inner().clone();
+    let passphrase = passphrase.map(Zeroizing::new);
+    eprintln!("[Vault] Creating vault ({})", if passphrase.is_some() { "passphrase" } else { "key file" });
+    tokio::task::spawn_blocking(move || {
+        vault.create(passphrase.as_ref().map(|p| p.as_str()))?;
+        Ok(vault.status())
+    })
+    .await?
+}
+
+#[tauri::command]
+pub async fn vault_unlock(passphrase: Option<String>, vault: State<'_, Vault>) -> Result<VaultStatus, CommandError> {
+    let vault = vault.inner().clone();
+    let passphrase = passphrase.map(Zeroizing::new);
+    tokio::task::spawn_blocking(move || {
+        vault.unlock(passphrase.as_ref().map(|p| p.as_str()))?;
+        eprintln!("[Vault] Unlocked");
+        Ok(vault.status())
+    })
+    .await?
+}
+
+#[tauri::command]
+pub fn vault_lock(vault: State<'_, Vault>) -> Result<VaultStatus, CommandError> {
+    vault.lock()?;
+    eprintln!("[Vault] Locked");
+    Ok(vault.status())
+}
+
+// A None `new_passphrase` switches the vault to a key file
+#[tauri::command]
+pub async fn vault_change_passphrase(
+    current_passphrase: Option<String>,
+    new_passphrase: Option<String>,
+    vault: State<'_, Vault>,
+) -> Result<VaultStatus, CommandError> {
+    let vault = vault.inner().clone();
+    let current_passphrase = current_passphrase.map(Zeroizing::new);
+    let new_passphrase = new_passphrase.map(Zeroizing::new);
+    tokio::task::spawn_blocking(move || {
+        vault.change_passphrase(
+            current_passphrase.as_ref().map(|p| p.as_str()),
+            new_passphrase.as_ref().map(|p| p.as_str()),
+        )?;
+        eprintln!("[Vault] Passphrase changed");
+        Ok(vault.status())
+    })
+    .await?
+}
+
+// 0 turns automatic locking off
+#[tauri::command]
+pub fn vault_set_auto_lock(minutes: u32, vault: State<'_, Vault>) -> Result<VaultStatus, CommandError> {
+    vault.set_auto_lock(minutes)?;
+    Ok(vault.status())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn open_vault(dir: &tempfile::TempDir) -> Vault {
+        Vault::open_dir(Some(dir.path().to_path_buf()))
+    }
+
+    fn set_secret(vault: &Vault, secret: &str) {
+        vault.update("secret", |value: &mut String| *value = secret.to_string()).unwrap();
+    }
+
+    fn secret(vault: &Vault) -> Result<Option<String>, CommandError> {
+        vault.get("secret")
+    }
+
+    // RFC 7914, section 12
+    #[test]
+    fn scrypt_test_vectors() {
+        let mut out = [0u8; 64];
+        derive("", b"", ScryptParams { log_n: 4, r: 1, p: 1 }, &mut out).unwrap();
+        assert_eq!(
+            hex(&out),
+            "77d6576238657b203b19ca42c18a0497f16b4844e3074ae8dfdffa3fede21442\
+             fcd0069ded0948f8326a753a0fc81f17e8d3e0fb2e0d3628cf35e20c38d18906"
+        );
+        derive("password", b"NaCl", ScryptParams { log_n: 10, r: 8, p: 16 }, &mut out).unwrap();
+        assert_eq!(
+            hex(&out),
+            "fdbabe1c9d3472007856e7190d01e9fe7c6ad7cbc8237830e77376634b373162\
+             2eaf30d92e22a3886ff109279d9830dac727afb94a83ee6d8360cbdfa2cc0640"
+        );
+    }
+
+    #[test]
+    fn create_and_unlock() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = open_vault(&dir);
+        vault.create(Some("correct horse")).unwrap();
+        set_secret(&vault, "sk-secret");
+        vault.lock().unwrap();
+        assert_eq!(secret(&vault).unwrap_err().kind, ErrorKind::Locked);
+
+        // As after a restart
+        let vault = open_vault(&dir);
+        let status = vault.status();
+        assert!(status.exists && status.has_passphrase && !status.unlocked);
+        vault.unlock(Some("correct horse")).unwrap();
+        assert_eq!(secret(&vault).unwrap().as_deref(), Some("sk-secret"));
+    }
+
+    #[test]
+    fn wrong_passphrase_is_locked() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = open_vault(&dir);
+        vault.create(Some("correct horse")).unwrap();
+        vault.lock().unwrap();
+
+        let err = vault.unlock(Some("battery staple")).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Locked);
+        assert!(!vault.status().unlocked);
+    }
+
+    #[test]
+    fn tampering_breaks_the_seal() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = open_vault(&dir);
+        set_secret(&vault, "sk-secret");
+        let path = dir.path().join(VAULT_FILE);
+        let original: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+
+        // The header is authenticated along with the entries
+        let mut header = original.clone();
+        header["autoLockMinutes"] = Value::from(0);
+        std::fs::write(&path, header.to_string()).unwrap();
+        assert_eq!(open_vault(&dir).unlock(None).unwrap_err().kind, ErrorKind::Internal);
+
+        let mut ciphertext = original.clone();
+        let mut bytes = BASE64.decode(original["ciphertext"].as_str().unwrap()).unwrap();
+        bytes[0] ^= 1;
+        ciphertext["ciphertext"] = Value::from(BASE64.encode(bytes));
+        std::fs::write(&path, ciphertext.to_string()).unwrap();
+        assert_eq!(open_vault(&dir).unlock(None).unwrap_err().kind, ErrorKind::Internal);
+
+        std::fs::write(&path, original.to_string()).unwrap();
+        assert_eq!(secret(&open_vault(&dir)).unwrap().as_deref(), Some("sk-secret"));
+    }
+
+    #[test]
+    fn idle_passphrase_vaults_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = open_vault(&dir);
+        vault.create(Some("correct horse")).unwrap();
+        vault.set_auto_lock(1).unwrap();
+        assert!(!vault.lock_if_idle());
+
+        vault.state().last_used = Instant::now() - Duration::from_secs(61);
+        assert!(vault.lock_if_idle());
+        assert!(!vault.status().unlocked);
+
+        // Turned off
+        vault.unlock(Some("correct horse")).unwrap();
+        vault.set_auto_lock(0).unwrap();
+        vault.state().last_used = Instant::now() - Duration::from_secs(3600);
+        assert!(!vault.lock_if_idle());
+    }
+
+    #[test]
+    fn background_reads_dont_hold_off_the_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = open_vault(&dir);
+        vault.create(Some("correct horse")).unwrap();
+        vault.set_auto_lock(1).unwrap();
+        set_secret(&vault, "sk-secret");
+
+        vault.state().last_used = Instant::now() - Duration::from_secs(61);
+        assert_eq!(vault.peek::<String>("secret").unwrap().as_deref(), Some("sk-secret"));
+        assert!(vault.lock_if_idle());
+        assert_eq!(vault.peek::<String>("secret").unwrap_err().kind, ErrorKind::Locked);
+
+        // Reads the user asked for do count
+        vault.unlock(Some("correct horse")).unwrap();
+        vault.state().last_used = Instant::now() - Duration::from_secs(61);
+        secret(&vault).unwrap();
+        assert!(!vault.lock_if_idle());
+    }
+
+    #[test]
+    fn key_file_vaults_never_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = open_vault(&dir);
+        set_secret(&vault, "sk-secret");
+        vault.state().last_used = Instant::now() - Duration::from_secs(3600);
+        assert!(!vault.lock_if_idle());
+        assert!(vault.lock().is_err());
+    }
+
+    #[test]
+    fn change_passphrase_keeps_the_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = open_vault(&dir);
+        vault.create(Some("first")).unwrap();
+        set_secret(&vault, "sk-secret");
+
+        assert_eq!(vault.change_passphrase(Some("wrong"), Some("second")).unwrap_err().kind, ErrorKind::Locked);
+        vault.change_passphrase(Some("first"), Some("second")).unwrap();
+        let reopened = open_vault(&dir);
+        assert_eq!(reopened.unlock(Some("first")).unwrap_err().kind, ErrorKind::Locked);
+        reopened.unlock(Some("second")).unwrap();
+        assert_eq!(secret(&reopened).unwrap().as_deref(), Some("sk-secret"));
+
+        // To a key file and back
+        vault.change_passphrase(Some("second"), None).unwrap();
+        assert!(dir.path().join(KEY_FILE).exists());
+        assert!(!dir.path().join(NEW_KEY_FILE).exists());
+        assert_eq!(secret(&open_vault(&dir)).unwrap().as_deref(), Some("sk-secret"));
+
+        vault.change_passphrase(None, Some("third")).unwrap();
+        assert!(!dir.path().join(KEY_FILE).exists());
+        let reopened = open_vault(&dir);
+        reopened.unlock(Some("third")).unwrap();
+        assert_eq!(secret(&reopened).unwrap().as_deref(), Some("sk-secret"));
+    }
+
+    #[test]
+    fn interrupted_key_file_change_recovers() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = open_vault(&dir);
+        set_secret(&vault, "sk-secret");
+
+        // Sealed under a new key file that never got put in place
+        let (key, source) = vault.new_key(None).unwrap();
+        let header = Header { key: source, ..vault.state().header.clone().unwrap() };
+        let entries = vault.state().unlocked.as_ref().unwrap().entries.clone();
+        vault.store(header, key, entries).unwrap();
+        assert!(dir.path().join(NEW_KEY_FILE).exists());
+
+        let reopened = open_vault(&dir);
+        assert_eq!(secret(&reopened).unwrap().as_deref(), Some("sk-secret"));
+        assert!(!dir.path().join(NEW_KEY_FILE).exists());
+        assert_eq!(secret(&open_vault(&dir)).unwrap().as_deref(), Some("sk-secret"));
+    }
+}
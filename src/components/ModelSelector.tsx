@@ -1,5 +1,5 @@
 import { useState, useRef, useEffect } from 'react'
-import { ChevronDown, Eye, Brain, MoreVertical, Trash2 } from 'lucide-react'
+import { ChevronDown, Cpu, Eye, Brain, MoreVertical, Trash2 } from 'lucide-react'
 import type { ProviderConfig, ModelInfo } from '../types'
 import { cn } from '../lib/utils'
 import { ProviderHealthMonitor, type ProviderHealthStatus } from '../services/ProviderHealthMonitor'
@@ -104,7 +104,7 @@ export function ModelSelector({
     })
 
     if (needsRefresh) {
-      healthMonitor.checkProviders(providers, { timeout: 2000 })
+      healthMonitor.checkProviders(providers)
     }
   }, [isOpen, providers, healthMonitor])
 
@@ -247,6 +247,9 @@ export function ModelSelector({
             <path d="M16.804 1.957l7.22 4.105v.087L16.73 10.21l.017-2.117-.821-.03c-1.059-.028-1.611.002-2.268.11-1.064.175-2.038.577-3.147 1.352L8.345 11.03c-.284.195-.495.336-.68.455l-.515.322-.397.234.385.23.53.338c.476.314 1.17.796 2.701 1.866 1.11.775 2.083 1.177 3.147 1.352l.3.045c.694.091 1.375.094 2.825.033l.022-2.159 7.22 4.105v.087L16.589 22l.014-1.862-.635.022c-1.386.042-2.137.002-3.138-.162-1.694-.28-3.26-.926-4.881-2.059l-2.158-1.5a21.997 21.997 0 00-.755-.498l-.467-.28a55.927 55.927 0 00-.76-.43C2.908 14.73.563 14.116 0 14.116V9.888l.14.004c.564-.007 2.91-.622 3.809-1.124l1.016-.58.438-.274c.428-.28 1.072-.726 2.686-1.853 1.621-1.133 3.186-1.78 4.881-2.059 1.152-.19 1.974-.213 3.814-.138l.02-1.907z"/>
           </svg>
         )
+      // Built-in provider: GGUF files found by the models folder scan
+      case 'local':
+        return <Cpu className="w-5 h-5" style={{ minWidth: '20px', minHeight: '20px' }} />
       default:
         return null
     }
@@ -338,6 +341,8 @@ export function ModelSelector({
                   const tooltipParts = []
                   if (hasVision) tooltipParts.push('Supports image analysis')
                   if (hasReasoning) tooltipParts.push('Supports reasoning')
+                  // GGUF models report their trained context length
+                  if (model.details?.contextLength) tooltipParts.push(`${model.details.contextLength.toLocaleString()} token context`)
                   const tooltip = tooltipParts.length > 0 ? tooltipParts.join(' • ') : undefined
                   const canDelete = selectedProvider?.type === 'ollama' // Only Ollama supports deletion
                   const isBeingDeleted = isDeleting === model.name
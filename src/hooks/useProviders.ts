@@ -20,6 +20,7 @@ export function useProviders() {
       ProviderFactory.getDefaultConfig('openai'),
       ProviderFactory.getDefaultConfig('anthropic'),
       ProviderFactory.getDefaultConfig('openrouter'),
+      ProviderFactory.getDefaultConfig('local'),
     ]
 
     // Load from localStorage if available
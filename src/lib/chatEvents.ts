@@ -0,0 +1,88 @@
+/**
+ * Provider-neutral chat schema shared with the Rust provider clients
+ * (ollama_chat, openai_compat_chat, anthropic_chat)
+ */
+
+import type { CommandError } from './commandError';
+import type { ToolCall, ToolDefinition, ToolDialect } from '../types/tools';
+
+export interface ChatRequestImage {
+  /** Base64 encoded, without a data: prefix */
+  data: string;
+  mimeType: string;
+}
+
+export interface ChatRequestMessage {
+  role: 'system' | 'user' | 'assistant' | 'tool';
+  content: string;
+  images?: ChatRequestImage[];
+  toolCalls?: ToolCall[];
+  /** For tool messages, the call they answer */
+  toolCallId?: string;
+  /** The reasoningBlocks of the completion this assistant message came from */
+  reasoningBlocks?: Record<string, unknown>[];
+}
+
+export interface ChatRequest {
+  model: string;
+  messages: ChatRequestMessage[];
+  tools?: ToolDefinition[];
+  /** JSON Schema the reply must follow (llama.cpp grammar, Ollama format, OpenAI response_format) */
+  responseSchema?: Record<string, unknown>;
+  /** Describe the tools in the prompt and parse calls out of the text, for models without native tool calling */
+  toolDialect?: ToolDialect;
+  maxTokens?: number;
+  temperature?: number;
+  topP?: number;
+  topK?: number;
+  frequencyPenalty?: number;
+  presencePenalty?: number;
+  seed?: number;
+  stop?: string[];
+  /**
+   * Turns on reasoning; Anthropic uses it as the thinking budget in tokens,
+   * which must stay below `maxTokens`
+   */
+  thinkingBudget?: number;
+  /** Provider-specific fields merged into the request body as-is */
+  extra?: Record<string, unknown>;
+}
+
+export type FinishReason = 'stop' | 'length' | 'tool_calls' | 'content_filter' | 'other';
+
+export interface ChatUsage {
+  inputTokens: number;
+  outputTokens: number;
+  totalTokens: number;
+}
+
+export interface ChatToolCallResult {
+  id: string;
+  name: string;
+  /** JSON text, exactly as the model produced it */
+  arguments: string;
+}
+
+export interface ChatCompletion {
+  model?: string;
+  content: string;
+  reasoning?: string;
+  /**
+   * Provider reasoning blocks to send back unchanged with this message when
+   * tool results follow (Anthropic's signed thinking)
+   */
+  reasoningBlocks?: Record<string, unknown>[];
+  toolCalls: ChatToolCallResult[];
+  finishReason: FinishReason;
+  usage?: ChatUsage;
+}
+
+export type ChatEvent =
+  | { event: 'textDelta'; data: { text: string } }
+  | { event: 'reasoningDelta'; data: { text: string } }
+  | { event: 'toolCallStart'; data: { index: number; id: string; name: string } }
+  | { event: 'toolCallArgsDelta'; data: { index: number; arguments: string } }
+  | { event: 'toolCallEnd'; data: { index: number } & ChatToolCallResult }
+  | { event: 'usage'; data: ChatUsage }
+  | { event: 'finish'; data: { reason: FinishReason } }
+  | { event: 'error'; data: CommandError };
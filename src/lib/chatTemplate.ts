@@ -0,0 +1,49 @@
+/**
+ * Chat templates rendered by the Rust backend, for endpoints that take a raw
+ * prompt (llama.cpp's /completion, koboldcpp). Templates are Jinja, rendered
+ * like Hugging Face's apply_chat_template.
+ */
+
+import { invoke } from '@tauri-apps/api/core';
+import type { ChatRequestMessage } from './chatEvents';
+import type { ToolDefinition } from '../types/tools';
+
+export interface ChatTemplateRequest {
+  messages: ChatRequestMessage[];
+  tools?: ToolDefinition[];
+  /** GGUF file to take the template and BOS/EOS tokens from */
+  modelPath?: string;
+  /** Built-in name (see listChatTemplates) or Jinja source; overrides the model's template */
+  template?: string;
+  bosToken?: string;
+  eosToken?: string;
+  /** Defaults to true */
+  addGenerationPrompt?: boolean;
+  /** Keep the leading BOS text; servers normally add the BOS token themselves */
+  keepBos?: boolean;
+}
+
+export interface RenderedChat {
+  prompt: string;
+  source: 'override' | 'model' | 'builtin';
+  templateName?: string;
+  bosToken: string;
+  eosToken: string;
+  /** Stop strings to send with the completion request */
+  stop: string[];
+}
+
+export interface BuiltinChatTemplate {
+  name: string;
+  template: string;
+  bosToken: string;
+  eosToken: string;
+}
+
+export function renderChatTemplate(request: ChatTemplateRequest): Promise<RenderedChat> {
+  return invoke('render_chat_template', { request });
+}
+
+export function listChatTemplates(): Promise<BuiltinChatTemplate[]> {
+  return invoke('list_chat_templates');
+}
@@ -0,0 +1,55 @@
+/**
+ * Typed errors returned by the networked Rust commands
+ * (fetch_url, search_duckduckgo, scrape_*, proxy_http_request...)
+ */
+
+export type CommandErrorKind =
+  | 'network'
+  | 'timeout'
+  | 'http_status'
+  | 'invalid_url'
+  | 'blocked'
+  | 'browser_unavailable'
+  | 'parse'
+  | 'cancelled'
+  | 'invalid_request'
+  | 'internal'
+  /** The secrets vault must be unlocked first, or the passphrase was wrong */
+  | 'locked';
+
+export interface CommandError {
+  kind: CommandErrorKind;
+  message: string;
+  /** HTTP status, for `http_status` errors */
+  status?: number;
+  url?: string;
+  /** Whether trying again later may succeed */
+  retryable: boolean;
+}
+
+export function isCommandError(value: unknown): value is CommandError {
+  return (
+    typeof value === 'object' &&
+    value !== null &&
+    typeof (value as CommandError).kind === 'string' &&
+    typeof (value as CommandError).message === 'string'
+  );
+}
+
+/**
+ * Normalize anything thrown by `invoke` (or a frontend timeout) into a CommandError
+ */
+export function toCommandError(
+  value: unknown,
+  fallbackKind: CommandErrorKind = 'internal'
+): CommandError {
+  if (isCommandError(value)) {
+    return value;
+  }
+  const message = value instanceof Error ? value.message : String(value);
+  return {
+    kind: fallbackKind,
+    message,
+    retryable: fallbackKind === 'network' || fallbackKind === 'timeout',
+  };
+}
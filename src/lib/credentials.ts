@@ -0,0 +1,80 @@
+/**
+ * Provider API keys held by the Rust backend.
+ * Keys are written once and never read back; requests name the provider id
+ * and the backend adds the Authorization / x-api-key header itself, but only
+ * on requests to the origin of the base URL the key was saved with.
+ */
+
+import { invoke } from '@tauri-apps/api/core';
+import { getApiKey, removeApiKey } from './secureStorage';
+
+export type AuthScheme = 'bearer' | 'x_api_key';
+
+export interface ProviderKeyStatus {
+  providerId: string;
+  /** Masked key, e.g. "sk-…abcd" */
+  preview: string;
+  scheme: AuthScheme;
+  /** "https://host:port" the key is sent to; null for old keys that need saving again */
+  origin: string | null;
+  updatedAt: string;
+}
+
+export function listProviderKeys(): Promise<ProviderKeyStatus[]> {
+  return invoke('list_provider_keys');
+}
+
+/**
+ * Store a key for the provider at `baseUrl`; the scheme defaults to x-api-key
+ * for "anthropic" and Bearer otherwise
+ */
+export function setProviderKey(
+  providerId: string,
+  key: string,
+  baseUrl: string,
+  scheme?: AuthScheme
+): Promise<ProviderKeyStatus> {
+  return invoke('set_provider_key', { providerId, key, baseUrl, scheme });
+}
+
+/**
+ * Check the stored key against the provider's model list; rejects with a CommandError,
+ * also when `baseUrl` isn't on the key's origin
+ */
+export function testProviderKey(providerId: string, baseUrl: string): Promise<void> {
+  return invoke('test_provider_key', { providerId, baseUrl });
+}
+
+/**
+ * Replace a key once the provider accepts the new one; the old key stays on failure.
+ * The new key keeps the old key's origin.
+ */
+export function rotateProviderKey(
+  providerId: string,
+  key: string,
+  baseUrl: string
+): Promise<ProviderKeyStatus> {
+  return invoke('rotate_provider_key', { providerId, key, baseUrl });
+}
+
+export function deleteProviderKey(providerId: string): Promise<boolean> {
+  return invoke('delete_provider_key', { providerId });
+}
+
+/**
+ * Move keys left in localStorage by older versions into the backend store
+ */
+export async function migrateLegacyApiKeys(
+  providers: Array<{ id: string; baseUrl: string }>
+): Promise<void> {
+  for (const { id: providerId, baseUrl } of providers) {
+    const key = getApiKey(providerId);
+    if (!key) continue;
+    try {
+      await setProviderKey(providerId, key, baseUrl);
+      removeApiKey(providerId);
+    } catch (error) {
+      console.warn(`Failed to migrate API key for ${providerId}:`, error);
+    }
+  }
+}
@@ -0,0 +1,40 @@
+/**
+ * Local LLM servers found by probing well-known ports from the Rust backend.
+ * Results carry the provider type and base URL the provider factory expects.
+ */
+
+import { invoke } from '@tauri-apps/api/core';
+import type { ProviderConfig, ProviderType } from '../types';
+
+export interface DiscoveredProvider {
+  providerType: ProviderType;
+  /** Software found, e.g. "vllm" or "openai-compatible" when served through another provider type */
+  server: string;
+  name: string;
+  baseUrl: string;
+  version?: string;
+  models: string[];
+  latencyMs: number;
+  /** Set when the server was found but needs setup before it can be used */
+  hint?: string;
+}
+
+/**
+ * Probe the well-known ports (Ollama, LM Studio, llama.cpp, koboldcpp,
+ * text-generation-webui, vLLM), or only `ports` when given
+ */
+export function discoverLocalProviders(
+  ports?: number[],
+  timeoutMs?: number
+): Promise<DiscoveredProvider[]> {
+  return invoke('discover_local_providers', { ports, timeoutMs });
+}
+
+export function toProviderConfig(found: DiscoveredProvider): ProviderConfig {
+  return {
+    type: found.providerType,
+    name: found.name,
+    baseUrl: found.baseUrl,
+    enabled: !found.hint,
+  };
+}
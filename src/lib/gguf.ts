@@ -0,0 +1,88 @@
+/**
+ * GGUF model files read by the Rust backend. Only the header is read, so
+ * inspecting a model is fast and doesn't load any weights.
+ */
+
+import { invoke } from '@tauri-apps/api/core';
+import type { ModelInfo } from '../types';
+
+export interface GgufInfo {
+  path: string;
+  fileName: string;
+  /** All parts of a split model */
+  sizeBytes: number;
+  splitCount: number;
+  version: number;
+  /** "model", "mmproj" (vision projector) or "adapter" (LoRA) */
+  kind: string;
+  name?: string;
+  architecture?: string;
+  /** e.g. "Q4_K_M" */
+  quantization?: string;
+  parameterCount: number;
+  /** e.g. "8B" */
+  sizeLabel?: string;
+  contextLength?: number;
+  embeddingLength?: number;
+  blockCount?: number;
+  /** "llama" (SentencePiece), "gpt2" (BPE), ... */
+  tokenizerModel?: string;
+  vocabSize?: number;
+  chatTemplate?: string;
+  tensorCount: number;
+  /** Remaining metadata; long arrays are replaced by { arrayLength } */
+  metadata: Record<string, unknown>;
+}
+
+export interface GgufSummary {
+  path: string;
+  fileName: string;
+  sizeBytes: number;
+  splitCount: number;
+  kind?: string;
+  name?: string;
+  architecture?: string;
+  quantization?: string;
+  parameterCount?: number;
+  sizeLabel?: string;
+  contextLength?: number;
+  hasChatTemplate?: boolean;
+  /** Set when the header couldn't be read */
+  error?: string;
+}
+
+export function inspectGguf(path: string): Promise<GgufInfo> {
+  return invoke('inspect_gguf', { path });
+}
+
+/**
+ * Every GGUF model under `dir`, including subfolders. Split models are
+ * listed once, by their first part.
+ */
+export function scanGgufModels(dir: string): Promise<GgufSummary[]> {
+  return invoke('scan_gguf_models', { dir });
+}
+
+export function formatModelSize(bytes: number): string {
+  const gb = bytes / 1024 ** 3;
+  return gb >= 1 ? `${gb.toFixed(1)} GB` : `${Math.round(bytes / 1024 ** 2)} MB`;
+}
+
+/**
+ * Model selector entry for a GGUF file
+ */
+export function ggufToModelInfo(info: GgufInfo | GgufSummary, name = info.fileName): ModelInfo {
+  const size = [formatModelSize(info.sizeBytes), info.sizeLabel, info.quantization].filter(Boolean).join(' · ');
+  return {
+    name,
+    size,
+    details: {
+      path: info.path,
+      architecture: info.architecture,
+      quantization: info.quantization,
+      parameterCount: info.parameterCount,
+      contextLength: info.contextLength,
+      ...('tokenizerModel' in info ? { tokenizerModel: info.tokenizerModel } : {}),
+    },
+  };
+}
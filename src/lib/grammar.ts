@@ -0,0 +1,11 @@
+/**
+ * GBNF grammars for llama.cpp, generated from JSON Schemas by the Rust
+ * backend. Chat requests sent through openai_compat_chat with kind
+ * "llamacpp" get them automatically; this is for raw /completion requests.
+ */
+
+import { invoke } from '@tauri-apps/api/core';
+
+export function jsonSchemaToGrammar(schema: Record<string, unknown>): Promise<string> {
+  return invoke('json_schema_to_grammar', { schema });
+}
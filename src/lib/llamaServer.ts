@@ -0,0 +1,132 @@
+/**
+ * llama.cpp `llama-server` processes managed by the Rust backend.
+ * Profiles describe how to launch a server; the backend starts it, reports
+ * when the model has loaded, restarts it after crashes and kills it when
+ * the app exits.
+ */
+
+import { invoke } from '@tauri-apps/api/core';
+import { listen, type UnlistenFn } from '@tauri-apps/api/event';
+
+export interface LlamaServerProfile {
+  /** Leave empty when creating a profile */
+  id: string;
+  name: string;
+  /** Path to llama-server, or a bare name looked up on PATH */
+  binaryPath: string;
+  /** GGUF file to load */
+  modelPath: string;
+  contextSize?: number;
+  threads?: number;
+  port: number;
+  /** Passed after the generated arguments */
+  extraArgs: string[];
+  restartOnCrash: boolean;
+}
+
+export type LlamaServerState = 'stopped' | 'starting' | 'ready' | 'crashed';
+
+export interface LlamaServerStatus {
+  profileId: string;
+  state: LlamaServerState;
+  /** Base URL for a llamacpp provider config */
+  baseUrl: string;
+  pid?: number;
+  startedAt?: string;
+  readyAt?: string;
+  exitCode?: number;
+  error?: string;
+  /** Automatic restarts since the server was last started by hand */
+  restarts: number;
+}
+
+export interface LlamaServerLogLine {
+  seq: number;
+  /** 'system' lines come from the supervisor (started, exited, ...) */
+  stream: 'stdout' | 'stderr' | 'system';
+  text: string;
+  at: string;
+}
+
+export function listLlamaServerProfiles(): Promise<LlamaServerProfile[]> {
+  return invoke('list_llama_server_profiles');
+}
+
+export function saveLlamaServerProfile(profile: LlamaServerProfile): Promise<LlamaServerProfile> {
+  return invoke('save_llama_server_profile', { profile });
+}
+
+/**
+ * Stops the server first if it's running
+ */
+export function deleteLlamaServerProfile(profileId: string): Promise<void> {
+  return invoke('delete_llama_server_profile', { profileId });
+}
+
+export function getLlamaServerStatus(): Promise<LlamaServerStatus[]> {
+  return invoke('get_llama_server_status');
+}
+
+/**
+ * Resolves once the process runs; the status turns 'ready' when the model has loaded
+ */
+export function startLlamaServer(profileId: string): Promise<LlamaServerStatus> {
+  return invoke('start_llama_server', { profileId });
+}
+
+export function stopLlamaServer(profileId: string): Promise<LlamaServerStatus> {
+  return invoke('stop_llama_server', { profileId });
+}
+
+export function restartLlamaServer(profileId: string): Promise<LlamaServerStatus> {
+  return invoke('restart_llama_server', { profileId });
+}
+
+/**
+ * Buffered output, oldest first; pass the last seen `seq` to get only newer lines
+ */
+export function getLlamaServerLogs(profileId: string, since?: number): Promise<LlamaServerLogLine[]> {
+  return invoke('get_llama_server_logs', { profileId, since });
+}
+
+export function onLlamaServerStatus(callback: (status: LlamaServerStatus) => void): Promise<UnlistenFn> {
+  return listen<LlamaServerStatus>('llama-server-status', event => callback(event.payload));
+}
+
+/**
+ * Feeds a log viewer: the buffered lines first, then each new line as it's written
+ */
+export async function followLlamaServerLogs(
+  profileId: string,
+  callback: (lines: LlamaServerLogLine[]) => void
+): Promise<UnlistenFn> {
+  let lastSeq = 0;
+  const deliver = (lines: LlamaServerLogLine[]) => {
+    const fresh = lines.filter(line => line.seq > lastSeq);
+    if (fresh.length > 0) {
+      lastSeq = fresh[fresh.length - 1].seq;
+      callback(fresh);
+    }
+  };
+
+  // Subscribe before reading the buffer so no line falls in between; lines
+  // that arrive meanwhile are held until the buffer has been delivered
+  let early: LlamaServerLogLine[] | null = [];
+  const unlisten = await listen<{ profileId: string; line: LlamaServerLogLine }>('llama-server-log', event => {
+    if (event.payload.profileId !== profileId) {
+      return;
+    }
+    if (early) {
+      early.push(event.payload.line);
+    } else {
+      deliver([event.payload.line]);
+    }
+  });
+  try {
+    deliver(await getLlamaServerLogs(profileId));
+  } finally {
+    deliver(early);
+    early = null;
+  }
+  return unlisten;
+}
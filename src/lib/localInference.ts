@@ -0,0 +1,77 @@
+/**
+ * GGUF models run on the CPU inside the app by the Rust backend, with no
+ * Ollama or llama.cpp server. Only available in builds with the
+ * `local-inference` cargo feature; check `localInferenceStatus().available`.
+ */
+
+import { Channel, invoke } from '@tauri-apps/api/core';
+import type { ChatCompletion, ChatEvent, ChatRequest } from './chatEvents';
+import type { GgufSummary } from './gguf';
+
+export interface LoadedLocalModel {
+  path: string;
+  name: string;
+  architecture: string;
+  contextLength: number;
+  threads: number;
+}
+
+export interface LocalInferenceStatus {
+  available: boolean;
+  /** Where local_list_models looks for models */
+  modelsDir?: string;
+  loaded?: LoadedLocalModel;
+}
+
+export function localInferenceStatus(): Promise<LocalInferenceStatus> {
+  return invoke('local_inference_status');
+}
+
+/**
+ * GGUF files in the models folder; pass `path` relative to it as the model name
+ */
+export function listLocalModels(): Promise<GgufSummary[]> {
+  return invoke('local_list_models');
+}
+
+/**
+ * Loads a model ahead of the first message. `model` is relative to the
+ * models folder or absolute; `threads` defaults to the number of CPU cores.
+ */
+export function loadLocalModel(model: string, threads?: number): Promise<LoadedLocalModel> {
+  return invoke('local_load_model', { model, threads });
+}
+
+/**
+ * Frees the loaded model; a response being generated ends with a 'cancelled' error
+ */
+export function unloadLocalModel(): Promise<void> {
+  return invoke('local_unload_model');
+}
+
+/**
+ * Streams a response over `onEvent`; aborting `signal` stops generation.
+ * `chatTemplate` (a built-in name or Jinja source) overrides the model's.
+ */
+export function localChat(
+  request: ChatRequest,
+  onEvent: (event: ChatEvent) => void,
+  options: { threads?: number; chatTemplate?: string; signal?: AbortSignal } = {}
+): Promise<ChatCompletion> {
+  const channel = new Channel<ChatEvent>();
+  channel.onmessage = onEvent;
+
+  const requestId = crypto.randomUUID();
+  options.signal?.addEventListener(
+    'abort',
+    () => { invoke('cancel_request', { requestId }).catch(() => {}); },
+    { once: true }
+  );
+  return invoke('local_chat', {
+    request,
+    requestId,
+    threads: options.threads,
+    chatTemplate: options.chatTemplate,
+    onEvent: channel,
+  });
+}
@@ -0,0 +1,69 @@
+/**
+ * Ollama model pulls queued in the Rust backend.
+ * Pulls run one at a time in the background and keep going across window
+ * reloads; unfinished pulls resume when the app starts again.
+ */
+
+import { invoke } from '@tauri-apps/api/core';
+import { listen, type UnlistenFn } from '@tauri-apps/api/event';
+import type { CommandError } from './commandError';
+
+export type PullState = 'queued' | 'active' | 'completed' | 'failed' | 'cancelled';
+
+export interface LayerProgress {
+  digest: string;
+  total: number;
+  completed: number;
+}
+
+export interface ModelPull {
+  id: string;
+  baseUrl: string;
+  model: string;
+  insecure: boolean;
+  state: PullState;
+  /** Ollama's latest status line, e.g. "pulling manifest" */
+  status: string;
+  layers: LayerProgress[];
+  /** Bytes over all layers */
+  completed: number;
+  total: number;
+  error?: CommandError;
+  attempts: number;
+  createdAt: string;
+  finishedAt?: string;
+}
+
+export function listModelPulls(): Promise<ModelPull[]> {
+  return invoke('list_model_pulls');
+}
+
+/**
+ * Queue a pull; returns the existing pull when the same model is already
+ * queued or running on that server
+ */
+export function queueModelPull(baseUrl: string, model: string, insecure?: boolean): Promise<ModelPull> {
+  return invoke('queue_model_pull', { baseUrl, model, insecure });
+}
+
+/**
+ * A running pull reports 'cancelled' through onModelPullProgress once its stream stops
+ */
+export function cancelModelPull(id: string): Promise<ModelPull> {
+  return invoke('cancel_model_pull', { id });
+}
+
+export function retryModelPull(id: string): Promise<ModelPull> {
+  return invoke('retry_model_pull', { id });
+}
+
+export function clearFinishedModelPulls(): Promise<ModelPull[]> {
+  return invoke('clear_finished_model_pulls');
+}
+
+/**
+ * Called with the whole pull whenever it starts, makes progress or ends
+ */
+export function onModelPullProgress(callback: (pull: ModelPull) => void): Promise<UnlistenFn> {
+  return listen<ModelPull>('model-pull-progress', event => callback(event.payload));
+}
@@ -5,7 +5,9 @@
  * This is NOT military-grade encryption, but prevents casual inspection
  * of localStorage and makes it harder to extract keys from disk.
  * 
- * For production use, consider using Tauri's secure storage plugin.
+ * Deprecated: API keys now live in the backend's encrypted vault (see
+ * credentials.ts and vault.ts). This module is kept to read and migrate keys
+ * stored by earlier versions.
  */
 
 // Generate a device-specific key based on browser fingerprint
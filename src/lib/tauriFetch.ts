@@ -5,6 +5,8 @@
  * This wrapper automatically uses the right implementation.
  */
 
+import type { CommandError } from './commandError'
+
 // Check if we're running in Tauri
 const isTauri = () => {
   return typeof window !== 'undefined' && '__TAURI__' in window
@@ -37,17 +39,87 @@ const getTauriCore = async () => {
 }
 
 /**
- * Fetch wrapper that works in both browser and Tauri environments
+ * Response returned by the Rust `proxy_http_request` command
+ */
+export interface ProxyResponse {
+  status: number
+  statusText: string
+  headers: Record<string, string>
+  body: string
+}
+
+const headersToRecord = (headers?: HeadersInit): Record<string, string> | undefined => {
+  if (!headers) return undefined
+  const record: Record<string, string> = {}
+  new Headers(headers).forEach((value, key) => {
+    record[key] = value
+  })
+  return record
+}
+
+// Cancel the backend request when the caller's signal aborts
+const linkAbortSignal = (core: any, signal?: AbortSignal | null): string => {
+  const requestId = crypto.randomUUID()
+  signal?.addEventListener(
+    'abort',
+    () => { core.invoke('cancel_request', { requestId }).catch(() => {}) },
+    { once: true }
+  )
+  return requestId
+}
+
+// Send a request through the Rust `proxy_http_request` command
+const proxyFetch = async (
+  core: any,
+  url: string,
+  options: RequestInit,
+  providerId?: string
+): Promise<Response> => {
+  const method = options.method || 'GET'
+  const body = options.body ? String(options.body) : undefined
+
+  const proxied = await core.invoke('proxy_http_request', {
+    url,
+    method,
+    body,
+    headers: headersToRecord(options.headers),
+    providerId,
+    requestId: linkAbortSignal(core, options.signal)
+  }) as ProxyResponse
+
+  // Create a Response from the proxied status, headers and body
+  const nullBody = method.toUpperCase() === 'HEAD' || [204, 205, 304].includes(proxied.status)
+  return new Response(nullBody ? null : proxied.body, {
+    status: proxied.status,
+    statusText: proxied.statusText,
+    headers: new Headers(proxied.headers)
+  })
+}
+
+/**
+ * Fetch wrapper that works in both browser and Tauri environments.
+ * With a `providerId`, the request goes through the Rust proxy, which adds
+ * the API key stored for that provider.
  */
 export async function tauriFetch(
   url: string,
-  options: RequestInit = {}
+  options: RequestInit = {},
+  providerId?: string
 ): Promise<Response> {
   // In browser, use standard fetch
   if (!isTauri()) {
     return fetch(url, options)
   }
 
+  // Only the backend knows the provider's API key
+  if (providerId) {
+    const core = await getTauriCore()
+    if (!core || !core.invoke) {
+      throw new Error('Rust proxy is only available in Tauri')
+    }
+    return proxyFetch(core, url, options, providerId)
+  }
+
   // In Tauri, try multiple strategies for localhost URLs
   if (url.includes('localhost') || url.includes('127.0.0.1')) {
     // Silent mode - only log if verbose debugging is needed
@@ -81,23 +153,7 @@ export async function tauriFetch(
       if (isVerbose) console.log('[TauriFetch] Strategy 3: Using Rust proxy')
       const core = await getTauriCore()
       if (core && core.invoke) {
-        const method = options.method || 'GET'
-        const body = options.body ? String(options.body) : undefined
-
-        const responseText = await core.invoke('proxy_http_request', {
-          url,
-          method,
-          body
-        })
-
-        // Create a Response-like object
-        return new Response(responseText, {
-          status: 200,
-          statusText: 'OK',
-          headers: new Headers({
-            'Content-Type': 'application/json'
-          })
-        })
+        return await proxyFetch(core, url, options)
       }
     } catch (e) {
       // Silent - expected failure when provider is not running
@@ -113,6 +169,58 @@ export async function tauriFetch(
   return fetch(url, options)
 }
 
+/**
+ * Events emitted by the Rust `proxy_http_request_stream` command
+ */
+export type ProxyStreamEvent =
+  | { event: 'started'; data: { status: number; headers: Record<string, string> } }
+  | { event: 'chunk'; data: { text: string } }
+  | { event: 'finished'; data: { status: number; totalBytes: number } }
+  | { event: 'error'; data: CommandError }
+
+/**
+ * Stream a response through the Rust backend proxy.
+ * Chunks are delivered to `onChunk` as they arrive; resolves with the final status.
+ * With a `providerId`, the backend adds the API key stored for that provider.
+ */
+export async function tauriFetchStream(
+  url: string,
+  options: RequestInit,
+  onChunk: (text: string) => void,
+  providerId?: string
+): Promise<number> {
+  const core = await getTauriCore()
+  if (!core || !core.invoke || !core.Channel) {
+    throw new Error('Rust proxy streaming is only available in Tauri')
+  }
+
+  let status = 0
+  const onEvent = new core.Channel() as { onmessage: (message: ProxyStreamEvent) => void }
+  onEvent.onmessage = (message: ProxyStreamEvent) => {
+    switch (message.event) {
+      case 'started':
+      case 'finished':
+        status = message.data.status
+        break
+      case 'chunk':
+        onChunk(message.data.text)
+        break
+    }
+  }
+
+  await core.invoke('proxy_http_request_stream', {
+    url,
+    method: options.method || 'GET',
+    body: options.body ? String(options.body) : undefined,
+    headers: headersToRecord(options.headers),
+    providerId,
+    requestId: linkAbortSignal(core, options.signal),
+    onEvent
+  })
+
+  return status
+}
+
 /**
  * Check if Tauri environment is available
  */
@@ -25,6 +25,7 @@ export class Tokenizer {
         case 'ollama':
         case 'lmstudio':
         case 'llamacpp':
+        case 'local':
         case 'koboldcpp':
         case 'textgen-webui':
           return this.countOllamaTokens(text, model);
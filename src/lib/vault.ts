@@ -0,0 +1,60 @@
+/**
+ * Encrypted secrets vault kept by the Rust backend (vault.json in the app
+ * data directory). Provider API keys are stored in it.
+ */
+
+import { invoke } from '@tauri-apps/api/core';
+import { listen, type UnlistenFn } from '@tauri-apps/api/event';
+
+export interface VaultStatus {
+  exists: boolean;
+  unlocked: boolean;
+  /** false when the vault is sealed with a generated key file instead */
+  hasPassphrase: boolean;
+  /** 0 means the vault never locks itself */
+  autoLockMinutes: number;
+}
+
+export function getVaultStatus(): Promise<VaultStatus> {
+  return invoke('vault_status');
+}
+
+/**
+ * Create the vault; without a passphrase a random key file is used
+ */
+export function createVault(passphrase?: string): Promise<VaultStatus> {
+  return invoke('vault_create', { passphrase });
+}
+
+/**
+ * Rejects with a CommandError ('locked' for a wrong passphrase,
+ * 'internal' when the file was damaged or tampered with)
+ */
+export function unlockVault(passphrase?: string): Promise<VaultStatus> {
+  return invoke('vault_unlock', { passphrase });
+}
+
+export function lockVault(): Promise<VaultStatus> {
+  return invoke('vault_lock');
+}
+
+/**
+ * Re-encrypt the vault; leaving out `newPassphrase` switches to a key file
+ */
+export function changeVaultPassphrase(
+  currentPassphrase: string | undefined,
+  newPassphrase: string | undefined
+): Promise<VaultStatus> {
+  return invoke('vault_change_passphrase', { currentPassphrase, newPassphrase });
+}
+
+export function setVaultAutoLock(minutes: number): Promise<VaultStatus> {
+  return invoke('vault_set_auto_lock', { minutes });
+}
+
+/**
+ * Called when the backend locks the vault after inactivity
+ */
+export function onVaultLocked(callback: () => void): Promise<UnlistenFn> {
+  return listen('vault-locked', () => callback());
+}
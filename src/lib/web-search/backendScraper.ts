@@ -1,4 +1,5 @@
 import { invoke } from '@tauri-apps/api/core';
+import { CommandError, toCommandError } from '../commandError';
 
 export interface ContentMetadata {
   published_date?: string;
@@ -17,13 +18,34 @@ export interface ScrapedContent {
 export interface ScrapeResult {
   success: boolean;
   content?: ScrapedContent;
-  error?: string;
+  /** Typed backend error; kind 'blocked' means the egress policy refused the URL */
+  error?: CommandError;
 }
 
 export interface ScrapeOptions {
   timeout_ms?: number;
   max_retries?: number;
   max_concurrent?: number;
+  /** Aborting cancels the scrape in the backend (closes browser tabs) */
+  signal?: AbortSignal;
+}
+
+/**
+ * Create a backend request id that is cancelled when the signal aborts
+ */
+function cancellableRequestId(signal?: AbortSignal): string | undefined {
+  if (!signal) {
+    return undefined;
+  }
+  const requestId = crypto.randomUUID();
+  signal.addEventListener(
+    'abort',
+    () => {
+      invoke('cancel_request', { requestId }).catch(() => {});
+    },
+    { once: true }
+  );
+  return requestId;
 }
 
 /**
@@ -102,6 +124,7 @@ export class BackendScraper {
           timeoutMs,
           maxRetries,
           maxConcurrent,
+          requestId: cancellableRequestId(options.signal),
         }),
         overallTimeout,
         `Scraping operation timed out after ${overallTimeout}ms`
@@ -113,14 +136,14 @@ export class BackendScraper {
       
       // Check if it's a timeout error
       const isTimeout = error instanceof Error && error.message.includes('timed out');
-      const errorMessage = isTimeout
-        ? `Scraping timed out after ${timeoutMs}ms per URL`
-        : `Failed to invoke scrape_urls: ${error}`;
+      const commandError = isTimeout
+        ? toCommandError(`Scraping timed out after ${timeoutMs}ms per URL`, 'timeout')
+        : toCommandError(error);
 
       // Return error results for all URLs
       return urls.map((url) => ({
         success: false,
-        error: `${errorMessage} (URL: ${url})`,
+        error: { ...commandError, url },
       }));
     }
   }
@@ -156,6 +179,7 @@ export class BackendScraper {
           url,
           timeoutMs,
           maxRetries,
+          requestId: cancellableRequestId(options.signal),
         }),
         overallTimeout,
         `Scraping timed out after ${overallTimeout}ms`
@@ -167,13 +191,13 @@ export class BackendScraper {
       
       // Check if it's a timeout error
       const isTimeout = error instanceof Error && error.message.includes('timed out');
-      const errorMessage = isTimeout
-        ? `Scraping timed out after ${timeoutMs}ms`
-        : `Failed to invoke scrape_url: ${error}`;
+      const commandError = isTimeout
+        ? toCommandError(`Scraping timed out after ${timeoutMs}ms`, 'timeout')
+        : toCommandError(error);
 
       return {
         success: false,
-        error: errorMessage,
+        error: { ...commandError, url },
       };
     }
   }
@@ -204,8 +228,20 @@ export class BackendScraper {
    */
   getFailed(results: ScrapeResult[]): Array<{ error: string }> {
     return results
-      .filter((r) => !r.success)
-      .map((r) => ({ error: r.error || 'Unknown error' }));
+      .filter((r) => !r.success && r.error?.kind !== 'blocked')
+      .map((r) => ({ error: r.error?.message || 'Unknown error' }));
+  }
+
+  /**
+   * Get results the backend refused to fetch because of its egress policy
+   *
+   * Blocked URLs are skipped silently instead of being reported as failures.
+   *
+   * @param results Array of scrape results
+   * @returns Array of blocked results
+   */
+  getBlocked(results: ScrapeResult[]): ScrapeResult[] {
+    return results.filter((r) => r.error?.kind === 'blocked');
   }
 
   /**
@@ -269,7 +305,7 @@ export class BackendScraper {
         // Add failed result and continue
         results.push({
           success: false,
-          error: `Failed to scrape ${url}: ${error}`,
+          error: { ...toCommandError(error), url },
         });
       }
     }
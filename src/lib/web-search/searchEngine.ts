@@ -4,117 +4,52 @@
  * This module implements web search functionality using DuckDuckGo's HTML interface.
  * Features:
  * - HTML scraping via Tauri backend (no CORS issues)
- * - Token bucket rate limiting (10 requests/minute)
+ * - Per-host rate limiting in the Rust backend (10 requests/minute for DuckDuckGo)
  * - Automatic retry with exponential backoff
  */
 
 import { invoke } from '@tauri-apps/api/core';
-import { SearchResult, SearchErrorType, RateLimitStatus } from './types';
+import { SearchResult, SearchErrorType, RateLimitStatus, HostRateLimitStatus } from './types';
+import { CommandErrorKind, toCommandError } from '../commandError';
 
 // User-Agent rotation is now handled by the Rust backend
 
 // ============================================================================
-// Rate Limiting (Token Bucket Algorithm)
+// Rate Limiting
 // ============================================================================
 
-class RateLimiter {
-  private tokens: number;
-  private readonly maxTokens: number;
-  private readonly refillRate: number; // tokens per millisecond
-  private lastRefill: number;
+// Requests are throttled per host by the Rust backend (shared across windows);
+// search_duckduckgo simply waits for its slot
+const SEARCH_HOST = 'duckduckgo.com';
 
-  constructor(maxRequestsPerMinute: number = 10) {
-    this.maxTokens = maxRequestsPerMinute;
-    this.tokens = maxRequestsPerMinute;
-    this.refillRate = maxRequestsPerMinute / 60000; // per millisecond
-    this.lastRefill = Date.now();
-  }
-
-  /**
-   * Refill tokens based on time elapsed
-   */
-  private refill(): void {
-    const now = Date.now();
-    const timePassed = now - this.lastRefill;
-    const tokensToAdd = timePassed * this.refillRate;
-    
-    this.tokens = Math.min(this.maxTokens, this.tokens + tokensToAdd);
-    this.lastRefill = now;
-  }
-
-  /**
-   * Try to consume a token
-   * @returns true if token was consumed, false if rate limited
-   */
-  tryConsume(): boolean {
-    this.refill();
-    
-    if (this.tokens >= 1) {
-      this.tokens -= 1;
-      return true;
-    }
-    
-    return false;
-  }
-
-  /**
-   * Wait until a token is available
-   */
-  async waitForToken(): Promise<void> {
-    this.refill();
-    
-    if (this.tokens >= 1) {
-      this.tokens -= 1;
-      return;
-    }
-    
-    // Calculate wait time
-    const tokensNeeded = 1 - this.tokens;
-    const waitTime = Math.ceil(tokensNeeded / this.refillRate);
-    
-    await new Promise(resolve => setTimeout(resolve, waitTime));
-    
-    // Try again after waiting
-    this.refill();
-    this.tokens -= 1;
-  }
-
-  /**
-   * Get current rate limit status
-   */
-  getStatus(): RateLimitStatus {
-    this.refill();
-    
-    const requestsRemaining = Math.floor(this.tokens);
-    const isLimited = this.tokens < 1;
-    
-    // Calculate when next token will be available
-    let resetTime = new Date();
-    if (isLimited) {
-      const tokensNeeded = 1 - this.tokens;
-      const waitTime = Math.ceil(tokensNeeded / this.refillRate);
-      resetTime = new Date(Date.now() + waitTime);
-    }
-    
-    return {
-      requestsRemaining,
-      resetTime,
-      isLimited
-    };
-  }
+/**
+ * Current backend limiter state for every host contacted so far
+ */
+export async function getHostRateLimits(): Promise<HostRateLimitStatus[]> {
+  return invoke<HostRateLimitStatus[]>('get_rate_limit_status');
 }
 
 // ============================================================================
 // SearchEngine Class
 // ============================================================================
 
-export class SearchEngine {
-  private rateLimiter: RateLimiter;
-
-  constructor(maxRequestsPerMinute: number = 10) {
-    this.rateLimiter = new RateLimiter(maxRequestsPerMinute);
+// Map a backend error kind onto the search error categories
+function searchErrorType(kind: CommandErrorKind, status?: number): SearchErrorType {
+  switch (kind) {
+    case 'timeout':
+      return SearchErrorType.TIMEOUT;
+    case 'blocked':
+      return SearchErrorType.BLOCKED;
+    case 'parse':
+      return SearchErrorType.PARSE_ERROR;
+    case 'http_status':
+      return status === 429 ? SearchErrorType.RATE_LIMITED : SearchErrorType.NETWORK_ERROR;
+    default:
+      return SearchErrorType.NETWORK_ERROR;
   }
+}
 
+export class SearchEngine {
   /**
    * Search DuckDuckGo and return results
    * Uses Tauri backend to avoid CORS issues
@@ -123,11 +58,9 @@ export class SearchEngine {
    * @returns Array of search results
    */
   async search(query: string, maxResults: number = 10): Promise<SearchResult[]> {
-    // Wait for rate limit token
-    await this.rateLimiter.waitForToken();
-
     try {
-      // Call Tauri backend to perform search (avoids CORS)
+      // Call Tauri backend to perform search (avoids CORS); it waits for the
+      // DuckDuckGo rate limit before sending
       const html = await invoke<string>('search_duckduckgo', { query });
 
       // Debug: Log HTML length and first 500 chars
@@ -146,10 +79,12 @@ export class SearchEngine {
 
     } catch (error) {
       console.error('Search error:', error);
+      const commandError = toCommandError(error, 'network');
       throw {
-        type: SearchErrorType.NETWORK_ERROR,
-        message: error instanceof Error ? error.message : 'Unknown search error',
-        retryable: true
+        type: searchErrorType(commandError.kind, commandError.status),
+        message: commandError.message,
+        url: commandError.url,
+        retryable: commandError.retryable
       };
     }
   }
@@ -249,10 +184,24 @@ export class SearchEngine {
   }
 
   /**
-   * Get current rate limit status
+   * Get current rate limit status of the search host from the backend limiter
    */
-  getRateLimitStatus(): RateLimitStatus {
-    return this.rateLimiter.getStatus();
+  async getRateLimitStatus(): Promise<RateLimitStatus> {
+    const hosts = await getHostRateLimits();
+    const status = hosts.find(
+      (h) => h.host === SEARCH_HOST || h.host.endsWith(`.${SEARCH_HOST}`)
+    );
+
+    if (!status) {
+      // Not contacted yet, so nothing is limited
+      return { requestsRemaining: 1, resetTime: new Date(), isLimited: false };
+    }
+
+    return {
+      requestsRemaining: Math.floor(status.tokensAvailable),
+      resetTime: new Date(Date.now() + status.waitMs),
+      isLimited: status.limited
+    };
   }
 }
 
@@ -6,11 +6,11 @@
  */
 
 import { SearchEngine } from './searchEngine';
-import type { SearchResult } from './types';
+import type { SearchResult, RateLimitStatus } from './types';
 
 export interface ISearchEngine {
   search(query: string, maxResults?: number): Promise<SearchResult[]>;
-  getRateLimitStatus(): any;
+  getRateLimitStatus(): Promise<RateLimitStatus>;
 }
 
 /**
@@ -69,7 +69,7 @@ export class SearchEngineFactory {
   /**
    * Get rate limit status from current engine
    */
-  getRateLimitStatus(): any {
+  getRateLimitStatus(): Promise<RateLimitStatus> {
     return this.engines[this.currentEngineIndex].getRateLimitStatus();
   }
 }
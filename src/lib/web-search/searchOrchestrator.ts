@@ -194,6 +194,11 @@ export class SearchOrchestrator {
       // Extract successful results
       const successful = this.backendScraper.getSuccessful(results);
       const failed = this.backendScraper.getFailed(results);
+      const blocked = this.backendScraper.getBlocked(results);
+
+      if (blocked.length > 0) {
+        console.info(`Skipped ${blocked.length} URLs blocked by the egress policy`);
+      }
 
       if (failed.length > 0) {
         console.warn(`Failed to scrape ${failed.length}/${urls.length} URLs`);
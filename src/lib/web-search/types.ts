@@ -102,7 +102,8 @@ export enum SearchErrorType {
   RATE_LIMITED = 'rate_limited',
   PARSE_ERROR = 'parse_error',
   NO_RESULTS = 'no_results',
-  SCRAPING_FAILED = 'scraping_failed'
+  SCRAPING_FAILED = 'scraping_failed',
+  BLOCKED = 'blocked'
 }
 
 export interface SearchError {
@@ -147,6 +148,26 @@ export interface RateLimitStatus {
   isLimited: boolean;
 }
 
+/**
+ * Backend per-host limiter state (get_rate_limit_status)
+ */
+export interface HostRateLimitStatus {
+  host: string;
+  limit: {
+    requestsPerMinute: number;
+    burst: number;
+    minDelayMs: number;
+  };
+  tokensAvailable: number;
+  /** Requests currently waiting for their slot */
+  waiting: number;
+  /** How long a request made now would wait */
+  waitMs: number;
+  limited: boolean;
+  totalRequests: number;
+  lastRequestAt?: string;
+}
+
 // ============================================================================
 // Output Formatting
 // ============================================================================
@@ -7,6 +7,7 @@ import { LlamaCppProvider } from './llamacpp'
 import { OpenAIProvider } from './openai'
 import { AnthropicProvider } from './anthropic'
 import { OpenRouterProvider } from './openrouter'
+import { LocalProvider } from './local'
 
 export class ProviderFactory {
   static createProvider(config: ProviderConfig): BaseProvider {
@@ -23,6 +24,8 @@ export class ProviderFactory {
         return new AnthropicProvider(config)
       case 'openrouter':
         return new OpenRouterProvider(config)
+      case 'local':
+        return new LocalProvider(config)
       default:
         throw new Error(`Unsupported provider type: ${config.type}`)
     }
@@ -78,6 +81,13 @@ export class ProviderFactory {
         baseUrl: 'https://openrouter.ai',
         enabled: false,
       },
+      // Runs in the app itself, so there's no server URL
+      local: {
+        type: 'local',
+        name: 'Local (built-in)',
+        baseUrl: '',
+        enabled: false,
+      },
     }
 
     return defaults[type]
@@ -5,4 +5,5 @@ export { LMStudioProvider } from './lmstudio'
 export { LlamaCppProvider } from './llamacpp'
 export { OpenAIProvider } from './openai'
 export { AnthropicProvider } from './anthropic'
+export { LocalProvider } from './local'
 export { ProviderFactory } from './factory'
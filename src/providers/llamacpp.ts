@@ -1,11 +1,34 @@
 // llama.cpp server provider implementation
 import { BaseProvider } from './base'
-import type { ChatCompletionRequest, ChatCompletionResponse, ModelInfo, StreamResponse } from '../types'
+import type { ChatCompletionRequest, ChatCompletionResponse, ModelInfo, ProviderConfig, StreamResponse } from '../types'
 import { createModelCapabilities } from '../lib/visionDetection'
+import { ggufToModelInfo, inspectGguf } from '../lib/gguf'
+import { isTauriEnvironment } from '../lib/tauriFetch'
+import { renderChatTemplate } from '../lib/chatTemplate'
 
 export class LlamaCppProvider extends BaseProvider {
+  // GGUF path reported by /props, for the model's BOS/EOS tokens
+  private modelPath?: Promise<string | undefined>
+
   async listModels(): Promise<ModelInfo[]> {
-    // llama.cpp doesn't have a models endpoint, return a default entry
+    // The server runs a single model; when it reports the file, describe it
+    // from the GGUF header (size, quantization, context length)
+    try {
+      const response = await this.fetchWithTimeout(`${this.config.baseUrl}/props`, { method: 'GET' }, 2000)
+      const props = response.ok ? await response.json() : undefined
+      if (props?.model_path && isTauriEnvironment()) {
+        const info = await inspectGguf(props.model_path)
+        const name = info.name || info.fileName
+        return [{
+          ...ggufToModelInfo(info, name),
+          capabilities: createModelCapabilities(name, 'llamacpp'),
+        }]
+      }
+    } catch (error) {
+      console.warn('llama.cpp model inspection failed:', error)
+    }
+
+    // Fall back to a default entry
     return [{
       name: 'llama.cpp-model',
       details: { info: 'Model loaded in llama.cpp server' },
@@ -18,6 +41,12 @@ export class LlamaCppProvider extends BaseProvider {
     onChunk?: (content: string) => void,
     signal?: AbortSignal
   ): Promise<string> {
+    // With a template override the prompt is rendered here and sent raw,
+    // bypassing the server's own (possibly missing or wrong) template
+    if (this.config.chatTemplate && isTauriEnvironment()) {
+      return this.sendRawCompletion(request, onChunk, signal)
+    }
+
     const url = `${this.config.baseUrl}/v1/chat/completions`
     
     const body: ChatCompletionRequest = {
@@ -137,6 +166,97 @@ export class LlamaCppProvider extends BaseProvider {
     return fullContent
   }
 
+  updateConfig(config: Partial<ProviderConfig>): void {
+    super.updateConfig(config)
+    this.modelPath = undefined
+  }
+
+  private async sendRawCompletion(
+    request: ChatCompletionRequest,
+    onChunk?: (content: string) => void,
+    signal?: AbortSignal
+  ): Promise<string> {
+    this.modelPath ??= this.fetchWithTimeout(`${this.config.baseUrl}/props`, { method: 'GET' }, 2000)
+      .then(response => (response.ok ? response.json() : undefined))
+      .then(props => props?.model_path as string | undefined)
+      .catch(() => undefined)
+
+    const rendered = await renderChatTemplate({
+      messages: request.messages,
+      modelPath: await this.modelPath,
+      template: this.config.chatTemplate,
+    })
+
+    const response = await this.fetchWithTimeout(
+      `${this.config.baseUrl}/completion`,
+      {
+        method: 'POST',
+        headers: { 'Content-Type': 'application/json' },
+        body: JSON.stringify({
+          prompt: rendered.prompt,
+          n_predict: request.max_tokens ?? -1,
+          temperature: request.temperature,
+          top_p: request.top_p,
+          stop: rendered.stop,
+          stream: !!onChunk,
+          cache_prompt: true,
+        }),
+      },
+      30000,
+      signal
+    )
+
+    if (!response.ok) {
+      throw new Error(`llama.cpp request failed: ${response.statusText}`)
+    }
+
+    if (!onChunk) {
+      const data = await response.json()
+      return data.content || ''
+    }
+
+    const reader = response.body?.getReader()
+    if (!reader) {
+      throw new Error('No response body')
+    }
+
+    // Events are `data: {"content": "...", "stop": false}` lines
+    const decoder = new TextDecoder()
+    let fullContent = ''
+    let buffer = ''
+
+    try {
+      while (true) {
+        const { done, value } = await reader.read()
+        if (done) break
+
+        buffer += decoder.decode(value, { stream: true })
+        const lines = buffer.split('\n')
+        buffer = lines.pop() ?? ''
+
+        for (const line of lines) {
+          if (!line.startsWith('data: ')) continue
+          try {
+            const json = JSON.parse(line.slice(6))
+            if (json.content) {
+              fullContent += json.content
+              onChunk(json.content)
+            }
+            if (json.stop) {
+              return fullContent
+            }
+          } catch (e) {
+            console.warn('Failed to parse chunk:', line)
+          }
+        }
+      }
+    } finally {
+      reader.releaseLock()
+    }
+
+    return fullContent
+  }
+
   async testConnection(timeout = 2000): Promise<boolean> {
     try {
       const response = await this.fetchWithTimeout(
@@ -0,0 +1,68 @@
+// Built-in local provider: runs GGUF models from the app's models folder on the CPU
+import { BaseProvider } from './base'
+import type { ChatCompletionRequest, ModelInfo } from '../types'
+import { createModelCapabilities } from '../lib/visionDetection'
+import { ggufToModelInfo } from '../lib/gguf'
+import { isTauriEnvironment } from '../lib/tauriFetch'
+import { listLocalModels, localChat, localInferenceStatus } from '../lib/localInference'
+
+export class LocalProvider extends BaseProvider {
+  async listModels(): Promise<ModelInfo[]> {
+    try {
+      const status = await localInferenceStatus()
+      if (!status.available || !status.modelsDir) {
+        return []
+      }
+
+      // Models are named by their path inside the models folder
+      const prefix = status.modelsDir.length + 1
+      const models = await listLocalModels()
+      return models
+        .filter(model => !model.error && (model.kind ?? 'model') === 'model')
+        .map(model => {
+          const name = model.path.slice(prefix)
+          return {
+            ...ggufToModelInfo(model, name),
+            capabilities: createModelCapabilities(name, 'local'),
+          }
+        })
+    } catch (error) {
+      console.warn('Local listModels error:', error)
+      return []
+    }
+  }
+
+  async sendMessage(
+    request: ChatCompletionRequest,
+    onChunk?: (content: string) => void,
+    signal?: AbortSignal
+  ): Promise<string> {
+    const completion = await localChat(
+      {
+        model: request.model,
+        messages: request.messages,
+        maxTokens: request.max_tokens,
+        temperature: request.temperature,
+        topP: request.top_p,
+      },
+      event => {
+        if (event.event === 'textDelta') {
+          onChunk?.(event.data.text)
+        }
+      },
+      { chatTemplate: this.config.chatTemplate, signal }
+    )
+    return completion.content
+  }
+
+  async testConnection(): Promise<boolean> {
+    if (!isTauriEnvironment()) {
+      return false
+    }
+    try {
+      return (await localInferenceStatus()).available
+    } catch {
+      return false
+    }
+  }
+}
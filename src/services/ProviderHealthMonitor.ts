@@ -1,123 +1,130 @@
 /**
  * ProviderHealthMonitor Service
- * 
- * Centralized service for managing provider health checks with background monitoring and caching.
- * Implements singleton pattern to ensure single source of truth for health status across the application.
- * 
+ *
+ * Frontend view of the provider health checks that run in the Rust backend.
+ * The backend probes every configured provider on a schedule (with backoff
+ * while a provider is down) and emits `provider-health` events to all
+ * windows; this service only forwards the provider list and listens.
+ *
  * @example
  * // Get the singleton instance
  * const monitor = ProviderHealthMonitor.getInstance();
- * 
+ *
  * // Start monitoring providers
  * monitor.start(providers);
- * 
+ *
  * // Subscribe to status updates
  * const unsubscribe = monitor.subscribe((statuses) => {
  *   console.log('Provider statuses updated:', statuses);
  * });
- * 
+ *
  * // Get status for a specific provider
  * const ollamaStatus = monitor.getStatus('ollama');
- * 
+ *
  * // Force immediate check
  * await monitor.checkProviders(providers, { forceRefresh: true });
- * 
+ *
  * // Cleanup when done
  * unsubscribe();
  * monitor.stop();
  */
 
+import { invoke } from '@tauri-apps/api/core'
+import { listen, type UnlistenFn } from '@tauri-apps/api/event'
 import type { ProviderConfig } from '../types'
-import { ProviderFactory } from '../providers/factory'
 
 /**
  * Health status information for a provider.
- * 
+ *
  * @interface ProviderHealthStatus
  * @property {string} type - Provider type identifier (e.g., 'ollama', 'lmstudio', 'anthropic')
  * @property {boolean | undefined} status - Health status: true=healthy, false=unhealthy, undefined=unknown
  * @property {number} timestamp - Unix timestamp (milliseconds) of last health check
  * @property {boolean} checking - Whether a health check is currently in progress for this provider
+ * @property {number} [latencyMs] - Response time of the last successful check
+ * @property {string} [error] - Why the last check failed or couldn't run
  */
 export interface ProviderHealthStatus {
   type: string
   status: boolean | undefined
   timestamp: number
   checking: boolean
+  latencyMs?: number
+  error?: string
 }
 
 /**
  * Configuration options for health check operations.
- * 
+ *
  * @interface HealthCheckOptions
- * @property {number} [timeout] - Connection timeout in milliseconds (default: 2000ms)
  * @property {boolean} [forceRefresh] - Force refresh even if cached status is still valid (default: false)
  */
 export interface HealthCheckOptions {
-  timeout?: number
   forceRefresh?: boolean
 }
 
+/**
+ * Payload of the backend's `provider-health` event
+ */
+interface BackendProviderHealth {
+  providerId: string
+  status: 'unknown' | 'up' | 'down'
+  latencyMs?: number
+  error?: string
+  checkedAt?: string
+  since?: string
+  consecutiveFailures: number
+}
+
 /**
  * ProviderHealthMonitor - Singleton service for provider health monitoring.
- * 
+ *
  * This service provides:
- * - Background monitoring of provider health with 30-second intervals
- * - Persistent caching of health status in localStorage (60-second TTL)
+ * - The backend's health results, pushed as they come in
  * - Event-based notifications when provider status changes
- * - Parallel health checks for fast status updates
- * - Aggressive 2-second timeouts for quick failure detection
- * 
+ * - On-demand checks through the backend
+ *
  * **Singleton Pattern:**
- * Only one instance exists per application. Use `getInstance()` to access it.
- * 
+ * Only one instance exists per window. Use `getInstance()` to access it.
+ *
  * **Event Subscription Lifecycle:**
  * 1. Subscribe using `subscribe(callback)` - returns unsubscribe function
  * 2. Callback receives Map of all provider statuses when any status changes
  * 3. Call unsubscribe function to stop receiving updates
  * 4. Always unsubscribe in component cleanup to prevent memory leaks
- * 
+ *
  * @class ProviderHealthMonitor
  */
 export class ProviderHealthMonitor {
   private static instance: ProviderHealthMonitor | null = null
 
-  // Status cache
+  // Latest status per provider
   private statusCache: Map<string, ProviderHealthStatus> = new Map()
 
   // Event listeners
   private listeners: Set<(statuses: Map<string, ProviderHealthStatus>) => void> = new Set()
 
-  // Background monitoring
-  private monitorInterval: number | null = null
+  // Backend event subscription
+  private unlisten: UnlistenFn | null = null
   private isMonitoring: boolean = false
 
-  // Configuration constants
+  // Results older than this are refreshed when the UI asks for them
   private readonly CACHE_TTL = 60_000      // 60 seconds
-  private readonly CHECK_INTERVAL = 30_000  // 30 seconds
-  private readonly DEFAULT_TIMEOUT = 2_000  // 2 seconds
 
   /**
    * Private constructor to enforce singleton pattern.
    * Use `getInstance()` to access the monitor instance.
-   * 
+   *
    * @private
    */
-  private constructor() {
-    // Initialize empty cache
-    this.statusCache = new Map()
-    this.listeners = new Set()
-  }
+  private constructor() {}
 
   /**
    * Get the singleton instance of ProviderHealthMonitor.
    * Creates the instance on first call, returns existing instance on subsequent calls.
-   * 
+   *
    * @static
    * @returns {ProviderHealthMonitor} The singleton instance
-   * 
-   * @example
-   * const monitor = ProviderHealthMonitor.getInstance();
    */
   public static getInstance(): ProviderHealthMonitor {
     if (!ProviderHealthMonitor.instance) {
@@ -128,30 +135,19 @@ export class ProviderHealthMonitor {
 
   /**
    * Subscribe to provider health status updates.
-   * 
+   *
    * The callback will be invoked whenever any provider's health status changes,
-   * receiving a Map of all current provider statuses. The callback receives a
-   * copy of the status map to prevent external modifications.
-   * 
+   * receiving a copy of the Map of all current provider statuses.
+   *
    * **Important:** Always call the returned unsubscribe function in component
    * cleanup (e.g., useEffect cleanup) to prevent memory leaks.
-   * 
+   *
    * @param {Function} callback - Function to call when statuses change
    * @returns {Function} Unsubscribe function to stop receiving updates
-   * 
-   * @example
-   * // In a React component
-   * useEffect(() => {
-   *   const unsubscribe = monitor.subscribe((statuses) => {
-   *     setConnectionStatus(new Map(statuses));
-   *   });
-   *   
-   *   return unsubscribe; // Cleanup on unmount
-   * }, []);
    */
   public subscribe(callback: (statuses: Map<string, ProviderHealthStatus>) => void): () => void {
     this.listeners.add(callback)
-    
+
     // Return unsubscribe function
     return () => {
       this.listeners.delete(callback)
@@ -160,18 +156,9 @@ export class ProviderHealthMonitor {
 
   /**
    * Get the current health status for a specific provider.
-   * 
-   * Returns cached status if available. Status may be stale if cache TTL has expired.
-   * Use `isCacheValid()` to check if the status is still fresh.
-   * 
+   *
    * @param {string} providerType - Provider type identifier (e.g., 'ollama', 'lmstudio')
    * @returns {ProviderHealthStatus | undefined} Provider health status or undefined if not found
-   * 
-   * @example
-   * const ollamaStatus = monitor.getStatus('ollama');
-   * if (ollamaStatus && monitor.isCacheValid(ollamaStatus)) {
-   *   console.log('Ollama is', ollamaStatus.status ? 'healthy' : 'unhealthy');
-   * }
    */
   public getStatus(providerType: string): ProviderHealthStatus | undefined {
     return this.statusCache.get(providerType)
@@ -179,16 +166,10 @@ export class ProviderHealthMonitor {
 
   /**
    * Get all provider health statuses.
-   * 
+   *
    * Returns a copy of the internal status cache to prevent external modifications.
-   * 
+   *
    * @returns {Map<string, ProviderHealthStatus>} Map of all provider health statuses
-   * 
-   * @example
-   * const allStatuses = monitor.getAllStatuses();
-   * allStatuses.forEach((status, providerType) => {
-   *   console.log(`${providerType}: ${status.status}`);
-   * });
    */
   public getAllStatuses(): Map<string, ProviderHealthStatus> {
     return new Map(this.statusCache)
@@ -209,199 +190,99 @@ export class ProviderHealthMonitor {
   }
 
   /**
-   * Initialize and start background monitoring for the specified providers.
-   * 
-   * This method:
-   * 1. Loads cached status from localStorage
-   * 2. Initializes status for any providers not in cache
-   * 3. Starts background monitoring with 30-second intervals
-   * 4. Performs an immediate health check
-   * 
-   * Safe to call multiple times - will warn and return if already running.
-   * 
+   * Store a backend result
+   */
+  private apply(health: BackendProviderHealth): void {
+    this.statusCache.set(health.providerId, {
+      type: health.providerId,
+      status: health.status === 'unknown' ? undefined : health.status === 'up',
+      timestamp: health.checkedAt ? Date.parse(health.checkedAt) : 0,
+      checking: false,
+      latencyMs: health.latencyMs,
+      error: health.error
+    })
+  }
+
+  /**
+   * Hand the providers to the backend monitor and start listening for results.
+   *
+   * The backend keeps checking while windows are hidden, and every window
+   * receives the same results. Safe to call multiple times - will warn and
+   * return if already running.
+   *
    * @param {ProviderConfig[]} providers - List of provider configurations to monitor
-   * 
-   * @example
-   * // In App.tsx or main entry point
-   * useEffect(() => {
-   *   const monitor = ProviderHealthMonitor.getInstance();
-   *   monitor.start(providers);
-   *   
-   *   return () => monitor.stop(); // Cleanup on unmount
-   * }, [providers]);
    */
   public start(providers: ProviderConfig[]): void {
     if (this.isMonitoring) {
       console.warn('ProviderHealthMonitor is already running')
       return
     }
+    this.isMonitoring = true
 
-    // Load cached status from localStorage
-    this.loadCache()
+    // Cache written by the webview-based monitor of earlier versions
+    localStorage.removeItem('oc.providerHealth')
 
-    // Initialize status for any providers not in cache
-    providers.forEach(provider => {
-      if (!this.statusCache.has(provider.type)) {
-        this.statusCache.set(provider.type, {
-          type: provider.type,
-          status: undefined,
-          timestamp: 0,
-          checking: false
-        })
-      }
+    listen<BackendProviderHealth>('provider-health', event => {
+      this.apply(event.payload)
+      this.notifyListeners()
     })
-
-    // Start background monitoring
-    this.startBackgroundMonitoring(providers)
-    this.isMonitoring = true
+      .then(unlisten => {
+        // stop() may have been called while subscribing
+        if (this.isMonitoring) {
+          this.unlisten = unlisten
+        } else {
+          unlisten()
+        }
+      })
+      .catch(error => console.warn('Failed to subscribe to provider health:', error))
+
+    // The built-in local provider has no server to check
+    const targets = providers.filter(provider => provider.type !== 'local').map(provider => ({
+      providerId: provider.type,
+      kind: provider.type,
+      baseUrl: provider.baseUrl
+    }))
+    invoke('set_health_targets', { targets })
+      .catch(error => console.warn('Failed to configure provider health checks:', error))
+
+    // Results from before this window subscribed
+    invoke<BackendProviderHealth[]>('get_provider_health')
+      .then(statuses => {
+        statuses.forEach(health => this.apply(health))
+        this.notifyListeners()
+      })
+      .catch(error => console.warn('Failed to load provider health:', error))
 
     console.log('ProviderHealthMonitor started')
   }
 
   /**
-   * Stop background monitoring and cleanup resources.
-   * 
-   * Clears the monitoring interval and sets monitoring flag to false.
-   * Does not clear the status cache, so cached data remains available.
-   * 
-   * @example
-   * // Cleanup when app unmounts
-   * monitor.stop();
+   * Stop listening for results. The backend keeps checking, since other
+   * windows may still be listening; cached statuses remain available.
    */
   public stop(): void {
-    this.stopBackgroundMonitoring()
+    this.unlisten?.()
+    this.unlisten = null
     this.isMonitoring = false
     console.log('ProviderHealthMonitor stopped')
   }
 
   /**
-   * Start background monitoring with interval
-   */
-  private startBackgroundMonitoring(providers: ProviderConfig[]): void {
-    // Perform initial check
-    this.performHealthChecks(providers)
-
-    // Set up interval for periodic checks
-    this.monitorInterval = window.setInterval(() => {
-      this.performHealthChecks(providers)
-    }, this.CHECK_INTERVAL)
-  }
-
-  /**
-   * Stop background monitoring
-   */
-  private stopBackgroundMonitoring(): void {
-    if (this.monitorInterval !== null) {
-      clearInterval(this.monitorInterval)
-      this.monitorInterval = null
-    }
-  }
-
-  /**
-   * Perform health checks for all providers in parallel
-   * Uses Promise.allSettled to ensure all checks complete independently
-   */
-  private async performHealthChecks(providers: ProviderConfig[]): Promise<void> {
-    // Set checking flag to true for all providers before starting
-    providers.forEach(provider => {
-      const currentStatus = this.statusCache.get(provider.type)
-      if (currentStatus) {
-        currentStatus.checking = true
-      } else {
-        this.statusCache.set(provider.type, {
-          type: provider.type,
-          status: undefined,
-          timestamp: Date.now(),
-          checking: true
-        })
-      }
-    })
-
-    // Notify listeners that checking has started
-    this.notifyListeners()
-
-    // Execute all provider tests in parallel using Promise.allSettled
-    await Promise.allSettled(
-      providers.map(async (provider) => {
-        try {
-          // Create provider instance
-          const providerInstance = ProviderFactory.createProvider(provider)
-          
-          // Test connection with default timeout
-          const isHealthy = await providerInstance.testConnection(this.DEFAULT_TIMEOUT)
-          
-          // Update status cache with result
-          this.statusCache.set(provider.type, {
-            type: provider.type,
-            status: isHealthy,
-            timestamp: Date.now(),
-            checking: false
-          })
-        } catch (error) {
-          // Isolate failures - one provider failure doesn't affect others
-          // Only log if it's an unexpected error (not connection refused/timeout)
-          const errorMsg = error instanceof Error ? error.message : String(error)
-          const isExpectedFailure = 
-            errorMsg.includes('not reachable') ||
-            errorMsg.includes('Failed to fetch') ||
-            errorMsg.includes('ERR_CONNECTION_REFUSED') ||
-            errorMsg.includes('ERR_FAILED') ||
-            errorMsg.includes('CORS') ||
-            errorMsg.includes('Network request failed')
-          
-          if (!isExpectedFailure) {
-            console.error(`Health check failed for ${provider.type}:`, error)
-          }
-          
-          // Mark provider as unhealthy
-          this.statusCache.set(provider.type, {
-            type: provider.type,
-            status: false,
-            timestamp: Date.now(),
-            checking: false
-          })
-        }
-      })
-    )
-
-    // Save updated cache to localStorage
-    this.saveCache()
-
-    // Notify all listeners of status updates
-    this.notifyListeners()
-  }
-
-  /**
-   * Force an immediate health check for specific providers.
-   * 
-   * By default, only checks providers with stale cache (older than 60 seconds).
-   * Use `forceRefresh: true` to check all providers regardless of cache age.
-   * 
-   * All checks run in parallel for fast completion. Individual provider failures
-   * are isolated and don't affect other providers.
-   * 
+   * Ask the backend for an immediate health check of specific providers.
+   *
+   * By default, only checks providers with stale results (older than 60 seconds).
+   * Use `forceRefresh: true` to check all providers regardless of age.
+   *
    * @param {ProviderConfig[]} providers - List of provider configurations to check
    * @param {HealthCheckOptions} [options] - Optional configuration
-   * @param {number} [options.timeout=2000] - Connection timeout in milliseconds
-   * @param {boolean} [options.forceRefresh=false] - Force refresh even if cache is valid
    * @returns {Promise<void>} Resolves when all checks complete
-   * 
-   * @example
-   * // Check providers with stale cache
-   * await monitor.checkProviders(providers);
-   * 
-   * // Force check all providers with custom timeout
-   * await monitor.checkProviders(providers, { 
-   *   forceRefresh: true, 
-   *   timeout: 5000 
-   * });
    */
   public async checkProviders(providers: ProviderConfig[], options?: HealthCheckOptions): Promise<void> {
     const forceRefresh = options?.forceRefresh ?? false
 
     // Filter providers based on forceRefresh option
-    const providersToCheck = forceRefresh 
-      ? providers 
+    const providersToCheck = forceRefresh
+      ? providers
       : providers.filter(provider => {
           const status = this.statusCache.get(provider.type)
           return !status || !this.isCacheValid(status)
@@ -415,147 +296,44 @@ export class ProviderHealthMonitor {
     // Set checking flag to true for providers being checked
     providersToCheck.forEach(provider => {
       const currentStatus = this.statusCache.get(provider.type)
-      if (currentStatus) {
-        currentStatus.checking = true
-      } else {
-        this.statusCache.set(provider.type, {
-          type: provider.type,
-          status: undefined,
-          timestamp: Date.now(),
-          checking: true
-        })
-      }
-    })
-
-    // Notify listeners that checking has started
-    this.notifyListeners()
-
-    // Execute all provider tests in parallel using Promise.allSettled
-    await Promise.allSettled(
-      providersToCheck.map(async (provider) => {
-        try {
-          // Create provider instance
-          const providerInstance = ProviderFactory.createProvider(provider)
-          
-          // Test connection with timeout from options or default
-          const timeout = options?.timeout ?? this.DEFAULT_TIMEOUT
-          const isHealthy = await providerInstance.testConnection(timeout)
-          
-          // Update status cache with result
-          this.statusCache.set(provider.type, {
-            type: provider.type,
-            status: isHealthy,
-            timestamp: Date.now(),
-            checking: false
-          })
-        } catch (error) {
-          // Isolate failures - one provider failure doesn't affect others
-          // Only log if it's an unexpected error (not connection refused/timeout)
-          const errorMsg = error instanceof Error ? error.message : String(error)
-          const isExpectedFailure = 
-            errorMsg.includes('not reachable') ||
-            errorMsg.includes('Failed to fetch') ||
-            errorMsg.includes('ERR_CONNECTION_REFUSED') ||
-            errorMsg.includes('ERR_FAILED') ||
-            errorMsg.includes('CORS') ||
-            errorMsg.includes('Network request failed')
-          
-          if (!isExpectedFailure) {
-            console.error(`Health check failed for ${provider.type}:`, error)
-          }
-          
-          // Mark provider as unhealthy
-          this.statusCache.set(provider.type, {
-            type: provider.type,
-            status: false,
-            timestamp: Date.now(),
-            checking: false
-          })
-        }
+      this.statusCache.set(provider.type, {
+        type: provider.type,
+        status: currentStatus?.status,
+        timestamp: currentStatus?.timestamp ?? 0,
+        checking: true,
+        latencyMs: currentStatus?.latencyMs,
+        error: currentStatus?.error
       })
-    )
-
-    // Save updated cache to localStorage
-    this.saveCache()
-
-    // Notify all listeners of status updates
+    })
     this.notifyListeners()
-  }
-
-  /**
-   * Load cached status from localStorage
-   */
-  private loadCache(): void {
-    try {
-      const cached = localStorage.getItem('oc.providerHealth')
-      if (!cached) return
-
-      const parsed = JSON.parse(cached)
-
-      // Validate and load cache entries
-      for (const [key, value] of Object.entries(parsed)) {
-        if (this.isValidCacheEntry(value)) {
-          this.statusCache.set(key, value as ProviderHealthStatus)
-        }
-      }
-
-      console.log('Loaded provider health cache:', this.statusCache.size, 'entries')
-    } catch (error) {
-      console.warn('Failed to load provider health cache:', error)
-      // Clear corrupted cache
-      localStorage.removeItem('oc.providerHealth')
-    }
-  }
 
-  /**
-   * Save current status to localStorage
-   */
-  private saveCache(): void {
     try {
-      const cacheObject: Record<string, ProviderHealthStatus> = {}
-      
-      this.statusCache.forEach((value, key) => {
-        cacheObject[key] = value
+      const results = await invoke<BackendProviderHealth[]>('check_provider_health', {
+        providerIds: providersToCheck.map(provider => provider.type)
       })
-
-      localStorage.setItem('oc.providerHealth', JSON.stringify(cacheObject))
+      results.forEach(health => this.apply(health))
     } catch (error) {
-      console.error('Failed to save provider health cache:', error)
+      console.warn('Provider health check failed:', error)
+    } finally {
+      // Providers the backend doesn't monitor get no result
+      providersToCheck.forEach(provider => {
+        const status = this.statusCache.get(provider.type)
+        if (status?.checking) {
+          status.checking = false
+        }
+      })
+      this.notifyListeners()
     }
   }
 
   /**
-   * Check if a cached status entry is still valid (not expired).
-   * 
-   * Cache entries are considered valid if they are less than 60 seconds old.
-   * 
+   * Check if a status entry is still fresh (less than 60 seconds old).
+   *
    * @param {ProviderHealthStatus} status - Provider health status to check
-   * @returns {boolean} True if cache is still valid, false if expired
-   * 
-   * @example
-   * const status = monitor.getStatus('ollama');
-   * if (status && !monitor.isCacheValid(status)) {
-   *   // Cache is stale, trigger a fresh check
-   *   await monitor.checkProviders([ollamaProvider]);
-   * }
+   * @returns {boolean} True if the status is still fresh, false if expired
    */
   public isCacheValid(status: ProviderHealthStatus): boolean {
     const age = Date.now() - status.timestamp
     return age < this.CACHE_TTL
   }
-
-  /**
-   * Validate cache entry structure
-   * @param entry Cache entry to validate
-   * @returns True if entry is valid
-   */
-  private isValidCacheEntry(entry: any): boolean {
-    return (
-      typeof entry === 'object' &&
-      typeof entry.type === 'string' &&
-      (typeof entry.status === 'boolean' || entry.status === undefined) &&
-      typeof entry.timestamp === 'number' &&
-      typeof entry.checking === 'boolean'
-    )
-  }
 }
@@ -1,6 +1,8 @@
 // Core types for the modular provider system
 
-export type ProviderType = 'ollama' | 'lmstudio' | 'llamacpp' | 'koboldcpp' | 'textgen-webui' | 'anthropic' | 'openai' | 'openrouter';
+import type { ToolDialect } from './tools';
+
+export type ProviderType = 'ollama' | 'lmstudio' | 'llamacpp' | 'koboldcpp' | 'textgen-webui' | 'anthropic' | 'openai' | 'openrouter' | 'local';
 
 export interface ImageAttachment {
   id: string;
@@ -94,6 +96,12 @@ export interface ProviderConfig {
   enabled: boolean;
   apiKey?: string;
   hiddenModels?: string[];
+  /** Chat template for raw prompts (llama.cpp, local): a built-in name or Jinja source */
+  chatTemplate?: string;
+  /** Per model: emulate tool calls in the prompt in this dialect */
+  toolDialects?: Record<string, ToolDialect>;
+  /** OpenAI-compatible servers: request token usage at the end of the stream (stream_options.include_usage) */
+  includeUsage?: boolean;
 }
 
 export interface ChatCompletionRequest {
@@ -33,6 +33,10 @@ export interface ToolDefinition {
   }
 }
 
+// How tool calls are written in the prompt for models without native tool
+// calling; 'auto' picks one from the model name
+export type ToolDialect = 'auto' | 'hermes' | 'xml' | 'fenced_json' | 'json'
+
 export interface MessageWithToolCalls {
   id: string
   role: 'user' | 'assistant' | 'system' | 'tool'